@@ -0,0 +1,225 @@
+//! Build-script helper that compiles a directory of GLSL shaders to SPIR-V
+//! with shaderc's `glslc` and generates a module embedding the results.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     patoka_build::ShaderBuild::new("shaders").compile().unwrap();
+//! }
+//!
+//! // src/main.rs
+//! mod shaders {
+//!     include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+//! }
+//!
+//! let code: &'static [u32] = shaders::gradient_comp();
+//! ```
+
+use std::env;
+use std::fmt;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SHADER_EXTENSIONS: [&str; 6] = ["vert", "frag", "comp", "geom", "tesc", "tese"];
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Compile(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => {
+                write!(f, "IO error: {err}")
+            }
+            Error::Compile(msg) => {
+                write!(f, "{msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub struct ShaderBuild {
+    source_dir: PathBuf,
+    module_name: String,
+    glslc: PathBuf,
+    target_env: String,
+    optimize: bool,
+    defines: Vec<(String, Option<String>)>,
+}
+
+impl ShaderBuild {
+    /// `source_dir` is relative to the crate root. `glslc` is looked up in
+    /// `$GLSLC`, then in `$VULKAN_SDK/bin`, then in `PATH`.
+    pub fn new(source_dir: impl AsRef<Path>) -> Self {
+        Self {
+            source_dir: source_dir.as_ref().to_path_buf(),
+            module_name: "shaders".to_string(),
+            glslc: find_glslc(),
+            target_env: "vulkan1.3".to_string(),
+            optimize: true,
+            defines: Vec::new(),
+        }
+    }
+
+    /// Name of the generated file, `$OUT_DIR/<name>.rs`. Defaults to `shaders`.
+    pub fn module_name(mut self, name: &str) -> Self {
+        self.module_name = name.to_string();
+        self
+    }
+
+    pub fn glslc(mut self, path: impl AsRef<Path>) -> Self {
+        self.glslc = path.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn target_env(mut self, target_env: &str) -> Self {
+        self.target_env = target_env.to_string();
+        self
+    }
+
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.defines.push((name.to_string(), value.map(str::to_string)));
+        self
+    }
+
+    /// Compiles every shader in the source directory to `$OUT_DIR/<module>/`
+    /// and writes the embedding module. Must be called from a build script.
+    pub fn compile(&self) -> Result<PathBuf> {
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR")
+            .ok_or_else(|| Error::Compile("OUT_DIR is not set, compile() must be called from build.rs".to_string()))?);
+        let spv_dir = out_dir.join(&self.module_name);
+        fs::create_dir_all(&spv_dir)?;
+
+        println!("cargo:rerun-if-changed={}", self.source_dir.display());
+        println!("cargo:rerun-if-env-changed=GLSLC");
+
+        let mut sources = collect_sources(&self.source_dir)?;
+        sources.sort();
+
+        let mut module = String::from("// @generated by patoka-build, do not edit.\n\n");
+
+        for source in &sources {
+            println!("cargo:rerun-if-changed={}", source.display());
+
+            let relative = source.strip_prefix(&self.source_dir).unwrap_or(source);
+            let file_name = function_name(&relative.to_string_lossy());
+            let output = spv_dir.join(format!("{file_name}.spv"));
+            self.compile_one(source, &output)?;
+
+            module.push_str(&format!(
+                "#[allow(dead_code)]\npub fn {}() -> &'static [u32] {{\n    patoka::include_bytes_align_as!(u32, {:?})\n}}\n\n",
+                file_name,
+                output.display().to_string()));
+        }
+
+        let module_path = out_dir.join(format!("{}.rs", self.module_name));
+        fs::write(&module_path, module)?;
+
+        Ok(module_path)
+    }
+
+    fn compile_one(&self, source: &Path, output: &Path) -> Result<()> {
+        let mut command = Command::new(&self.glslc);
+        command
+            .arg(format!("--target-env={}", self.target_env))
+            .arg("-I")
+            .arg(&self.source_dir)
+            .arg("-o")
+            .arg(output)
+            .arg(source);
+
+        if self.optimize {
+            command.arg("-O");
+        }
+
+        for (name, value) in &self.defines {
+            match value {
+                Some(value) => command.arg(format!("-D{name}={value}")),
+                None => command.arg(format!("-D{name}")),
+            };
+        }
+
+        let result = command.output().map_err(|err| {
+            Error::Compile(format!("Failed to run {}: {err}", self.glslc.display()))
+        })?;
+
+        if !result.status.success() {
+            return Err(Error::Compile(format!(
+                "Failed to compile {}:\n{}",
+                source.display(),
+                String::from_utf8_lossy(&result.stderr))));
+        }
+
+        Ok(())
+    }
+}
+
+fn find_glslc() -> PathBuf {
+    if let Some(path) = env::var_os("GLSLC") {
+        return PathBuf::from(path);
+    }
+
+    if let Some(sdk) = env::var_os("VULKAN_SDK") {
+        let path = Path::new(&sdk).join("bin").join("glslc");
+        if path.exists() {
+            return path;
+        }
+    }
+
+    PathBuf::from("glslc")
+}
+
+fn collect_sources(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            res.extend(collect_sources(&path)?);
+            continue;
+        }
+
+        let is_shader = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| SHADER_EXTENSIONS.contains(&ext));
+
+        if is_shader {
+            res.push(path);
+        }
+    }
+
+    Ok(res)
+}
+
+fn function_name(file_name: &str) -> String {
+    let mut name = file_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect::<String>();
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
@@ -0,0 +1,396 @@
+//! Headless compute benchmarks, for tracking the HAL's overhead across
+//! releases.
+//!
+//! ```text
+//! patoka-bench [--list] [--adapter <index>]... [--iterations <count>]
+//! ```
+//!
+//! Every workload runs on each selected adapter, by default all adapters
+//! the renderer supports, and the GPU times measured with timestamp
+//! queries are printed per workload.
+extern crate patoka;
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use ash::vk;
+
+use patoka::include_bytes_align_as;
+use patoka::render::debug::profiler::{GpuProfiler, GpuProfilerCreateInfo};
+use patoka::render::hal::*;
+use patoka::render::hal::vulkan::buffer::Buffer;
+use patoka::render::hal::vulkan::command_list::CommandList;
+use patoka::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use patoka::render::hal::vulkan::image::Texture;
+use patoka::render::hal::vulkan::pipeline::{ComputePipeline, PipelineLayout};
+use patoka::render::hal::vulkan::renderer::Renderer;
+use patoka::render::hal::vulkan::shader::Shader;
+use patoka::render::hal::vulkan::sync::Fence;
+use patoka::render::passes::algorithms::{GpuAlgorithms, GpuAlgorithmsCreateInfo, ReduceOp};
+
+const WARMUP_ITERATIONS: usize = 8;
+const DEFAULT_ITERATIONS: usize = 100;
+
+const GRADIENT_EXTENT: vk::Extent2D = vk::Extent2D { width: 3840, height: 2160 };
+const BANDWIDTH_BYTES: u64 = 256 << 20;
+const REDUCE_ELEMENTS: u32 = 16 << 20;
+
+struct Options {
+    list: bool,
+    adapters: Vec<usize>,
+    iterations: usize,
+}
+
+fn parse_options() -> std::result::Result<Options, String> {
+    let mut options = Options { list: false, adapters: Vec::new(), iterations: DEFAULT_ITERATIONS };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .and_then(|value| value.parse::<usize>().ok())
+                .ok_or_else(|| format!("{name} expects a number"))
+        };
+
+        match arg.as_str() {
+            "--list" => options.list = true,
+            "--adapter" => options.adapters.push(value("--adapter")?),
+            "--iterations" => options.iterations = value("--iterations")?.max(1),
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+
+    Ok(options)
+}
+
+trait Workload {
+    fn name(&self) -> &'static str;
+
+    /// Commands recorded outside of the measured range.
+    fn prepare(&self, _command_list: &mut CommandList) {}
+
+    fn record(&self, command_list: &mut CommandList);
+
+    /// Work done per run, reported next to the median time.
+    fn throughput(&self, ms: f64) -> String;
+}
+
+/// The demo's gradient shader writing a 4K `R16G16B16A16_SFLOAT` image.
+struct GradientFill {
+    texture: Texture,
+    pipeline: Arc<ComputePipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    descriptor_set: Arc<DescriptorSet>,
+}
+
+impl GradientFill {
+    fn new(renderer: Arc<Renderer>) -> Self {
+        let texture = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::R16G16B16A16_SFLOAT,
+                extent: vk::Extent3D { width: GRADIENT_EXTENT.width, height: GRADIENT_EXTENT.height, depth: 1 },
+                usage: vk::ImageUsageFlags::STORAGE,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: vec![DescriptorSetBinding {
+                    stage: ShaderStages::Compute,
+                    typ: BindingType::Texture,
+                    binding: 0,
+                }],
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_set_layout.clone());
+        descriptor_set.write_texture(0, &texture);
+
+        let shader = {
+            let create_info = ShaderCreateInfo {
+                code: include_bytes_align_as!(u32, "shaders/gradient.spv"),
+            };
+            Shader::new(renderer.clone(), create_info)
+        };
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_set_layout],
+                push_constant_ranges: vec![],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let create_info = ComputePipelineCreateInfo {
+                shader,
+                pipeline_layout: pipeline_layout.clone(),
+                entrypoint: c"main",
+                subgroup_operations: SubgroupOperations::empty(),
+            };
+            ComputePipeline::new(renderer, create_info)
+        };
+
+        Self { texture, pipeline, pipeline_layout, descriptor_set }
+    }
+}
+
+impl Workload for GradientFill {
+    fn name(&self) -> &'static str {
+        "gradient fill"
+    }
+
+    fn prepare(&self, command_list: &mut CommandList) {
+        command_list.transition_texture_layout(&self.texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+    }
+
+    fn record(&self, command_list: &mut CommandList) {
+        command_list.bind_compute_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, self.descriptor_set.clone());
+        command_list.dispatch_compute_pipeline(GRADIENT_EXTENT.width.div_ceil(16), GRADIENT_EXTENT.height.div_ceil(16), 1);
+    }
+
+    fn throughput(&self, ms: f64) -> String {
+        let pixels = GRADIENT_EXTENT.width as f64 * GRADIENT_EXTENT.height as f64;
+        format!("{:.2} Gpix/s", pixels / (ms * 1e6))
+    }
+}
+
+/// Buffer to buffer copy between two device local buffers.
+struct MemoryBandwidth {
+    src: Buffer,
+    dst: Buffer,
+}
+
+impl MemoryBandwidth {
+    fn new(renderer: Arc<Renderer>) -> Self {
+        let buffer = |usage| {
+            let create_info = BufferCreateInfo {
+                size: BANDWIDTH_BYTES,
+                usage,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            src: buffer(vk::BufferUsageFlags::TRANSFER_SRC),
+            dst: buffer(vk::BufferUsageFlags::TRANSFER_DST),
+        }
+    }
+}
+
+impl Workload for MemoryBandwidth {
+    fn name(&self) -> &'static str {
+        "memory bandwidth"
+    }
+
+    fn record(&self, command_list: &mut CommandList) {
+        let region = BufferCopy { src_offset: 0, dst_offset: 0, size: BANDWIDTH_BYTES };
+        command_list.copy_buffer(&self.src, &self.dst, &[region]);
+    }
+
+    fn throughput(&self, ms: f64) -> String {
+        // Every byte is read once and written once.
+        format!("{:.2} GB/s", 2.0 * BANDWIDTH_BYTES as f64 / (ms * 1e6))
+    }
+}
+
+/// `GpuAlgorithms::reduce` over 16M values.
+struct Reduction {
+    algorithms: GpuAlgorithms,
+    input: Buffer,
+    output: Buffer,
+}
+
+impl Reduction {
+    fn new(renderer: Arc<Renderer>) -> Self {
+        let buffer = |elements: u32| {
+            let create_info = BufferCreateInfo {
+                size: elements as u64 * 4,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            input: buffer(REDUCE_ELEMENTS),
+            output: buffer(1),
+            algorithms: GpuAlgorithms::new(renderer.clone(), GpuAlgorithmsCreateInfo { max_elements: REDUCE_ELEMENTS }),
+        }
+    }
+}
+
+impl Workload for Reduction {
+    fn name(&self) -> &'static str {
+        "reduction"
+    }
+
+    fn record(&self, command_list: &mut CommandList) {
+        self.algorithms.reduce(command_list, &self.input, &self.output, 0, REDUCE_ELEMENTS, ReduceOp::Add);
+    }
+
+    fn throughput(&self, ms: f64) -> String {
+        format!("{:.2} Gelem/s", REDUCE_ELEMENTS as f64 / (ms * 1e6))
+    }
+}
+
+/// Runs every workload `iterations` times after a warmup and returns the
+/// GPU times of each run in milliseconds, per workload.
+fn measure(renderer: Arc<Renderer>, workloads: &[Box<dyn Workload>], iterations: usize) -> Result<Vec<Vec<f64>>> {
+    let mut command_list = {
+        let create_info = CommandListCreateInfo { queue: QueueType::Graphics };
+        CommandList::new(renderer.clone(), create_info)
+    };
+
+    let fence = Fence::new(renderer.clone());
+
+    let mut profiler = {
+        let create_info = GpuProfilerCreateInfo {
+            max_zones: workloads.len() as u32,
+            history_frames: iterations,
+        };
+        GpuProfiler::new(renderer.clone(), create_info)
+    };
+
+    for _ in 0..WARMUP_ITERATIONS + iterations {
+        fence.wait()?;
+        fence.reset()?;
+
+        command_list.reset();
+        command_list.begin();
+        profiler.begin_frame(&command_list);
+
+        for workload in workloads {
+            workload.prepare(&mut command_list);
+        }
+        command_list.memory_barrier();
+
+        for workload in workloads {
+            let zone = profiler.begin_zone(&command_list, workload.name());
+            workload.record(&mut command_list);
+            profiler.end_zone(&command_list, zone);
+            command_list.memory_barrier();
+        }
+
+        profiler.end_frame();
+        command_list.end();
+
+        renderer.submit(&command_list, &[], &[], &fence)?;
+    }
+
+    // Headless renderers stay on one frame slot, the profiler resolves the
+    // last submission the next time the slot begins a frame.
+    fence.wait()?;
+    command_list.reset();
+    command_list.begin();
+    profiler.begin_frame(&command_list);
+    command_list.end();
+
+    let mut timings = vec![Vec::new(); workloads.len()];
+    for frame in profiler.frames() {
+        for (timing, zone) in timings.iter_mut().zip(&frame.zones) {
+            timing.push(zone.duration_ms());
+        }
+    }
+    Ok(timings)
+}
+
+fn run(adapter: usize, info: &AdapterInfo, iterations: usize) -> Result<()> {
+    let renderer = {
+        let create_info = RendererCreateInfo {
+            adapter: Some(adapter),
+            pipeline_compile_threads: 0,
+            ..Default::default()
+        };
+        Renderer::new_headless(create_info, GRADIENT_EXTENT)?
+    };
+
+    println!("Adapter {adapter}: {} ({:?}, {:?})", info.name, info.device_type, renderer.api_version());
+
+    let workloads: Vec<Box<dyn Workload>> = vec![
+        Box::new(GradientFill::new(renderer.clone())),
+        Box::new(MemoryBandwidth::new(renderer.clone())),
+        Box::new(Reduction::new(renderer.clone())),
+    ];
+
+    let timings = measure(renderer, &workloads, iterations)?;
+
+    for (workload, mut timing) in workloads.iter().zip(timings) {
+        if timing.is_empty() {
+            println!("  {:<18} no results", workload.name());
+            continue;
+        }
+
+        timing.sort_by(f64::total_cmp);
+        let min = timing[0];
+        let median = timing[timing.len() / 2];
+        let mean = timing.iter().sum::<f64>() / timing.len() as f64;
+
+        println!(
+            "  {:<18} min {min:8.3} ms  median {median:8.3} ms  mean {mean:8.3} ms  {}",
+            workload.name(),
+            workload.throughput(median),
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!("Usage: patoka-bench [--list] [--adapter <index>]... [--iterations <count>]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let adapters = match Renderer::enumerate_adapters() {
+        Ok(adapters) => adapters,
+        Err(err) => {
+            eprintln!("Failed to enumerate adapters: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.list {
+        for (idx, adapter) in adapters.iter().enumerate() {
+            match adapter.api_version {
+                Some(api_version) => println!("{idx}: {} ({:?}, {api_version:?})", adapter.name, adapter.device_type),
+                None => println!("{idx}: {} ({:?}, unsupported)", adapter.name, adapter.device_type),
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let selected = if options.adapters.is_empty() {
+        (0..adapters.len()).filter(|&idx| adapters[idx].api_version.is_some()).collect()
+    } else {
+        options.adapters
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for adapter in selected {
+        let Some(info) = adapters.get(adapter) else {
+            eprintln!("There's no adapter {adapter}, see --list");
+            status = ExitCode::FAILURE;
+            continue;
+        };
+
+        if let Err(err) = run(adapter, info, options.iterations) {
+            eprintln!("Adapter {adapter} failed: {err}");
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
@@ -9,15 +9,38 @@ use winit::window::WindowBuilder;
 use patoka::include_bytes_align_as;
 use patoka::render::hal::*;
 use patoka::render::hal::RendererCreateInfo;
-use patoka::render::hal::vulkan::command_list::CommandList;
 use patoka::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use patoka::render::hal::vulkan::frame::FrameContext;
 use patoka::render::hal::vulkan::image::Texture;
 use patoka::render::hal::vulkan::pipeline::{ComputePipeline, PipelineLayout};
 use patoka::render::hal::vulkan::renderer::Renderer;
 use patoka::render::hal::vulkan::shader::Shader;
-use patoka::render::hal::vulkan::sync::{Fence, Semaphore};
+use patoka::render::passes::HDR_FORMAT;
+use patoka::render::passes::tonemap::{TonemapOperator, TonemapPass, TonemapPassCreateInfo};
+
+/// Prints validation messages and other engine logs to stderr.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
 
 fn main() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
     let event_loop = EventLoop::new().unwrap();
     let window = Arc::new(WindowBuilder::new()
         .with_title("Patoka Game")
@@ -25,34 +48,28 @@ fn main() {
         .build(&event_loop).unwrap());
 
     let renderer = {
-        let create_info = RendererCreateInfo {};
+        let create_info = RendererCreateInfo::default();
         Renderer::new(window, create_info).unwrap()
     };
 
-    let mut command_list = {
-        let create_info = CommandListCreateInfo {};
-        CommandList::new(renderer.clone(), create_info)
-    };
+    let mut frames = FrameContext::new(renderer.clone());
 
-    let render_fence = {
-        Fence::new(renderer.clone())
-    };
-
-    let swapchain_semaphore = {
-        Semaphore::new(renderer.clone())
-    };
-
-    let render_semaphore = {
-        Semaphore::new(renderer.clone())
-    };
+    let render_extent = renderer.render_extent();
 
     let texture = {
-        let extent = vk::Extent3D { width: 800, height: 600, depth: 1 };
-        let usage = vk::ImageUsageFlags::TRANSFER_SRC
-            | vk::ImageUsageFlags::TRANSFER_DST
-            | vk::ImageUsageFlags::STORAGE
-            | vk::ImageUsageFlags::COLOR_ATTACHMENT;
-        Texture::new(renderer.clone(), vk::Format::R16G16B16A16_SFLOAT, extent, usage, vk::ImageAspectFlags::COLOR)
+        let create_info = TextureCreateInfo {
+            format: HDR_FORMAT,
+            extent: vk::Extent3D { width: render_extent.width, height: render_extent.height, depth: 1 },
+            usage: vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            aspect: vk::ImageAspectFlags::COLOR,
+            array_layers: 1,
+            mip_levels: 1,
+            kind: TextureKind::D2,
+        };
+        Texture::new(renderer.clone(), create_info)
     };
 
     let draw_image_descriptor_layout = {
@@ -62,6 +79,7 @@ fn main() {
                 typ: BindingType::Texture,
                 binding: 0,
             }],
+            push_descriptor: false,
         };
         DescriptorSetLayout::new(renderer.clone(), create_info)
     };
@@ -79,6 +97,7 @@ fn main() {
     let pipeline_layout = {
         let create_info = PipelineLayoutCreateInfo {
             sets: vec![draw_image_descriptor_layout.clone()],
+            push_constant_ranges: vec![],
         };
 
         PipelineLayout::new(renderer.clone(), create_info)
@@ -89,32 +108,42 @@ fn main() {
             shader: shader.clone(),
             pipeline_layout: pipeline_layout.clone(),
             entrypoint: c"main",
+            subgroup_operations: SubgroupOperations::empty(),
         };
 
         ComputePipeline::new(renderer.clone(), create_info)
     };
 
-    loop {
-        render_fence.wait();
-        render_fence.reset();
+    let tonemap_pass = {
+        let create_info = TonemapPassCreateInfo {
+            extent: texture.extent(),
+            operator: TonemapOperator::Aces,
+            exposure: 1.0,
+            paper_white: 203.0,
+            filter: Filter::Linear,
+            scaling: ScalingMode::Letterbox,
+        };
+        TonemapPass::new(renderer.clone(), create_info)
+    };
 
-        renderer.start_frame(&swapchain_semaphore);
+    loop {
+        let mut frame = match frames.begin_frame() {
+            Ok(frame) => frame,
+            // The GPU is paused, e.g. by a debugger, try again.
+            Err(Error::Timeout) => continue,
+            Err(err) => panic!("{err}"),
+        };
 
         descriptor_set.write_texture(0, &texture);
 
-        command_list.reset();
-        command_list.begin();
+        let command_list = frame.command_list();
         command_list.transition_texture_layout(&texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
         command_list.bind_compute_pipeline(pipeline.clone());
-        command_list.bind_descriptor_set(pipeline_layout.clone(), descriptor_set.clone());
-        command_list.dispatch_compute_pipeline(800 / 16, 600 / 16, 1);
-
-        command_list.copy_to_framebuffer(&texture);
-
-        command_list.end();
+        command_list.bind_descriptor_set(pipeline_layout.clone(), 0, descriptor_set.clone());
+        command_list.dispatch_compute_pipeline(render_extent.width.div_ceil(16), render_extent.height.div_ceil(16), 1);
 
-        renderer.submit(&command_list, &[&swapchain_semaphore], &[&render_semaphore], &render_fence);
+        tonemap_pass.record(command_list, &texture);
 
-        renderer.present(&render_semaphore);
+        frame.end().unwrap();
     }
 }
@@ -1 +1,2 @@
+pub mod platform;
 pub mod render;
\ No newline at end of file
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+/// Game time as seen by the audio, advanced once per frame by `Audio::tick`
+/// alongside rendering so both agree on when a frame happens.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioClock {
+    /// Scaled game time since the first tick, doesn't advance while paused.
+    pub time: Duration,
+    /// Scaled time since the previous tick.
+    pub delta: Duration,
+    /// Ticks since creation, paused ones included.
+    pub frame: u64,
+    pub paused: bool,
+    /// Playback speed the game runs at, e.g. for slow motion.
+    pub time_scale: f32,
+}
+
+/// Integration point for an audio library such as kira or rodio, which mix
+/// on their own thread. The backend is told the game clock every frame and
+/// schedules or adjusts its sounds from it, e.g. matching the playback rate
+/// to `time_scale`.
+pub trait AudioBackend {
+    /// Called by `Audio::tick` once per frame, after the clock advanced.
+    fn update(&mut self, clock: &AudioClock);
+
+    /// Called when the clock is paused or resumed.
+    fn set_paused(&mut self, paused: bool) {
+        let _ = paused;
+    }
+
+    /// Delay between submitting samples and hearing them, the renderer
+    /// can present that much later to keep picture and sound in sync.
+    fn output_latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Owns the audio clock next to the render loop and drives an optional
+/// `AudioBackend` from it:
+///
+/// ```ignore
+/// let mut audio = Audio::new();
+/// audio.set_backend(Box::new(KiraBackend::new()?));
+/// loop {
+///     let mut frame = frames.begin_frame()?;
+///     audio.tick(Instant::now());
+///     ..
+///     frame.end()?;
+/// }
+/// ```
+pub struct Audio {
+    /// Longest step a single tick advances the clock by, so a hitch or a
+    /// debugger break doesn't skip sounds ahead.
+    pub max_delta: Duration,
+
+    clock: AudioClock,
+    last_tick: Option<Instant>,
+    backend: Option<Box<dyn AudioBackend>>,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Self {
+            max_delta: Duration::from_millis(100),
+            clock: AudioClock {
+                time: Duration::ZERO,
+                delta: Duration::ZERO,
+                frame: 0,
+                paused: false,
+                time_scale: 1.0,
+            },
+            last_tick: None,
+            backend: None,
+        }
+    }
+
+    pub fn clock(&self) -> &AudioClock {
+        &self.clock
+    }
+
+    pub fn set_backend(&mut self, backend: Box<dyn AudioBackend>) {
+        self.backend = Some(backend);
+    }
+
+    pub fn backend_mut(&mut self) -> Option<&mut (dyn AudioBackend + 'static)> {
+        self.backend.as_deref_mut()
+    }
+
+    /// Latency of the backend, 0 without one.
+    pub fn output_latency(&self) -> Duration {
+        self.backend.as_ref().map_or(Duration::ZERO, |b| b.output_latency())
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.clock.paused == paused {
+            return;
+        }
+        self.clock.paused = paused;
+        if let Some(backend) = &mut self.backend {
+            backend.set_paused(paused);
+        }
+    }
+
+    /// Negative scales are clamped to 0.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.clock.time_scale = time_scale.max(0.0);
+    }
+
+    /// Advances the clock to `now`, the frame's start time, and updates the
+    /// backend. Call once per frame from the game loop.
+    pub fn tick(&mut self, now: Instant) {
+        let elapsed = self.last_tick.map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_tick = Some(now);
+
+        self.clock.delta = if self.clock.paused {
+            Duration::ZERO
+        } else {
+            elapsed.min(self.max_delta).mul_f32(self.clock.time_scale)
+        };
+        self.clock.time += self.clock.delta;
+        self.clock.frame += 1;
+
+        if let Some(backend) = &mut self.backend {
+            backend.update(&self.clock);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Deserialize;
+
+use crate::render::hal::{PresentMode, RendererCreateInfo};
+use crate::render::hal::vulkan::renderer::Renderer;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    /// The file extension is neither `toml` nor `ron`.
+    UnknownFormat(PathBuf),
+    Parse(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => {
+                write!(f, "Failed to read config: {err}")
+            }
+            ConfigError::UnknownFormat(path) => {
+                write!(f, "Unknown config format: {}", path.display())
+            }
+            ConfigError::Parse(msg) => {
+                write!(f, "Failed to parse config: {msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigFormat {
+    Toml,
+    Ron,
+}
+
+impl ConfigFormat {
+    /// Format of a file by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(ConfigFormat::Toml),
+            "ron" => Some(ConfigFormat::Ron),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationLevel {
+    Off,
+    /// The Khronos validation layer, see
+    /// `RendererCreateInfo::validation_layers`.
+    Layers,
+    /// The validation layer and patoka's own usage checks, see
+    /// `RendererCreateInfo::validate_usage`.
+    Full,
+}
+
+/// Engine settings read from a TOML or RON file. Missing entries keep
+/// their defaults:
+///
+/// ```toml
+/// window_size = [1920, 1080]
+/// vsync = false
+/// render_scale = 0.75
+/// validation = "off"
+///
+/// [features]
+/// rtao = true
+/// bloom = false
+/// ```
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    /// Initial inner size of the window in logical pixels.
+    pub window_size: (u32, u32),
+    /// Present with FIFO, otherwise with mailbox or immediate, whichever
+    /// the surface supports.
+    pub vsync: bool,
+    /// See `RendererCreateInfo::render_scale`.
+    pub render_scale: f32,
+    pub validation: ValidationLevel,
+    /// Switches for optional passes and game systems, see `feature`.
+    pub features: BTreeMap<String, bool>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            window_size: (800, 600),
+            vsync: true,
+            render_scale: 1.0,
+            validation: ValidationLevel::Layers,
+            features: BTreeMap::new(),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads a config in the format its extension names.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let format = ConfigFormat::from_path(path).ok_or_else(|| ConfigError::UnknownFormat(path.to_path_buf()))?;
+        Self::parse(&fs::read_to_string(path)?, format)
+    }
+
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        match format {
+            ConfigFormat::Toml => toml::from_str(text).map_err(|err| ConfigError::Parse(err.to_string())),
+            ConfigFormat::Ron => ron::from_str(text).map_err(|err| ConfigError::Parse(err.to_string())),
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Mailbox
+        }
+    }
+
+    /// State of a feature toggle, `None` when the file doesn't mention it.
+    pub fn feature(&self, name: &str) -> Option<bool> {
+        self.features.get(name).copied()
+    }
+
+    /// Renderer settings of the config, the rest at their defaults.
+    pub fn renderer_create_info(&self) -> RendererCreateInfo {
+        RendererCreateInfo {
+            present_mode: self.present_mode(),
+            render_scale: self.render_scale,
+            validation_layers: self.validation != ValidationLevel::Off,
+            validate_usage: self.validation == ValidationLevel::Full,
+            ..Default::default()
+        }
+    }
+
+    /// Pushes the settings that can change at runtime into `renderer`,
+    /// which picks them up in the next `start_frame`.
+    pub fn apply(&self, renderer: &Renderer) {
+        renderer.set_present_mode(self.present_mode());
+        renderer.set_render_scale(self.render_scale);
+    }
+
+    /// What differs from `previous`.
+    pub fn changes(&self, previous: &EngineConfig) -> ConfigChanges {
+        let names: BTreeSet<&String> = self.features.keys().chain(previous.features.keys()).collect();
+        let features = names
+            .into_iter()
+            .filter(|name| self.feature(name) != previous.feature(name))
+            .cloned()
+            .collect();
+
+        ConfigChanges {
+            present_mode: self.present_mode() != previous.present_mode(),
+            render_scale: self.render_scale != previous.render_scale,
+            features,
+            requires_restart: self.window_size != previous.window_size || self.validation != previous.validation,
+        }
+    }
+}
+
+/// Difference between two configs, see `EngineConfig::changes`.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ConfigChanges {
+    pub present_mode: bool,
+    /// Render targets sized by `Renderer::render_extent` need recreating
+    /// after the next `start_frame`.
+    pub render_scale: bool,
+    /// Toggles that were switched, added or removed.
+    pub features: Vec<String>,
+    /// Window size and validation are only read at startup.
+    pub requires_restart: bool,
+}
+
+/// An `EngineConfig` reloaded whenever its file is saved, so settings can
+/// be tweaked while the game runs. Changes reach the renderer at the next
+/// frame boundary:
+///
+/// ```ignore
+/// let mut config = ConfigWatcher::new("engine.toml")?;
+/// let renderer = Renderer::new(window, config.config().renderer_create_info())?;
+/// loop {
+///     match config.update(&renderer, Instant::now()) {
+///         Ok(Some(changes)) if changes.render_scale => recreate_targets = true,
+///         Err(err) => log::warn!("{err}"),
+///         _ => {}
+///     }
+///     let mut frame = frames.begin_frame()?;
+///     ..
+/// }
+/// ```
+pub struct ConfigWatcher {
+    /// How often the modification time of the file is checked.
+    pub poll_interval: Duration,
+
+    path: PathBuf,
+    config: EngineConfig,
+    modified: Option<SystemTime>,
+    last_poll: Option<Instant>,
+}
+
+impl ConfigWatcher {
+    /// Loads the config at `path`, which has to exist and parse.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, ConfigError> {
+        let path = path.into();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let config = EngineConfig::load(&path)?;
+        Ok(Self {
+            poll_interval: Duration::from_millis(500),
+            path,
+            config,
+            modified,
+            last_poll: None,
+        })
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reloads the config when the file was modified since the last poll
+    /// and `poll_interval` has passed. Returns the changes of a reload. A
+    /// file that fails to load keeps the previous config, the next save is
+    /// tried again.
+    pub fn poll(&mut self, now: Instant) -> Result<Option<ConfigChanges>, ConfigError> {
+        if self.last_poll.is_some_and(|last| now.saturating_duration_since(last) < self.poll_interval) {
+            return Ok(None);
+        }
+        self.last_poll = Some(now);
+
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified == self.modified {
+            return Ok(None);
+        }
+        self.modified = modified;
+
+        let config = EngineConfig::load(&self.path)?;
+        let changes = config.changes(&self.config);
+        self.config = config;
+        Ok(Some(changes))
+    }
+
+    /// `poll`, then pushes a reloaded config into `renderer`, see
+    /// `EngineConfig::apply`.
+    pub fn update(&mut self, renderer: &Renderer, now: Instant) -> Result<Option<ConfigChanges>, ConfigError> {
+        let changes = self.poll(now)?;
+        if changes.is_some() {
+            self.config.apply(renderer);
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_entries_keep_their_defaults() {
+        let config = EngineConfig::parse("vsync = false\n[features]\nrtao = true\n", ConfigFormat::Toml).unwrap();
+
+        assert!(!config.vsync);
+        assert_eq!(config.present_mode(), PresentMode::Mailbox);
+        assert_eq!(config.window_size, EngineConfig::default().window_size);
+        assert_eq!(config.feature("rtao"), Some(true));
+        assert_eq!(config.feature("bloom"), None);
+    }
+
+    #[test]
+    fn toml_and_ron_parse_the_same() {
+        let toml = "window_size = [1920, 1080]\nrender_scale = 0.75\nvalidation = \"full\"\n";
+        let ron = "(window_size: (1920, 1080), render_scale: 0.75, validation: full)";
+
+        let config = EngineConfig::parse(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(config, EngineConfig::parse(ron, ConfigFormat::Ron).unwrap());
+        assert_eq!(config.window_size, (1920, 1080));
+        assert!(config.renderer_create_info().validate_usage);
+    }
+
+    #[test]
+    fn invalid_configs_fail_to_parse() {
+        assert!(matches!(EngineConfig::parse("vsync = 3", ConfigFormat::Toml), Err(ConfigError::Parse(_))));
+        assert_eq!(ConfigFormat::from_path(Path::new("engine.ron")), Some(ConfigFormat::Ron));
+        assert_eq!(ConfigFormat::from_path(Path::new("engine.json")), None);
+    }
+
+    #[test]
+    fn changes_list_what_differs() {
+        let previous = EngineConfig::parse("[features]\nrtao = true\nbloom = true\n", ConfigFormat::Toml).unwrap();
+        let config = EngineConfig::parse("render_scale = 0.5\n[features]\nrtao = true\nbloom = false\nfog = true\n", ConfigFormat::Toml).unwrap();
+
+        let changes = config.changes(&previous);
+        assert_eq!(changes, ConfigChanges {
+            present_mode: false,
+            render_scale: true,
+            features: vec!["bloom".to_string(), "fog".to_string()],
+            requires_restart: false,
+        });
+        assert_eq!(config.changes(&config), ConfigChanges::default());
+
+        let resized = EngineConfig { window_size: (1280, 720), ..config.clone() };
+        assert!(resized.changes(&config).requires_restart);
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::fmt::Display;
+
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window};
+
+use crate::render::hal::vulkan::renderer::Renderer;
+
+#[derive(Debug)]
+pub enum DisplayError {
+    /// A headless renderer has no window.
+    NoWindow,
+    /// The index is out of range of `monitors`, or the window is on no
+    /// monitor.
+    NoMonitor(Option<usize>),
+    /// The monitor reports no video modes.
+    NoVideoMode,
+}
+
+impl Display for DisplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayError::NoWindow => {
+                write!(f, "The renderer has no window")
+            }
+            DisplayError::NoMonitor(Some(index)) => {
+                write!(f, "There's no monitor {index}")
+            }
+            DisplayError::NoMonitor(None) => {
+                write!(f, "The window isn't on any monitor")
+            }
+            DisplayError::NoVideoMode => {
+                write!(f, "The monitor reports no video modes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DisplayError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VideoModeInfo {
+    /// In physical pixels.
+    pub size: (u32, u32),
+    pub bit_depth: u16,
+    pub refresh_rate_millihertz: u32,
+}
+
+impl VideoModeInfo {
+    fn new(mode: &VideoMode) -> Self {
+        Self {
+            size: mode.size().into(),
+            bit_depth: mode.bit_depth(),
+            refresh_rate_millihertz: mode.refresh_rate_millihertz(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MonitorInfo {
+    pub name: Option<String>,
+    /// Top left corner on the desktop, in physical pixels.
+    pub position: (i32, i32),
+    /// Current resolution in physical pixels.
+    pub size: (u32, u32),
+    pub scale_factor: f64,
+    /// Current refresh rate, `None` where the platform doesn't report it.
+    pub refresh_rate_millihertz: Option<u32>,
+    pub primary: bool,
+    /// What `WindowMode::Exclusive` can switch to.
+    pub video_modes: Vec<VideoModeInfo>,
+}
+
+/// How the window covers the screen, see `set_window_mode`. Monitors are
+/// indices into `monitors`, `None` is the monitor the window is on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    /// A borderless window covering the monitor at its desktop resolution
+    /// and refresh rate, switches from and to other windows instantly.
+    Borderless { monitor: Option<usize> },
+    /// Changes the video mode of the monitor, see `select_video_mode`.
+    /// With `VK_EXT_full_screen_exclusive` the swapchain also takes
+    /// exclusive control of the display, bypassing the compositor.
+    Exclusive {
+        monitor: Option<usize>,
+        /// Desktop resolution with `None`.
+        size: Option<(u32, u32)>,
+        /// Highest available with `None`.
+        refresh_rate_millihertz: Option<u32>,
+    },
+}
+
+/// The monitors connected to the system, in the order `WindowMode` indexes
+/// them.
+pub fn monitors(window: &Window) -> Vec<MonitorInfo> {
+    let primary = window.primary_monitor();
+    window
+        .available_monitors()
+        .map(|monitor| MonitorInfo {
+            name: monitor.name(),
+            position: monitor.position().into(),
+            size: monitor.size().into(),
+            scale_factor: monitor.scale_factor(),
+            refresh_rate_millihertz: monitor.refresh_rate_millihertz(),
+            primary: primary.as_ref() == Some(&monitor),
+            video_modes: monitor.video_modes().map(|mode| VideoModeInfo::new(&mode)).collect(),
+        })
+        .collect()
+}
+
+/// Index into `monitors` of the monitor the window is on.
+pub fn current_monitor(window: &Window) -> Option<usize> {
+    let current = window.current_monitor()?;
+    window.available_monitors().position(|monitor| monitor == current)
+}
+
+fn find_monitor(window: &Window, index: Option<usize>) -> Result<MonitorHandle, DisplayError> {
+    let monitor = match index {
+        Some(index) => window.available_monitors().nth(index),
+        None => window.current_monitor().or_else(|| window.primary_monitor()),
+    };
+    monitor.ok_or(DisplayError::NoMonitor(index))
+}
+
+/// The video mode of `monitor` with `size`, its desktop resolution with
+/// `None`, and the refresh rate closest to `refresh_rate_millihertz`, the
+/// highest with `None`. Modes in other sizes are only picked when none
+/// matches. Ties go to the higher bit depth.
+pub fn select_video_mode(monitor: &MonitorHandle, size: Option<(u32, u32)>, refresh_rate_millihertz: Option<u32>) -> Option<VideoMode> {
+    let size = size.unwrap_or_else(|| monitor.size().into());
+    let modes = monitor.video_modes().collect::<Vec<_>>();
+    let matching = modes.iter().filter(|mode| <(u32, u32)>::from(mode.size()) == size).collect::<Vec<_>>();
+    let candidates = if matching.is_empty() {
+        // The largest modes not exceeding the requested size, or the
+        // smallest ones when all exceed it.
+        let area = |mode: &VideoMode| mode.size().width as u64 * mode.size().height as u64;
+        let requested = size.0 as u64 * size.1 as u64;
+        let best = modes.iter().map(area).filter(|&a| a <= requested).max()
+            .or_else(|| modes.iter().map(area).min())?;
+        modes.iter().filter(|mode| area(mode) == best).collect()
+    } else {
+        matching
+    };
+
+    candidates
+        .into_iter()
+        .max_by_key(|mode| {
+            let refresh = mode.refresh_rate_millihertz();
+            let refresh_score = match refresh_rate_millihertz {
+                Some(requested) => u32::MAX - refresh.abs_diff(requested),
+                None => refresh,
+            };
+            (refresh_score, mode.bit_depth())
+        })
+        .cloned()
+}
+
+/// Switches the window of `renderer` to `mode` and has the renderer
+/// recreate its swapchain for it in the next `Renderer::start_frame`.
+/// Returns the video mode switched to by `WindowMode::Exclusive`.
+///
+/// ```ignore
+/// let monitors = display::monitors(&window);
+/// let mode = WindowMode::Exclusive { monitor: Some(0), size: Some((1920, 1080)), refresh_rate_millihertz: Some(144_000) };
+/// if let Err(err) = display::set_window_mode(&renderer, mode) {
+///     log::warn!("{err}");
+/// }
+/// ```
+pub fn set_window_mode(renderer: &Renderer, mode: WindowMode) -> Result<Option<VideoModeInfo>, DisplayError> {
+    let window = renderer.window().ok_or(DisplayError::NoWindow)?;
+    let (fullscreen, video_mode) = match mode {
+        WindowMode::Windowed => (None, None),
+        WindowMode::Borderless { monitor } => {
+            let monitor = find_monitor(&window, monitor)?;
+            (Some(Fullscreen::Borderless(Some(monitor))), None)
+        }
+        WindowMode::Exclusive { monitor, size, refresh_rate_millihertz } => {
+            let monitor = find_monitor(&window, monitor)?;
+            let video_mode = select_video_mode(&monitor, size, refresh_rate_millihertz).ok_or(DisplayError::NoVideoMode)?;
+            let info = VideoModeInfo::new(&video_mode);
+            (Some(Fullscreen::Exclusive(video_mode)), Some(info))
+        }
+    };
+
+    window.set_fullscreen(fullscreen);
+    renderer.set_fullscreen_exclusive(matches!(mode, WindowMode::Exclusive { .. }));
+    Ok(video_mode)
+}
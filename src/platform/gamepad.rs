@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::platform::input::{GamepadAxis, GamepadButton, Input};
+
+fn convert_button(button: Button) -> Option<GamepadButton> {
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::West => GamepadButton::West,
+        Button::North => GamepadButton::North,
+        Button::LeftTrigger => GamepadButton::LeftShoulder,
+        Button::RightTrigger => GamepadButton::RightShoulder,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+fn convert_axis(axis: Axis) -> Option<GamepadAxis> {
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        _ => return None,
+    })
+}
+
+/// Gamepad backend of `Input` on top of gilrs. Follows the gamepad used
+/// last, so picking up a different controller just works:
+///
+/// ```ignore
+/// let mut gamepads = Gamepads::new()?;
+/// // Every frame, before reading `input`.
+/// gamepads.poll(&mut input);
+/// if input.action_just_pressed("fire") {
+///     gamepads.rumble(1.0, 0.5, Duration::from_millis(150));
+/// }
+/// ```
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+    /// Playing rumble, gilrs stops effects whose handles are dropped.
+    rumble: Option<Effect>,
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Self, gilrs::Error> {
+        Ok(Self { gilrs: Gilrs::new()?, active: None, rumble: None })
+    }
+
+    /// The gamepad `Input` reports, `None` before any gamepad was used.
+    pub fn active(&self) -> Option<GamepadId> {
+        self.active
+    }
+
+    /// Name of the active gamepad.
+    pub fn name(&self) -> Option<String> {
+        self.active.map(|id| self.gilrs.gamepad(id).name().to_string())
+    }
+
+    /// Feeds the gamepad events since the last poll into `input`.
+    pub fn poll(&mut self, input: &mut Input) {
+        while let Some(event) = self.gilrs.next_event() {
+            if self.active != Some(event.id) {
+                if matches!(event.event, EventType::Connected | EventType::Disconnected) {
+                    continue;
+                }
+                // Another gamepad took over, the old one's state is stale.
+                input.clear_gamepad();
+                self.active = Some(event.id);
+            }
+
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = convert_button(button) {
+                        input.set_gamepad_button(button, true);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = convert_button(button) {
+                        input.set_gamepad_button(button, false);
+                    }
+                }
+                // Analog triggers report as buttons with a value.
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => input.set_gamepad_axis(GamepadAxis::LeftTrigger, value),
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => input.set_gamepad_axis(GamepadAxis::RightTrigger, value),
+                EventType::AxisChanged(axis, value, _) => {
+                    if let Some(axis) = convert_axis(axis) {
+                        input.set_gamepad_axis(axis, value);
+                    }
+                }
+                EventType::Disconnected => {
+                    input.clear_gamepad();
+                    self.active = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Vibrates the active gamepad, `strong` and `weak` drive the low and
+    /// high frequency motors from 0 to 1. Returns false where force
+    /// feedback isn't supported.
+    pub fn rumble(&mut self, strong: f32, weak: f32, duration: Duration) -> bool {
+        let Some(id) = self.active else {
+            return false;
+        };
+        if !self.gilrs.gamepad(id).is_ff_supported() {
+            return false;
+        }
+
+        let magnitude = |value: f32| (value.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        let scheduling = Replay { play_for: Ticks::from_ms(duration.as_millis() as u32), ..Default::default() };
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect { kind: BaseEffectType::Strong { magnitude: magnitude(strong) }, scheduling, ..Default::default() })
+            .add_effect(BaseEffect { kind: BaseEffectType::Weak { magnitude: magnitude(weak) }, scheduling, ..Default::default() })
+            .gamepads(&[id])
+            .finish(&mut self.gilrs);
+        match effect.and_then(|effect| effect.play().map(|_| effect)) {
+            Ok(effect) => {
+                self.rumble = Some(effect);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use winit::dpi::PhysicalPosition;
+use winit::error::ExternalError;
+use winit::event::{DeviceEvent, ElementState, Event, MouseScrollDelta, WindowEvent};
+use winit::keyboard::PhysicalKey;
+use winit::window::{CursorGrabMode, Window};
+
+pub use winit::event::MouseButton;
+pub use winit::keyboard::KeyCode;
+
+/// Pixels per line of `MouseScrollDelta::LineDelta`, scroll deltas are
+/// reported in lines.
+const PIXELS_PER_LINE: f32 = 20.0;
+/// Value past which an axis bound to an action counts as pressed.
+const AXIS_PRESS_THRESHOLD: f32 = 0.5;
+
+/// Gamepad buttons, named by position rather than by label so layouts of
+/// different vendors map the same.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadButton {
+    /// A on Xbox, cross on PlayStation.
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadAxis {
+    LeftStickX,
+    /// Up is positive.
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    /// 0 released, 1 fully pressed.
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    fn is_trigger(self) -> bool {
+        matches!(self, GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger)
+    }
+
+    /// The other axis of the same stick.
+    fn partner(self) -> Option<GamepadAxis> {
+        match self {
+            GamepadAxis::LeftStickX => Some(GamepadAxis::LeftStickY),
+            GamepadAxis::LeftStickY => Some(GamepadAxis::LeftStickX),
+            GamepadAxis::RightStickX => Some(GamepadAxis::RightStickY),
+            GamepadAxis::RightStickY => Some(GamepadAxis::RightStickX),
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => None,
+        }
+    }
+}
+
+/// Half of an axis, for binding a stick direction or a trigger to an
+/// action.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// An input an action can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+    /// Analog, the action's value follows the axis and it counts as
+    /// pressed past half way.
+    GamepadAxis(GamepadAxis, AxisDirection),
+}
+
+/// Maps raw axis values to what the game sees: values within `deadzone`
+/// read 0, values past `outer_deadzone` read 1, and the range between is
+/// rescaled and raised to `exponent` for finer control near the center.
+/// Sticks apply it to their length, keeping the direction.
+#[derive(Clone, Copy, Debug)]
+pub struct AxisResponse {
+    pub deadzone: f32,
+    pub outer_deadzone: f32,
+    /// 1 is linear.
+    pub exponent: f32,
+}
+
+impl AxisResponse {
+    /// Response of a magnitude in 0 to 1.
+    pub fn apply(&self, magnitude: f32) -> f32 {
+        let range = (self.outer_deadzone - self.deadzone).max(1e-6);
+        ((magnitude - self.deadzone) / range).clamp(0.0, 1.0).powf(self.exponent)
+    }
+}
+
+/// Held buttons and the transitions since the last `Input::end_frame`. A
+/// press and release within one frame reports both.
+struct Buttons<T> {
+    down: HashSet<T>,
+    just_pressed: HashSet<T>,
+    just_released: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> Buttons<T> {
+    fn new() -> Self {
+        Self { down: HashSet::new(), just_pressed: HashSet::new(), just_released: HashSet::new() }
+    }
+
+    fn set(&mut self, button: T, pressed: bool) {
+        if pressed {
+            // Key repeat sends presses of held keys.
+            if self.down.insert(button) {
+                self.just_pressed.insert(button);
+            }
+        } else if self.down.remove(&button) {
+            self.just_released.insert(button);
+        }
+    }
+
+    fn release_all(&mut self) {
+        self.just_released.extend(self.down.drain());
+    }
+
+    fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+/// Keyboard, mouse and gamepad state built from winit events, with
+/// actions bound to any number of inputs. Events of a frame are fed with
+/// `handle_event`, the game queries the state while updating and calls
+/// `end_frame` afterwards:
+///
+/// ```ignore
+/// input.bind("jump", Binding::Key(KeyCode::Space));
+/// input.bind("jump", Binding::Gamepad(GamepadButton::South));
+/// input.bind("move_right", Binding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Positive));
+/// event_loop.run(move |event, target| {
+///     input.handle_event(&event);
+///     if let Event::AboutToWait = event {
+///         if input.action_just_pressed("jump") { .. }
+///         let [dx, dy] = input.mouse_delta();
+///         input.end_frame();
+///     }
+/// })?;
+/// ```
+pub struct Input {
+    pub stick_response: AxisResponse,
+    pub trigger_response: AxisResponse,
+
+    keys: Buttons<KeyCode>,
+    mouse_buttons: Buttons<MouseButton>,
+    gamepad_buttons: Buttons<GamepadButton>,
+    /// Raw values as reported by the backend.
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    /// `gamepad_axis` at the last `end_frame`.
+    previous_axes: HashMap<GamepadAxis, f32>,
+    cursor_position: Option<[f32; 2]>,
+    mouse_delta: [f32; 2],
+    scroll_delta: [f32; 2],
+    cursor_grabbed: bool,
+    actions: HashMap<String, Vec<Binding>>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            stick_response: AxisResponse { deadzone: 0.15, outer_deadzone: 0.95, exponent: 1.0 },
+            trigger_response: AxisResponse { deadzone: 0.05, outer_deadzone: 1.0, exponent: 1.0 },
+            keys: Buttons::new(),
+            mouse_buttons: Buttons::new(),
+            gamepad_buttons: Buttons::new(),
+            gamepad_axes: HashMap::new(),
+            previous_axes: HashMap::new(),
+            cursor_position: None,
+            mouse_delta: [0.0; 2],
+            scroll_delta: [0.0; 2],
+            cursor_grabbed: false,
+            actions: HashMap::new(),
+        }
+    }
+
+    /// Updates the state from window and device events, others are
+    /// ignored.
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        match event {
+            Event::WindowEvent { event, .. } => self.handle_window_event(event),
+            Event::DeviceEvent { event, .. } => self.handle_device_event(event),
+            _ => {}
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(key) = event.physical_key {
+                    self.keys.set(key, event.state == ElementState::Pressed);
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.mouse_buttons.set(*button, *state == ElementState::Pressed);
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some([position.x as f32, position.y as f32]);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let [x, y] = match *delta {
+                    MouseScrollDelta::LineDelta(x, y) => [x, y],
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { x, y }) => [x as f32 / PIXELS_PER_LINE, y as f32 / PIXELS_PER_LINE],
+                };
+                self.scroll_delta[0] += x;
+                self.scroll_delta[1] += y;
+            }
+            // Releases are lost while unfocused.
+            WindowEvent::Focused(false) => {
+                self.keys.release_all();
+                self.mouse_buttons.release_all();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = *event {
+            self.mouse_delta[0] += x as f32;
+            self.mouse_delta[1] += y as f32;
+        }
+    }
+
+    /// Clears the per frame transitions and deltas, after the frame's
+    /// update read them.
+    pub fn end_frame(&mut self) {
+        self.keys.end_frame();
+        self.mouse_buttons.end_frame();
+        self.gamepad_buttons.end_frame();
+        self.previous_axes = self.gamepad_axes.keys().map(|&axis| (axis, self.gamepad_axis(axis))).collect();
+        self.mouse_delta = [0.0; 2];
+        self.scroll_delta = [0.0; 2];
+    }
+
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.keys.down.contains(&key)
+    }
+
+    pub fn key_just_pressed(&self, key: KeyCode) -> bool {
+        self.keys.just_pressed.contains(&key)
+    }
+
+    pub fn key_just_released(&self, key: KeyCode) -> bool {
+        self.keys.just_released.contains(&key)
+    }
+
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.down.contains(&button)
+    }
+
+    pub fn mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.just_pressed.contains(&button)
+    }
+
+    pub fn mouse_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.just_released.contains(&button)
+    }
+
+    /// In physical pixels from the window's top left corner, `None` while
+    /// the cursor is outside.
+    pub fn cursor_position(&self) -> Option<[f32; 2]> {
+        self.cursor_position
+    }
+
+    /// Raw mouse motion since the last `end_frame`, unaffected by the
+    /// cursor reaching the window border. Use it for camera look while the
+    /// cursor is grabbed.
+    pub fn mouse_delta(&self) -> [f32; 2] {
+        self.mouse_delta
+    }
+
+    /// Scrolled lines since the last `end_frame`, positive y scrolls up.
+    pub fn scroll_delta(&self) -> [f32; 2] {
+        self.scroll_delta
+    }
+
+    pub fn gamepad_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.down.contains(&button)
+    }
+
+    pub fn gamepad_just_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.just_pressed.contains(&button)
+    }
+
+    pub fn gamepad_just_released(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.just_released.contains(&button)
+    }
+
+    fn raw_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepad_axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    /// -1 to 1 for sticks, 0 to 1 for triggers, 0 without a gamepad. Goes
+    /// through `stick_response` or `trigger_response`.
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        let value = self.raw_axis(axis);
+        let Some(partner) = axis.partner() else {
+            return self.trigger_response.apply(value.abs());
+        };
+        // Radial, a diagonal isn't cut off by the deadzone of either axis.
+        let length = value.hypot(self.raw_axis(partner));
+        if length <= 0.0 {
+            return 0.0;
+        }
+        (value / length * self.stick_response.apply(length.min(1.0))).clamp(-1.0, 1.0)
+    }
+
+    /// Fed by a gamepad backend, winit doesn't report gamepads.
+    pub fn set_gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        self.gamepad_buttons.set(button, pressed);
+    }
+
+    /// Fed by a gamepad backend with raw values, see `gamepad_axis` for the
+    /// ranges.
+    pub fn set_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        let value = if axis.is_trigger() { value.clamp(0.0, 1.0) } else { value.clamp(-1.0, 1.0) };
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    /// Releases the gamepad buttons and centers the axes, when the gamepad
+    /// disconnects.
+    pub fn clear_gamepad(&mut self) {
+        self.gamepad_buttons.release_all();
+        self.gamepad_axes.clear();
+    }
+
+    /// Hides the cursor and locks it in place, or confines it to the window
+    /// where locking isn't supported. `mouse_delta` keeps reporting motion.
+    pub fn set_cursor_grab(&mut self, window: &Window, grab: bool) -> Result<(), ExternalError> {
+        if grab {
+            window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))?;
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)?;
+        }
+        window.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+        Ok(())
+    }
+
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    /// Adds `binding` to `action`, an action triggers from any of its
+    /// bindings.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.actions.get_mut(action) {
+            bindings.retain(|&b| b != binding);
+        }
+    }
+
+    /// Empty for unknown actions.
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// 0 to 1, the part of the axis in `direction`.
+    fn axis_value(&self, axis: GamepadAxis, direction: AxisDirection, previous: bool) -> f32 {
+        let value = if previous {
+            self.previous_axes.get(&axis).copied().unwrap_or(0.0)
+        } else {
+            self.gamepad_axis(axis)
+        };
+        match direction {
+            AxisDirection::Positive => value.max(0.0),
+            AxisDirection::Negative => (-value).max(0.0),
+        }
+    }
+
+    fn binding_state(&self, binding: Binding) -> (bool, bool, bool) {
+        match binding {
+            Binding::Key(key) => (self.key_pressed(key), self.key_just_pressed(key), self.key_just_released(key)),
+            Binding::Mouse(button) => (self.mouse_pressed(button), self.mouse_just_pressed(button), self.mouse_just_released(button)),
+            Binding::Gamepad(button) => (self.gamepad_pressed(button), self.gamepad_just_pressed(button), self.gamepad_just_released(button)),
+            Binding::GamepadAxis(axis, direction) => {
+                let down = self.axis_value(axis, direction, false) > AXIS_PRESS_THRESHOLD;
+                let was_down = self.axis_value(axis, direction, true) > AXIS_PRESS_THRESHOLD;
+                (down, down && !was_down, !down && was_down)
+            }
+        }
+    }
+
+    fn binding_value(&self, binding: Binding) -> f32 {
+        match binding {
+            Binding::GamepadAxis(axis, direction) => self.axis_value(axis, direction, false),
+            _ => self.binding_state(binding).0 as i32 as f32,
+        }
+    }
+
+    /// Any binding of `action` is held.
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|&b| self.binding_state(b).0)
+    }
+
+    /// A binding of `action` was pressed this frame while none was held
+    /// before.
+    pub fn action_just_pressed(&self, action: &str) -> bool {
+        let bindings = self.bindings(action);
+        bindings.iter().any(|&b| self.binding_state(b).1)
+            && bindings.iter().all(|&b| {
+                let (down, just_pressed, _) = self.binding_state(b);
+                just_pressed || !down
+            })
+    }
+
+    /// The last held binding of `action` was released this frame.
+    pub fn action_just_released(&self, action: &str) -> bool {
+        let bindings = self.bindings(action);
+        bindings.iter().any(|&b| self.binding_state(b).2) && !self.action_pressed(action)
+    }
+
+    /// 0 to 1, the largest value of the bindings of `action`. Buttons are
+    /// 0 or 1, axes are analog.
+    pub fn action_value(&self, action: &str) -> f32 {
+        self.bindings(action).iter().map(|&b| self.binding_value(b)).fold(0.0, f32::max)
+    }
+
+    /// -1 to 1 from two opposing actions, e.g. "move_left" and
+    /// "move_right" for a strafe axis bound to keys and a stick.
+    pub fn action_axis(&self, negative: &str, positive: &str) -> f32 {
+        self.action_value(positive) - self.action_value(negative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_trigger_from_any_binding() {
+        let mut input = Input::new();
+        input.bind("jump", Binding::Key(KeyCode::Space));
+        input.bind("jump", Binding::Gamepad(GamepadButton::South));
+        input.bind("jump", Binding::Key(KeyCode::Space));
+        assert_eq!(input.bindings("jump").len(), 2);
+
+        input.keys.set(KeyCode::Space, true);
+        assert!(input.action_pressed("jump"));
+        assert!(input.action_just_pressed("jump"));
+        input.end_frame();
+
+        // A second binding pressed while the first is held isn't a new press.
+        input.set_gamepad_button(GamepadButton::South, true);
+        assert!(!input.action_just_pressed("jump"));
+        input.end_frame();
+
+        input.keys.set(KeyCode::Space, false);
+        assert!(!input.action_just_released("jump"));
+        input.set_gamepad_button(GamepadButton::South, false);
+        assert!(input.action_just_released("jump"));
+        assert!(!input.action_pressed("jump"));
+    }
+
+    #[test]
+    fn unbound_actions_are_idle() {
+        let mut input = Input::new();
+        input.bind("fire", Binding::Key(KeyCode::KeyF));
+        input.unbind("fire", Binding::Key(KeyCode::KeyF));
+        input.keys.set(KeyCode::KeyF, true);
+
+        assert!(input.bindings("fire").is_empty());
+        assert!(!input.action_pressed("fire"));
+        assert_eq!(input.action_value("unknown"), 0.0);
+    }
+
+    #[test]
+    fn axis_bindings_are_analog_and_press_past_half_way() {
+        let mut input = Input::new();
+        input.stick_response = AxisResponse { deadzone: 0.0, outer_deadzone: 1.0, exponent: 1.0 };
+        input.bind("move_right", Binding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Positive));
+        input.bind("move_left", Binding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Negative));
+        input.bind("move_left", Binding::Key(KeyCode::KeyA));
+
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, 0.25);
+        assert!((input.action_value("move_right") - 0.25).abs() < 1e-6);
+        assert!(!input.action_pressed("move_right"));
+        input.end_frame();
+
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, 0.75);
+        assert!(input.action_just_pressed("move_right"));
+        assert!((input.action_axis("move_left", "move_right") - 0.75).abs() < 1e-6);
+        input.end_frame();
+        assert!(!input.action_just_pressed("move_right"));
+
+        input.keys.set(KeyCode::KeyA, true);
+        assert!((input.action_axis("move_left", "move_right") + 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn deadzones_shape_the_stick() {
+        let mut input = Input::new();
+        input.stick_response = AxisResponse { deadzone: 0.2, outer_deadzone: 0.8, exponent: 1.0 };
+
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, 0.1);
+        assert_eq!(input.gamepad_axis(GamepadAxis::LeftStickX), 0.0);
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, -0.5);
+        assert!((input.gamepad_axis(GamepadAxis::LeftStickX) + 0.5).abs() < 1e-6);
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, 0.9);
+        assert_eq!(input.gamepad_axis(GamepadAxis::LeftStickX), 1.0);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod audio;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod display;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod input;
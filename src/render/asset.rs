@@ -0,0 +1,340 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, mpsc};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::thread::JoinHandle;
+
+use ash::vk;
+
+use crate::render::hal::{BufferCopy, BufferCreateInfo, BufferTextureCopy, ColorEncoding, Error, MemoryLocation, Result, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::util::log_target;
+
+/// Decoded image ready to be uploaded, `data` is tightly packed. Decoders
+/// report the `UNORM` format of 8 bit images, `load_texture` picks the
+/// encoding.
+pub struct ImageData {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub data: Vec<u8>,
+}
+
+pub type ImageDecoder = fn(&[u8]) -> Result<ImageData>;
+pub type BufferDecoder = fn(&[u8]) -> Result<Vec<u8>>;
+
+enum LoadState<T> {
+    Loading,
+    Ready(Arc<T>),
+    Failed(Error),
+}
+
+struct HandleInner<T> {
+    state: Mutex<(LoadState<T>, Option<Waker>)>,
+}
+
+impl<T> HandleInner<T> {
+    fn resolve(&self, state: LoadState<T>) {
+        let mut guard = self.state.lock().unwrap();
+        guard.0 = state;
+        if let Some(waker) = guard.1.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Shared reference to an asset that is loaded in the background. Can be
+/// polled every frame with `get` or awaited as a future.
+pub struct Handle<T> {
+    inner: Arc<HandleInner<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Handle<T> {
+    fn new() -> Self {
+        Self { inner: Arc::new(HandleInner { state: Mutex::new((LoadState::Loading, None)) }) }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        matches!(self.inner.state.lock().unwrap().0, LoadState::Loading)
+    }
+
+    /// The asset, once it's uploaded and safe to use on the GPU.
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &self.inner.state.lock().unwrap().0 {
+            LoadState::Ready(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<Error> {
+        match &self.inner.state.lock().unwrap().0 {
+            LoadState::Failed(err) => Some(err.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<T> Future for Handle<T> {
+    type Output = Result<Arc<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut guard = self.inner.state.lock().unwrap();
+        match &guard.0 {
+            LoadState::Ready(asset) => Poll::Ready(Ok(asset.clone())),
+            LoadState::Failed(err) => Poll::Ready(Err(err.clone())),
+            LoadState::Loading => {
+                guard.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+enum Decoded {
+    Texture(ImageData, vk::ImageUsageFlags, ColorEncoding, Arc<HandleInner<Texture>>),
+    Buffer(Vec<u8>, vk::BufferUsageFlags, Arc<HandleInner<Buffer>>),
+}
+
+enum Upload {
+    Texture(Arc<Texture>, Arc<HandleInner<Texture>>),
+    Buffer(Arc<Buffer>, Arc<HandleInner<Buffer>>),
+}
+
+impl Upload {
+    fn complete(self) {
+        match self {
+            Upload::Texture(texture, handle) => handle.resolve(LoadState::Ready(texture)),
+            Upload::Buffer(buffer, handle) => handle.resolve(LoadState::Ready(buffer)),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct AssetServerCreateInfo {
+    pub worker_threads: usize,
+    /// Files are resolved relative to this directory.
+    pub root: PathBuf,
+}
+
+/// Loads and decodes assets on a pool of worker threads. Decoded data is
+/// handed back to the render thread through a channel and uploaded by
+/// `process_uploads`, which records the copies into the frame's command
+/// list. Handles become ready once the frame that uploaded them finished.
+///
+/// Copies run on the graphics queue with the rest of the frame, the
+/// renderer has no dedicated transfer queue to overlap them with.
+pub struct AssetServer {
+    root: PathBuf,
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    decoded_sender: mpsc::Sender<Decoded>,
+    decoded: mpsc::Receiver<Decoded>,
+    in_flight: [Vec<(Buffer, Upload)>; FRAME_OVERLAP],
+    renderer: Arc<Renderer>,
+}
+
+impl AssetServer {
+    pub fn new(renderer: Arc<Renderer>, create_info: AssetServerCreateInfo) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..create_info.worker_threads.max(1)).map(|idx| {
+            let job_receiver = job_receiver.clone();
+            thread::Builder::new()
+                .name(format!("patoka-asset-{idx}"))
+                .spawn(move || loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .unwrap()
+        }).collect();
+
+        let (decoded_sender, decoded) = mpsc::channel();
+
+        Self {
+            root: create_info.root,
+            jobs: Some(jobs),
+            workers,
+            decoded_sender,
+            decoded,
+            in_flight: Default::default(),
+            renderer,
+        }
+    }
+
+    fn spawn(&self, job: Job) {
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+
+    fn read(path: &Path) -> Result<Vec<u8>> {
+        let bytes = std::fs::read(path).map_err(|err| Error::Asset(format!("Failed to read {}: {err}", path.display())))?;
+        if bytes.is_empty() {
+            return Err(Error::Asset(format!("{} is empty", path.display())));
+        }
+        Ok(bytes)
+    }
+
+    /// Zero sized resources can't be created, reject them before they
+    /// reach `process_uploads`.
+    fn check_not_empty(path: &Path, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Err(Error::Asset(format!("Decoding {} produced no data", path.display())));
+        }
+        Ok(())
+    }
+
+    /// Loads an image into a texture. Color maps such as albedo or emissive
+    /// are `ColorEncoding::Srgb` and get an `*_SRGB` format that decodes
+    /// when sampled, normal, roughness and other data maps are `Linear`.
+    pub fn load_texture(&self, path: impl AsRef<Path>, decoder: ImageDecoder, usage: vk::ImageUsageFlags, encoding: ColorEncoding) -> Handle<Texture> {
+        let handle = Handle::new();
+        let inner = handle.inner.clone();
+        let path = self.root.join(path);
+        let sender = self.decoded_sender.clone();
+
+        self.spawn(Box::new(move || {
+            crate::trace_span!("AssetServer::load_texture", path = %path.display());
+            let image = Self::read(&path)
+                .and_then(|bytes| decoder(&bytes))
+                .and_then(|image| {
+                    let vk::Extent3D { width, height, depth } = image.extent;
+                    if width == 0 || height == 0 || depth == 0 {
+                        return Err(Error::Asset(format!("Decoding {} produced an empty image", path.display())));
+                    }
+                    Self::check_not_empty(&path, &image.data).map(|_| image)
+                });
+            match image {
+                Ok(image) => {
+                    let _ = sender.send(Decoded::Texture(image, usage, encoding, inner));
+                }
+                Err(err) => {
+                    log::warn!(target: log_target::ASSET, "Failed to load {}: {err}", path.display());
+                    inner.resolve(LoadState::Failed(err));
+                }
+            }
+        }));
+
+        handle
+    }
+
+    /// Loads raw GPU data such as vertex or index streams into a device local buffer.
+    pub fn load_buffer(&self, path: impl AsRef<Path>, decoder: BufferDecoder, usage: vk::BufferUsageFlags) -> Handle<Buffer> {
+        let handle = Handle::new();
+        let inner = handle.inner.clone();
+        let path = self.root.join(path);
+        let sender = self.decoded_sender.clone();
+
+        self.spawn(Box::new(move || {
+            crate::trace_span!("AssetServer::load_buffer", path = %path.display());
+            let data = Self::read(&path)
+                .and_then(|bytes| decoder(&bytes))
+                .and_then(|data| Self::check_not_empty(&path, &data).map(|_| data));
+            match data {
+                Ok(data) => {
+                    let _ = sender.send(Decoded::Buffer(data, usage, inner));
+                }
+                Err(err) => {
+                    log::warn!(target: log_target::ASSET, "Failed to load {}: {err}", path.display());
+                    inner.resolve(LoadState::Failed(err));
+                }
+            }
+        }));
+
+        handle
+    }
+
+    fn staging_buffer(&self, data: &[u8]) -> Buffer {
+        let create_info = BufferCreateInfo {
+            size: data.len() as u64,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            location: MemoryLocation::CpuToGpu,
+        };
+        let mut staging = Buffer::new(self.renderer.clone(), create_info);
+        staging.write(0, data);
+        staging
+    }
+
+    /// Resolves handles uploaded the last time this frame slot was recorded
+    /// and records copies for everything decoded since. Must be called after
+    /// the frame fence has been waited on. Uploaded textures are left in
+    /// `GENERAL` layout.
+    pub fn process_uploads(&mut self, command_list: &mut CommandList) {
+        crate::trace_span!("AssetServer::process_uploads");
+        let frame = self.renderer.current_frame();
+
+        for (_, upload) in self.in_flight[frame].drain(..) {
+            upload.complete();
+        }
+
+        while let Ok(decoded) = self.decoded.try_recv() {
+            match decoded {
+                Decoded::Texture(image, usage, encoding, handle) => {
+                    let staging = self.staging_buffer(&image.data);
+                    let texture = {
+                        let create_info = TextureCreateInfo {
+                            format: encoding.apply(image.format),
+                            extent: image.extent,
+                            usage: usage | vk::ImageUsageFlags::TRANSFER_DST,
+                            aspect: vk::ImageAspectFlags::COLOR,
+                            array_layers: 1,
+                            mip_levels: 1,
+                            kind: TextureKind::D2,
+                        };
+                        Texture::new(self.renderer.clone(), create_info)
+                    };
+
+                    command_list.transition_texture_layout(&texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+                    command_list.copy_buffer_to_texture(&staging, &texture, &[BufferTextureCopy::whole(&texture)]);
+
+                    self.in_flight[frame].push((staging, Upload::Texture(Arc::new(texture), handle)));
+                }
+                Decoded::Buffer(data, usage, handle) => {
+                    let staging = self.staging_buffer(&data);
+                    let buffer = {
+                        let create_info = BufferCreateInfo {
+                            size: data.len() as u64,
+                            usage: usage | vk::BufferUsageFlags::TRANSFER_DST,
+                            location: MemoryLocation::GpuOnly,
+                        };
+                        Buffer::new(self.renderer.clone(), create_info)
+                    };
+
+                    command_list.copy_buffer(&staging, &buffer, &[BufferCopy {
+                        src_offset: 0,
+                        dst_offset: 0,
+                        size: data.len() as u64,
+                    }]);
+
+                    self.in_flight[frame].push((staging, Upload::Buffer(Arc::new(buffer), handle)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for AssetServer {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        // Staging buffers and resources in flight may still be used by
+        // frames that haven't completed.
+        let _ = self.renderer.wait_idle();
+    }
+}
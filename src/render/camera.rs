@@ -0,0 +1,156 @@
+use ash::vk;
+
+use crate::render::math::{Mat4, Quat, QUAT_IDENTITY, Vec3};
+
+/// Number of distinct jitter positions before the sequence repeats.
+const JITTER_PHASES: u64 = 8;
+
+/// Element `index` of the Halton low discrepancy sequence in `base`, in
+/// [0, 1).
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while index > 0 {
+        f /= base as f32;
+        result += f * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Offsets `projection` by `offset` in NDC, for any projection.
+pub fn jitter_projection(projection: Mat4, offset: [f32; 2]) -> Mat4 {
+    let mut m = projection;
+    for column in &mut m.0 {
+        column[0] += offset[0] * column[3];
+        column[1] += offset[1] * column[3];
+    }
+    m
+}
+
+/// Physical lens of a camera, for depth of field. Lengths on the lens and
+/// sensor are in millimeters, the focus distance in world units, taken as
+/// meters.
+#[derive(Clone, Copy, Debug)]
+pub struct Lens {
+    pub focal_length: f32,
+    /// Focal length over aperture diameter.
+    pub f_stop: f32,
+    pub focus_distance: f32,
+    /// Height of the sensor, 24 for full frame.
+    pub sensor_height: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            f_stop: 2.8,
+            focus_distance: 10.0,
+            sensor_height: 24.0,
+        }
+    }
+}
+
+impl Lens {
+    /// Vertical field of view this lens gives on its sensor.
+    pub fn fov_y(&self) -> f32 {
+        2.0 * (self.sensor_height / (2.0 * self.focal_length)).atan()
+    }
+
+    /// Radius in pixels of the circle of confusion of points at infinity,
+    /// on an image `image_height` pixels high. From the thin lens equation,
+    /// a point at distance `d` has a radius of `coc_scale * (1 - focus / d)`,
+    /// negative in front of the focus distance.
+    pub fn coc_scale(&self, image_height: u32) -> f32 {
+        let focus = (self.focus_distance * 1000.0).max(self.focal_length + 1.0);
+        let aperture = self.focal_length / self.f_stop.max(0.1);
+        let diameter = aperture * self.focal_length / (focus - self.focal_length);
+        0.5 * diameter / self.sensor_height * image_height as f32
+    }
+
+    /// Signed circle of confusion radius in pixels of a point `distance`
+    /// world units away.
+    pub fn circle_of_confusion(&self, distance: f32, image_height: u32) -> f32 {
+        self.coc_scale(image_height) * (1.0 - self.focus_distance / distance.max(f32::EPSILON))
+    }
+}
+
+/// Perspective camera with the engine's conventions: infinite reverse-Z
+/// projection, looking down -Z of its rotation.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub fov_y: f32,
+    pub aspect: f32,
+    pub near: f32,
+    /// Subpixel offset in NDC applied by `jittered_projection`, for
+    /// temporal anti-aliasing and upscaling.
+    pub jitter: [f32; 2],
+    /// Only used for depth of field, `set_focal_length` also updates
+    /// `fov_y`.
+    pub lens: Lens,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: QUAT_IDENTITY,
+            fov_y: std::f32::consts::FRAC_PI_3,
+            aspect: 16.0 / 9.0,
+            near: 0.1,
+            jitter: [0.0; 2],
+            lens: Lens::default(),
+        }
+    }
+}
+
+impl Camera {
+    pub fn view(&self) -> Mat4 {
+        Mat4::from_trs(self.position, self.rotation, [1.0; 3])
+            .inverse()
+            .unwrap_or(Mat4::IDENTITY)
+    }
+
+    pub fn projection(&self) -> Mat4 {
+        Mat4::perspective(self.fov_y, self.aspect, self.near)
+    }
+
+    /// Projection to render with, offset by `jitter`.
+    pub fn jittered_projection(&self) -> Mat4 {
+        jitter_projection(self.projection(), self.jitter)
+    }
+
+    /// Unjittered, for culling and reprojection.
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection() * self.view()
+    }
+
+    pub fn jittered_view_projection(&self) -> Mat4 {
+        self.jittered_projection() * self.view()
+    }
+
+    /// Zooms to `focal_length` millimeters, keeping the sensor.
+    pub fn set_focal_length(&mut self, focal_length: f32) {
+        self.lens.focal_length = focal_length;
+        self.fov_y = self.lens.fov_y();
+    }
+
+    /// Sets `jitter` to the Halton (2, 3) position of `frame_index`, within
+    /// a pixel of a target of size `extent`.
+    pub fn set_jitter(&mut self, frame_index: u64, extent: vk::Extent2D) {
+        self.set_jitter_phases(frame_index, extent, JITTER_PHASES);
+    }
+
+    /// Like `set_jitter` with a sequence of `phases` positions, upscalers
+    /// need more of them the larger the upscaling ratio, see
+    /// `Upscaler::jitter_phases`.
+    pub fn set_jitter_phases(&mut self, frame_index: u64, extent: vk::Extent2D, phases: u64) {
+        let index = (frame_index % phases.max(1)) as u32 + 1;
+        let x = halton(index, 2) - 0.5;
+        let y = halton(index, 3) - 0.5;
+        self.jitter = [2.0 * x / extent.width as f32, 2.0 * y / extent.height as f32];
+    }
+}
@@ -0,0 +1,198 @@
+use crate::render::math::{add, length, scale, sub, Mat4, Vec3, Vec4};
+
+/// Axis aligned bounding box. The default box is empty, with `min` above
+/// `max`, and grows to include points.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self { min: [f32::MAX; 3], max: [f32::MIN; 3] }
+    }
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut aabb = Self::default();
+        for point in points {
+            aabb.include(point);
+        }
+        aabb
+    }
+
+    pub fn include(&mut self, point: Vec3) {
+        self.min = [0, 1, 2].map(|i| self.min[i].min(point[i]));
+        self.max = [0, 1, 2].map(|i| self.max[i].max(point[i]));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
+    pub fn center(&self) -> Vec3 {
+        scale(add(self.min, self.max), 0.5)
+    }
+
+    /// Half the size along every axis.
+    pub fn extents(&self) -> Vec3 {
+        scale(sub(self.max, self.min), 0.5)
+    }
+
+    /// Box enclosing this box transformed by an affine `transform`.
+    pub fn transform(&self, transform: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = transform.transform_point(self.center());
+        let extents = self.extents();
+        let mut half = [0.0; 3];
+        for (i, half) in half.iter_mut().enumerate() {
+            *half = (0..3).map(|j| transform.0[j][i].abs() * extents[j]).sum();
+        }
+        Self { min: sub(center, half), max: add(center, half) }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere { center: self.center(), radius: length(self.extents()) }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around `center` reaching the furthest of `points`, tighter
+    /// than `Aabb::bounding_sphere` when `center` is the center of the
+    /// points' box.
+    pub fn from_points(center: Vec3, points: impl IntoIterator<Item = Vec3>) -> Self {
+        let radius = points.into_iter().map(|point| length(sub(point, center))).fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// Sphere enclosing this sphere transformed by an affine `transform`,
+    /// scaled by its largest axis scale.
+    pub fn transform(&self, transform: &Mat4) -> Self {
+        let scale = (0..3).map(|j| length([transform.0[j][0], transform.0[j][1], transform.0[j][2]])).fold(0.0, f32::max);
+        Self { center: transform.transform_point(self.center), radius: self.radius * scale }
+    }
+}
+
+/// Clip planes of a view projection, pointing inwards. Planes of infinite
+/// projections that don't bound anything are left out.
+#[derive(Clone, Debug)]
+pub struct Frustum {
+    planes: Vec<Vec4>,
+}
+
+impl Frustum {
+    /// Planes in world space for a `view_projection` with Vulkan's 0..1
+    /// clip depth, extracted from its rows.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        let combine = |a: Vec4, b: Vec4, s: f32| [a[0] + s * b[0], a[1] + s * b[1], a[2] + s * b[2], a[3] + s * b[3]];
+
+        let planes = [combine(w, x, 1.0), combine(w, x, -1.0), combine(w, y, 1.0), combine(w, y, -1.0), z, combine(w, z, -1.0)]
+            .into_iter()
+            .filter_map(|plane| {
+                let normal_length = length([plane[0], plane[1], plane[2]]);
+                (normal_length > f32::EPSILON).then(|| plane.map(|v| v / normal_length))
+            })
+            .collect();
+        Self { planes }
+    }
+
+    fn distance(plane: &Vec4, point: Vec3) -> f32 {
+        plane[0] * point[0] + plane[1] * point[1] + plane[2] * point[2] + plane[3]
+    }
+
+    /// Conservative, boxes crossing the corners outside the frustum pass.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal.
+            let corner = [0, 1, 2].map(|i| if plane[i] >= 0.0 { aabb.max[i] } else { aabb.min[i] });
+            Self::distance(plane, corner) >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+}
+
+/// Draws tested and rejected by the last cull.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CullStats {
+    pub submitted: usize,
+    pub culled: usize,
+}
+
+impl CullStats {
+    pub fn visible(&self) -> usize {
+        self.submitted - self.culled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_frustum() -> Frustum {
+        let view = Mat4::look_at([0.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]);
+        let projection = Mat4::perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1);
+        Frustum::from_view_projection(projection * view)
+    }
+
+    #[test]
+    fn infinite_projection_has_five_planes() {
+        assert_eq!(camera_frustum().planes.len(), 5);
+    }
+
+    #[test]
+    fn spheres_are_culled_outside_the_planes() {
+        let frustum = camera_frustum();
+        let sphere = |center, radius| BoundingSphere { center, radius };
+
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -1000.0], 1.0)));
+        // Behind the camera and beside the 90 degree field of view.
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([20.0, 0.0, -10.0], 1.0)));
+        // Straddling the left plane.
+        assert!(frustum.intersects_sphere(&sphere([-10.5, 0.0, -10.0], 1.0)));
+    }
+
+    #[test]
+    fn boxes_are_culled_outside_the_planes() {
+        let frustum = camera_frustum();
+        let aabb = |center: Vec3| Aabb { min: sub(center, [1.0; 3]), max: add(center, [1.0; 3]) };
+
+        assert!(frustum.intersects_aabb(&aabb([0.0, 0.0, -10.0])));
+        assert!(!frustum.intersects_aabb(&aabb([0.0, 0.0, 10.0])));
+        assert!(!frustum.intersects_aabb(&aabb([0.0, 20.0, -10.0])));
+        assert!(frustum.intersects_aabb(&aabb([0.0, 10.5, -10.0])));
+    }
+
+    #[test]
+    fn transformed_aabb_encloses_rotated_corners() {
+        let aabb = Aabb { min: [-1.0, -2.0, -3.0], max: [1.0, 2.0, 3.0] };
+        let half_angle = std::f32::consts::FRAC_PI_4;
+        let rotation = Mat4::from_rotation([0.0, half_angle.sin(), 0.0, half_angle.cos()]);
+        let transform = Mat4::from_translation([5.0, 0.0, 0.0]) * rotation;
+
+        // A quarter turn around Y swaps the X and Z extents.
+        let transformed = aabb.transform(&transform);
+        for (actual, expected) in transformed.min.iter().zip([2.0, -2.0, -1.0]) {
+            assert!((actual - expected).abs() < 1e-5, "{:?}", transformed);
+        }
+        for (actual, expected) in transformed.max.iter().zip([8.0, 2.0, 1.0]) {
+            assert!((actual - expected).abs() < 1e-5, "{:?}", transformed);
+        }
+        assert!(Aabb::default().transform(&transform).is_empty());
+    }
+}
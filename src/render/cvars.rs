@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::render::hal::vulkan::renderer::Renderer;
+
+/// Value of a console variable.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CVarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+impl CVarValue {
+    /// Parses `text` as a value of the same kind, e.g. for the console.
+    pub fn parse_like(&self, text: &str) -> Option<CVarValue> {
+        let text = text.trim();
+        Some(match self {
+            CVarValue::Bool(_) => CVarValue::Bool(match text {
+                "1" | "true" | "on" => true,
+                "0" | "false" | "off" => false,
+                _ => return None,
+            }),
+            CVarValue::Int(_) => CVarValue::Int(text.parse().ok()?),
+            CVarValue::Float(_) => CVarValue::Float(text.parse().ok()?),
+            CVarValue::String(_) => CVarValue::String(text.to_string()),
+        })
+    }
+
+    fn same_kind(&self, other: &CVarValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn clamp(self, range: &Option<(CVarValue, CVarValue)>) -> CVarValue {
+        match (self, range) {
+            (CVarValue::Int(v), Some((CVarValue::Int(min), CVarValue::Int(max)))) => CVarValue::Int(v.clamp(*min, *max)),
+            (CVarValue::Float(v), Some((CVarValue::Float(min), CVarValue::Float(max)))) => CVarValue::Float(v.clamp(*min, *max)),
+            (value, _) => value,
+        }
+    }
+}
+
+impl Display for CVarValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CVarValue::Bool(v) => write!(f, "{v}"),
+            CVarValue::Int(v) => write!(f, "{v}"),
+            CVarValue::Float(v) => write!(f, "{v}"),
+            CVarValue::String(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// Rust types stored in a `CVarValue`.
+pub trait CVarType: Sized {
+    fn to_value(self) -> CVarValue;
+    fn from_value(value: &CVarValue) -> Option<Self>;
+
+    /// Range of unranged variables, so `set_str` can't store values the
+    /// type can't hold.
+    fn bounds() -> Option<(CVarValue, CVarValue)> {
+        None
+    }
+}
+
+impl CVarType for bool {
+    fn to_value(self) -> CVarValue {
+        CVarValue::Bool(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        match value {
+            CVarValue::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl CVarType for i64 {
+    fn to_value(self) -> CVarValue {
+        CVarValue::Int(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        match value {
+            CVarValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl CVarType for i32 {
+    fn to_value(self) -> CVarValue {
+        CVarValue::Int(self as i64)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        i64::from_value(value).and_then(|v| v.try_into().ok())
+    }
+
+    fn bounds() -> Option<(CVarValue, CVarValue)> {
+        Some((i32::MIN.to_value(), i32::MAX.to_value()))
+    }
+}
+
+impl CVarType for u32 {
+    fn to_value(self) -> CVarValue {
+        CVarValue::Int(self as i64)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        i64::from_value(value).and_then(|v| v.try_into().ok())
+    }
+
+    fn bounds() -> Option<(CVarValue, CVarValue)> {
+        Some((u32::MIN.to_value(), u32::MAX.to_value()))
+    }
+}
+
+impl CVarType for f32 {
+    fn to_value(self) -> CVarValue {
+        CVarValue::Float(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        match value {
+            CVarValue::Float(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl CVarType for String {
+    fn to_value(self) -> CVarValue {
+        CVarValue::String(self)
+    }
+
+    fn from_value(value: &CVarValue) -> Option<Self> {
+        match value {
+            CVarValue::String(v) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CVarError {
+    Unknown(String),
+    /// The text doesn't parse as the variable's type.
+    InvalidValue { name: String, value: String },
+}
+
+impl Display for CVarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CVarError::Unknown(name) => {
+                write!(f, "Unknown cvar {name}")
+            }
+            CVarError::InvalidValue { name, value } => {
+                write!(f, "Invalid value {value} for {name}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CVarError {}
+
+/// Typed handle of a registered variable.
+pub struct CVar<T> {
+    index: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for CVar<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CVar<T> {}
+
+type ChangeCallback = Box<dyn FnMut(&CVarValue)>;
+
+pub struct CVarEntry {
+    name: String,
+    description: String,
+    value: CVarValue,
+    default: CVarValue,
+    range: Option<(CVarValue, CVarValue)>,
+    callbacks: Vec<ChangeCallback>,
+}
+
+impl CVarEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn value(&self) -> &CVarValue {
+        &self.value
+    }
+
+    pub fn default_value(&self) -> &CVarValue {
+        &self.default
+    }
+
+    /// Inclusive bounds of numeric variables, values are clamped to them.
+    pub fn range(&self) -> Option<(&CVarValue, &CVarValue)> {
+        self.range.as_ref().map(|(min, max)| (min, max))
+    }
+}
+
+/// Console variables: named, typed parameters tweakable at runtime from the
+/// console or `cvar_panel` instead of recompiling. Names are dotted paths
+/// such as `r.ssao.radius`, the first segment groups them.
+///
+/// ```ignore
+/// let mut cvars = CVars::new();
+/// register_renderer_cvars(&mut cvars, &renderer);
+/// ssao.register_cvars(&mut cvars, "r.ssao");
+/// loop {
+///     ssao.apply_cvars(&cvars, "r.ssao");
+///     ssao.record(..);
+/// }
+/// ```
+#[derive(Default)]
+pub struct CVars {
+    entries: Vec<CVarEntry>,
+    indices: HashMap<String, usize>,
+}
+
+impl CVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a variable, or returns the existing one of the same name, which
+    /// keeps its value. Panics when that one has a different type.
+    pub fn register<T: CVarType>(&mut self, name: &str, default: T, description: &str) -> CVar<T> {
+        self.insert(name, default.to_value(), T::bounds(), description)
+    }
+
+    /// `register` for numbers limited to `range`.
+    pub fn register_ranged<T: CVarType>(&mut self, name: &str, default: T, range: RangeInclusive<T>, description: &str) -> CVar<T> {
+        let (min, max) = range.into_inner();
+        self.insert(name, default.to_value(), Some((min.to_value(), max.to_value())), description)
+    }
+
+    fn insert<T>(&mut self, name: &str, default: CVarValue, range: Option<(CVarValue, CVarValue)>, description: &str) -> CVar<T> {
+        if let Some(&index) = self.indices.get(name) {
+            assert!(self.entries[index].value.same_kind(&default), "CVar {name} is already registered with another type");
+            return CVar { index, _marker: PhantomData };
+        }
+
+        let value = default.clone().clamp(&range);
+        let index = self.entries.len();
+        self.entries.push(CVarEntry {
+            name: name.to_string(),
+            description: description.to_string(),
+            value,
+            default,
+            range,
+            callbacks: Vec::new(),
+        });
+        self.indices.insert(name.to_string(), index);
+        CVar { index, _marker: PhantomData }
+    }
+
+    /// Handle of a registered variable, `None` when it's missing or of
+    /// another type.
+    pub fn find<T: CVarType>(&self, name: &str) -> Option<CVar<T>> {
+        let &index = self.indices.get(name)?;
+        T::from_value(&self.entries[index].value)?;
+        Some(CVar { index, _marker: PhantomData })
+    }
+
+    pub fn get<T: CVarType>(&self, cvar: CVar<T>) -> T {
+        T::from_value(&self.entries[cvar.index].value).unwrap()
+    }
+
+    /// Clamps `value` to the range and calls the change callbacks when the
+    /// value changed.
+    pub fn set<T: CVarType>(&mut self, cvar: CVar<T>, value: T) {
+        self.set_at(cvar.index, value.to_value());
+    }
+
+    /// Overwrites `target` with the variable's value, if registered with
+    /// that type. Meant for copying into pass fields once per frame.
+    pub fn read<T: CVarType>(&self, name: &str, target: &mut T) {
+        if let Some(cvar) = self.find(name) {
+            *target = self.get(cvar);
+        }
+    }
+
+    /// Sets a variable from text, as typed into the console.
+    pub fn set_str(&mut self, name: &str, text: &str) -> Result<(), CVarError> {
+        let &index = self.indices.get(name).ok_or_else(|| CVarError::Unknown(name.to_string()))?;
+        let value = self.entries[index].value.parse_like(text).ok_or_else(|| CVarError::InvalidValue {
+            name: name.to_string(),
+            value: text.to_string(),
+        })?;
+        self.set_at(index, value);
+        Ok(())
+    }
+
+    pub fn reset(&mut self, name: &str) -> Result<(), CVarError> {
+        let &index = self.indices.get(name).ok_or_else(|| CVarError::Unknown(name.to_string()))?;
+        self.set_at(index, self.entries[index].default.clone());
+        Ok(())
+    }
+
+    fn set_at(&mut self, index: usize, value: CVarValue) {
+        let entry = &mut self.entries[index];
+        assert!(entry.value.same_kind(&value), "CVar {} set with another type", entry.name);
+        let value = value.clamp(&entry.range);
+        if entry.value == value {
+            return;
+        }
+        entry.value = value;
+        for callback in &mut entry.callbacks {
+            callback(&entry.value);
+        }
+    }
+
+    /// Calls `callback` with the new value on every change, e.g. to
+    /// recreate resources a parameter sizes.
+    pub fn on_change<T: CVarType + 'static>(&mut self, cvar: CVar<T>, mut callback: impl FnMut(T) + 'static) {
+        self.entries[cvar.index].callbacks.push(Box::new(move |value| {
+            if let Some(value) = T::from_value(value) {
+                callback(value);
+            }
+        }));
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&CVarEntry> {
+        self.indices.get(name).map(|&index| &self.entries[index])
+    }
+
+    /// All variables in registration order.
+    pub fn entries(&self) -> impl Iterator<Item = &CVarEntry> {
+        self.entries.iter()
+    }
+}
+
+/// Parameters a pass exposes as cvars under a prefix of the caller's
+/// choice, so one pass type can be instanced with separate settings.
+pub trait CVarParams {
+    /// Registers the parameters with the pass's current values as defaults.
+    fn register_cvars(&self, cvars: &mut CVars, prefix: &str);
+
+    /// Copies the current values into the pass, before recording.
+    fn apply_cvars(&mut self, cvars: &CVars, prefix: &str);
+}
+
+/// Registers `r.render_scale`, which forwards changes to
+/// `Renderer::set_render_scale`.
+pub fn register_renderer_cvars(cvars: &mut CVars, renderer: &Arc<Renderer>) {
+    let render_scale = cvars.register_ranged("r.render_scale", renderer.render_scale(), 0.25..=2.0, "Internal resolution relative to the swapchain");
+    let renderer = renderer.clone();
+    cvars.on_change(render_scale, move |scale| renderer.set_render_scale(scale));
+}
+
+/// Auto-generated egui widgets for every variable, grouped by the first
+/// segment of their names: sliders for ranged numbers, drag values for the
+/// others, checkboxes and text fields. Hovering shows the description,
+/// right clicking resets to the default.
+#[cfg(feature = "egui")]
+pub fn cvar_panel(ui: &mut egui::Ui, cvars: &mut CVars) {
+    let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+    for (index, entry) in cvars.entries.iter().enumerate() {
+        let group = entry.name.split('.').next().unwrap_or_default();
+        match groups.iter_mut().find(|(name, _)| *name == group) {
+            Some((_, indices)) => indices.push(index),
+            None => groups.push((group, vec![index])),
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (group, indices) in groups {
+        egui::CollapsingHeader::new(group).default_open(true).show(ui, |ui| {
+            for index in indices {
+                let entry = &cvars.entries[index];
+                let mut value = entry.value.clone();
+                let response = match (&mut value, &entry.range) {
+                    (CVarValue::Bool(v), _) => ui.checkbox(v, &entry.name),
+                    (CVarValue::Int(v), Some((CVarValue::Int(min), CVarValue::Int(max)))) => ui.add(egui::Slider::new(v, *min..=*max).text(&entry.name)),
+                    (CVarValue::Float(v), Some((CVarValue::Float(min), CVarValue::Float(max)))) => ui.add(egui::Slider::new(v, *min..=*max).text(&entry.name)),
+                    (CVarValue::Int(v), _) => ui.horizontal(|ui| ui.add(egui::DragValue::new(v)) | ui.label(&entry.name)).inner,
+                    (CVarValue::Float(v), _) => ui.horizontal(|ui| ui.add(egui::DragValue::new(v).speed(0.01)) | ui.label(&entry.name)).inner,
+                    (CVarValue::String(v), _) => ui.horizontal(|ui| ui.text_edit_singleline(v) | ui.label(&entry.name)).inner,
+                };
+                let response = response.on_hover_text(&entry.description);
+                if response.secondary_clicked() {
+                    changes.push((index, entry.default.clone()));
+                } else if response.changed() {
+                    changes.push((index, value));
+                }
+            }
+        });
+    }
+
+    for (index, value) in changes {
+        cvars.set_at(index, value);
+    }
+}
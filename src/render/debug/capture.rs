@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+#[cfg(feature = "ffmpeg")]
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::{BufferCreateInfo, Error, MemoryLocation, Result};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+
+/// A presented frame read back to host memory, tightly packed rows.
+pub struct CapturedFrame<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// The swapchain format.
+    pub format: vk::Format,
+    /// `Renderer` frame index the image was presented at.
+    pub frame_index: u64,
+    pub data: &'a [u8],
+}
+
+/// Destination of captured frames, an encoder or an image sequence writer.
+pub trait FrameSink {
+    fn write_frame(&mut self, frame: &CapturedFrame) -> Result<()>;
+
+    /// Called by `FrameCapture::stop`, flushes and closes the output.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Checked after every frame, the recording stops once the sink has
+    /// all the frames it wants.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+pub struct FrameCaptureCreateInfo {
+    /// Captures every n-th frame, 1 records every frame.
+    pub frame_interval: u32,
+}
+
+/// Records presented frames for demos and bug reports. The swapchain image
+/// is copied to host memory after `copy_to_framebuffer` and handed to a
+/// `FrameSink` `FRAME_OVERLAP` frames later, without stalling the GPU.
+/// Frames are encoded on the CPU, hardware video encode queues aren't used.
+pub struct FrameCapture {
+    frame_interval: u32,
+    readback: [Buffer; FRAME_OVERLAP],
+    /// Frame index of the copy recorded into each readback buffer.
+    pending: [Option<u64>; FRAME_OVERLAP],
+    data: Vec<u8>,
+    sink: Option<Box<dyn FrameSink>>,
+    renderer: Arc<Renderer>,
+}
+
+/// Bytes per texel of the swapchain formats `select_surface_format` picks.
+fn texel_size(format: vk::Format) -> u64 {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    }
+}
+
+impl FrameCapture {
+    pub fn new(renderer: Arc<Renderer>, create_info: FrameCaptureCreateInfo) -> Self {
+        let extent = renderer.swapchain_extent();
+        let size = extent.width as u64 * extent.height as u64 * texel_size(renderer.swapchain_format().format);
+        let readback = std::array::from_fn(|_| {
+            let create_info = BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuToCpu,
+            };
+            let buffer = Buffer::new(renderer.clone(), create_info);
+            buffer.set_name("frame capture readback");
+            buffer
+        });
+
+        Self {
+            frame_interval: create_info.frame_interval.max(1),
+            readback,
+            pending: [None; FRAME_OVERLAP],
+            data: vec![0; size as usize],
+            sink: None,
+            renderer,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Starts sending frames to `sink`, finishing the previous recording.
+    pub fn start(&mut self, sink: Box<dyn FrameSink>) -> Result<()> {
+        if !self.renderer.framebuffer_capture_supported() {
+            return Err(Error::Backend("Swapchain images can't be read back".to_string()));
+        }
+        self.stop()?;
+        self.sink = Some(sink);
+        Ok(())
+    }
+
+    /// Saves the next presented frame to `path`, see `ScreenshotSink`.
+    pub fn screenshot(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        self.start(Box::new(ScreenshotSink::new(path)))
+    }
+
+    /// Finishes the recording. Frames still in flight are dropped.
+    pub fn stop(&mut self) -> Result<()> {
+        self.pending = [None; FRAME_OVERLAP];
+        match self.sink.take() {
+            Some(mut sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Hands the frame copied the last time this frame slot was used to the
+    /// sink and records the copy of the current one. Must be called after
+    /// the frame fence has been waited on and after `copy_to_framebuffer`.
+    pub fn record(&mut self, command_list: &CommandList) -> Result<()> {
+        let Some(sink) = &mut self.sink else {
+            return Ok(());
+        };
+
+        let frame = self.renderer.current_frame();
+        if let Some(frame_index) = self.pending[frame].take() {
+            self.readback[frame].read(0, &mut self.data);
+            let extent = self.renderer.swapchain_extent();
+            sink.write_frame(&CapturedFrame {
+                width: extent.width,
+                height: extent.height,
+                format: self.renderer.swapchain_format().format,
+                frame_index,
+                data: &self.data,
+            })?;
+            if sink.is_finished() {
+                return self.stop();
+            }
+        }
+
+        let frame_index = self.renderer.frame_index();
+        if frame_index.is_multiple_of(self.frame_interval as u64) {
+            command_list.copy_framebuffer_to_buffer(&self.readback[frame]);
+            self.pending[frame] = Some(frame_index);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Writes the first frame it gets to a binary PPM image and finishes.
+/// Alpha is dropped and colors are stored as presented, e.g. PQ encoded
+/// on HDR swapchains.
+pub struct ScreenshotSink {
+    path: PathBuf,
+    written: bool,
+}
+
+impl ScreenshotSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), written: false }
+    }
+}
+
+impl FrameSink for ScreenshotSink {
+    fn write_frame(&mut self, frame: &CapturedFrame) -> Result<()> {
+        if self.written {
+            return Ok(());
+        }
+        self.written = true;
+
+        let texels = frame.data.chunks_exact(4);
+        let pixels: Vec<u8> = match frame.format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => texels.flat_map(|t| [t[2], t[1], t[0]]).collect(),
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => texels.flat_map(|t| [t[0], t[1], t[2]]).collect(),
+            vk::Format::A2B10G10R10_UNORM_PACK32 => texels
+                .flat_map(|t| {
+                    let texel = u32::from_le_bytes([t[0], t[1], t[2], t[3]]);
+                    [0, 10, 20].map(|shift| (texel >> (shift + 2)) as u8)
+                })
+                .collect(),
+            format => return Err(Error::Backend(format!("Can't save screenshots in {format:?}"))),
+        };
+
+        let save = || -> std::io::Result<()> {
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            write!(file, "P6\n{} {}\n255\n", frame.width, frame.height)?;
+            file.write_all(&pixels)?;
+            file.flush()
+        };
+        save().map_err(|err| Error::Backend(format!("Failed to write {}: {err}", self.path.display())))
+    }
+
+    fn is_finished(&self) -> bool {
+        self.written
+    }
+}
+
+/// Encodes frames into a video file by piping raw frames to an `ffmpeg`
+/// executable found in `PATH`. The container is picked from the extension
+/// of the output path, e.g. `.mp4`.
+#[cfg(feature = "ffmpeg")]
+pub struct FfmpegSink {
+    path: PathBuf,
+    frame_rate: u32,
+    process: Option<Child>,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl FfmpegSink {
+    pub fn new(path: impl Into<PathBuf>, frame_rate: u32) -> Self {
+        Self { path: path.into(), frame_rate, process: None }
+    }
+
+    /// Started on the first frame, once its size and format are known.
+    fn spawn(&self, frame: &CapturedFrame) -> Result<Child> {
+        let pixel_format = match frame.format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => "bgra",
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => "rgba",
+            vk::Format::A2B10G10R10_UNORM_PACK32 => "x2bgr10le",
+            format => return Err(Error::Backend(format!("Can't encode frames in {format:?}"))),
+        };
+
+        Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", pixel_format])
+            .args(["-s", &format!("{}x{}", frame.width, frame.height)])
+            .args(["-r", &self.frame_rate.to_string()])
+            .args(["-i", "-", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::Backend(format!("Failed to start ffmpeg: {err}")))
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl FrameSink for FfmpegSink {
+    fn write_frame(&mut self, frame: &CapturedFrame) -> Result<()> {
+        if self.process.is_none() {
+            self.process = Some(self.spawn(frame)?);
+        }
+        let stdin = self.process.as_mut().and_then(|process| process.stdin.as_mut()).unwrap();
+        stdin.write_all(frame.data)
+            .map_err(|err| Error::Backend(format!("Failed to write frame to ffmpeg: {err}")))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let Some(mut process) = self.process.take() else {
+            return Ok(());
+        };
+        // Closing stdin ends the stream.
+        drop(process.stdin.take());
+        let status = process.wait()
+            .map_err(|err| Error::Backend(format!("Failed to wait for ffmpeg: {err}")))?;
+        if !status.success() {
+            return Err(Error::Backend(format!("ffmpeg failed: {status}")));
+        }
+        Ok(())
+    }
+}
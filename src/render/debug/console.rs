@@ -0,0 +1,646 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ash::vk;
+use winit::event::{ElementState, WindowEvent};
+use winit::keyboard::{Key, KeyCode, NamedKey, PhysicalKey};
+
+use crate::render::cvars::CVars;
+use crate::render::debug::text::DebugText;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::Vec4;
+
+const MAX_HISTORY: usize = 100;
+/// Lines moved by page up and page down.
+const SCROLL_STEP: usize = 8;
+
+const BACKGROUND_COLOR: Vec4 = [0.02, 0.02, 0.04, 0.85];
+const INPUT_COLOR: Vec4 = [1.0, 1.0, 1.0, 1.0];
+const OUTPUT_COLOR: Vec4 = [0.75, 0.75, 0.75, 1.0];
+const ERROR_COLOR: Vec4 = [1.0, 0.35, 0.3, 1.0];
+
+/// Commands handled by the console itself, with their descriptions.
+const BUILTINS: &[(&str, &str)] = &[
+    ("help", "Lists the commands"),
+    ("clear", "Clears the output"),
+    ("history", "Lists the previously entered lines"),
+    ("list", "Lists the cvars, optionally those starting with a prefix: list [prefix]"),
+    ("get", "Prints a cvar: get <name>"),
+    ("set", "Sets a cvar: set <name> <value>"),
+    ("reset", "Sets a cvar back to its default: reset <name>"),
+];
+
+/// Work a command hands to the game loop, which owns the objects involved.
+/// Collected with `Console::take_requests`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConsoleRequest {
+    /// Save the next presented frame, e.g. with `FrameCapture::screenshot`.
+    Screenshot(PathBuf),
+    /// Recreate the pipelines from their shader sources.
+    ReloadShaders,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LineKind {
+    Input,
+    Output,
+    Error,
+}
+
+struct Line {
+    text: String,
+    kind: LineKind,
+}
+
+/// What a command gets to work with besides its arguments.
+pub struct CommandContext<'a> {
+    pub cvars: &'a mut CVars,
+    output: Vec<String>,
+    requests: &'a mut Vec<ConsoleRequest>,
+}
+
+impl CommandContext<'_> {
+    /// Adds `text` to the console output.
+    pub fn print(&mut self, text: &str) {
+        self.output.extend(text.lines().map(str::to_string));
+    }
+
+    pub fn request(&mut self, request: ConsoleRequest) {
+        self.requests.push(request);
+    }
+}
+
+/// Runs a command with the words typed after its name. Errors are printed
+/// in red.
+pub type CommandHandler = Box<dyn FnMut(&mut CommandContext, &[&str]) -> Result<(), String>>;
+
+struct Command {
+    description: String,
+    handler: CommandHandler,
+}
+
+/// Drop-down console drawn with `DebugText`. Lines are split into words at
+/// whitespace, the first one names a command or a cvar: a cvar name alone
+/// prints the variable, followed by a value it sets it. Up and down walk
+/// the history, tab completes names:
+///
+/// ```ignore
+/// let mut console = Console::new();
+/// register_engine_commands(&mut console, &renderer);
+/// console.register("god", "Toggles invulnerability", move |context, _| {
+///     context.print("god mode on");
+///     Ok(())
+/// });
+/// event_loop.run(move |event, target| {
+///     if let Event::WindowEvent { event, .. } = &event {
+///         if console.handle_window_event(event, &mut cvars) {
+///             return;
+///         }
+///     }
+///     input.handle_event(&event);
+///     ..
+///     for request in console.take_requests() {
+///         match request {
+///             ConsoleRequest::Screenshot(path) => capture.screenshot(path)?,
+///             ConsoleRequest::ReloadShaders => passes = Passes::new(renderer.clone()),
+///         }
+///     }
+///     console.draw(&mut text, ui.extent());
+/// })?;
+/// ```
+pub struct Console {
+    /// Opens and closes the console, the key left of 1 on most layouts.
+    pub toggle_key: KeyCode,
+    /// Fraction of the window covered when open.
+    pub height: f32,
+    /// Older output lines are dropped.
+    pub max_lines: usize,
+
+    open: bool,
+    input: String,
+    /// Byte offset into `input`.
+    cursor: usize,
+    history: Vec<String>,
+    /// Entry of `history` shown in the input line while browsing it.
+    history_index: Option<usize>,
+    lines: VecDeque<Line>,
+    /// Lines scrolled up from the newest.
+    scroll: usize,
+    commands: HashMap<String, Command>,
+    requests: Vec<ConsoleRequest>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            toggle_key: KeyCode::Backquote,
+            height: 0.5,
+            max_lines: 1000,
+            open: false,
+            input: String::new(),
+            cursor: 0,
+            history: Vec::new(),
+            history_index: None,
+            lines: VecDeque::new(),
+            scroll: 0,
+            commands: HashMap::new(),
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    /// Adds a command, replacing a registered one of the same name. Panics
+    /// when `name` is a built-in command.
+    pub fn register(
+        &mut self,
+        name: &str,
+        description: &str,
+        handler: impl FnMut(&mut CommandContext, &[&str]) -> Result<(), String> + 'static,
+    ) {
+        assert!(!BUILTINS.iter().any(|(builtin, _)| *builtin == name), "'{name}' is a built-in console command");
+        let command = Command { description: description.to_string(), handler: Box::new(handler) };
+        self.commands.insert(name.to_string(), command);
+    }
+
+    pub fn unregister(&mut self, name: &str) {
+        self.commands.remove(name);
+    }
+
+    /// Lines entered so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Requests of the commands run since the last call.
+    pub fn take_requests(&mut self) -> Vec<ConsoleRequest> {
+        mem::take(&mut self.requests)
+    }
+
+    /// Adds `text` to the output, e.g. for game messages.
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            self.push_line(line.to_string(), LineKind::Output);
+        }
+    }
+
+    pub fn print_error(&mut self, text: &str) {
+        for line in text.lines() {
+            self.push_line(line.to_string(), LineKind::Error);
+        }
+    }
+
+    fn push_line(&mut self, text: String, kind: LineKind) {
+        self.lines.push_back(Line { text, kind });
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+        // Keep the view on the same lines while scrolled up.
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    /// Runs `line` as if it was typed, e.g. for startup scripts.
+    pub fn execute(&mut self, line: &str, cvars: &mut CVars) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+
+        self.push_line(format!("> {line}"), LineKind::Input);
+        if self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if let Err(err) = self.run(&words, cvars) {
+            self.print_error(&err);
+        }
+    }
+
+    fn run(&mut self, words: &[&str], cvars: &mut CVars) -> Result<(), String> {
+        let (&name, args) = words.split_first().unwrap();
+        match name {
+            "help" => {
+                let mut commands = BUILTINS.to_vec();
+                commands.extend(self.commands.iter().map(|(name, command)| (name.as_str(), command.description.as_str())));
+                commands.sort();
+                let help = commands.iter().map(|(name, description)| format!("{name}: {description}")).collect::<Vec<_>>();
+                for line in help {
+                    self.push_line(line, LineKind::Output);
+                }
+            }
+            "clear" => {
+                self.lines.clear();
+                self.scroll = 0;
+            }
+            "history" => {
+                let history = self.history.iter().enumerate().map(|(i, line)| format!("{i:3} {line}")).collect::<Vec<_>>();
+                for line in history {
+                    self.push_line(line, LineKind::Output);
+                }
+            }
+            "list" => {
+                let prefix = args.first().copied().unwrap_or("");
+                let lines = cvars.entries()
+                    .filter(|entry| entry.name().starts_with(prefix))
+                    .map(|entry| format!("{} = {}  {}", entry.name(), entry.value(), entry.description()).trim_end().to_string())
+                    .collect::<Vec<_>>();
+                for line in lines {
+                    self.push_line(line, LineKind::Output);
+                }
+            }
+            "get" => {
+                let [name] = args else {
+                    return Err("Usage: get <name>".to_string());
+                };
+                self.print_cvar(cvars, name)?;
+            }
+            "set" => {
+                let [name, value @ ..] = args else {
+                    return Err("Usage: set <name> <value>".to_string());
+                };
+                if value.is_empty() {
+                    return Err("Usage: set <name> <value>".to_string());
+                }
+                self.set_cvar(cvars, name, &value.join(" "))?;
+            }
+            "reset" => {
+                let [name] = args else {
+                    return Err("Usage: reset <name>".to_string());
+                };
+                cvars.reset(name).map_err(|err| err.to_string())?;
+                self.print_cvar(cvars, name)?;
+            }
+            _ => {
+                if let Some(command) = self.commands.get_mut(name) {
+                    let mut context = CommandContext { cvars, output: Vec::new(), requests: &mut self.requests };
+                    let result = (command.handler)(&mut context, args);
+                    for line in context.output {
+                        self.push_line(line, LineKind::Output);
+                    }
+                    return result;
+                }
+                if cvars.entry(name).is_none() {
+                    return Err(format!("Unknown command '{name}', see 'help'"));
+                }
+                if args.is_empty() {
+                    self.print_cvar(cvars, name)?;
+                } else {
+                    self.set_cvar(cvars, name, &args.join(" "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_cvar(&mut self, cvars: &CVars, name: &str) -> Result<(), String> {
+        let entry = cvars.entry(name).ok_or_else(|| format!("Unknown cvar '{name}'"))?;
+        let mut text = format!("{} = {} (default {})", entry.name(), entry.value(), entry.default_value());
+        if let Some((min, max)) = entry.range() {
+            text += &format!(", {min} to {max}");
+        }
+        if !entry.description().is_empty() {
+            text += &format!("\n  {}", entry.description());
+        }
+        self.print(&text);
+        Ok(())
+    }
+
+    fn set_cvar(&mut self, cvars: &mut CVars, name: &str, value: &str) -> Result<(), String> {
+        cvars.set_str(name, value).map_err(|err| err.to_string())?;
+        // Shows where the value was clamped to.
+        let entry = cvars.entry(name).unwrap();
+        self.print(&format!("{} = {}", entry.name(), entry.value()));
+        Ok(())
+    }
+
+    /// Feeds a window event to the console. Returns true when the console
+    /// used it, the game should then ignore it. All keyboard input goes to
+    /// the console while it's open.
+    pub fn handle_window_event(&mut self, event: &WindowEvent, cvars: &mut CVars) -> bool {
+        let WindowEvent::KeyboardInput { event, .. } = event else {
+            return false;
+        };
+        if event.physical_key == PhysicalKey::Code(self.toggle_key) {
+            if event.state == ElementState::Pressed && !event.repeat {
+                self.open = !self.open;
+            }
+            return true;
+        }
+        if !self.open {
+            return false;
+        }
+        if event.state == ElementState::Released {
+            return true;
+        }
+
+        match &event.logical_key {
+            Key::Named(NamedKey::Enter) => {
+                let line = mem::take(&mut self.input);
+                self.cursor = 0;
+                self.history_index = None;
+                self.scroll = 0;
+                self.execute(&line, cvars);
+            }
+            Key::Named(NamedKey::Backspace) => {
+                if self.cursor > 0 {
+                    self.cursor = self.previous_char(self.cursor);
+                    self.input.remove(self.cursor);
+                }
+            }
+            Key::Named(NamedKey::Delete) => {
+                if self.cursor < self.input.len() {
+                    self.input.remove(self.cursor);
+                }
+            }
+            Key::Named(NamedKey::ArrowLeft) => self.cursor = self.previous_char(self.cursor),
+            Key::Named(NamedKey::ArrowRight) => self.cursor = self.next_char(self.cursor),
+            Key::Named(NamedKey::Home) => self.cursor = 0,
+            Key::Named(NamedKey::End) => self.cursor = self.input.len(),
+            Key::Named(NamedKey::ArrowUp) => self.browse_history(true),
+            Key::Named(NamedKey::ArrowDown) => self.browse_history(false),
+            Key::Named(NamedKey::PageUp) => self.scroll = (self.scroll + SCROLL_STEP).min(self.lines.len().saturating_sub(1)),
+            Key::Named(NamedKey::PageDown) => self.scroll = self.scroll.saturating_sub(SCROLL_STEP),
+            Key::Named(NamedKey::Tab) => self.complete(cvars),
+            Key::Named(NamedKey::Escape) => self.open = false,
+            _ => {
+                if let Some(text) = &event.text {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        self.input.insert(self.cursor, c);
+                        self.cursor += c.len_utf8();
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn previous_char(&self, offset: usize) -> usize {
+        self.input[..offset].char_indices().next_back().map_or(0, |(i, _)| i)
+    }
+
+    fn next_char(&self, offset: usize) -> usize {
+        self.input[offset..].chars().next().map_or(offset, |c| offset + c.len_utf8())
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        self.history_index = match (self.history_index, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            _ => None,
+        };
+        self.input = self.history_index.map(|i| self.history[i].clone()).unwrap_or_default();
+        self.cursor = self.input.len();
+    }
+
+    /// Completes the word before the cursor to a command or cvar name, as
+    /// far as the names starting with it agree. Prints the candidates when
+    /// there are several.
+    fn complete(&mut self, cvars: &CVars) {
+        let start = self.input[..self.cursor].char_indices().rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &self.input[start..self.cursor];
+
+        let mut candidates = cvars.entries().map(|entry| entry.name().to_string()).collect::<Vec<_>>();
+        if start == 0 {
+            candidates.extend(BUILTINS.iter().map(|(name, _)| name.to_string()));
+            candidates.extend(self.commands.keys().cloned());
+        }
+        candidates.retain(|name| name.starts_with(prefix));
+        candidates.sort();
+        candidates.dedup();
+
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let mut completion = first.clone();
+        for name in &candidates[1..] {
+            let common = completion.chars().zip(name.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+            completion.truncate(common);
+        }
+        if candidates.len() == 1 {
+            completion.push(' ');
+        } else {
+            self.print(&candidates.join("  "));
+        }
+
+        self.input.replace_range(start..self.cursor, &completion);
+        self.cursor = start + completion.len();
+    }
+
+    /// Adds the console to `text` when open, covering the top of a layer
+    /// of `extent`. The newest output is at the bottom above the input.
+    pub fn draw(&self, text: &mut DebugText, extent: vk::Extent2D) {
+        if !self.open {
+            return;
+        }
+
+        let scale = text.scale() as f32;
+        let line_height = text.line_height();
+        let margin = 2.0 * scale;
+        let height = (extent.height as f32 * self.height.clamp(0.0, 1.0)).floor();
+        text.rect([0.0, 0.0], [extent.width as f32, height], BACKGROUND_COLOR);
+
+        let input_y = height - margin - line_height;
+        text.text([margin, input_y], &format!("> {}", self.input), INPUT_COLOR);
+        let column = 2 + self.input[..self.cursor].chars().count();
+        let cursor_x = margin + column as f32 * text.char_width() - scale;
+        text.rect([cursor_x, input_y - scale], [cursor_x + scale, input_y + line_height - scale], INPUT_COLOR);
+
+        let mut y = input_y - line_height;
+        for line in self.lines.iter().rev().skip(self.scroll) {
+            if y < margin {
+                break;
+            }
+            let color = match line.kind {
+                LineKind::Input => INPUT_COLOR,
+                LineKind::Output => OUTPUT_COLOR,
+                LineKind::Error => ERROR_COLOR,
+            };
+            text.text([margin, y], &line.text, color);
+            y -= line_height;
+        }
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+#[cfg(feature = "renderdoc")]
+fn capture_frames(renderer: &Renderer, frames: u32) -> Result<(), String> {
+    if !renderer.capture_frames(frames) {
+        return Err("RenderDoc isn't attached".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "renderdoc"))]
+fn capture_frames(_renderer: &Renderer, _frames: u32) -> Result<(), String> {
+    Err("Built without the renderdoc feature".to_string())
+}
+
+/// Registers the engine's commands:
+///
+/// - `screenshot [path]` requests `ConsoleRequest::Screenshot`, by default
+///   to `screenshot_<frame>.ppm`.
+/// - `capture [frames]` captures the next frames with RenderDoc.
+/// - `dump_memory [path]` prints the heap usage and writes the report of
+///   `Renderer::dump_live_resources` to `path`.
+/// - `reload_shaders` requests `ConsoleRequest::ReloadShaders`.
+pub fn register_engine_commands(console: &mut Console, renderer: &Arc<Renderer>) {
+    let renderer_ = renderer.clone();
+    console.register("screenshot", "Saves the next frame: screenshot [path]", move |context, args| {
+        let path = match args.first() {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(format!("screenshot_{}.ppm", renderer_.frame_index())),
+        };
+        context.print(&format!("Saving screenshot to {}", path.display()));
+        context.request(ConsoleRequest::Screenshot(path));
+        Ok(())
+    });
+
+    let renderer_ = renderer.clone();
+    console.register("capture", "Captures the next frames with RenderDoc: capture [frames]", move |context, args| {
+        let frames = match args.first() {
+            Some(arg) => arg.parse().map_err(|_| format!("Invalid frame count '{arg}'"))?,
+            None => 1,
+        };
+        capture_frames(&renderer_, frames)?;
+        context.print(&format!("Capturing {frames} frame(s)"));
+        Ok(())
+    });
+
+    let renderer_ = renderer.clone();
+    console.register("dump_memory", "Prints GPU memory usage, writes live resources to a file: dump_memory [path]", move |context, args| {
+        let stats = renderer_.memory_stats();
+        for (i, heap) in stats.heaps.iter().enumerate() {
+            let kind = if heap.device_local { "device local" } else { "host" };
+            context.print(&format!(
+                "heap {i} ({kind}): {:.1} / {:.1} MiB used, {} allocations",
+                mib(heap.usage), mib(heap.budget), heap.allocation_count));
+        }
+        context.print(&format!(
+            "{} textures {:.1} MiB, {} buffers {:.1} MiB",
+            stats.textures.count, mib(stats.textures.bytes), stats.buffers.count, mib(stats.buffers.bytes)));
+
+        if let Some(path) = args.first() {
+            let report = renderer_.dump_live_resources();
+            if report.is_empty() {
+                return Err("No live resources recorded, see RendererCreateInfo::track_resources".to_string());
+            }
+            fs::write(path, report).map_err(|err| format!("Failed to write {path}: {err}"))?;
+            context.print(&format!("Live resources written to {path}"));
+        }
+        Ok(())
+    });
+
+    console.register("reload_shaders", "Recreates the pipelines", |context, _| {
+        context.request(ConsoleRequest::ReloadShaders);
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn output(console: &Console) -> Vec<(&str, LineKind)> {
+        console.lines.iter().map(|line| (line.text.as_str(), line.kind)).collect()
+    }
+
+    #[test]
+    fn cvar_names_get_and_set() {
+        let mut console = Console::new();
+        let mut cvars = CVars::new();
+        let exposure = cvars.register_ranged("exposure", 1.0f32, 0.0..=4.0, "");
+
+        console.execute("exposure   2.5", &mut cvars);
+        assert_eq!(cvars.get(exposure), 2.5);
+        console.execute("set exposure 10", &mut cvars);
+        assert_eq!(cvars.get(exposure), 4.0);
+        console.execute("reset exposure", &mut cvars);
+        assert_eq!(cvars.get(exposure), 1.0);
+
+        assert_eq!(console.history(), ["exposure   2.5", "set exposure 10", "reset exposure"]);
+        assert_eq!(output(&console)[1], ("exposure = 2.5", LineKind::Output));
+    }
+
+    #[test]
+    fn commands_get_their_arguments() {
+        let mut console = Console::new();
+        let mut cvars = CVars::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        console.register("shot", "Takes a screenshot", move |context, args| {
+            sink.borrow_mut().extend(args.iter().map(|arg| arg.to_string()));
+            let [path] = args else {
+                return Err("Usage: shot <path>".to_string());
+            };
+            context.request(ConsoleRequest::Screenshot(PathBuf::from(path)));
+            context.print("saved");
+            Ok(())
+        });
+
+        console.execute("  shot  frame.png ", &mut cvars);
+        console.execute("shot", &mut cvars);
+
+        assert_eq!(*received.borrow(), ["frame.png"]);
+        assert_eq!(console.take_requests(), [ConsoleRequest::Screenshot(PathBuf::from("frame.png"))]);
+        assert!(console.take_requests().is_empty());
+        assert_eq!(output(&console), [
+            ("> shot  frame.png", LineKind::Input),
+            ("saved", LineKind::Output),
+            ("> shot", LineKind::Input),
+            ("Usage: shot <path>", LineKind::Error),
+        ]);
+    }
+
+    #[test]
+    fn unknown_names_and_bad_usage_are_errors() {
+        let mut console = Console::new();
+        let mut cvars = CVars::new();
+
+        console.execute("teleport", &mut cvars);
+        console.execute("get", &mut cvars);
+        console.execute("set missing 1", &mut cvars);
+        console.execute("   ", &mut cvars);
+
+        let errors = output(&console).into_iter().filter(|(_, kind)| *kind == LineKind::Error).map(|(text, _)| text).collect::<Vec<_>>();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], "Unknown command 'teleport', see 'help'");
+        assert_eq!(errors[1], "Usage: get <name>");
+        assert_eq!(console.history().len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "built-in")]
+    fn builtins_cant_be_replaced() {
+        Console::new().register("help", "", |_, _| Ok(()));
+    }
+}
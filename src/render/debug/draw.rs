@@ -0,0 +1,230 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BufferCreateInfo, GraphicsPipelineCreateInfo, MemoryLocation, PipelineLayoutCreateInfo, PushConstantRange, RasterState, ShaderCreateInfo, ShaderStages, VertexLayout, VertexSemantic};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::{self, Mat4, Vec3, Vec4};
+
+const PUSH_CONSTANTS_SIZE: u32 = 64;
+/// Position and color.
+const VERTEX_SIZE: usize = 28;
+const SPHERE_SEGMENTS: usize = 32;
+
+pub struct DebugDrawCreateInfo {
+    /// Lines beyond this are dropped until the next flush.
+    pub max_lines: u32,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// Hide lines behind geometry. The depth buffer is never written.
+    pub depth_test: bool,
+}
+
+/// Immediate-mode line drawing for visualizing bounds, light volumes and
+/// such. Shapes are accumulated during the frame and drawn by `flush`.
+pub struct DebugDraw {
+    vertices: Vec<u8>,
+    max_lines: u32,
+    vertex_buffers: Vec<Option<Arc<Buffer>>>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    renderer: Arc<Renderer>,
+}
+
+impl DebugDraw {
+    pub fn new(renderer: Arc<Renderer>, create_info: DebugDrawCreateInfo) -> Self {
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: PUSH_CONSTANTS_SIZE,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/debug_line_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/debug_line_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: VertexLayout::interleaved(&[VertexSemantic::Position, VertexSemantic::Color]),
+                color_formats: vec![create_info.color_format],
+                depth_format: create_info.depth_format,
+                extent: create_info.extent,
+                raster: RasterState {
+                    topology: vk::PrimitiveTopology::LINE_LIST,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    depth_test: create_info.depth_test,
+                    depth_write: false,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            vertices: Vec::new(),
+            max_lines: create_info.max_lines,
+            vertex_buffers: vec![None; FRAME_OVERLAP],
+            pipeline,
+            pipeline_layout,
+            renderer,
+        }
+    }
+
+    /// Lines accumulated since the last flush.
+    pub fn line_count(&self) -> u32 {
+        (self.vertices.len() / (2 * VERTEX_SIZE)) as u32
+    }
+
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: Vec4) {
+        if self.line_count() >= self.max_lines {
+            return;
+        }
+        for p in [a, b] {
+            for v in p.iter().chain(&color) {
+                self.vertices.extend_from_slice(&v.to_ne_bytes());
+            }
+        }
+    }
+
+    /// Axis aligned box.
+    pub fn wire_box(&mut self, min: Vec3, max: Vec3, color: Vec4) {
+        let corners = std::array::from_fn(|i| {
+            [
+                if i & 1 == 0 { min[0] } else { max[0] },
+                if i & 2 == 0 { min[1] } else { max[1] },
+                if i & 4 == 0 { min[2] } else { max[2] },
+            ]
+        });
+        self.box_edges(&corners, color);
+    }
+
+    /// The `[-1, 1]` cube transformed by `transform`, for oriented boxes.
+    pub fn oriented_box(&mut self, transform: Mat4, color: Vec4) {
+        let corners = std::array::from_fn(|i| {
+            let p = [
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            ];
+            transform.transform_point(p)
+        });
+        self.box_edges(&corners, color);
+    }
+
+    /// Camera or light frustum given its view projection. Works with
+    /// reverse-Z and infinite projections, the far plane is taken at a
+    /// tiny depth instead of 0.
+    pub fn frustum(&mut self, view_projection: Mat4, color: Vec4) {
+        let Some(inverse) = view_projection.inverse() else {
+            return;
+        };
+        let corners = std::array::from_fn(|i| {
+            let ndc = [
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 1.0 } else { 1e-3 },
+            ];
+            inverse.transform_point(ndc)
+        });
+        self.box_edges(&corners, color);
+    }
+
+    /// Three circles around the coordinate axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        for axis in 0..3 {
+            let point = |i: usize| {
+                let angle = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                let (s, c) = angle.sin_cos();
+                let mut p = [0.0; 3];
+                p[(axis + 1) % 3] = c * radius;
+                p[(axis + 2) % 3] = s * radius;
+                math::add(center, p)
+            };
+            for i in 0..SPHERE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    /// Red, green and blue lines along the x, y and z axes of `transform`.
+    pub fn axes(&mut self, transform: Mat4, size: f32) {
+        let origin = transform.transform_point([0.0; 3]);
+        for axis in 0..3 {
+            let mut direction = [0.0; 3];
+            direction[axis] = size;
+            let mut color = [0.0, 0.0, 0.0, 1.0];
+            color[axis] = 1.0;
+            self.line(origin, transform.transform_point(direction), color);
+        }
+    }
+
+    /// Corners indexed by bits: 1 is +x, 2 is +y and 4 is +z.
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: Vec4) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    fn upload(&mut self) -> Arc<Buffer> {
+        let slot = &mut self.vertex_buffers[self.renderer.current_frame()];
+
+        // Reuse the buffer when the command list already released it.
+        let reusable = slot.as_mut()
+            .and_then(Arc::get_mut)
+            .is_some_and(|b| b.size() >= self.vertices.len() as u64);
+        if !reusable {
+            let create_info = BufferCreateInfo {
+                size: (self.vertices.len() as u64).next_power_of_two(),
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            *slot = Some(Arc::new(Buffer::new(self.renderer.clone(), create_info)));
+        }
+
+        let buffer = slot.as_mut().unwrap();
+        Arc::get_mut(buffer).unwrap().write(0, &self.vertices);
+        buffer.clone()
+    }
+
+    /// Draws and clears the accumulated lines. Must be called inside
+    /// `begin_rendering`, usually last so the lines are drawn on top.
+    pub fn flush(&mut self, command_list: &mut CommandList, view_projection: Mat4) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let vertex_buffer = self.upload();
+        let vertex_count = (self.vertices.len() / VERTEX_SIZE) as u32;
+
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+        command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &view_projection.to_bytes());
+        command_list.bind_vertex_buffers(0, &[vertex_buffer]);
+        command_list.draw(vertex_count, 1, 0, 0);
+
+        self.vertices.clear();
+    }
+}
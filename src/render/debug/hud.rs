@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+
+use crate::render::debug::profiler::GpuFrame;
+use crate::render::debug::text::DebugText;
+use crate::render::hal::FrameStats;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::Vec4;
+
+/// Frames shown by the graphs, one font pixel wide each.
+const HISTORY: usize = 120;
+/// In font pixels.
+const GRAPH_HEIGHT: f32 = 24.0;
+
+const BACKGROUND_COLOR: Vec4 = [0.02, 0.02, 0.04, 0.75];
+const GRAPH_BACKGROUND_COLOR: Vec4 = [0.1, 0.1, 0.12, 0.8];
+const TEXT_COLOR: Vec4 = [0.9, 0.9, 0.9, 1.0];
+const CPU_COLOR: Vec4 = [0.35, 0.8, 0.4, 1.0];
+const GPU_COLOR: Vec4 = [0.95, 0.6, 0.2, 1.0];
+const TARGET_COLOR: Vec4 = [1.0, 1.0, 1.0, 0.4];
+
+struct Sample {
+    cpu_ms: f32,
+    /// `None` without a profiled frame.
+    gpu_ms: Option<f32>,
+}
+
+fn format_count(count: u64) -> String {
+    match count {
+        0..1_000 => count.to_string(),
+        1_000..1_000_000 => format!("{:.1}k", count as f64 / 1e3),
+        _ => format!("{:.2}M", count as f64 / 1e6),
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// Summary of the last, average and worst value.
+fn timing(label: &str, values: &[f32]) -> String {
+    let Some(&last) = values.last() else {
+        return format!("{label} n/a");
+    };
+    let average = values.iter().sum::<f32>() / values.len() as f32;
+    let max = values.iter().copied().fold(0.0, f32::max);
+    format!("{label} {last:5.2} ms  avg {average:5.2}  max {max:5.2}")
+}
+
+/// Overlay of CPU and GPU frame time graphs and the renderer's per-frame
+/// counters, drawn with `DebugText`. GPU times come from a `GpuProfiler`
+/// whose zones cover the frame:
+///
+/// ```ignore
+/// renderer.present()?;
+/// hud.update(&renderer, profiler.last_frame());
+/// ..
+/// hud.draw(&mut text);
+/// ```
+pub struct StatsHud {
+    /// Top left corner in pixels.
+    pub position: [f32; 2],
+    /// Frame time at the top of the graphs, longer frames are cut off.
+    pub graph_max_ms: f32,
+    /// Frame budget marked across the graphs.
+    pub target_ms: f32,
+
+    samples: VecDeque<Sample>,
+    stats: FrameStats,
+    vram_usage: u64,
+    vram_budget: u64,
+}
+
+impl Default for StatsHud {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsHud {
+    pub fn new() -> Self {
+        Self {
+            position: [8.0, 8.0],
+            graph_max_ms: 33.3,
+            target_ms: 16.7,
+            samples: VecDeque::with_capacity(HISTORY),
+            stats: FrameStats::default(),
+            vram_usage: 0,
+            vram_budget: 0,
+        }
+    }
+
+    /// Takes the stats of the frame presented last and the latest resolved
+    /// GPU frame. Call once per frame after `Renderer::present`.
+    pub fn update(&mut self, renderer: &Renderer, gpu_frame: Option<&GpuFrame>) {
+        self.stats = renderer.frame_stats();
+
+        let memory = renderer.memory_stats();
+        let device_local = memory.heaps.iter().filter(|heap| heap.device_local);
+        self.vram_usage = device_local.clone().map(|heap| heap.usage).sum();
+        self.vram_budget = device_local.map(|heap| heap.budget).sum();
+
+        if self.samples.len() == HISTORY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            cpu_ms: self.stats.cpu_time.as_secs_f32() * 1000.0,
+            gpu_ms: gpu_frame.map(|frame| frame.duration_ms() as f32),
+        });
+    }
+
+    /// Adds the overlay to `text`.
+    pub fn draw(&self, text: &mut DebugText) {
+        let scale = text.scale() as f32;
+        let line_height = text.line_height();
+        let padding = 4.0 * scale;
+        let graph_size = [HISTORY as f32 * scale, GRAPH_HEIGHT * scale];
+
+        let cpu_times = self.samples.iter().map(|sample| sample.cpu_ms).collect::<Vec<_>>();
+        let gpu_times = self.samples.iter().filter_map(|sample| sample.gpu_ms).collect::<Vec<_>>();
+        let counters = [
+            format!("draws {}  dispatches {}", self.stats.draw_calls, self.stats.dispatches),
+            format!("triangles {}", format_count(self.stats.triangles)),
+            format!("descriptor sets {}", self.stats.descriptor_set_allocations),
+            format!("VRAM {:.0} / {:.0} MiB", mib(self.vram_usage), mib(self.vram_budget)),
+        ];
+        let graphs = [(timing("CPU", &cpu_times), CPU_COLOR, false), (timing("GPU", &gpu_times), GPU_COLOR, true)];
+
+        let text_width = graphs.iter().map(|(label, ..)| label)
+            .chain(&counters)
+            .map(|line| text.measure(line)[0])
+            .fold(0.0, f32::max);
+        let width = text_width.max(graph_size[0]) + 2.0 * padding;
+        let height = 2.0 * (line_height + graph_size[1] + padding) + counters.len() as f32 * line_height + 2.0 * padding;
+        let [x, mut y] = [self.position[0] + padding, self.position[1] + padding];
+        text.rect(self.position, [self.position[0] + width, self.position[1] + height], BACKGROUND_COLOR);
+
+        for (label, color, is_gpu) in graphs {
+            text.text([x, y], &label, TEXT_COLOR);
+            y += line_height;
+            let values = self.samples.iter().map(|sample| if is_gpu { sample.gpu_ms } else { Some(sample.cpu_ms) });
+            self.graph(text, [x, y], graph_size, values, color);
+            y += graph_size[1] + padding;
+        }
+        for line in &counters {
+            text.text([x, y], line, TEXT_COLOR);
+            y += line_height;
+        }
+    }
+
+    /// Bars of `values` with the newest on the right, `None` leaves a gap.
+    fn graph(&self, text: &mut DebugText, origin: [f32; 2], size: [f32; 2], values: impl ExactSizeIterator<Item = Option<f32>>, color: Vec4) {
+        let bottom = origin[1] + size[1];
+        let bar_width = size[0] / HISTORY as f32;
+        let to_height = |ms: f32| (ms / self.graph_max_ms).clamp(0.0, 1.0) * size[1];
+        text.rect(origin, [origin[0] + size[0], bottom], GRAPH_BACKGROUND_COLOR);
+
+        let first = HISTORY - values.len();
+        for (i, value) in values.enumerate() {
+            if let Some(ms) = value {
+                let left = origin[0] + (first + i) as f32 * bar_width;
+                text.rect([left, bottom - to_height(ms)], [left + bar_width, bottom], color);
+            }
+        }
+
+        let target = bottom - to_height(self.target_ms);
+        text.rect([origin[0], target], [origin[0] + size[0], target + bar_width], TARGET_COLOR);
+    }
+}
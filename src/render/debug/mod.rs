@@ -0,0 +1,9 @@
+pub mod capture;
+pub mod console;
+pub mod draw;
+pub mod hud;
+pub mod probes;
+pub mod profiler;
+#[cfg(feature = "renderdoc")]
+pub mod renderdoc;
+pub mod text;
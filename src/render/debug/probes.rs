@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::debug::text::DebugText;
+use crate::render::hal::{BufferCreateInfo, BufferTextureCopy, MemoryLocation};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::{Mat4, Vec3, Vec4};
+
+/// Every probed texel occupies one 16 byte slot in the readback buffer,
+/// which fits the widest supported format.
+const TEXEL_SLOT_SIZE: u64 = 16;
+
+const BACKGROUND_COLOR: Vec4 = [0.02, 0.02, 0.04, 0.75];
+const TEXT_COLOR: Vec4 = [0.9, 0.9, 0.9, 1.0];
+const MARKER_COLOR: Vec4 = [1.0, 0.2, 0.8, 1.0];
+
+pub struct DebugProbesCreateInfo {
+    pub max_probes: u32,
+    /// Maximum number of textures (color, depth, normals, ...) sampled per probe.
+    pub max_targets: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProbePosition {
+    /// Pixel of the targets.
+    Screen(u32, u32),
+    /// Projected into the targets with the view-projection passed to `record`.
+    World(Vec3),
+}
+
+#[derive(Clone, Debug)]
+pub struct ProbeSample {
+    pub position: ProbePosition,
+    /// Pixel the first target was read at, `None` when the probe was
+    /// outside of it.
+    pub pixel: Option<(u32, u32)>,
+    /// One value per target passed to `record`, in the same order. `None`
+    /// when the probe was outside of the target or behind the camera.
+    pub values: Vec<Option<[f32; 4]>>,
+}
+
+impl ProbeSample {
+    /// Rec.709 luminance of the first target, usually the HDR color buffer.
+    pub fn luminance(&self) -> Option<f32> {
+        self.values.first()
+            .copied()
+            .flatten()
+            .map(|[r, g, b, _]| 0.2126 * r + 0.7152 * g + 0.0722 * b)
+    }
+}
+
+struct PendingReadback {
+    positions: Vec<ProbePosition>,
+    formats: Vec<vk::Format>,
+    /// Pixel read per probe and target, `None` for slots left unwritten.
+    pixels: Vec<Option<(u32, u32)>>,
+}
+
+/// Pixel of `extent` that `position` lands on, `None` outside of it.
+fn probe_pixel(position: ProbePosition, view_projection: Mat4, extent: vk::Extent3D) -> Option<(u32, u32)> {
+    let (x, y) = match position {
+        ProbePosition::Screen(x, y) => (x, y),
+        ProbePosition::World([x, y, z]) => {
+            let [cx, cy, _, w] = view_projection.transform([x, y, z, 1.0]);
+            if w <= 0.0 {
+                return None;
+            }
+            let u = (cx / w * 0.5 + 0.5) * extent.width as f32;
+            let v = (cy / w * 0.5 + 0.5) * extent.height as f32;
+            if u < 0.0 || v < 0.0 {
+                return None;
+            }
+            (u as u32, v as u32)
+        }
+    };
+    (x < extent.width && y < extent.height).then_some((x, y))
+}
+
+/// Reads back texels of render targets at registered screen or world
+/// positions. Results lag `FRAME_OVERLAP` frames behind, no GPU stall is
+/// introduced. `draw` lists the latest values in an overlay:
+///
+/// ```ignore
+/// probes.record(&command_list, &[&hdr_color, &depth, &normals], view_projection);
+/// ..
+/// probes.draw(&mut text, [8.0, 200.0]);
+/// ```
+pub struct DebugProbes {
+    positions: Vec<ProbePosition>,
+    max_probes: u32,
+    max_targets: u32,
+    readback: [Buffer; FRAME_OVERLAP],
+    pending: [Option<PendingReadback>; FRAME_OVERLAP],
+    samples: Vec<ProbeSample>,
+    renderer: Arc<Renderer>,
+}
+
+impl DebugProbes {
+    pub fn new(renderer: Arc<Renderer>, create_info: DebugProbesCreateInfo) -> Self {
+        let size = create_info.max_probes as u64 * create_info.max_targets as u64 * TEXEL_SLOT_SIZE;
+        let readback = std::array::from_fn(|_| {
+            let create_info = BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuToCpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        });
+
+        Self {
+            positions: Vec::new(),
+            max_probes: create_info.max_probes,
+            max_targets: create_info.max_targets,
+            readback,
+            pending: std::array::from_fn(|_| None),
+            samples: Vec::new(),
+            renderer,
+        }
+    }
+
+    /// Registers a probe at a pixel position, returns its index in `samples()`.
+    pub fn register(&mut self, x: u32, y: u32) -> usize {
+        self.register_at(ProbePosition::Screen(x, y))
+    }
+
+    /// Registers a probe following a world position, returns its index in
+    /// `samples()`.
+    pub fn register_world(&mut self, position: Vec3) -> usize {
+        self.register_at(ProbePosition::World(position))
+    }
+
+    fn register_at(&mut self, position: ProbePosition) -> usize {
+        assert!((self.positions.len() as u32) < self.max_probes, "Too many debug probes");
+        self.positions.push(position);
+        self.positions.len() - 1
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+
+    /// Latest decoded values, one entry per probe.
+    pub fn samples(&self) -> &[ProbeSample] {
+        &self.samples
+    }
+
+    /// Collects the results recorded the last time this frame slot was used
+    /// and records copies for the current frame. Must be called after the
+    /// frame fence has been waited on. Targets must be in `GENERAL` layout
+    /// and have `TRANSFER_SRC` usage. `view_projection` places world probes.
+    pub fn record(&mut self, command_list: &CommandList, targets: &[&Texture], view_projection: Mat4) {
+        assert!(targets.len() as u32 <= self.max_targets, "Too many probe targets");
+
+        let frame = self.renderer.current_frame();
+
+        if let Some(pending) = self.pending[frame].take() {
+            self.samples = self.decode(&self.readback[frame], &pending);
+        }
+
+        let mut pixels = Vec::with_capacity(self.positions.len() * targets.len());
+        let mut regions = Vec::new();
+        for (probe, &position) in self.positions.iter().enumerate() {
+            for (target_idx, target) in targets.iter().enumerate() {
+                let pixel = probe_pixel(position, view_projection, target.extent());
+                pixels.push(pixel);
+                let Some((x, y)) = pixel else {
+                    continue;
+                };
+
+                let slot = (probe * self.max_targets as usize + target_idx) as u64;
+                let region = BufferTextureCopy {
+                    buffer_offset: slot * TEXEL_SLOT_SIZE,
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                    texture_offset: vk::Offset3D { x: x as i32, y: y as i32, z: 0 },
+                    texture_extent: vk::Extent3D { width: 1, height: 1, depth: 1 },
+                };
+                regions.push((target_idx, region));
+            }
+        }
+
+        for (target_idx, target) in targets.iter().enumerate() {
+            let target_regions = regions.iter()
+                .filter(|(idx, _)| *idx == target_idx)
+                .map(|(_, region)| *region)
+                .collect::<Vec<_>>();
+
+            if !target_regions.is_empty() {
+                command_list.copy_texture_to_buffer(target, &self.readback[frame], &target_regions);
+            }
+        }
+
+        self.pending[frame] = Some(PendingReadback {
+            positions: self.positions.clone(),
+            formats: targets.iter().map(|t| t.format()).collect(),
+            pixels,
+        });
+    }
+
+    fn decode(&self, buffer: &Buffer, pending: &PendingReadback) -> Vec<ProbeSample> {
+        let mut data = vec![0u8; buffer.size() as usize];
+        buffer.read(0, &mut data);
+
+        let target_count = pending.formats.len();
+        pending.positions.iter().enumerate().map(|(probe, &position)| {
+            let pixels = &pending.pixels[probe * target_count..(probe + 1) * target_count];
+            let values = pending.formats.iter().zip(pixels).enumerate().map(|(target_idx, (&format, pixel))| {
+                // Slots of probes outside of the target hold stale data.
+                pixel.map(|_| {
+                    let offset = ((probe * self.max_targets as usize + target_idx) as u64 * TEXEL_SLOT_SIZE) as usize;
+                    decode_texel(format, &data[offset..offset + TEXEL_SLOT_SIZE as usize])
+                })
+            }).collect();
+            ProbeSample { position, pixel: pixels.first().copied().flatten(), values }
+        }).collect()
+    }
+
+    /// Adds a marker at every probe and a panel listing their values to
+    /// `text`, with its top left corner at `position` in pixels.
+    pub fn draw(&self, text: &mut DebugText, position: [f32; 2]) {
+        let scale = text.scale() as f32;
+        let padding = 4.0 * scale;
+
+        for sample in &self.samples {
+            if let Some((x, y)) = sample.pixel {
+                let [x, y] = [x as f32, y as f32];
+                text.rect([x - scale, y - 3.0 * scale], [x + scale, y + 3.0 * scale], MARKER_COLOR);
+                text.rect([x - 3.0 * scale, y - scale], [x + 3.0 * scale, y + scale], MARKER_COLOR);
+            }
+        }
+
+        let lines = self.samples.iter().enumerate().flat_map(|(probe, sample)| {
+            let header = match sample.position {
+                ProbePosition::Screen(x, y) => format!("probe {probe} at ({x}, {y})"),
+                ProbePosition::World([x, y, z]) => format!("probe {probe} at ({x:.2}, {y:.2}, {z:.2})"),
+            };
+            let luminance = sample.luminance().map_or("n/a".to_string(), |l| format!("{l:.4}"));
+            let values = sample.values.iter().enumerate().map(|(target, value)| match value {
+                Some([r, g, b, a]) => format!("  {target}: {r:.4} {g:.4} {b:.4} {a:.4}"),
+                None => format!("  {target}: n/a"),
+            });
+            [header, format!("  luminance {luminance}")].into_iter().chain(values)
+        }).collect::<Vec<_>>();
+        if lines.is_empty() {
+            return;
+        }
+
+        let width = lines.iter().map(|line| text.measure(line)[0]).fold(0.0, f32::max) + 2.0 * padding;
+        let height = lines.len() as f32 * text.line_height() + 2.0 * padding;
+        text.rect(position, [position[0] + width, position[1] + height], BACKGROUND_COLOR);
+
+        let [x, mut y] = [position[0] + padding, position[1] + padding];
+        for line in &lines {
+            text.text([x, y], line, TEXT_COLOR);
+            y += text.line_height();
+        }
+    }
+}
+
+fn decode_texel(format: vk::Format, bytes: &[u8]) -> [f32; 4] {
+    let u16_at = |i: usize| u16::from_ne_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
+    let f32_at = |i: usize| f32::from_ne_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+    let unorm8_at = |i: usize| bytes[i] as f32 / 255.0;
+
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => [f16_to_f32(u16_at(0)), f16_to_f32(u16_at(1)), f16_to_f32(u16_at(2)), f16_to_f32(u16_at(3))],
+        vk::Format::R32G32B32A32_SFLOAT => [f32_at(0), f32_at(1), f32_at(2), f32_at(3)],
+        vk::Format::R32_SFLOAT | vk::Format::D32_SFLOAT => [f32_at(0), 0.0, 0.0, 0.0],
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => [unorm8_at(0), unorm8_at(1), unorm8_at(2), unorm8_at(3)],
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => [unorm8_at(2), unorm8_at(1), unorm8_at(0), unorm8_at(3)],
+        _ => [f32::NAN; 4],
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+#[cfg(feature = "chrome-trace")]
+use std::io::Write;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+
+pub struct GpuProfilerCreateInfo {
+    /// Maximum number of zones recorded per frame.
+    pub max_zones: u32,
+    /// Number of resolved frames kept for `frames` and the exporters.
+    pub history_frames: usize,
+}
+
+/// A timed range of GPU work.
+#[derive(Clone, Debug)]
+pub struct GpuZone {
+    pub name: String,
+    /// Nesting level, 0 for top level zones.
+    pub depth: u32,
+    /// GPU clock in nanoseconds, only comparable with other timestamps of
+    /// the same device.
+    pub begin_ns: u64,
+    pub end_ns: u64,
+}
+
+impl GpuZone {
+    pub fn duration_ms(&self) -> f64 {
+        self.end_ns.saturating_sub(self.begin_ns) as f64 / 1_000_000.0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuFrame {
+    /// `Renderer` frame index the zones were recorded at.
+    pub frame_index: u64,
+    pub zones: Vec<GpuZone>,
+}
+
+impl GpuFrame {
+    /// From the start of the first zone to the end of the last, 0 without
+    /// zones. Covers the whole frame when the top level zones do.
+    pub fn duration_ms(&self) -> f64 {
+        let begin = self.zones.iter().map(|zone| zone.begin_ns).min();
+        let end = self.zones.iter().map(|zone| zone.end_ns).max();
+        match (begin, end) {
+            (Some(begin), Some(end)) => end.saturating_sub(begin) as f64 / 1_000_000.0,
+            _ => 0.0,
+        }
+    }
+}
+
+struct PendingZone {
+    name: String,
+    depth: u32,
+    /// The end query is `begin_query + 1`.
+    begin_query: u32,
+}
+
+struct PendingFrame {
+    frame_index: u64,
+    zones: Vec<PendingZone>,
+}
+
+/// Handle returned by `begin_zone`, passed back to `end_zone`.
+#[derive(Clone, Copy, Debug)]
+pub struct ZoneId(usize);
+
+/// Measures GPU time of command ranges with timestamp queries. Results lag
+/// `FRAME_OVERLAP` frames behind, no GPU stall is introduced.
+pub struct GpuProfiler {
+    query_pools: [vk::QueryPool; FRAME_OVERLAP],
+    max_zones: u32,
+    recording: Option<PendingFrame>,
+    pending: [Option<PendingFrame>; FRAME_OVERLAP],
+    depth: u32,
+    history: VecDeque<GpuFrame>,
+    history_frames: usize,
+    renderer: Arc<Renderer>,
+}
+
+impl GpuProfiler {
+    pub fn new(renderer: Arc<Renderer>, create_info: GpuProfilerCreateInfo) -> Self {
+        let query_pools = std::array::from_fn(|_| {
+            let create_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(create_info.max_zones * 2);
+            unsafe { renderer.device.create_query_pool(&create_info, None).unwrap() }
+        });
+
+        Self {
+            query_pools,
+            max_zones: create_info.max_zones,
+            recording: None,
+            pending: std::array::from_fn(|_| None),
+            depth: 0,
+            history: VecDeque::new(),
+            history_frames: create_info.history_frames.max(1),
+            renderer,
+        }
+    }
+
+    /// Resolves the zones recorded the last time this frame slot was used
+    /// and starts recording the current frame. Must be called after the
+    /// frame fence has been waited on, before any `begin_zone`.
+    pub fn begin_frame(&mut self, command_list: &CommandList) {
+        let frame = self.renderer.current_frame();
+
+        if let Some(pending) = self.pending[frame].take() {
+            let resolved = self.resolve(frame, pending);
+            if self.history.len() == self.history_frames {
+                self.history.pop_front();
+            }
+            self.history.push_back(resolved);
+        }
+
+        command_list.reset_query_pool(self.query_pools[frame], 0, self.max_zones * 2);
+        self.recording = Some(PendingFrame { frame_index: self.renderer.frame_index(), zones: Vec::new() });
+        self.depth = 0;
+    }
+
+    /// Ends the frame started by `begin_frame`, zones left open are dropped.
+    pub fn end_frame(&mut self) {
+        let frame = self.renderer.current_frame();
+        self.pending[frame] = self.recording.take();
+    }
+
+    pub fn begin_zone(&mut self, command_list: &CommandList, name: &str) -> ZoneId {
+        let recording = self.recording.as_mut().expect("begin_zone outside of begin_frame/end_frame");
+        assert!((recording.zones.len() as u32) < self.max_zones, "Too many profiler zones");
+
+        let begin_query = recording.zones.len() as u32 * 2;
+        command_list.write_timestamp(self.query_pools[self.renderer.current_frame()], begin_query);
+        recording.zones.push(PendingZone { name: name.to_string(), depth: self.depth, begin_query });
+        self.depth += 1;
+        ZoneId(recording.zones.len() - 1)
+    }
+
+    pub fn end_zone(&mut self, command_list: &CommandList, zone: ZoneId) {
+        let recording = self.recording.as_mut().expect("end_zone outside of begin_frame/end_frame");
+        let end_query = recording.zones[zone.0].begin_query + 1;
+        command_list.write_timestamp(self.query_pools[self.renderer.current_frame()], end_query);
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// Latest resolved frame.
+    pub fn last_frame(&self) -> Option<&GpuFrame> {
+        self.history.back()
+    }
+
+    /// Resolved frames, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &GpuFrame> {
+        self.history.iter()
+    }
+
+    fn resolve(&self, frame: usize, pending: PendingFrame) -> GpuFrame {
+        let query_count = pending.zones.len() * 2;
+        let mut results = vec![[0u64; 2]; query_count];
+        if query_count > 0 {
+            // NOT_READY when a zone was left open, availability tells which.
+            let _ = unsafe {
+                self.renderer.device.get_query_pool_results(
+                    self.query_pools[frame],
+                    0,
+                    &mut results,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY)
+            };
+        }
+
+        let period = self.renderer.timestamp_period as f64;
+        let to_ns = |ticks: u64| (ticks as f64 * period) as u64;
+        let zones = pending.zones.into_iter()
+            .filter_map(|zone| {
+                let [begin, begin_available] = results[zone.begin_query as usize];
+                let [end, end_available] = results[zone.begin_query as usize + 1];
+                (begin_available != 0 && end_available != 0).then(|| GpuZone {
+                    name: zone.name,
+                    depth: zone.depth,
+                    begin_ns: to_ns(begin),
+                    end_ns: to_ns(end),
+                })
+            })
+            .collect();
+
+        GpuFrame { frame_index: pending.frame_index, zones }
+    }
+
+    /// Writes the resolved frames in the Chrome trace event format, for
+    /// `chrome://tracing` or Perfetto. Every zone is a complete event on
+    /// the GPU track, frames are instant events.
+    #[cfg(feature = "chrome-trace")]
+    pub fn write_chrome_trace(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "{{\"traceEvents\":[")?;
+        write!(writer, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{{\"name\":\"GPU\"}}}}")?;
+        for frame in &self.history {
+            if let Some(first) = frame.zones.iter().map(|zone| zone.begin_ns).min() {
+                write!(writer, ",{{\"name\":\"frame {}\",\"ph\":\"i\",\"s\":\"g\",\"pid\":0,\"tid\":0,\"ts\":{:.3}}}", frame.frame_index, first as f64 / 1000.0)?;
+            }
+            for zone in &frame.zones {
+                write!(
+                    writer,
+                    ",{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":{:.3},\"dur\":{:.3}}}",
+                    escape_json(&zone.name),
+                    zone.begin_ns as f64 / 1000.0,
+                    zone.end_ns.saturating_sub(zone.begin_ns) as f64 / 1000.0)?;
+            }
+        }
+        writeln!(writer, "],\"displayTimeUnit\":\"ms\"}}")
+    }
+}
+
+#[cfg(feature = "chrome-trace")]
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        for pool in self.query_pools {
+            unsafe { self.renderer.device.destroy_query_pool(pool, None) }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::ffi::{c_char, c_int, c_void};
+use std::ptr;
+
+use libloading::Library;
+
+/// `eRENDERDOC_API_Version_1_1_2`, the oldest version with
+/// `TriggerMultiFrameCapture`.
+const API_VERSION: c_int = 10102;
+
+type GetApi = unsafe extern "C" fn(version: c_int, out_api_pointers: *mut *mut c_void) -> c_int;
+type DevicePointer = *mut c_void;
+type WindowHandle = *mut c_void;
+
+/// `RENDERDOC_API_1_1_2`, entries that aren't used are left untyped.
+#[repr(C)]
+struct Api {
+    get_api_version: *const c_void,
+    set_capture_option_u32: *const c_void,
+    set_capture_option_f32: *const c_void,
+    get_capture_option_u32: *const c_void,
+    get_capture_option_f32: *const c_void,
+    set_focus_toggle_keys: *const c_void,
+    set_capture_keys: *const c_void,
+    get_overlay_bits: *const c_void,
+    mask_overlay_bits: *const c_void,
+    remove_hooks: *const c_void,
+    unload_crash_handler: *const c_void,
+    set_capture_file_path_template: unsafe extern "C" fn(path_template: *const c_char),
+    get_capture_file_path_template: *const c_void,
+    get_num_captures: unsafe extern "C" fn() -> u32,
+    get_capture: *const c_void,
+    trigger_capture: unsafe extern "C" fn(),
+    is_target_control_connected: unsafe extern "C" fn() -> u32,
+    launch_replay_ui: *const c_void,
+    set_active_window: *const c_void,
+    start_frame_capture: unsafe extern "C" fn(device: DevicePointer, window: WindowHandle),
+    is_frame_capturing: unsafe extern "C" fn() -> u32,
+    end_frame_capture: unsafe extern "C" fn(device: DevicePointer, window: WindowHandle) -> u32,
+    trigger_multi_frame_capture: unsafe extern "C" fn(num_frames: u32),
+}
+
+/// RenderDoc in-app API, available when the application was launched or
+/// injected into by RenderDoc. Captures are taken from presented frames and
+/// saved to the location set in the RenderDoc UI or `set_capture_path`.
+pub struct RenderDoc {
+    api: *const Api,
+    /// Keeps the library loaded while `api` is in use.
+    _library: Library,
+}
+
+// The API is documented as thread safe.
+unsafe impl Send for RenderDoc {}
+unsafe impl Sync for RenderDoc {}
+
+#[cfg(target_os = "linux")]
+unsafe fn open_loaded_library() -> Option<Library> {
+    use libloading::os::unix;
+
+    // Not exposed by libloading, opens the library only if already loaded.
+    const RTLD_NOLOAD: c_int = 0x4;
+    unix::Library::open(Some("librenderdoc.so"), unix::RTLD_NOW | RTLD_NOLOAD).ok().map(Library::from)
+}
+
+#[cfg(windows)]
+unsafe fn open_loaded_library() -> Option<Library> {
+    libloading::os::windows::Library::open_already_loaded("renderdoc.dll").ok().map(Library::from)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+unsafe fn open_loaded_library() -> Option<Library> {
+    None
+}
+
+impl RenderDoc {
+    /// `None` when RenderDoc isn't loaded into the process. Never loads the
+    /// library itself, a capture layer added after the Vulkan instance has
+    /// been created wouldn't hook it.
+    pub fn load() -> Option<Self> {
+        unsafe {
+            let library = open_loaded_library()?;
+            let get_api = library.get::<GetApi>(b"RENDERDOC_GetAPI\0").ok()?;
+            let mut api = ptr::null_mut();
+            if get_api(API_VERSION, &mut api) != 1 || api.is_null() {
+                return None;
+            }
+            Some(Self { api: api as *const Api, _library: library })
+        }
+    }
+
+    fn api(&self) -> &Api {
+        unsafe { &*self.api }
+    }
+
+    /// Captures the next presented frame.
+    pub fn trigger_capture(&self) {
+        unsafe { (self.api().trigger_capture)() }
+    }
+
+    /// Captures the next `frames` presented frames, one capture each.
+    pub fn trigger_multi_frame_capture(&self, frames: u32) {
+        unsafe { (self.api().trigger_multi_frame_capture)(frames) }
+    }
+
+    /// Starts capturing commands outside of the present cycle, e.g. for
+    /// compute only workloads, until `end_frame_capture`.
+    pub fn start_frame_capture(&self) {
+        unsafe { (self.api().start_frame_capture)(ptr::null_mut(), ptr::null_mut()) }
+    }
+
+    /// Returns false when the capture failed.
+    pub fn end_frame_capture(&self) -> bool {
+        unsafe { (self.api().end_frame_capture)(ptr::null_mut(), ptr::null_mut()) != 0 }
+    }
+
+    pub fn is_frame_capturing(&self) -> bool {
+        unsafe { (self.api().is_frame_capturing)() != 0 }
+    }
+
+    /// Number of captures taken so far.
+    pub fn capture_count(&self) -> u32 {
+        unsafe { (self.api().get_num_captures)() }
+    }
+
+    /// Whether the RenderDoc UI is attached and receives the captures.
+    pub fn is_ui_connected(&self) -> bool {
+        unsafe { (self.api().is_target_control_connected)() != 0 }
+    }
+
+    /// Path prefix of capture files, e.g. `captures/glitch` produces
+    /// `captures/glitch_frame123.rdc`.
+    pub fn set_capture_path(&self, path_template: &str) {
+        let Ok(path_template) = std::ffi::CString::new(path_template) else {
+            return;
+        };
+        unsafe { (self.api().set_capture_file_path_template)(path_template.as_ptr()) }
+    }
+}
@@ -0,0 +1,16 @@
+#version 460
+
+layout(push_constant) uniform Params {
+    mat4 view_projection;
+} params;
+
+layout(location = 0) in vec3 position;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main()
+{
+    out_color = color;
+    gl_Position = params.view_projection * vec4(position, 1.0);
+}
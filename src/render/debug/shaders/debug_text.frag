@@ -0,0 +1,28 @@
+#version 460
+
+// Rows of the 5x7 glyphs from the space character on, 8 bytes per glyph
+// with the leftmost pixel in bit 4.
+layout(set = 0, binding = 0) readonly buffer Font {
+    uint rows[];
+} font;
+
+// Texel of the font strip, the glyphs side by side. Negative for solid
+// rectangles.
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main()
+{
+    if (uv.x >= 0.0) {
+        uint glyph = uint(uv.x) / 5;
+        uint x = uint(uv.x) % 5;
+        uint y = uint(uv.y);
+        uint row = (font.rows[glyph * 2 + y / 4] >> ((y % 4) * 8)) & 0xff;
+        if (((row >> (4 - x)) & 1) == 0) {
+            discard;
+        }
+    }
+    out_color = vec4(color.rgb * color.a, color.a);
+}
@@ -0,0 +1,19 @@
+#version 460
+
+layout(push_constant) uniform Params {
+    vec2 extent;
+} params;
+
+layout(location = 0) in vec3 position;
+layout(location = 2) in vec2 uv;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+void main()
+{
+    out_uv = uv;
+    out_color = color;
+    gl_Position = vec4(position.xy / params.extent * 2.0 - 1.0, 0.0, 1.0);
+}
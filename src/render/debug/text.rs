@@ -0,0 +1,373 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BlendMode, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, DynamicState, GraphicsPipelineCreateInfo, MemoryLocation, PipelineLayoutCreateInfo, PushConstantRange, RasterState, ShaderCreateInfo, ShaderStages, VertexLayout, VertexSemantic};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::Vec4;
+
+const PUSH_CONSTANTS_SIZE: u32 = 8;
+/// Position, uv and color.
+const VERTEX_SIZE: usize = 36;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// Glyph cell including the spacing to the next character and line.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 2;
+
+pub struct DebugTextCreateInfo {
+    /// Glyphs and rectangles beyond this are dropped until the next flush.
+    pub max_quads: u32,
+    /// Size of a font pixel in screen pixels.
+    pub scale: u32,
+    /// `UI_LAYER_FORMAT` to draw into a `UiCompositor`.
+    pub color_format: vk::Format,
+}
+
+/// Immediate-mode text and rectangles in the UI layer for consoles and
+/// HUDs, with a built-in 5x7 pixel font covering printable ASCII. Positions
+/// are in pixels from the top left corner of the layer:
+///
+/// ```ignore
+/// text.rect([0.0, 0.0], [200.0, 20.0], [0.0, 0.0, 0.0, 0.6]);
+/// text.text([4.0, 4.0], &format!("{fps:.0} fps"), [1.0; 4]);
+/// let dirty = [previous_bounds, text.bounds()].into_iter().flatten().collect::<Vec<_>>();
+/// if ui.begin(command_list, &dirty) {
+///     text.flush(command_list, ui.extent());
+///     ui.end(command_list);
+/// }
+/// ```
+pub struct DebugText {
+    vertices: Vec<u8>,
+    max_quads: u32,
+    scale: u32,
+    bounds: Option<[f32; 4]>,
+    vertex_buffers: Vec<Option<Arc<Buffer>>>,
+    /// Kept alive for the descriptor set.
+    _font: Buffer,
+    descriptor_set: Arc<DescriptorSet>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    renderer: Arc<Renderer>,
+}
+
+impl DebugText {
+    pub fn new(renderer: Arc<Renderer>, create_info: DebugTextCreateInfo) -> Self {
+        let font = {
+            let create_info = BufferCreateInfo {
+                size: (FONT.len() * 8) as u64,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            let mut buffer = Buffer::new(renderer.clone(), create_info);
+            buffer.set_name("debug text font");
+            buffer.write(0, FONT.as_flattened());
+            buffer
+        };
+
+        let descriptor_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: vec![DescriptorSetBinding {
+                    stage: ShaderStages::Fragment,
+                    typ: BindingType::StorageBuffer,
+                    binding: 0,
+                }],
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_layout.clone());
+        descriptor_set.write_storage_buffer(0, &font);
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_layout],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: PUSH_CONSTANTS_SIZE,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/debug_text_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/debug_text_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: VertexLayout::interleaved(&[VertexSemantic::Position, VertexSemantic::Uv, VertexSemantic::Color]),
+                color_formats: vec![create_info.color_format],
+                depth_format: vk::Format::UNDEFINED,
+                extent: vk::Extent2D::default(),
+                raster: RasterState {
+                    cull_mode: vk::CullModeFlags::NONE,
+                    blend: BlendMode::Alpha,
+                    depth_test: false,
+                    depth_write: false,
+                    dynamic: DynamicState::Viewport | DynamicState::Scissor,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            vertices: Vec::new(),
+            max_quads: create_info.max_quads,
+            scale: create_info.scale.max(1),
+            bounds: None,
+            vertex_buffers: vec![None; FRAME_OVERLAP],
+            _font: font,
+            descriptor_set,
+            pipeline,
+            pipeline_layout,
+            renderer,
+        }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale.max(1);
+    }
+
+    /// Advance from one character to the next, the font is monospaced.
+    pub fn char_width(&self) -> f32 {
+        (CELL_WIDTH * self.scale) as f32
+    }
+
+    pub fn line_height(&self) -> f32 {
+        (CELL_HEIGHT * self.scale) as f32
+    }
+
+    /// Width and height `text` takes up.
+    pub fn measure(&self, text: &str) -> [f32; 2] {
+        let columns = text.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        let lines = text.lines().count().max(1);
+        [columns as f32 * self.char_width(), lines as f32 * self.line_height()]
+    }
+
+    /// Glyphs and rectangles accumulated since the last flush.
+    pub fn quad_count(&self) -> u32 {
+        (self.vertices.len() / (6 * VERTEX_SIZE)) as u32
+    }
+
+    /// Pixel rectangle covering everything accumulated since the last
+    /// flush, the region of the UI layer `flush` draws to.
+    pub fn bounds(&self) -> Option<vk::Rect2D> {
+        self.bounds.map(|[x0, y0, x1, y1]| {
+            let (x0, y0) = (x0.floor().max(0.0), y0.floor().max(0.0));
+            vk::Rect2D {
+                offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+                extent: vk::Extent2D { width: (x1.ceil() - x0).max(0.0) as u32, height: (y1.ceil() - y0).max(0.0) as u32 },
+            }
+        })
+    }
+
+    /// Draws `text` with its top left corner at `position`. Lines are
+    /// broken at `\n`, characters outside printable ASCII show as `?`.
+    pub fn text(&mut self, position: [f32; 2], text: &str, color: Vec4) {
+        let [mut x, mut y] = position;
+        let size = [(GLYPH_WIDTH * self.scale) as f32, (GLYPH_HEIGHT * self.scale) as f32];
+        for c in text.chars() {
+            if c == '\n' {
+                x = position[0];
+                y += self.line_height();
+                continue;
+            }
+            let glyph = if (' '..='~').contains(&c) { c as u32 - ' ' as u32 } else { '?' as u32 - ' ' as u32 };
+            if c != ' ' {
+                let u = (glyph * GLYPH_WIDTH) as f32;
+                self.quad([x, y], [x + size[0], y + size[1]], [u, 0.0], [u + GLYPH_WIDTH as f32, GLYPH_HEIGHT as f32], color);
+            }
+            x += self.char_width();
+        }
+    }
+
+    /// Filled rectangle between the `min` and `max` corners.
+    pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: Vec4) {
+        self.quad(min, max, [-1.0; 2], [-1.0; 2], color);
+    }
+
+    fn quad(&mut self, min: [f32; 2], max: [f32; 2], uv_min: [f32; 2], uv_max: [f32; 2], color: Vec4) {
+        if self.quad_count() >= self.max_quads {
+            return;
+        }
+        let bounds = self.bounds.get_or_insert([min[0], min[1], max[0], max[1]]);
+        *bounds = [bounds[0].min(min[0]), bounds[1].min(min[1]), bounds[2].max(max[0]), bounds[3].max(max[1])];
+
+        // Two triangles, corners indexed by bits: 1 is +x and 2 is +y.
+        for corner in [0, 1, 2, 2, 1, 3] {
+            let x = if corner & 1 == 0 { 0 } else { 1 };
+            let y = if corner & 2 == 0 { 0 } else { 1 };
+            let position = [[min[0], max[0]][x], [min[1], max[1]][y], 0.0];
+            let uv = [[uv_min[0], uv_max[0]][x], [uv_min[1], uv_max[1]][y]];
+            for v in position.iter().chain(&uv).chain(&color) {
+                self.vertices.extend_from_slice(&v.to_ne_bytes());
+            }
+        }
+    }
+
+    fn upload(&mut self) -> Arc<Buffer> {
+        let slot = &mut self.vertex_buffers[self.renderer.current_frame()];
+
+        // Reuse the buffer when the command list already released it.
+        let reusable = slot.as_mut()
+            .and_then(Arc::get_mut)
+            .is_some_and(|b| b.size() >= self.vertices.len() as u64);
+        if !reusable {
+            let create_info = BufferCreateInfo {
+                size: (self.vertices.len() as u64).next_power_of_two(),
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            *slot = Some(Arc::new(Buffer::new(self.renderer.clone(), create_info)));
+        }
+
+        let buffer = slot.as_mut().unwrap();
+        Arc::get_mut(buffer).unwrap().write(0, &self.vertices);
+        buffer.clone()
+    }
+
+    /// Draws and clears the accumulated text. Must be called between
+    /// `UiCompositor::begin` and `end`, `extent` is the layer's.
+    pub fn flush(&mut self, command_list: &mut CommandList, extent: vk::Extent2D) {
+        self.bounds = None;
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let vertex_buffer = self.upload();
+        let vertex_count = (self.vertices.len() / VERTEX_SIZE) as u32;
+
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&(extent.width as f32).to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&(extent.height as f32).to_ne_bytes());
+
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, self.descriptor_set.clone());
+        command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &push_constants);
+        command_list.bind_vertex_buffers(0, &[vertex_buffer]);
+        command_list.draw(vertex_count, 1, 0, 0);
+
+        self.vertices.clear();
+    }
+}
+
+/// Rows of the glyphs from `' '` to `'~'`, top to bottom with the leftmost
+/// pixel in bit 4. The eighth row pads each glyph to two `uint`s.
+const FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04, 0x00], // !
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a, 0x00], // #
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04, 0x00], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03, 0x00], // %
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d, 0x00], // &
+    [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02, 0x00], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08, 0x00], // )
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08, 0x00], // ,
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00, 0x00], // /
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e, 0x00], // 0
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00], // 1
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f, 0x00], // 2
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e, 0x00], // 3
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02, 0x00], // 4
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e, 0x00], // 5
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e, 0x00], // 6
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08, 0x00], // 7
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e, 0x00], // 8
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c, 0x00], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08, 0x00], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02, 0x00], // <
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08, 0x00], // >
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00], // ?
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e, 0x00], // @
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11, 0x00], // A
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e, 0x00], // B
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e, 0x00], // C
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c, 0x00], // D
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f, 0x00], // E
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10, 0x00], // F
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f, 0x00], // G
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11, 0x00], // H
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c, 0x00], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11, 0x00], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f, 0x00], // L
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11, 0x00], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x00], // N
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e, 0x00], // O
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10, 0x00], // P
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d, 0x00], // Q
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11, 0x00], // R
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e, 0x00], // S
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e, 0x00], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04, 0x00], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a, 0x00], // W
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11, 0x00], // X
+    [0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x04, 0x00], // Y
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f, 0x00], // Z
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e, 0x00], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00, 0x00], // \
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e, 0x00], // ]
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f, 0x00], // _
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f, 0x00], // a
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e, 0x00], // b
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e, 0x00], // c
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f, 0x00], // d
+    [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e, 0x00], // e
+    [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08, 0x00], // f
+    [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e, 0x00], // g
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00], // h
+    [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e, 0x00], // i
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0c, 0x00], // j
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12, 0x00], // k
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e, 0x00], // l
+    [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11, 0x00], // m
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11, 0x00], // n
+    [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e, 0x00], // o
+    [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10, 0x00], // p
+    [0x00, 0x00, 0x0d, 0x13, 0x0f, 0x01, 0x01, 0x00], // q
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10, 0x00], // r
+    [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e, 0x00], // s
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06, 0x00], // t
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d, 0x00], // u
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04, 0x00], // v
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a, 0x00], // w
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x00], // x
+    [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e, 0x00], // y
+    [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f, 0x00], // z
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02, 0x00], // {
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x00], // |
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08, 0x00], // }
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00, 0x00], // ~
+];
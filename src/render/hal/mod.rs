@@ -2,16 +2,28 @@ use std::ffi::CStr;
 use std::fmt;
 use std::fmt::{Debug, Display};
 use std::sync::Arc;
+use std::time::Duration;
 
+use ash::vk;
+
+use crate::render::hal::vulkan::buffer::Buffer;
 use crate::render::hal::vulkan::descriptor_set::DescriptorSetLayout;
+use crate::render::hal::vulkan::image::Texture;
 use crate::render::hal::vulkan::pipeline::PipelineLayout;
 use crate::render::hal::vulkan::shader::Shader;
 
 pub mod vulkan;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Error {
-    Backend(String)
+    Backend(String),
+    Asset(String),
+    /// The GPU crashed or was reset. Every object created from the renderer
+    /// is unusable, see `Renderer::device_lost_report` for diagnostics.
+    DeviceLost,
+    /// A fence wait or swapchain acquire didn't complete in time, see
+    /// `RendererCreateInfo::fence_timeout`.
+    Timeout,
 }
 
 impl Display for Error {
@@ -20,6 +32,15 @@ impl Display for Error {
             Error::Backend(msg) => {
                 write!(f, "{msg}")
             }
+            Error::Asset(msg) => {
+                write!(f, "Asset error: {msg}")
+            }
+            Error::DeviceLost => {
+                write!(f, "Device lost")
+            }
+            Error::Timeout => {
+                write!(f, "Timed out waiting for the GPU")
+            }
         }
     }
 }
@@ -28,28 +49,555 @@ impl std::error::Error for Error {}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub struct RendererCreateInfo {}
+pub struct RendererCreateInfo {
+    /// Requested number of swapchain images, clamped to what the surface
+    /// supports. See `Renderer::swapchain_image_count` for the actual value.
+    pub swapchain_images: u32,
+    pub prefer_hdr: bool,
+    /// Falls back to a supported mode, see `Renderer::present_mode`. Can be
+    /// changed later with `Renderer::set_present_mode`.
+    pub present_mode: PresentMode,
+    /// Record name, size and creation backtrace of every texture and buffer
+    /// for `Renderer::dump_live_resources`. Capturing backtraces is slow.
+    pub track_resources: bool,
+    /// Enable `VK_NV_device_diagnostic_checkpoints`, or `VK_AMD_buffer_marker`
+    /// without it, when available, so the last checkpoints reached by the
+    /// GPU are reported on device loss.
+    pub gpu_crash_diagnostics: bool,
+    /// Threads compiling pipelines created with `new_async`. With 0 the
+    /// pipelines are compiled on the calling thread.
+    pub pipeline_compile_threads: usize,
+    /// Contents of a previous `Renderer::pipeline_cache_data`, used to warm
+    /// up the pipeline cache. Ignored by the driver if it doesn't match.
+    pub pipeline_cache_data: Vec<u8>,
+    /// Falls back to `DescriptorBackend::Pool` when the requested backend
+    /// isn't supported, see `Renderer::descriptor_backend`.
+    pub descriptor_backend: DescriptorBackend,
+    /// Internal render resolution relative to the swapchain, e.g. 0.5 to
+    /// render at half resolution and upscale, or 2.0 to supersample. See
+    /// `Renderer::render_extent` and `Renderer::set_render_scale`.
+    pub render_scale: f32,
+    /// Enable the Khronos validation layer. Reports API misuse at a large
+    /// CPU cost, see `Renderer::validation_error_count`.
+    pub validation_layers: bool,
+    /// With the `renderdoc` feature and RenderDoc attached, captures the
+    /// frame following every frame that raised validation errors.
+    pub capture_on_validation_error: bool,
+    /// Highest Vulkan version to use. Devices without it fall back to a
+    /// lower tier, see `Renderer::api_version`.
+    pub max_api_version: ApiVersion,
+    /// Check patoka-level invariants, such as begin/end pairing, bound
+    /// pipelines, set layouts and texture usage flags, and panic with an
+    /// explanation on misuse. Cheap, but not free.
+    pub validate_usage: bool,
+    /// Index into `Renderer::enumerate_adapters`, or
+    /// `RenderInstance::enumerate_adapters` of a shared instance. With
+    /// `None` the first device of the highest supported tier is used.
+    pub adapter: Option<usize>,
+    /// How long `Fence::wait` waits, `None` waits forever. Capture tools
+    /// and debuggers pausing the GPU need long or infinite timeouts.
+    pub fence_timeout: Option<Duration>,
+    /// How long `Renderer::start_frame` waits for a swapchain image, `None`
+    /// waits forever.
+    pub acquire_timeout: Option<Duration>,
+    /// Size of the memory blocks the allocator takes from large heaps, 0
+    /// for the VMA default of 256 MiB. Textures and buffers created with
+    /// `new_in` come from their `MemoryPool` instead.
+    pub memory_block_size: u64,
+}
+
+impl Default for RendererCreateInfo {
+    fn default() -> Self {
+        Self {
+            swapchain_images: 3,
+            prefer_hdr: false,
+            present_mode: PresentMode::Fifo,
+            track_resources: false,
+            gpu_crash_diagnostics: false,
+            pipeline_compile_threads: 2,
+            pipeline_cache_data: Vec::new(),
+            descriptor_backend: DescriptorBackend::Pool,
+            render_scale: 1.0,
+            validation_layers: true,
+            capture_on_validation_error: false,
+            max_api_version: ApiVersion::Vulkan13,
+            validate_usage: false,
+            adapter: None,
+            fence_timeout: Some(Duration::from_secs(1)),
+            acquire_timeout: Some(Duration::from_secs(1)),
+            memory_block_size: 0,
+        }
+    }
+}
+
+/// Feature tiers the renderer runs on.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ApiVersion {
+    /// Vulkan 1.2 with dynamic rendering, synchronization2 and
+    /// copy_commands2 as extensions, for older drivers and MoltenVK.
+    Vulkan12,
+    Vulkan13,
+}
+
+impl ApiVersion {
+    pub(crate) fn to_vk(self) -> u32 {
+        match self {
+            ApiVersion::Vulkan12 => vk::API_VERSION_1_2,
+            ApiVersion::Vulkan13 => vk::API_VERSION_1_3,
+        }
+    }
+}
+
+/// How finished frames are queued for display.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PresentMode {
+    /// Vsync, presentation waits for the vertical blank. Always supported.
+    Fifo,
+    /// Vsync, but a late frame is shown right away and may tear.
+    FifoRelaxed,
+    /// No tearing without waiting, a newer frame replaces the queued one.
+    Mailbox,
+    /// No vsync, frames are shown right away and may tear.
+    Immediate,
+}
+
+impl PresentMode {
+    pub(crate) fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+}
+
+/// A physical device as reported by `Renderer::enumerate_adapters`.
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    /// `None` when the device lacks the features of the lowest tier.
+    pub api_version: Option<ApiVersion>,
+}
+
+bitflags::bitflags! {
+    /// Optional device functionality, see `Renderer::capabilities`.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct Capabilities: u32 {
+        const PushDescriptor = 0x1;
+        const DescriptorBuffer = 0x2;
+        const MemoryBudget = 0x4;
+        const CrashDiagnostics = 0x8;
+        const FramebufferCapture = 0x10;
+        /// Non-conformant implementation layered on another API, such as
+        /// MoltenVK, with the restrictions of `VK_KHR_portability_subset`.
+        const Portability = 0x20;
+        /// Geometry shader stage, `GraphicsPipelineCreateInfo::geometry_shader`.
+        const GeometryShader = 0x40;
+        /// Tessellation shader stages, `GraphicsPipelineCreateInfo::tessellation`.
+        const TessellationShader = 0x80;
+        /// Acceleration structures and ray queries from any shader stage,
+        /// see `AccelerationStructure`. Never set without the `ray-tracing`
+        /// feature.
+        const RayQuery = 0x100;
+        /// A compute queue separate from the graphics queue, see
+        /// `QueueType::Compute`.
+        const AsyncCompute = 0x200;
+        /// Memory and semaphores shared with other APIs and processes, see
+        /// `Texture::new_exportable` and `Semaphore::new_exportable`.
+        const ExternalMemory = 0x400;
+        /// Draws broadcast to several layers of the targets in one pass, see
+        /// `CommandList::begin_rendering_views`.
+        const Multiview = 0x800;
+    }
+}
+
+bitflags::bitflags! {
+    /// Classes of `GL_KHR_shader_subgroup_*` operations.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct SubgroupOperations: u32 {
+        const Basic = 0x1;
+        const Vote = 0x2;
+        const Arithmetic = 0x4;
+        const Ballot = 0x8;
+        const Shuffle = 0x10;
+        const ShuffleRelative = 0x20;
+        const Clustered = 0x40;
+        const Quad = 0x80;
+    }
+}
+
+/// Properties of the device shaders can be specialized for, see
+/// `Renderer::device_capabilities`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeviceCapabilities {
+    /// Invocations per subgroup.
+    pub subgroup_size: u32,
+    /// Stages subgroup operations are available in.
+    pub subgroup_stages: ShaderStages,
+    pub subgroup_operations: SubgroupOperations,
+    /// Views rendered at most by a multiview pass, at least 6 with
+    /// `Capabilities::Multiview`.
+    pub max_multiview_views: u32,
+}
+
+/// Commonly needed device limits, see `Renderer::limits`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Limits {
+    /// Largest width and height of a 2D texture.
+    pub max_texture_size: u32,
+    pub max_texture_array_layers: u32,
+    pub max_bound_descriptor_sets: u32,
+    /// Offsets of uniform buffer bindings are multiples of this.
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub min_storage_buffer_offset_alignment: u64,
+    pub max_uniform_buffer_range: u32,
+    /// In bytes, at least 128.
+    pub max_push_constants_size: u32,
+    pub max_color_attachments: u32,
+    pub max_compute_workgroup_count: [u32; 3],
+    pub max_compute_workgroup_size: [u32; 3],
+    pub max_compute_workgroup_invocations: u32,
+    /// Nanoseconds per timestamp query tick.
+    pub timestamp_period: f32,
+}
+
+/// Where descriptor sets live.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DescriptorBackend {
+    /// Sets allocated from a descriptor pool.
+    Pool,
+    /// Sets written into a mapped buffer and bound by offset with
+    /// `VK_EXT_descriptor_buffer`, cheaper to bind with many materials.
+    Buffer,
+}
+
+/// Queue a `CommandList` is submitted to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum QueueType {
+    #[default]
+    Graphics,
+    /// Dedicated compute queue running alongside the graphics queue, see
+    /// `Capabilities::AsyncCompute`. The graphics queue stands in without
+    /// one. Lists for it only record compute, copy and acceleration
+    /// structure commands.
+    Compute,
+}
+
+pub struct CommandListCreateInfo {
+    pub queue: QueueType,
+}
+
+pub struct TextureCreateInfo {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    /// Values above 1 create a 2D array texture with a view per layer.
+    pub array_layers: u32,
+    pub mip_levels: u32,
+    pub kind: TextureKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureKind {
+    /// 2D texture or 2D array.
+    D2,
+    /// Every 6 layers form a cubemap face set, the texture is sampled as a
+    /// cube (array) and written per mip level as a 2D array.
+    Cube,
+    /// Volume texture spanning `extent.depth` slices, with a single layer.
+    D3,
+}
+
+/// Formats with an `*_SRGB` twin, as `(UNORM, SRGB)`.
+const SRGB_FORMATS: [(vk::Format, vk::Format); 14] = [
+    (vk::Format::R8_UNORM, vk::Format::R8_SRGB),
+    (vk::Format::R8G8_UNORM, vk::Format::R8G8_SRGB),
+    (vk::Format::R8G8B8_UNORM, vk::Format::R8G8B8_SRGB),
+    (vk::Format::B8G8R8_UNORM, vk::Format::B8G8R8_SRGB),
+    (vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB),
+    (vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB),
+    (vk::Format::A8B8G8R8_UNORM_PACK32, vk::Format::A8B8G8R8_SRGB_PACK32),
+    (vk::Format::BC1_RGB_UNORM_BLOCK, vk::Format::BC1_RGB_SRGB_BLOCK),
+    (vk::Format::BC1_RGBA_UNORM_BLOCK, vk::Format::BC1_RGBA_SRGB_BLOCK),
+    (vk::Format::BC2_UNORM_BLOCK, vk::Format::BC2_SRGB_BLOCK),
+    (vk::Format::BC3_UNORM_BLOCK, vk::Format::BC3_SRGB_BLOCK),
+    (vk::Format::BC7_UNORM_BLOCK, vk::Format::BC7_SRGB_BLOCK),
+    (vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK, vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK),
+    (vk::Format::ASTC_4X4_UNORM_BLOCK, vk::Format::ASTC_4X4_SRGB_BLOCK),
+];
+
+/// How the color channels of a texture are stored. Color authored for
+/// the screen, like albedo maps and UI images, is sRGB encoded and has to
+/// be decoded before lighting, which `*_SRGB` formats do in the sampler.
+/// Data such as normals, roughness or HDR color is linear.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorEncoding {
+    Linear,
+    Srgb,
+}
+
+impl ColorEncoding {
+    pub fn of_format(format: vk::Format) -> Self {
+        if SRGB_FORMATS.iter().any(|&(_, srgb)| srgb == format) {
+            ColorEncoding::Srgb
+        } else {
+            ColorEncoding::Linear
+        }
+    }
+
+    /// The variant of `format` decoding with this encoding, `format` itself
+    /// when there's none, e.g. for float formats.
+    pub fn apply(self, format: vk::Format) -> vk::Format {
+        let pair = SRGB_FORMATS.iter().find(|&&(unorm, srgb)| unorm == format || srgb == format);
+        match (self, pair) {
+            (ColorEncoding::Linear, Some(&(unorm, _))) => unorm,
+            (ColorEncoding::Srgb, Some(&(_, srgb))) => srgb,
+            (_, None) => format,
+        }
+    }
+
+    /// Whether `format` has an `*_SRGB` twin, which also means its 8 bit
+    /// texels most likely hold sRGB encoded color when they are color.
+    pub fn has_srgb_variant(format: vk::Format) -> bool {
+        SRGB_FORMATS.iter().any(|&(unorm, srgb)| unorm == format || srgb == format)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryLocation {
+    GpuOnly,
+    CpuToGpu,
+    GpuToCpu,
+}
+
+/// What a `MemoryPool` holds, used to pick its memory type.
+#[derive(Clone, Copy, Debug)]
+pub enum PoolResources {
+    Buffers { usage: vk::BufferUsageFlags, location: MemoryLocation },
+    /// Device local optimal tiling textures.
+    Textures { format: vk::Format, usage: vk::ImageUsageFlags },
+}
 
-pub struct CommandListCreateInfo {}
+/// A separate set of memory blocks for textures or buffers created with
+/// `new_in`, e.g. one for small and one for large allocations, so that
+/// streaming doesn't fragment the default pools.
+pub struct MemoryPoolCreateInfo {
+    pub resources: PoolResources,
+    /// Size of every block, 0 lets the allocator choose and also allows
+    /// dedicated allocations for resources larger than a block.
+    pub block_size: u64,
+    /// Blocks allocated up front and kept while the pool lives.
+    pub min_block_count: usize,
+    /// 0 for no limit. Allocations beyond the limit fail.
+    pub max_block_count: usize,
+    /// Place allocations one after another in a ring instead of reusing
+    /// freed space, for per-frame transient data that's freed in the order
+    /// it was allocated.
+    pub linear: bool,
+}
+
+pub struct BufferCreateInfo {
+    pub size: u64,
+    pub usage: vk::BufferUsageFlags,
+    pub location: MemoryLocation,
+}
+
+pub struct StreamingBufferCreateInfo {
+    /// Bytes available to every frame, the buffer holds `FRAME_OVERLAP`
+    /// times as much.
+    pub frame_size: u64,
+    pub usage: vk::BufferUsageFlags,
+}
+
+pub struct ReadbackBufferCreateInfo {
+    /// Largest copy read back at once.
+    pub size: u64,
+}
+
+/// Formatted view of a buffer range, for texel buffer bindings. `offset`
+/// must be a multiple of `minTexelBufferOffsetAlignment`.
+pub struct BufferViewCreateInfo {
+    pub buffer: Arc<Buffer>,
+    pub format: vk::Format,
+    pub offset: u64,
+    /// In bytes, `vk::WHOLE_SIZE` for the rest of the buffer.
+    pub range: u64,
+}
+
+/// How `CommandList::begin_rendering_attachments` initializes an
+/// attachment, all attachments are stored at the end.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LoadOp<T> {
+    /// Keeps the previous content.
+    Load,
+    Clear(T),
+    /// Leaves the content undefined, for targets the pass fully overwrites.
+    DontCare,
+}
+
+/// Color target of a render pass, in `GENERAL` layout.
+#[derive(Clone, Copy)]
+pub struct ColorAttachment<'a> {
+    pub texture: &'a Texture,
+    pub load: LoadOp<[f32; 4]>,
+}
+
+/// Depth target of a render pass, in `GENERAL` layout. Cleared to 0 for
+/// the far plane with reverse-Z.
+#[derive(Clone, Copy)]
+pub struct DepthAttachment<'a> {
+    pub texture: &'a Texture,
+    pub load: LoadOp<f32>,
+}
+
+#[derive(Clone, Copy)]
+pub struct BufferCopy {
+    pub src_offset: u64,
+    pub dst_offset: u64,
+    pub size: u64,
+}
+
+/// Region of a buffer <-> texture copy.
+#[derive(Clone, Copy)]
+pub struct BufferTextureCopy {
+    pub buffer_offset: u64,
+    /// Row pitch of the buffer data in texels, 0 means tightly packed.
+    pub buffer_row_length: u32,
+    /// Rows per layer of the buffer data, 0 means tightly packed.
+    pub buffer_image_height: u32,
+    pub mip_level: u32,
+    pub base_array_layer: u32,
+    pub layer_count: u32,
+    pub texture_offset: vk::Offset3D,
+    pub texture_extent: vk::Extent3D,
+}
+
+impl BufferTextureCopy {
+    /// Tightly packed copy of every layer of the top mip level.
+    pub fn whole(texture: &Texture) -> Self {
+        Self {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: texture.array_layers(),
+            texture_offset: vk::Offset3D::default(),
+            texture_extent: texture.extent(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+    pub size: u64,
+    pub device_local: bool,
+    /// Estimated memory available to the process, from `VK_EXT_memory_budget`
+    /// when supported or a fraction of the heap size otherwise.
+    pub budget: u64,
+    /// Estimated memory used by the process, including memory allocated
+    /// outside of the allocator.
+    pub usage: u64,
+    pub block_bytes: u64,
+    pub allocation_bytes: u64,
+    pub block_count: u32,
+    pub allocation_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    pub heaps: Vec<HeapStats>,
+    pub textures: ResourceStats,
+    pub buffers: ResourceStats,
+    pub total_allocation_count: u32,
+    pub total_allocation_bytes: u64,
+}
+
+/// Work recorded between two presents, see `Renderer::frame_stats`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    /// Wall time between the presents around the frame.
+    pub cpu_time: Duration,
+    /// Direct draws, plus the commands of indirect ones.
+    pub draw_calls: u32,
+    pub dispatches: u32,
+    /// Triangles of direct draws with list, strip or fan topologies. The
+    /// counts of indirect draws are only known to the GPU.
+    pub triangles: u64,
+    /// Descriptor sets allocated, `DescriptorSet::new` allocates one per
+    /// frame slot.
+    pub descriptor_set_allocations: u32,
+}
 
 pub struct SemaphoreCreateInfo {}
 
 pub struct FenceCreateInfo {}
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressMode {
+    Repeat,
+    ClampToEdge,
+    /// Reads outside the texture return 0, the far plane with reverse-Z.
+    ClampToBorder,
+}
+
+pub struct SamplerCreateInfo {
+    pub filter: Filter,
+    pub address_mode: AddressMode,
+    /// Makes it a comparison sampler for `sampler2DShadow` and friends.
+    pub compare: Option<vk::CompareOp>,
+}
+
+/// How a texture is fitted into a destination of a different size.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScalingMode {
+    /// Stretch to cover the whole destination.
+    Stretch,
+    /// Preserve the aspect ratio, filling the uncovered area with black bars.
+    Letterbox,
+}
+
 #[derive(Clone, Copy)]
 pub enum BindingType {
     UniformBuffer,
     StorageBuffer,
     Texture,
     Sampler,
+    /// Texture read through a sampler, see `DescriptorSet::write_sampled_texture`.
+    SampledTexture,
+    /// Read-only formatted buffer, `textureBuffer` / `samplerBuffer` in GLSL.
+    /// See `BufferView`.
+    UniformTexelBuffer,
+    /// Formatted buffer with image loads and stores, `imageBuffer` in GLSL.
+    StorageTexelBuffer,
+    /// Top level `AccelerationStructure`, `accelerationStructureEXT` in GLSL.
+    AccelerationStructure,
 }
 
 bitflags::bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
     pub struct ShaderStages: u8 {
         const Vertex = 0x1;
         const Fragment = 0x2;
         const Compute = 0x4;
+        const Geometry = 0x8;
+        const TessellationControl = 0x10;
+        const TessellationEvaluation = 0x20;
     }
 }
 pub struct DescriptorSetBinding {
@@ -59,18 +607,258 @@ pub struct DescriptorSetBinding {
 }
 pub struct DescriptorSetLayoutCreateInfo {
     pub bindings: Vec<DescriptorSetBinding>,
+    /// The set is written with `CommandList::push_descriptor_set` instead
+    /// of allocating `DescriptorSet`s.
+    pub push_descriptor: bool,
+}
+
+pub struct ShaderCreateInfo<'a> {
+    pub code: &'a [u32],
 }
 
-pub struct ShaderCreateInfo {
-    pub code: &'static [u32],
+pub struct PushConstantRange {
+    pub stage: ShaderStages,
+    pub offset: u32,
+    pub size: u32,
 }
 
 pub struct PipelineLayoutCreateInfo {
     pub sets: Vec<Arc<DescriptorSetLayout>>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
 }
 
 pub struct ComputePipelineCreateInfo {
     pub shader: Arc<Shader>,
     pub pipeline_layout: Arc<PipelineLayout>,
     pub entrypoint: &'static CStr,
+    /// Subgroup operations the shader uses, creating the pipeline panics
+    /// when the device doesn't support them in compute shaders.
+    pub subgroup_operations: SubgroupOperations,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum BlendMode {
+    Opaque,
+    /// Premultiplied alpha.
+    Alpha,
+    Additive,
+}
+
+bitflags::bitflags! {
+    /// Pipeline state set on the command list instead of baked into the
+    /// pipeline. With `Viewport` and `Scissor` the pipeline's `extent` is
+    /// ignored and the pipeline survives window resizes.
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub struct DynamicState: u8 {
+        /// Set with `CommandList::set_viewport(s)`.
+        const Viewport = 0x1;
+        /// Set with `CommandList::set_scissor(s)`.
+        const Scissor = 0x2;
+        /// Set with `CommandList::set_depth_bias`.
+        const DepthBias = 0x4;
+        /// Set with `CommandList::set_blend_constants`.
+        const BlendConstants = 0x8;
+    }
+}
+
+/// Fixed function state of a graphics pipeline.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RasterState {
+    pub topology: vk::PrimitiveTopology,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub blend: BlendMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// Has to be set before drawing with the pipeline.
+    pub dynamic: DynamicState,
+}
+
+impl Default for RasterState {
+    fn default() -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend: BlendMode::Opaque,
+            depth_test: true,
+            depth_write: true,
+            dynamic: DynamicState::empty(),
+        }
+    }
+}
+
+/// Standard vertex attributes. The semantic decides the shader input
+/// location, so shaders don't depend on how the streams are laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum VertexSemantic {
+    Position,
+    Normal,
+    Uv,
+    Tangent,
+    Color,
+    /// Indices of the four joints influencing a skinned vertex.
+    Joints,
+    Weights,
+    /// Per instance model matrix, occupies four consecutive locations.
+    InstanceTransform,
+}
+
+impl VertexSemantic {
+    pub fn location(self) -> u32 {
+        self as u32
+    }
+
+    /// Format of each location the attribute occupies.
+    pub fn format(self) -> vk::Format {
+        match self {
+            VertexSemantic::Position | VertexSemantic::Normal => vk::Format::R32G32B32_SFLOAT,
+            VertexSemantic::Uv => vk::Format::R32G32_SFLOAT,
+            VertexSemantic::Tangent | VertexSemantic::Color | VertexSemantic::Weights => vk::Format::R32G32B32A32_SFLOAT,
+            VertexSemantic::Joints => vk::Format::R32G32B32A32_UINT,
+            VertexSemantic::InstanceTransform => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    /// Number of 32 bit components.
+    pub fn components(self) -> u32 {
+        match self {
+            VertexSemantic::Position | VertexSemantic::Normal => 3,
+            VertexSemantic::Uv => 2,
+            VertexSemantic::Tangent | VertexSemantic::Color | VertexSemantic::Joints | VertexSemantic::Weights => 4,
+            VertexSemantic::InstanceTransform => 16,
+        }
+    }
+
+    pub fn location_count(self) -> u32 {
+        match self {
+            VertexSemantic::InstanceTransform => 4,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct VertexAttribute {
+    pub semantic: VertexSemantic,
+    /// Vertex buffer the attribute is read from.
+    pub binding: u32,
+    pub offset: u32,
+}
+
+/// Location of an attribute read with vertex pulling, in 32 bit words of
+/// its stream bound as `uint data[]`. Component `c` of vertex `v` is at
+/// `data[v * stride + offset + c]`. Laid out to be passed to the shader in
+/// a push constant or uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PulledAttribute {
+    /// Vertex buffer, `Mesh::vertex_buffers()[stream]`.
+    pub stream: u32,
+    pub offset: u32,
+    pub stride: u32,
+    pub components: u32,
+}
+
+/// How vertex attributes are spread over vertex buffers.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+    /// Stride of each vertex buffer binding.
+    pub strides: Vec<u32>,
+    /// Bindings advanced per instance rather than per vertex.
+    pub instance_bindings: Vec<u32>,
+}
+
+impl VertexLayout {
+    /// All attributes packed into a single vertex buffer.
+    pub fn interleaved(semantics: &[VertexSemantic]) -> Self {
+        let mut attributes = Vec::with_capacity(semantics.len());
+        let mut offset = 0;
+        for &semantic in semantics {
+            attributes.push(VertexAttribute { semantic, binding: 0, offset });
+            offset += semantic.components() * 4;
+        }
+        Self { attributes, strides: vec![offset], instance_bindings: Vec::new() }
+    }
+
+    /// One vertex buffer per attribute.
+    pub fn planar(semantics: &[VertexSemantic]) -> Self {
+        let attributes = semantics.iter().enumerate()
+            .map(|(binding, &semantic)| VertexAttribute { semantic, binding: binding as u32, offset: 0 })
+            .collect();
+        let strides = semantics.iter().map(|s| s.components() * 4).collect();
+        Self { attributes, strides, instance_bindings: Vec::new() }
+    }
+
+    /// Appends an interleaved per instance stream after the vertex streams.
+    pub fn with_instance_stream(mut self, semantics: &[VertexSemantic]) -> Self {
+        let binding = self.strides.len() as u32;
+        let mut offset = 0;
+        for &semantic in semantics {
+            self.attributes.push(VertexAttribute { semantic, binding, offset });
+            offset += semantic.components() * 4;
+        }
+        self.strides.push(offset);
+        self.instance_bindings.push(binding);
+        self
+    }
+
+    /// Number of per vertex streams, instance streams come after them.
+    pub fn vertex_stream_count(&self) -> usize {
+        self.strides.len() - self.instance_bindings.len()
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.strides.len()
+    }
+
+    pub fn attribute(&self, semantic: VertexSemantic) -> Option<&VertexAttribute> {
+        self.attributes.iter().find(|a| a.semantic == semantic)
+    }
+
+    /// Where the shader fetches `semantic` from when the streams are bound
+    /// as storage buffers rather than vertex buffers.
+    pub fn pulled_attribute(&self, semantic: VertexSemantic) -> Option<PulledAttribute> {
+        self.attribute(semantic).map(|a| PulledAttribute {
+            stream: a.binding,
+            offset: a.offset / 4,
+            stride: self.strides[a.binding as usize] / 4,
+            components: semantic.components(),
+        })
+    }
+}
+
+/// Tessellation stages of a graphics pipeline, both with entry point
+/// `main`. The pipeline draws `PATCH_LIST`s regardless of the raster
+/// state's topology.
+pub struct TessellationState {
+    pub control_shader: Arc<Shader>,
+    pub evaluation_shader: Arc<Shader>,
+    pub patch_control_points: u32,
+}
+
+/// Pipeline rendering into attachments of the given formats with dynamic
+/// rendering, `depth_format` is `UNDEFINED` when there's no depth buffer.
+pub struct GraphicsPipelineCreateInfo {
+    pub vertex_shader: Arc<Shader>,
+    /// `None` for depth only pipelines.
+    pub fragment_shader: Option<Arc<Shader>>,
+    /// Entry point `main`, needs `Capabilities::GeometryShader`.
+    pub geometry_shader: Option<Arc<Shader>>,
+    /// Needs `Capabilities::TessellationShader`.
+    pub tessellation: Option<TessellationState>,
+    pub pipeline_layout: Arc<PipelineLayout>,
+    pub vertex_entrypoint: &'static CStr,
+    pub fragment_entrypoint: &'static CStr,
+    /// Empty when vertices are generated or fetched in the shader.
+    pub vertex_layout: VertexLayout,
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: vk::Format,
+    /// Ignored with `DynamicState::Viewport` and `DynamicState::Scissor`.
+    pub extent: vk::Extent2D,
+    pub raster: RasterState,
+    /// Views the pipeline draws into, has to match the one given to
+    /// `CommandList::begin_rendering_views`. 0 outside multiview passes.
+    pub view_mask: u32,
 }
\ No newline at end of file
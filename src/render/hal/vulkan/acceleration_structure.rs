@@ -0,0 +1,490 @@
+use std::sync::Arc;
+
+use ash::{Device, Instance, vk};
+use ash::khr::{acceleration_structure, deferred_host_operations, ray_query};
+
+use crate::render::hal::{BufferCreateInfo, MemoryLocation, QueueType, Result};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::Mat4;
+
+/// Extensions needed for `Capabilities::RayQuery`.
+pub(crate) fn get_device_extensions() -> [&'static std::ffi::CStr; 3] {
+    [acceleration_structure::NAME, ray_query::NAME, deferred_host_operations::NAME]
+}
+
+pub(crate) fn is_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+    let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::default()
+        .push_next(&mut acceleration_structure_features)
+        .push_next(&mut ray_query_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+    acceleration_structure_features.acceleration_structure == vk::TRUE && ray_query_features.ray_query == vk::TRUE
+}
+
+/// Loader and limits of `VK_KHR_acceleration_structure`, present when
+/// `Capabilities::RayQuery` is.
+pub(crate) struct RayTracingSupport {
+    pub(crate) loader: acceleration_structure::Device,
+    scratch_alignment: u64,
+}
+
+impl RayTracingSupport {
+    pub(crate) fn new(instance: &Instance, device: &Device, physical_device: vk::PhysicalDevice) -> Self {
+        let mut properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+        Self {
+            loader: acceleration_structure::Device::new(instance, device),
+            scratch_alignment: properties.min_acceleration_structure_scratch_offset_alignment.max(1) as u64,
+        }
+    }
+}
+
+/// Triangles of a bottom level acceleration structure. The buffers need
+/// `ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR` and
+/// `SHADER_DEVICE_ADDRESS` usage, see `MeshBuilder::ray_tracing`.
+pub struct Triangles<'a> {
+    /// `R32G32B32_SFLOAT` positions at `vertex_offset`, every `vertex_stride`
+    /// bytes.
+    pub vertex_buffer: &'a Buffer,
+    pub vertex_offset: u64,
+    pub vertex_stride: u64,
+    pub vertex_count: u32,
+    /// `None` for non-indexed triangles.
+    pub index_buffer: Option<&'a Buffer>,
+    pub index_type: vk::IndexType,
+    /// First index and index count, or first vertex and vertex count
+    /// without indices.
+    pub first: u32,
+    pub count: u32,
+    /// Added to every index.
+    pub base_vertex: u32,
+    /// Skips any hit processing, for geometry without alpha testing.
+    pub opaque: bool,
+}
+
+/// Placement of a bottom level structure in a top level one.
+#[derive(Clone)]
+pub struct AccelerationStructureInstance {
+    pub blas: Arc<AccelerationStructure>,
+    pub transform: Mat4,
+    /// `gl_InstanceCustomIndexEXT` / `rayQueryGetIntersectionInstanceCustomIndexEXT`,
+    /// 24 bits.
+    pub custom_index: u32,
+    /// Rays only hit instances whose mask shares a bit with the ray's cull
+    /// mask.
+    pub mask: u8,
+}
+
+impl AccelerationStructureInstance {
+    pub fn new(blas: Arc<AccelerationStructure>, transform: Mat4) -> Self {
+        Self { blas, transform, custom_index: 0, mask: 0xff }
+    }
+
+    /// `VkAccelerationStructureInstanceKHR`, 64 bytes.
+    fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        // Row major 3x4.
+        for row in 0..3 {
+            for column in 0..4 {
+                let offset = (row * 4 + column) * 4;
+                bytes[offset..offset + 4].copy_from_slice(&self.transform.0[column][row].to_ne_bytes());
+            }
+        }
+        let custom_index_and_mask = (self.custom_index & 0xff_ffff) | ((self.mask as u32) << 24);
+        bytes[48..52].copy_from_slice(&custom_index_and_mask.to_ne_bytes());
+        // Shader binding table offset 0, no flags.
+        bytes[52..56].copy_from_slice(&0u32.to_ne_bytes());
+        bytes[56..64].copy_from_slice(&self.blas.address.to_ne_bytes());
+        bytes
+    }
+}
+
+/// Geometries of one structure of a batched build.
+struct BuildInput<'a> {
+    level: vk::AccelerationStructureTypeKHR,
+    geometries: Vec<vk::AccelerationStructureGeometryKHR<'a>>,
+    ranges: Vec<vk::AccelerationStructureBuildRangeInfoKHR>,
+    instances: Vec<Arc<AccelerationStructure>>,
+}
+
+/// Bottom level structure of triangles, or top level structure of
+/// instances, built on the GPU by the command list passed to its
+/// constructor. Ray queries see it once that command list executed.
+/// Structures built on the compute queue are usable from the graphics
+/// queue as well.
+pub struct AccelerationStructure {
+    pub(crate) handle: vk::AccelerationStructureKHR,
+    pub(crate) address: vk::DeviceAddress,
+    level: vk::AccelerationStructureTypeKHR,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+    size: u64,
+    update_scratch_size: u64,
+    _buffer: Buffer,
+    /// Instances of a top level structure, their bottom level structures
+    /// have to outlive it.
+    instances: Vec<Arc<AccelerationStructure>>,
+    renderer: Arc<Renderer>,
+}
+
+impl AccelerationStructure {
+    /// Builds a bottom level structure of `geometries`, one per submesh.
+    /// Panics without `Capabilities::RayQuery`.
+    pub fn bottom_level(renderer: Arc<Renderer>, command_list: &mut CommandList, geometries: &[Triangles]) -> Arc<Self> {
+        let inputs = vec![Self::triangles_input(geometries)];
+        Self::build_batch(renderer, command_list, inputs, vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE).pop().unwrap()
+    }
+
+    /// Builds a bottom level structure per entry of `structures` with a
+    /// single build command sharing one scratch buffer, cheaper than
+    /// building them one by one. Structures to be compacted later need
+    /// `ALLOW_COMPACTION` in `flags`.
+    pub fn bottom_level_batch(renderer: Arc<Renderer>, command_list: &mut CommandList, structures: &[&[Triangles]], flags: vk::BuildAccelerationStructureFlagsKHR) -> Vec<Arc<Self>> {
+        let inputs = structures.iter().map(|geometries| Self::triangles_input(geometries)).collect();
+        Self::build_batch(renderer, command_list, inputs, flags)
+    }
+
+    fn triangles_input<'a>(geometries: &[Triangles]) -> BuildInput<'a> {
+        let triangles = geometries.iter().map(|g| {
+            let index_data = g.index_buffer.map_or(0, |b| b.device_address());
+            let data = vk::AccelerationStructureGeometryTrianglesDataKHR::default()
+                .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                .vertex_data(vk::DeviceOrHostAddressConstKHR { device_address: g.vertex_buffer.device_address() + g.vertex_offset })
+                .vertex_stride(g.vertex_stride)
+                .max_vertex(g.vertex_count.saturating_sub(1))
+                .index_type(if g.index_buffer.is_some() { g.index_type } else { vk::IndexType::NONE_KHR })
+                .index_data(vk::DeviceOrHostAddressConstKHR { device_address: index_data });
+            let flags = if g.opaque { vk::GeometryFlagsKHR::OPAQUE } else { vk::GeometryFlagsKHR::empty() };
+            vk::AccelerationStructureGeometryKHR::default()
+                .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+                .geometry(vk::AccelerationStructureGeometryDataKHR { triangles: data })
+                .flags(flags)
+        }).collect::<Vec<_>>();
+
+        let ranges = geometries.iter().map(|g| {
+            let index_size = match g.index_type {
+                vk::IndexType::UINT16 => 2,
+                _ => 4,
+            };
+            match g.index_buffer {
+                Some(_) => vk::AccelerationStructureBuildRangeInfoKHR::default()
+                    .primitive_count(g.count / 3)
+                    .primitive_offset(g.first * index_size)
+                    .first_vertex(g.base_vertex),
+                None => vk::AccelerationStructureBuildRangeInfoKHR::default()
+                    .primitive_count(g.count / 3)
+                    .first_vertex(g.first),
+            }
+        }).collect::<Vec<_>>();
+
+        BuildInput { level: vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL, geometries: triangles, ranges, instances: Vec::new() }
+    }
+
+    /// Builds a top level structure of `instances`. Their bottom level
+    /// structures are kept alive with it. Panics without
+    /// `Capabilities::RayQuery`.
+    pub fn top_level(renderer: Arc<Renderer>, command_list: &mut CommandList, instances: &[AccelerationStructureInstance]) -> Arc<Self> {
+        Self::top_level_with_flags(renderer, command_list, instances, vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+    }
+
+    /// Like `top_level`, structures to be refit later need `ALLOW_UPDATE`
+    /// in `flags`.
+    pub fn top_level_with_flags(renderer: Arc<Renderer>, command_list: &mut CommandList, instances: &[AccelerationStructureInstance], flags: vk::BuildAccelerationStructureFlagsKHR) -> Arc<Self> {
+        let (instance_buffer, geometry) = Self::instance_geometry(&renderer, instances);
+        let input = BuildInput {
+            level: vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            geometries: vec![geometry],
+            ranges: vec![vk::AccelerationStructureBuildRangeInfoKHR::default().primitive_count(instances.len() as u32)],
+            instances: instances.iter().map(|i| i.blas.clone()).collect(),
+        };
+        let tlas = Self::build_batch(renderer, command_list, vec![input], flags).pop().unwrap();
+        command_list.retain(Arc::new(instance_buffer));
+        tlas
+    }
+
+    fn instance_geometry(renderer: &Arc<Renderer>, instances: &[AccelerationStructureInstance]) -> (Buffer, vk::AccelerationStructureGeometryKHR<'static>) {
+        let data = instances.iter().flat_map(|i| i.to_bytes()).collect::<Vec<_>>();
+        let mut instance_buffer = {
+            let create_info = BufferCreateInfo {
+                size: data.len().max(64) as u64,
+                usage: vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+        instance_buffer.write(0, &data);
+
+        let geometry = vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                instances: vk::AccelerationStructureGeometryInstancesDataKHR::default()
+                    .data(vk::DeviceOrHostAddressConstKHR { device_address: instance_buffer.device_address() }),
+            });
+        (instance_buffer, geometry)
+    }
+
+    fn build_batch(renderer: Arc<Renderer>, command_list: &mut CommandList, mut inputs: Vec<BuildInput>, flags: vk::BuildAccelerationStructureFlagsKHR) -> Vec<Arc<Self>> {
+        let support = renderer.ray_tracing.as_ref().expect("Acceleration structures need Capabilities::RayQuery");
+        if inputs.is_empty() {
+            return Vec::new();
+        }
+        let shared = command_list.queue() == QueueType::Compute;
+
+        let mut structures = Vec::with_capacity(inputs.len());
+        let mut scratch_offsets = Vec::with_capacity(inputs.len());
+        let mut scratch_size = 0;
+        for input in &mut inputs {
+            let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+                .ty(input.level)
+                .flags(flags)
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .geometries(&input.geometries);
+            let primitive_counts = input.ranges.iter().map(|r| r.primitive_count).collect::<Vec<_>>();
+            let mut sizes = vk::AccelerationStructureBuildSizesInfoKHR::default();
+            unsafe {
+                support.loader.get_acceleration_structure_build_sizes(vk::AccelerationStructureBuildTypeKHR::DEVICE, &build_info, &primitive_counts, &mut sizes);
+            }
+
+            let (handle, address, buffer) = Self::create(&renderer, input.level, sizes.acceleration_structure_size, shared);
+            scratch_offsets.push(scratch_size);
+            scratch_size = (scratch_size + sizes.build_scratch_size).next_multiple_of(support.scratch_alignment);
+            structures.push(Arc::new(Self {
+                handle,
+                address,
+                level: input.level,
+                flags,
+                size: sizes.acceleration_structure_size,
+                update_scratch_size: sizes.update_scratch_size,
+                _buffer: buffer,
+                instances: std::mem::take(&mut input.instances),
+                renderer: renderer.clone(),
+            }));
+        }
+
+        let scratch = Self::scratch_buffer(&renderer, scratch_size);
+        let scratch_address = scratch.device_address().next_multiple_of(support.scratch_alignment);
+
+        let build_infos = inputs.iter().zip(&structures).zip(&scratch_offsets).map(|((input, structure), offset)| {
+            vk::AccelerationStructureBuildGeometryInfoKHR::default()
+                .ty(input.level)
+                .flags(flags)
+                .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                .geometries(&input.geometries)
+                .dst_acceleration_structure(structure.handle)
+                .scratch_data(vk::DeviceOrHostAddressKHR { device_address: scratch_address + offset })
+        }).collect::<Vec<_>>();
+        let ranges = inputs.iter().map(|input| input.ranges.as_slice()).collect::<Vec<_>>();
+        unsafe {
+            support.loader.cmd_build_acceleration_structures(command_list.get_current(), &build_infos, &ranges);
+        }
+        // Later builds and ray queries read the result.
+        command_list.memory_barrier();
+        command_list.retain(Arc::new(scratch));
+
+        for structure in &structures {
+            command_list.retain(structure.clone());
+        }
+        structures
+    }
+
+    fn create(renderer: &Arc<Renderer>, level: vk::AccelerationStructureTypeKHR, size: u64, shared: bool) -> (vk::AccelerationStructureKHR, vk::DeviceAddress, Buffer) {
+        let support = renderer.ray_tracing.as_ref().unwrap();
+        let buffer = {
+            let create_info = BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::GpuOnly,
+            };
+            if shared {
+                Buffer::new_shared(renderer.clone(), create_info)
+            } else {
+                Buffer::new(renderer.clone(), create_info)
+            }
+        };
+        let handle = {
+            let create_info = vk::AccelerationStructureCreateInfoKHR::default()
+                .buffer(buffer.buffer)
+                .size(size)
+                .ty(level);
+            unsafe { support.loader.create_acceleration_structure(&create_info, None).unwrap() }
+        };
+        let address = {
+            let info = vk::AccelerationStructureDeviceAddressInfoKHR::default().acceleration_structure(handle);
+            unsafe { support.loader.get_acceleration_structure_device_address(&info) }
+        };
+        (handle, address, buffer)
+    }
+
+    fn scratch_buffer(renderer: &Arc<Renderer>, size: u64) -> Buffer {
+        let support = renderer.ray_tracing.as_ref().unwrap();
+        let create_info = BufferCreateInfo {
+            size: size + support.scratch_alignment,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::GpuOnly,
+        };
+        Buffer::new(renderer.clone(), create_info)
+    }
+
+    /// A top level structure built with `ALLOW_UPDATE` can be refit to
+    /// `instances` when they reference the same bottom level structures in
+    /// the same order.
+    pub fn can_refit(&self, instances: &[AccelerationStructureInstance]) -> bool {
+        self.is_top_level()
+            && self.flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE)
+            && self.instances.len() == instances.len()
+            && self.instances.iter().zip(instances).all(|(blas, instance)| Arc::ptr_eq(blas, &instance.blas))
+    }
+
+    /// Updates the structure in place to the transforms, masks and custom
+    /// indices of `instances`. Cheaper than a rebuild, but traversal slows
+    /// down the further instances moved since the build. Panics unless
+    /// `can_refit`.
+    pub fn refit(self: &Arc<Self>, command_list: &mut CommandList, instances: &[AccelerationStructureInstance]) {
+        assert!(self.can_refit(instances), "Acceleration structure refit to different instances");
+        let support = self.renderer.ray_tracing.as_ref().unwrap();
+
+        let (instance_buffer, geometry) = Self::instance_geometry(&self.renderer, instances);
+        let geometries = [geometry];
+        let scratch = Self::scratch_buffer(&self.renderer, self.update_scratch_size);
+        let scratch_address = scratch.device_address().next_multiple_of(support.scratch_alignment);
+
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
+            .ty(self.level)
+            .flags(self.flags)
+            .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+            .geometries(&geometries)
+            .src_acceleration_structure(self.handle)
+            .dst_acceleration_structure(self.handle)
+            .scratch_data(vk::DeviceOrHostAddressKHR { device_address: scratch_address });
+        let ranges = [vk::AccelerationStructureBuildRangeInfoKHR::default().primitive_count(instances.len() as u32)];
+        // Ray queries of earlier commands may still read the old version.
+        command_list.memory_barrier();
+        unsafe {
+            support.loader.cmd_build_acceleration_structures(command_list.get_current(), &[build_info], &[&ranges]);
+        }
+        command_list.memory_barrier();
+        command_list.retain(Arc::new(scratch));
+        command_list.retain(Arc::new(instance_buffer));
+        command_list.retain(self.clone());
+    }
+
+    /// Records a copy into a new structure of `compacted_size` bytes, as
+    /// read back with `CompactedSizeQueries`. The structure needs
+    /// `ALLOW_COMPACTION` and can be dropped once the copy executed.
+    pub fn compact(self: &Arc<Self>, command_list: &mut CommandList, compacted_size: u64) -> Arc<Self> {
+        assert!(self.flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION), "Acceleration structure compacted without ALLOW_COMPACTION");
+        let support = self.renderer.ray_tracing.as_ref().unwrap();
+        let shared = command_list.queue() == QueueType::Compute;
+
+        let (handle, address, buffer) = Self::create(&self.renderer, self.level, compacted_size, shared);
+        let copy_info = vk::CopyAccelerationStructureInfoKHR::default()
+            .src(self.handle)
+            .dst(handle)
+            .mode(vk::CopyAccelerationStructureModeKHR::COMPACT);
+        unsafe { support.loader.cmd_copy_acceleration_structure(command_list.get_current(), &copy_info) };
+        command_list.memory_barrier();
+
+        let compacted = Arc::new(Self {
+            handle,
+            address,
+            level: self.level,
+            flags: self.flags,
+            size: compacted_size,
+            update_scratch_size: self.update_scratch_size,
+            _buffer: buffer,
+            instances: self.instances.clone(),
+            renderer: self.renderer.clone(),
+        });
+        command_list.retain(self.clone());
+        command_list.retain(compacted.clone());
+        compacted
+    }
+
+    pub fn is_top_level(&self) -> bool {
+        self.level == vk::AccelerationStructureTypeKHR::TOP_LEVEL
+    }
+
+    /// Size of the structure in GPU memory, excluding build inputs.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.renderer.set_object_name(self.handle, name);
+    }
+}
+
+impl Drop for AccelerationStructure {
+    fn drop(&mut self) {
+        let loader = &self.renderer.ray_tracing.as_ref().unwrap().loader;
+        unsafe { loader.destroy_acceleration_structure(self.handle, None) };
+    }
+}
+
+/// Query pool reading back the compacted sizes of bottom level structures
+/// built with `ALLOW_COMPACTION`, for `AccelerationStructure::compact`.
+pub struct CompactedSizeQueries {
+    query_pool: vk::QueryPool,
+    capacity: u32,
+    renderer: Arc<Renderer>,
+}
+
+impl CompactedSizeQueries {
+    pub fn new(renderer: Arc<Renderer>, capacity: u32) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+            .query_count(capacity.max(1));
+        let query_pool = unsafe { renderer.device.create_query_pool(&create_info, None).unwrap() };
+        Self { query_pool, capacity, renderer }
+    }
+
+    /// Maximum number of structures per `write`.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Records a query of the compacted size of each of `structures`,
+    /// after their builds. Replaces the results of the previous `write`.
+    pub fn write(&self, command_list: &CommandList, structures: &[Arc<AccelerationStructure>]) {
+        assert!(structures.len() as u32 <= self.capacity, "More compacted size queries than their capacity");
+        if structures.is_empty() {
+            return;
+        }
+        let loader = &self.renderer.ray_tracing.as_ref().expect("Acceleration structures need Capabilities::RayQuery").loader;
+        command_list.reset_query_pool(self.query_pool, 0, self.capacity.max(1));
+        let handles = structures.iter().map(|s| s.handle).collect::<Vec<_>>();
+        unsafe {
+            loader.cmd_write_acceleration_structures_properties(
+                command_list.get_current(),
+                &handles,
+                vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                self.query_pool,
+                0)
+        };
+    }
+
+    /// Compacted sizes of the first `count` structures of the last `write`.
+    /// Waits for them, call once its command list executed.
+    pub fn results(&self, count: u32) -> Result<Vec<u64>> {
+        let mut sizes = vec![0u64; count.min(self.capacity) as usize];
+        if !sizes.is_empty() {
+            self.renderer.check(unsafe {
+                self.renderer.device.get_query_pool_results(
+                    self.query_pool,
+                    0,
+                    &mut sizes,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT)
+            })?;
+        }
+        Ok(sizes)
+    }
+}
+
+impl Drop for CompactedSizeQueries {
+    fn drop(&mut self) {
+        unsafe { self.renderer.device.destroy_query_pool(self.query_pool, None) }
+    }
+}
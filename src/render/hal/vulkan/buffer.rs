@@ -0,0 +1,419 @@
+use std::{ptr, slice};
+use std::sync::Arc;
+
+use ash::vk;
+use vk_mem::{Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, MemoryUsage};
+
+use crate::render::hal::{BufferCopy, BufferCreateInfo, BufferTextureCopy, BufferViewCreateInfo, MemoryLocation, PoolResources, ReadbackBufferCreateInfo, Result, StreamingBufferCreateInfo};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::memory::{MemoryPool, ResourceKind};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sync::HostEvent;
+
+pub struct Buffer {
+    pub(crate) buffer: vk::Buffer,
+    pub(super) allocation: Allocation,
+    pub(super) size: u64,
+    pub(super) location: MemoryLocation,
+    pub(super) usage: vk::BufferUsageFlags,
+    mapped: *mut u8,
+    tracking_id: Option<u64>,
+    /// Kept alive until the allocation is freed.
+    pool: Option<Arc<MemoryPool>>,
+    renderer: Arc<Renderer>,
+}
+
+// The mapped pointer refers to memory owned by the allocation, writes go
+// through `&mut self` so the usual borrow rules prevent host side races.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    pub fn new(renderer: Arc<Renderer>, create_info: BufferCreateInfo) -> Self {
+        Self::create(renderer, create_info, None, false)
+    }
+
+    /// Like `new`, but usable from both the graphics and the compute queue
+    /// without ownership transfers, see `QueueType`.
+    pub fn new_shared(renderer: Arc<Renderer>, create_info: BufferCreateInfo) -> Self {
+        Self::create(renderer, create_info, None, true)
+    }
+
+    /// Allocates the buffer from `pool`, which must hold buffers of the
+    /// same location.
+    pub fn new_in(renderer: Arc<Renderer>, create_info: BufferCreateInfo, pool: &Arc<MemoryPool>) -> Self {
+        match pool.resources() {
+            PoolResources::Buffers { location, .. } => {
+                assert_eq!(location, create_info.location, "Buffer location doesn't match its memory pool");
+            }
+            PoolResources::Textures { .. } => panic!("Buffer created in a texture memory pool"),
+        }
+        Self::create(renderer, create_info, Some(pool.clone()), false)
+    }
+
+    fn create(renderer: Arc<Renderer>, create_info: BufferCreateInfo, pool: Option<Arc<MemoryPool>>, shared: bool) -> Self {
+        let usage = Self::usage(&renderer, create_info.usage);
+        let queue_family_indices = [renderer.graphics_family_idx, renderer.compute_family_idx.unwrap_or(renderer.graphics_family_idx)];
+        let mut buffer_create_info = vk::BufferCreateInfo::default()
+            .size(create_info.size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        if shared && queue_family_indices[0] != queue_family_indices[1] {
+            buffer_create_info = buffer_create_info
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(&queue_family_indices);
+        }
+        let allocation_info = Self::allocation_info(create_info.location);
+
+        let (buffer, allocation) = unsafe {
+            match &pool {
+                Some(pool) => pool.pool.create_buffer(&buffer_create_info, &allocation_info).unwrap(),
+                None => renderer.allocator.create_buffer(&buffer_create_info, &allocation_info).unwrap(),
+            }
+        };
+        let size = renderer.allocator.get_allocation_info(&allocation).size;
+        renderer.buffer_memory.add(size);
+        let tracking_id = renderer.resource_tracker.as_ref().map(|t| t.track(ResourceKind::Buffer, size));
+
+        let mapped = match create_info.location {
+            MemoryLocation::GpuOnly => ptr::null_mut(),
+            _ => renderer.allocator.get_allocation_info(&allocation).mapped_data as *mut u8,
+        };
+
+        Buffer { buffer, allocation, size: create_info.size, location: create_info.location, usage, mapped, tracking_id, pool, renderer }
+    }
+
+    pub(super) fn usage(renderer: &Renderer, mut usage: vk::BufferUsageFlags) -> vk::BufferUsageFlags {
+        // Buffer descriptors in a descriptor buffer are written from addresses.
+        let descriptor_usage = vk::BufferUsageFlags::UNIFORM_BUFFER
+            | vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+            | vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER;
+        if renderer.descriptor_heap.is_some() && usage.intersects(descriptor_usage) {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+        usage
+    }
+
+    pub(super) fn allocation_info(location: MemoryLocation) -> AllocationCreateInfo {
+        match location {
+            MemoryLocation::GpuOnly => AllocationCreateInfo {
+                usage: MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            },
+            MemoryLocation::CpuToGpu => AllocationCreateInfo {
+                usage: MemoryUsage::Auto,
+                flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE | AllocationCreateFlags::MAPPED,
+                ..Default::default()
+            },
+            MemoryLocation::GpuToCpu => AllocationCreateInfo {
+                usage: MemoryUsage::Auto,
+                flags: AllocationCreateFlags::HOST_ACCESS_RANDOM | AllocationCreateFlags::MAPPED,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The pool the buffer was allocated from with `new_in`.
+    pub fn memory_pool(&self) -> Option<&Arc<MemoryPool>> {
+        self.pool.as_ref()
+    }
+
+    /// Names the buffer in debug tools and the live resources report.
+    pub fn set_name(&self, name: &str) {
+        self.renderer.set_object_name(self.buffer, name);
+        if let (Some(tracker), Some(id)) = (&self.renderer.resource_tracker, self.tracking_id) {
+            tracker.set_name(id, name);
+        }
+    }
+
+    /// GPU address for buffer device address access in shaders, the buffer
+    /// needs `SHADER_DEVICE_ADDRESS` usage.
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        debug_assert!(self.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS), "Buffer has no SHADER_DEVICE_ADDRESS usage");
+        let info = vk::BufferDeviceAddressInfo::default().buffer(self.buffer);
+        unsafe { self.renderer.device.get_buffer_device_address(&info) }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn location(&self) -> MemoryLocation {
+        self.location
+    }
+
+    /// Copies `data` into a host visible buffer at `offset`.
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        assert!(!self.mapped.is_null(), "Buffer is not host visible");
+        assert!(offset + data.len() as u64 <= self.size, "Write out of buffer bounds");
+
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.mapped.add(offset as usize), data.len()) };
+        self.renderer.allocator.flush_allocation(&self.allocation, offset, data.len() as u64).unwrap();
+    }
+
+    /// Copies the content of a host visible buffer at `offset` into `data`.
+    pub fn read(&self, offset: u64, data: &mut [u8]) {
+        assert!(!self.mapped.is_null(), "Buffer is not host visible");
+        assert!(offset + data.len() as u64 <= self.size, "Read out of buffer bounds");
+
+        self.renderer.allocator.invalidate_allocation(&self.allocation, offset, data.len() as u64).unwrap();
+        unsafe { ptr::copy_nonoverlapping(self.mapped.add(offset as usize), data.as_mut_ptr(), data.len()) };
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let (Some(tracker), Some(id)) = (&self.renderer.resource_tracker, self.tracking_id) {
+            tracker.untrack(id);
+        }
+        self.renderer.buffer_memory.remove(self.renderer.allocator.get_allocation_info(&self.allocation).size);
+        unsafe { self.renderer.allocator.destroy_buffer(self.buffer, &mut self.allocation) };
+    }
+}
+
+/// Persistently mapped ring of `FRAME_OVERLAP` regions for data written
+/// every frame, such as dynamic uniforms, sprite and text vertices or UI
+/// draw data. Allocations stay valid until the frame is presented, the
+/// region is reused `FRAME_OVERLAP` frames later, once the frame's fence
+/// has signaled.
+///
+/// ```ignore
+/// let offset = streaming.push(&params, 256).expect("streaming buffer full");
+/// streaming.flush();
+/// ```
+pub struct StreamingBuffer {
+    buffer: Arc<Buffer>,
+    frame_size: u64,
+    /// Frame the allocations belong to, a new frame starts its region over.
+    frame_index: u64,
+    cursor: u64,
+    flushed: u64,
+    renderer: Arc<Renderer>,
+}
+
+impl StreamingBuffer {
+    pub fn new(renderer: Arc<Renderer>, create_info: StreamingBufferCreateInfo) -> Self {
+        let buffer = {
+            let create_info = BufferCreateInfo {
+                size: create_info.frame_size * FRAME_OVERLAP as u64,
+                usage: create_info.usage,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+        buffer.set_name("streaming buffer");
+
+        Self { buffer: Arc::new(buffer), frame_size: create_info.frame_size, frame_index: u64::MAX, cursor: 0, flushed: 0, renderer }
+    }
+
+    /// The whole ring, allocations are addressed by their offset in it.
+    pub fn buffer(&self) -> &Arc<Buffer> {
+        &self.buffer
+    }
+
+    pub fn frame_size(&self) -> u64 {
+        self.frame_size
+    }
+
+    /// Bytes allocated in the current frame, including alignment padding.
+    pub fn used(&self) -> u64 {
+        if self.frame_index == self.renderer.frame_index() { self.cursor } else { 0 }
+    }
+
+    fn region_start(&self) -> u64 {
+        self.renderer.current_frame() as u64 * self.frame_size
+    }
+
+    /// Reserves `size` bytes in the current frame's region, returning the
+    /// mapped memory and its offset in `buffer`, a multiple of `align`, or
+    /// `None` when the region is full. Call `flush` once written.
+    pub fn alloc(&mut self, size: u64, align: u64) -> Option<(&mut [u8], u64)> {
+        let frame_index = self.renderer.frame_index();
+        if frame_index != self.frame_index {
+            self.frame_index = frame_index;
+            self.cursor = 0;
+            self.flushed = 0;
+        }
+
+        // Aligned in the whole buffer, dynamic offsets are checked against
+        // the device alignment there, whatever `frame_size` is.
+        let region_start = self.region_start();
+        let offset = (region_start + self.cursor).next_multiple_of(align.max(1));
+        let start = offset - region_start;
+        if start + size > self.frame_size {
+            return None;
+        }
+        self.cursor = start + size;
+
+        // The region isn't read by the GPU until this frame is submitted and
+        // the range is handed out once, `&mut self` keeps it exclusive.
+        let data = unsafe { slice::from_raw_parts_mut(self.buffer.mapped.add(offset as usize), size as usize) };
+        Some((data, offset))
+    }
+
+    /// Copies `data` into a new allocation and returns its offset.
+    pub fn push(&mut self, data: &[u8], align: u64) -> Option<u64> {
+        let (memory, offset) = self.alloc(data.len() as u64, align)?;
+        memory.copy_from_slice(data);
+        Some(offset)
+    }
+
+    /// Makes the writes since the last flush visible to the GPU, call it
+    /// before the frame is submitted.
+    pub fn flush(&mut self) {
+        if self.frame_index != self.renderer.frame_index() || self.cursor == self.flushed {
+            return;
+        }
+        let offset = self.region_start() + self.flushed;
+        self.renderer.allocator.flush_allocation(&self.buffer.allocation, offset, self.cursor - self.flushed).unwrap();
+        self.flushed = self.cursor;
+    }
+}
+
+struct PendingReadback {
+    /// Set by the command list once the copy completed and is visible to
+    /// the host.
+    event: Arc<HostEvent>,
+    size: u64,
+}
+
+/// Copies GPU data to the host without stalling the frame: record a copy
+/// into a command list, then `poll` in later frames or `wait` when the
+/// result is needed right away. One copy is in flight at a time.
+///
+/// ```ignore
+/// readback.copy_texture(frame.command_list(), &ids, &[region]);
+/// // Frames later:
+/// if let Some(bytes) = readback.poll()? { ... }
+/// ```
+pub struct ReadbackBuffer {
+    buffer: Buffer,
+    pending: Option<PendingReadback>,
+    renderer: Arc<Renderer>,
+}
+
+impl ReadbackBuffer {
+    pub fn new(renderer: Arc<Renderer>, create_info: ReadbackBufferCreateInfo) -> Self {
+        let buffer = {
+            let create_info = BufferCreateInfo {
+                size: create_info.size,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuToCpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+        buffer.set_name("readback buffer");
+
+        Self { buffer, pending: None, renderer }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.buffer.size()
+    }
+
+    /// A copy was recorded and its result not taken yet.
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Records a copy of `size` bytes of `src` at `offset`. A result not
+    /// taken yet is dropped.
+    pub fn copy_buffer(&mut self, command_list: &mut CommandList, src: &Buffer, offset: u64, size: u64) {
+        let region = BufferCopy { src_offset: offset, dst_offset: 0, size };
+        command_list.copy_buffer(src, &self.buffer, &[region]);
+        self.signal(command_list, size);
+    }
+
+    /// Like `copy_buffer` for texture regions, the result covers the whole
+    /// buffer laid out as `regions` specify. `texture` is expected in
+    /// `GENERAL` layout.
+    pub fn copy_texture(&mut self, command_list: &mut CommandList, texture: &Texture, regions: &[BufferTextureCopy]) {
+        command_list.copy_texture_to_buffer(texture, &self.buffer, regions);
+        self.signal(command_list, self.buffer.size());
+    }
+
+    fn signal(&mut self, command_list: &mut CommandList, size: u64) {
+        // A new event per copy, the one of a dropped result may still be
+        // set by a command buffer in flight.
+        let event = Arc::new(HostEvent::new(self.renderer.clone()));
+        command_list.signal_host(&event);
+        command_list.retain(event.clone());
+        self.pending = Some(PendingReadback { event, size });
+    }
+
+    /// Takes the result once the copy completed, `None` while the GPU is
+    /// still busy or when nothing was recorded.
+    pub fn poll(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(pending) = &self.pending else {
+            return Ok(None);
+        };
+        Ok(if pending.event.is_set()? { Some(self.take()) } else { None })
+    }
+
+    /// Waits for the copy for up to `RendererCreateInfo::fence_timeout` and
+    /// takes the result, `None` when nothing was recorded. The command list
+    /// has to be submitted.
+    pub fn wait(&mut self) -> Result<Option<Vec<u8>>> {
+        crate::trace_span!("ReadbackBuffer::wait");
+        let Some(pending) = &self.pending else {
+            return Ok(None);
+        };
+        pending.event.wait()?;
+        Ok(Some(self.take()))
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        let pending = self.pending.take().unwrap();
+        let mut data = vec![0u8; pending.size as usize];
+        self.buffer.read(0, &mut data);
+        data
+    }
+}
+
+/// A buffer range interpreted as texels of `format`, bound with
+/// `DescriptorSet::write_uniform_texel_buffer` or `write_storage_texel_buffer`.
+/// The buffer needs the matching texel buffer usage.
+pub struct BufferView {
+    pub(crate) view: vk::BufferView,
+    pub(crate) buffer: Arc<Buffer>,
+    pub(crate) format: vk::Format,
+    pub(crate) offset: u64,
+    /// Resolved, never `vk::WHOLE_SIZE`.
+    pub(crate) range: u64,
+
+    renderer: Arc<Renderer>,
+}
+
+impl BufferView {
+    pub fn new(renderer: Arc<Renderer>, create_info: BufferViewCreateInfo) -> Arc<Self> {
+        let BufferViewCreateInfo { buffer, format, offset, range } = create_info;
+        let range = if range == vk::WHOLE_SIZE { buffer.size - offset } else { range };
+
+        let info = vk::BufferViewCreateInfo::default()
+            .buffer(buffer.buffer)
+            .format(format)
+            .offset(offset)
+            .range(range);
+
+        let view = unsafe { renderer.device.create_buffer_view(&info, None).unwrap() };
+
+        Arc::new(BufferView { view, buffer, format, offset, range, renderer })
+    }
+
+    pub fn buffer(&self) -> &Arc<Buffer> {
+        &self.buffer
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+}
+
+impl Drop for BufferView {
+    fn drop(&mut self) {
+        unsafe { self.renderer.device.destroy_buffer_view(self.view, None); }
+    }
+}
@@ -1,33 +1,75 @@
+use std::any::Any;
 use std::sync::Arc;
 
 use ash::vk;
 use ash::vk::Offset3D;
 
-use crate::render::hal::CommandListCreateInfo;
-use crate::render::hal::vulkan::descriptor_set::DescriptorSet;
+use crate::render::hal::{BufferCopy, BufferTextureCopy, Capabilities, ColorAttachment, CommandListCreateInfo, DepthAttachment, Error, Filter, LoadOp, QueueType, Result, ScalingMode, ShaderStages};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::descriptor_set::{convert_shader_stage, with_vk_writes, DescriptorSet, DescriptorWrite, SetHandle, TransientDescriptorSet};
 use crate::render::hal::vulkan::FRAME_OVERLAP;
 use crate::render::hal::vulkan::image::Texture;
-use crate::render::hal::vulkan::pipeline::{ComputePipeline, PipelineLayout};
+use crate::render::hal::vulkan::pipeline::{ComputePipeline, GraphicsPipeline, PipelineLayout};
 use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sync::{Event, HostEvent};
+use crate::render::hal::vulkan::validation::{self, CommandListValidator};
 
+/// Each command list owns its command pool, so lists can be recorded on
+/// different threads without synchronization.
 pub struct CommandList {
+    command_pool: vk::CommandPool,
     command_buffers: [vk::CommandBuffer; FRAME_OVERLAP],
+    queue: QueueType,
     renderer: Arc<Renderer>,
 
-    owned_resources: Vec<Arc<dyn Drop>>,
+    /// Resources referenced by the commands recorded for each frame slot,
+    /// kept alive until the slot is reset after its fence signaled.
+    retained_resources: [Vec<Arc<dyn Any + Send + Sync>>; FRAME_OVERLAP],
+
+    /// Bind point of the last bound pipeline, descriptor sets are bound to it.
+    bind_point: vk::PipelineBindPoint,
+    /// Of the last bound graphics pipeline, for counting triangles.
+    topology: vk::PrimitiveTopology,
+    /// The descriptor heap is bound once per recording, on first use.
+    descriptor_heap_bound: bool,
+    /// Tracks recording state when `RendererCreateInfo::validate_usage` is set.
+    validator: Option<CommandListValidator>,
 }
 impl CommandList {
     pub fn new(renderer: Arc<Renderer>, info: CommandListCreateInfo) -> Self {
+        let command_pool = {
+            let create_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(renderer.queue_family(info.queue));
+            unsafe { renderer.device.create_command_pool(&create_info, None).unwrap() }
+        };
+
         let command_buffers = {
             let alloc_info = vk::CommandBufferAllocateInfo::default()
-                .command_pool(renderer.command_pool)
+                .command_pool(command_pool)
                 .command_buffer_count(FRAME_OVERLAP as u32)
                 .level(vk::CommandBufferLevel::PRIMARY);
 
             unsafe { renderer.device.allocate_command_buffers(&alloc_info).unwrap().as_slice().try_into().unwrap() }
         };
 
-        Self { command_buffers, renderer, owned_resources: Vec::new() }
+        let validator = renderer.validate_usage().then(CommandListValidator::default);
+
+        Self {
+            command_pool,
+            command_buffers,
+            queue: info.queue,
+            renderer,
+            retained_resources: Default::default(),
+            bind_point: vk::PipelineBindPoint::COMPUTE,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            descriptor_heap_bound: false,
+            validator,
+        }
+    }
+
+    pub fn queue(&self) -> QueueType {
+        self.queue
     }
 
     pub(crate) fn get_current(&self) -> vk::CommandBuffer {
@@ -35,17 +77,45 @@ impl CommandList {
         self.command_buffers[frame]
     }
 
-    pub fn reset(&self) {
+    fn validate(&self, check: impl FnOnce(&CommandListValidator)) {
+        if let Some(validator) = &self.validator {
+            check(validator);
+        }
+    }
+
+    pub(crate) fn retain(&mut self, resource: Arc<dyn Any + Send + Sync>) {
+        let frame = self.renderer.current_frame();
+        self.retained_resources[frame].push(resource);
+    }
+
+    /// Resets the command buffer of the current frame and releases the
+    /// resources its previous recording kept alive. Must only be called
+    /// after the fence of the current frame has been waited on.
+    pub fn reset(&mut self) {
+        self.validate(|v| v.reset());
+        let frame = self.renderer.current_frame();
+        self.retained_resources[frame].clear();
+        self.descriptor_heap_bound = false;
+        self.bind_point = vk::PipelineBindPoint::COMPUTE;
+
         let reset_flags = vk::CommandBufferResetFlags::default();
         unsafe { self.renderer.device.reset_command_buffer(self.get_current(), reset_flags).unwrap() };
     }
 
     pub fn begin(&self) {
+        self.validate(|v| v.begin());
         let info = vk::CommandBufferBeginInfo::default();
         unsafe { self.renderer.device.begin_command_buffer(self.get_current(), &info).unwrap(); }
     }
 
+    /// Marks a point in the command stream. When GPU crash diagnostics are
+    /// enabled, the last checkpoints reached are reported on device loss.
+    pub fn checkpoint(&self, label: &str) {
+        self.renderer.set_checkpoint(self.get_current(), self.queue, label);
+    }
+
     pub fn end(&self) {
+        self.validate(|v| v.end());
         unsafe { self.renderer.device.end_command_buffer(self.get_current()).unwrap() };
     }
 
@@ -59,13 +129,16 @@ impl CommandList {
     }
 
     fn transition_image_layout(&self, image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
-        unsafe {
-            let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
-                vk::ImageAspectFlags::DEPTH
-            } else {
-                vk::ImageAspectFlags::COLOR
-            };
+        let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        };
+        self.image_barrier(image, aspect_mask, old_layout, new_layout);
+    }
 
+    fn image_barrier(&self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
+        unsafe {
             let image_barrier = vk::ImageMemoryBarrier2::default()
                 .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
                 .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
@@ -81,24 +154,93 @@ impl CommandList {
             let dependency_info = vk::DependencyInfo::default()
                 .image_memory_barriers(&barriers);
 
-            self.renderer.device.cmd_pipeline_barrier2(self.get_current(), &dependency_info);
+            self.renderer.cmd_pipeline_barrier2(self.get_current(), &dependency_info);
         }
     }
 
     pub fn transition_texture_layout(&self, texture: &Texture, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
-        self.transition_image_layout(texture.image, old_layout, new_layout);
+        self.image_barrier(texture.image, texture.aspect, old_layout, new_layout);
+    }
+
+    /// Hands an exported texture over to other APIs, which must not touch it
+    /// before this command list has signaled an exported semaphore, see
+    /// `Texture::new_exportable`. The texture is left in `layout`.
+    pub fn release_to_external(&self, texture: &Texture, old_layout: vk::ImageLayout, layout: vk::ImageLayout) {
+        let family = self.renderer.queue_family(self.queue());
+        self.external_barrier(texture, old_layout, layout, family, vk::QUEUE_FAMILY_EXTERNAL);
     }
 
-    fn copy_image_to_image(&self, source: vk::Image, dest: vk::Image, src_size: vk::Extent2D, dst_size: vk::Extent2D) {
+    /// Takes an exported texture back from other APIs, after waiting on
+    /// the semaphore they signaled when done with it.
+    pub fn acquire_from_external(&self, texture: &Texture, old_layout: vk::ImageLayout, layout: vk::ImageLayout) {
+        let family = self.renderer.queue_family(self.queue());
+        self.external_barrier(texture, old_layout, layout, vk::QUEUE_FAMILY_EXTERNAL, family);
+    }
+
+    fn external_barrier(&self, texture: &Texture, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, src_family: u32, dst_family: u32) {
+        let image_barrier = vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .subresource_range(Self::subresource_range(texture.aspect))
+            .image(texture.image);
+        let barriers = [image_barrier];
+        let dependency_info = vk::DependencyInfo::default()
+            .image_memory_barriers(&barriers);
+        unsafe { self.renderer.cmd_pipeline_barrier2(self.get_current(), &dependency_info) };
+    }
+
+    fn convert_filter(filter: Filter) -> vk::Filter {
+        match filter {
+            Filter::Nearest => vk::Filter::NEAREST,
+            Filter::Linear => vk::Filter::LINEAR,
+        }
+    }
+
+    fn attachment_load_op<T>(load: LoadOp<T>) -> vk::AttachmentLoadOp {
+        match load {
+            LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            LoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+
+    /// Destination rectangle for `src_size` fitted into `dst_size`.
+    fn fit_rect(src_size: vk::Extent2D, dst_size: vk::Extent2D, scaling: ScalingMode) -> [Offset3D; 2] {
+        match scaling {
+            ScalingMode::Stretch => [
+                Offset3D::default(),
+                Offset3D { x: dst_size.width as i32, y: dst_size.height as i32, z: 1 }
+            ],
+            ScalingMode::Letterbox => {
+                let scale = f64::min(
+                    dst_size.width as f64 / src_size.width as f64,
+                    dst_size.height as f64 / src_size.height as f64,
+                );
+                let width = ((src_size.width as f64 * scale).round() as u32).min(dst_size.width);
+                let height = ((src_size.height as f64 * scale).round() as u32).min(dst_size.height);
+                let x = ((dst_size.width - width) / 2) as i32;
+                let y = ((dst_size.height - height) / 2) as i32;
+                [
+                    Offset3D { x, y, z: 0 },
+                    Offset3D { x: x + width as i32, y: y + height as i32, z: 1 }
+                ]
+            }
+        }
+    }
+
+    fn copy_image_to_image(&self, source: vk::Image, dest: vk::Image, src_size: vk::Extent2D, dst_offsets: [Offset3D; 2], filter: Filter) {
         let blit_regions = [vk::ImageBlit2::default()
             .src_offsets([
                 Offset3D::default(),
                 Offset3D { x: src_size.width as i32, y: src_size.height as i32, z: 1 }
             ])
-            .dst_offsets([
-                Offset3D::default(),
-                Offset3D { x: dst_size.width as i32, y: dst_size.height as i32, z: 1 }
-            ])
+            .dst_offsets(dst_offsets)
             .src_subresource(vk::ImageSubresourceLayers {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_array_layer: 0,
@@ -117,41 +259,678 @@ impl CommandList {
             .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .src_image(source)
             .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-            .filter(vk::Filter::LINEAR)
+            .filter(Self::convert_filter(filter))
             .regions(&blit_regions);
 
-        unsafe { self.renderer.device.cmd_blit_image2(self.get_current(), &blit_info) }
+        unsafe { self.renderer.cmd_blit_image2(self.get_current(), &blit_info) }
     }
 
-    pub fn copy_to_framebuffer(&self, texture: &Texture) {
+    fn clear_color_image(&self, image: vk::Image, color: [f32; 4]) {
+        let clear_value = vk::ClearColorValue { float32: color };
+        let ranges = [Self::subresource_range(vk::ImageAspectFlags::COLOR)];
+        unsafe { self.renderer.device.cmd_clear_color_image(self.get_current(), image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &clear_value, &ranges) }
+    }
+
+    /// Blits `texture` into the current swapchain image. The blit decodes
+    /// `*_SRGB` sources and encodes into `*_SRGB` swapchains, other texels
+    /// are copied as they are, so they have to be encoded for the surface.
+    pub fn copy_to_framebuffer(&self,texture: &Texture, filter: Filter, scaling: ScalingMode) {
+        self.validate(|v| {
+            v.outside_rendering("copy_to_framebuffer");
+            validation::check_texture_usage(texture, vk::ImageUsageFlags::TRANSFER_SRC, "copy_to_framebuffer");
+            v.present_encoding(texture.format(), self.renderer.swapchain_format());
+        });
+        let src_size = vk::Extent2D { width: texture.extent.width, height: texture.extent.height };
+        let dst_size = self.renderer.swapchain_extent();
+        let dst_offsets = Self::fit_rect(src_size, dst_size, scaling);
+        let swapchain_img = self.renderer.get_current_swapchain_img();
+
         self.transition_image_layout(texture.image, vk::ImageLayout::GENERAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
-        self.transition_image_layout(self.renderer.get_current_swapchain_img(), vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
-        self.copy_image_to_image(texture.image, self.renderer.get_current_swapchain_img(), vk::Extent2D { width: 800, height: 600 }, vk::Extent2D { width: 800, height: 600 });
-        self.transition_image_layout(self.renderer.get_current_swapchain_img(), vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR);
+        self.transition_image_layout(swapchain_img, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        // Sizes differ whenever a render scale is used, only clear when the
+        // fitted rectangle leaves bars.
+        let covers_target = dst_offsets[0] == Offset3D::default()
+            && dst_offsets[1] == Offset3D { x: dst_size.width as i32, y: dst_size.height as i32, z: 1 };
+        if !covers_target {
+            self.clear_color_image(swapchain_img, [0.0, 0.0, 0.0, 1.0]);
+            self.transition_image_layout(swapchain_img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        }
+        self.copy_image_to_image(texture.image, swapchain_img, src_size, dst_offsets, filter);
+        self.transition_image_layout(swapchain_img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    /// Copies the current swapchain image, tightly packed in the swapchain
+    /// format, into `buffer`. Must follow `copy_to_framebuffer`, see
+    /// `Renderer::framebuffer_capture_supported`.
+    pub fn copy_framebuffer_to_buffer(&self, buffer: &Buffer) {
+        self.validate(|v| {
+            v.outside_rendering("copy_framebuffer_to_buffer");
+            validation::check_buffer_usage(buffer, vk::BufferUsageFlags::TRANSFER_DST, "copy_framebuffer_to_buffer");
+        });
+        assert!(self.renderer.framebuffer_capture_supported(), "Swapchain images can't be read back");
+
+        let extent = self.renderer.swapchain_extent();
+        let swapchain_img = self.renderer.get_current_swapchain_img();
+        let copies = [vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })];
+
+        self.transition_image_layout(swapchain_img, vk::ImageLayout::PRESENT_SRC_KHR, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        unsafe {
+            self.renderer.device.cmd_copy_image_to_buffer(
+                self.get_current(),
+                swapchain_img,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &copies)
+        };
+        self.transition_image_layout(swapchain_img, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    fn buffer_image_copies(texture: &Texture, regions: &[BufferTextureCopy]) -> Vec<vk::BufferImageCopy> {
+        regions.iter().map(|r| {
+            assert!(r.mip_level < texture.mip_levels, "Mip level out of range");
+            assert!(r.base_array_layer + r.layer_count <= texture.array_layers, "Array layers out of range");
+
+            vk::BufferImageCopy::default()
+                .buffer_offset(r.buffer_offset)
+                .buffer_row_length(r.buffer_row_length)
+                .buffer_image_height(r.buffer_image_height)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: texture.aspect,
+                    mip_level: r.mip_level,
+                    base_array_layer: r.base_array_layer,
+                    layer_count: r.layer_count,
+                })
+                .image_offset(r.texture_offset)
+                .image_extent(r.texture_extent)
+        }).collect()
+    }
+
+    /// Copies buffer data into `texture`, which must be in `GENERAL` layout.
+    pub fn copy_buffer_to_texture(&self, buffer: &Buffer, texture: &Texture, regions: &[BufferTextureCopy]) {
+        self.validate(|v| {
+            v.outside_rendering("copy_buffer_to_texture");
+            validation::check_buffer_usage(buffer, vk::BufferUsageFlags::TRANSFER_SRC, "copy_buffer_to_texture");
+            validation::check_texture_usage(texture, vk::ImageUsageFlags::TRANSFER_DST, "copy_buffer_to_texture");
+        });
+        let copies = Self::buffer_image_copies(texture, regions);
+        unsafe {
+            self.renderer.device.cmd_copy_buffer_to_image(
+                self.get_current(),
+                buffer.buffer,
+                texture.image,
+                vk::ImageLayout::GENERAL,
+                &copies)
+        };
+    }
+
+    /// Copies texels of `texture`, which must be in `GENERAL` layout, into a buffer.
+    pub fn copy_texture_to_buffer(&self, texture: &Texture, buffer: &Buffer, regions: &[BufferTextureCopy]) {
+        self.validate(|v| {
+            v.outside_rendering("copy_texture_to_buffer");
+            validation::check_texture_usage(texture, vk::ImageUsageFlags::TRANSFER_SRC, "copy_texture_to_buffer");
+            validation::check_buffer_usage(buffer, vk::BufferUsageFlags::TRANSFER_DST, "copy_texture_to_buffer");
+        });
+        let copies = Self::buffer_image_copies(texture, regions);
+        unsafe {
+            self.renderer.device.cmd_copy_image_to_buffer(
+                self.get_current(),
+                texture.image,
+                vk::ImageLayout::GENERAL,
+                buffer.buffer,
+                &copies)
+        };
+    }
+
+    pub fn copy_buffer(&self, src: &Buffer, dst: &Buffer, regions: &[BufferCopy]) {
+        self.validate(|v| {
+            v.outside_rendering("copy_buffer");
+            validation::check_buffer_usage(src, vk::BufferUsageFlags::TRANSFER_SRC, "copy_buffer");
+            validation::check_buffer_usage(dst, vk::BufferUsageFlags::TRANSFER_DST, "copy_buffer");
+        });
+        let copies = regions.iter().map(|r| {
+            assert!(r.src_offset + r.size <= src.size, "Copy source out of buffer bounds");
+            assert!(r.dst_offset + r.size <= dst.size, "Copy destination out of buffer bounds");
+
+            vk::BufferCopy::default()
+                .src_offset(r.src_offset)
+                .dst_offset(r.dst_offset)
+                .size(r.size)
+        }).collect::<Vec<_>>();
+
+        unsafe { self.renderer.device.cmd_copy_buffer(self.get_current(), src.buffer, dst.buffer, &copies) };
+    }
+
+    /// Fills `size` bytes at `offset` with a repeated 32-bit `value`.
+    /// `size` of `vk::WHOLE_SIZE` fills up to the end of the buffer.
+    pub fn fill_buffer(&self, dst: &Buffer, offset: u64, size: u64, value: u32) {
+        self.validate(|v| {
+            v.outside_rendering("fill_buffer");
+            validation::check_buffer_usage(dst, vk::BufferUsageFlags::TRANSFER_DST, "fill_buffer");
+        });
+        assert_eq!(offset % 4, 0, "Fill offset must be a multiple of 4");
+        assert!(offset < dst.size, "Fill offset out of buffer bounds");
+        assert!(size == vk::WHOLE_SIZE || (size > 0 && size.is_multiple_of(4) && offset + size <= dst.size), "Invalid fill size");
+
+        unsafe { self.renderer.device.cmd_fill_buffer(self.get_current(), dst.buffer, offset, size, value) };
+    }
+
+    /// Inline update of a small region, the data is stored in the command buffer.
+    pub fn update_buffer(&self, dst: &Buffer, offset: u64, data: &[u8]) {
+        self.validate(|v| {
+            v.outside_rendering("update_buffer");
+            validation::check_buffer_usage(dst, vk::BufferUsageFlags::TRANSFER_DST, "update_buffer");
+        });
+        assert_eq!(offset % 4, 0, "Update offset must be a multiple of 4");
+        assert!(!data.is_empty(), "Update of an empty slice");
+        assert_eq!(data.len() % 4, 0, "Update size must be a multiple of 4");
+        assert!(data.len() <= 65536, "Inline updates are limited to 64KB");
+        assert!(offset + data.len() as u64 <= dst.size, "Update out of buffer bounds");
+
+        unsafe { self.renderer.device.cmd_update_buffer(self.get_current(), dst.buffer, offset, data) };
+    }
+
+    /// Resets `query_count` queries from `first_query` so they can be
+    /// written again, outside of rendering.
+    #[cfg(any(feature = "debug", feature = "ray-tracing"))]
+    pub(crate) fn reset_query_pool(&self, query_pool: vk::QueryPool, first_query: u32, query_count: u32) {
+        unsafe { self.renderer.device.cmd_reset_query_pool(self.get_current(), query_pool, first_query, query_count) };
+    }
+
+    /// Writes a timestamp once all previously submitted commands completed.
+    #[cfg(feature = "debug")]
+    pub(crate) fn write_timestamp(&self, query_pool: vk::QueryPool, query: u32) {
+        unsafe { self.renderer.cmd_write_timestamp2(self.get_current(), vk::PipelineStageFlags2::ALL_COMMANDS, query_pool, query) };
+    }
+
+    /// Makes all writes of the previous commands visible to the following
+    /// ones, e.g. between an upload and the first use of a buffer.
+    pub fn memory_barrier(&self) {
+        let barriers = [Self::full_memory_barrier()];
+
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&barriers);
+
+        unsafe { self.renderer.cmd_pipeline_barrier2(self.get_current(), &dependency_info) };
+    }
+
+    fn full_memory_barrier() -> vk::MemoryBarrier2<'static> {
+        vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
+    }
+
+    /// First half of a split `memory_barrier`, signals `event` once the
+    /// commands recorded so far finished.
+    pub fn set_event(&self, event: &Event) {
+        self.validate(|v| v.outside_rendering("set_event"));
+        let barriers = [Self::full_memory_barrier()];
+
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&barriers);
+
+        unsafe { self.renderer.cmd_set_event2(self.get_current(), event.get_current(), &dependency_info) };
+    }
+
+    /// Makes the transfer writes recorded so far visible to host reads
+    /// and sets `event` once they finished.
+    pub(crate) fn signal_host(&self, event: &HostEvent) {
+        let barriers = [vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)];
+
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&barriers);
+
+        unsafe {
+            self.renderer.cmd_pipeline_barrier2(self.get_current(), &dependency_info);
+            self.renderer.cmd_set_event2(self.get_current(), event.event, &dependency_info);
+        }
+    }
+
+    /// Second half of a split `memory_barrier`, the following commands
+    /// wait for `event` and see the writes made before `set_event`. The
+    /// event is reset afterwards, every `set_event` is paired with one
+    /// `wait_event`.
+    pub fn wait_event(&self, event: &Event) {
+        self.validate(|v| v.outside_rendering("wait_event"));
+        // Has to match the dependency given to `set_event`.
+        let barriers = [Self::full_memory_barrier()];
+
+        let dependency_infos = [vk::DependencyInfo::default()
+            .memory_barriers(&barriers)];
+
+        unsafe {
+            self.renderer.cmd_wait_events2(self.get_current(), &[event.get_current()], &dependency_infos);
+            self.renderer.cmd_reset_event2(self.get_current(), event.get_current(), vk::PipelineStageFlags2::ALL_COMMANDS);
+        }
+    }
+
+    pub fn bind_vertex_buffers(&mut self, first_binding: u32, buffers: &[Arc<Buffer>]) {
+        let handles = buffers.iter().map(|b| b.buffer).collect::<Vec<_>>();
+        let offsets = vec![0; buffers.len()];
+        unsafe { self.renderer.device.cmd_bind_vertex_buffers(self.get_current(), first_binding, &handles, &offsets) };
+        for buffer in buffers {
+            self.retain(buffer.clone());
+        }
+    }
+
+    pub fn bind_index_buffer(&mut self, buffer: Arc<Buffer>, index_type: vk::IndexType) {
+        unsafe { self.renderer.device.cmd_bind_index_buffer(self.get_current(), buffer.buffer, 0, index_type) };
+        self.retain(buffer);
     }
 
     pub fn bind_compute_pipeline(&mut self, pipeline: Arc<ComputePipeline>) {
+        self.validate(|v| v.bind_pipeline(vk::PipelineBindPoint::COMPUTE, pipeline.layout.set_bindings()));
         unsafe { self.renderer.device.cmd_bind_pipeline(self.get_current(), vk::PipelineBindPoint::COMPUTE, pipeline.pipeline) };
-        self.owned_resources.push(pipeline);
+        self.bind_point = vk::PipelineBindPoint::COMPUTE;
+        self.retain(pipeline);
+    }
+
+    pub fn bind_graphics_pipeline(&mut self, pipeline: Arc<GraphicsPipeline>) {
+        self.validate(|v| v.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, pipeline.layout.set_bindings()));
+        unsafe { self.renderer.device.cmd_bind_pipeline(self.get_current(), vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline) };
+        self.bind_point = vk::PipelineBindPoint::GRAPHICS;
+        self.topology = pipeline.topology;
+        self.retain(pipeline);
+    }
+
+    /// Binds consecutive sets starting at `first_set`, all from the same
+    /// descriptor backend.
+    fn bind_sets(&mut self, pipeline_layout: &PipelineLayout, first_set: u32, sets: &[SetHandle], dynamic_offsets: &[u32]) {
+        match sets.first() {
+            None => {}
+            Some(SetHandle::Pool(_)) => {
+                let sets = sets.iter().map(|set| match set {
+                    SetHandle::Pool(set) => *set,
+                    SetHandle::Heap(_) => unreachable!(),
+                }).collect::<Vec<_>>();
+                unsafe {
+                    self.renderer.device.cmd_bind_descriptor_sets(
+                        self.get_current(),
+                        self.bind_point,
+                        pipeline_layout.layout,
+                        first_set,
+                        &sets,
+                        dynamic_offsets)
+                }
+            }
+            Some(SetHandle::Heap(_)) => {
+                assert!(dynamic_offsets.is_empty(), "Descriptor buffers don't support dynamic offsets");
+                let offsets = sets.iter().map(|set| match set {
+                    SetHandle::Heap(offset) => *offset,
+                    SetHandle::Pool(_) => unreachable!(),
+                }).collect::<Vec<_>>();
+                let buffer_indices = vec![0; offsets.len()];
+
+                let heap = self.renderer.descriptor_heap.as_ref().unwrap();
+                if !self.descriptor_heap_bound {
+                    let binding_infos = [vk::DescriptorBufferBindingInfoEXT::default()
+                        .address(heap.address)
+                        .usage(vk::BufferUsageFlags::RESOURCE_DESCRIPTOR_BUFFER_EXT | vk::BufferUsageFlags::SAMPLER_DESCRIPTOR_BUFFER_EXT)];
+                    unsafe { heap.loader.cmd_bind_descriptor_buffers(self.get_current(), &binding_infos) };
+                    self.descriptor_heap_bound = true;
+                }
+                unsafe { heap.loader.cmd_set_descriptor_buffer_offsets(self.get_current(), self.bind_point, pipeline_layout.layout, first_set, &buffer_indices, &offsets) };
+            }
+        }
     }
 
-    pub fn bind_descriptor_set(&mut self, pipeline_layout: Arc<PipelineLayout>, descriptor_set: Arc<DescriptorSet>) {
+    /// Binds `descriptor_set` as set `set_index` of `pipeline_layout`, at
+    /// the bind point of the pipeline bound last, compute or graphics. Bind
+    /// the pipeline first.
+    pub fn bind_descriptor_set(&mut self, pipeline_layout: Arc<PipelineLayout>, set_index: u32, descriptor_set: Arc<DescriptorSet>) {
+        self.bind_descriptor_sets(pipeline_layout, set_index, &[descriptor_set], &[]);
+    }
+
+    /// Binds `descriptor_sets` as consecutive sets starting at `first_set`,
+    /// e.g. per-frame, per-material and per-object sets in one call.
+    /// `dynamic_offsets` has one entry per dynamic buffer binding of the
+    /// sets, in set and binding order.
+    pub fn bind_descriptor_sets(&mut self, pipeline_layout: Arc<PipelineLayout>, first_set: u32, descriptor_sets: &[Arc<DescriptorSet>], dynamic_offsets: &[u32]) {
+        self.validate(|v| {
+            for (set_index, descriptor_set) in (first_set..).zip(descriptor_sets) {
+                v.bind_set(self.bind_point, set_index, &descriptor_set.layout);
+            }
+        });
+        let sets = descriptor_sets.iter().map(|set| set.get_current()).collect::<Vec<_>>();
+        self.bind_sets(&pipeline_layout, first_set, &sets, dynamic_offsets);
+        self.retain(pipeline_layout);
+        for descriptor_set in descriptor_sets {
+            self.retain(descriptor_set.clone());
+        }
+    }
+
+    /// Binds `writes` to set `set_index` of `pipeline_layout` without
+    /// allocating a `DescriptorSet`, for transient per-draw bindings. The
+    /// set layout must be created with `push_descriptor`. Without
+    /// `VK_KHR_push_descriptor` a single use set is allocated instead.
+    pub fn push_descriptor_set(&mut self, pipeline_layout: Arc<PipelineLayout>, set_index: u32, writes: &[DescriptorWrite]) {
+        let set_layout = pipeline_layout.set_layout(set_index).clone();
+        self.validate(|v| v.bind_set(self.bind_point, set_index, &set_layout));
+        match &self.renderer.push_descriptor_loader {
+            Some(loader) if set_layout.push_descriptor => {
+                with_vk_writes(writes, |vk_writes| unsafe {
+                    loader.cmd_push_descriptor_set(self.get_current(), self.bind_point, pipeline_layout.layout, set_index, vk_writes)
+                });
+            }
+            _ => {
+                let transient = TransientDescriptorSet::new(self.renderer.clone(), set_layout, writes);
+                self.bind_sets(&pipeline_layout, set_index, &[transient.set], &[]);
+                self.retain(Arc::new(transient));
+            }
+        }
+
+        for write in writes {
+            for resource in write.resources() {
+                self.retain(resource);
+            }
+        }
+        self.retain(pipeline_layout);
+    }
+
+    pub fn push_constants(&mut self, pipeline_layout: Arc<PipelineLayout>, stage: ShaderStages, offset: u32, data: &[u8]) {
         unsafe {
-            self.renderer.device.cmd_bind_descriptor_sets(
+            self.renderer.device.cmd_push_constants(
                 self.get_current(),
-                vk::PipelineBindPoint::COMPUTE,
                 pipeline_layout.layout,
-                0,
-                &[descriptor_set.get_current()],
-                &[])
+                convert_shader_stage(stage),
+                offset,
+                data)
         };
-        self.owned_resources.push(pipeline_layout);
-        self.owned_resources.push(descriptor_set);
+        self.retain(pipeline_layout);
     }
 
     pub fn dispatch_compute_pipeline(&self, x: u32, y: u32, z: u32) {
+        self.try_dispatch_compute_pipeline(x, y, z).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Like `dispatch_compute_pipeline`, but in debug builds returns an
+    /// error instead of recording when a group count exceeds the device's
+    /// `maxComputeWorkGroupCount`.
+    pub fn try_dispatch_compute_pipeline(&self, x: u32, y: u32, z: u32) -> Result<()> {
+        if cfg!(debug_assertions) {
+            let max = self.renderer.limits.max_compute_work_group_count;
+            if x > max[0] || y > max[1] || z > max[2] {
+                return Err(Error::Backend(format!("Dispatch of {:?} workgroups exceeds the device limit of {max:?}", [x, y, z])));
+            }
+        }
+
+        self.validate(|v| v.dispatch());
         unsafe {
             self.renderer.device.cmd_dispatch(self.get_current(), x, y, z);
         };
+        self.renderer.frame_counters.dispatch();
+        Ok(())
+    }
+
+    /// Starts rendering into `color`, and `depth` when given. Attachments
+    /// are cleared when a clear value is given and must be in `GENERAL`
+    /// layout. Depth is cleared to 0, the far plane with reverse-Z.
+    pub fn begin_rendering(&self, color: &Texture, depth: Option<&Texture>, clear_color: Option<[f32; 4]>) {
+        let colors = [ColorAttachment {
+            texture: color,
+            load: clear_color.map_or(LoadOp::Load, LoadOp::Clear),
+        }];
+        let depth = depth.map(|texture| DepthAttachment {
+            texture,
+            load: if clear_color.is_some() { LoadOp::Clear(0.0) } else { LoadOp::Load },
+        });
+        self.begin_rendering_attachments(&colors, depth);
+    }
+
+    /// Starts rendering into several color targets, e.g. a G-buffer, and
+    /// an optional depth target, all of the same extent. Color targets are
+    /// bound to the fragment outputs in order and have to match the
+    /// pipeline's `color_formats`.
+    pub fn begin_rendering_attachments(&self, colors: &[ColorAttachment], depth: Option<DepthAttachment>) {
+        self.begin_rendering_with(colors, depth, 0, "begin_rendering_attachments");
+    }
+
+    /// Like `begin_rendering_attachments`, with every draw broadcast to the
+    /// layers in `view_mask`: the faces of a cubemap, the cascades of a
+    /// shadow map or the eyes of a stereo pair in one pass. Shaders pick
+    /// per-view data with `gl_ViewIndex`, see `view::MultiviewData`. The
+    /// targets need a layer per view and a single mip level, pipelines the
+    /// same `GraphicsPipelineCreateInfo::view_mask`. Needs
+    /// `Capabilities::Multiview`.
+    pub fn begin_rendering_views(&self, colors: &[ColorAttachment], depth: Option<DepthAttachment>, view_mask: u32) {
+        assert!(view_mask != 0, "begin_rendering_views needs at least one view");
+        assert!(self.renderer.capabilities().contains(Capabilities::Multiview), "begin_rendering_views needs Capabilities::Multiview");
+        self.validate(|_| {
+            let views = u32::BITS - view_mask.leading_zeros();
+            let textures = colors.iter().map(|c| c.texture).chain(depth.map(|d| d.texture));
+            for texture in textures {
+                if texture.array_layers() < views {
+                    validation::fail(&format!("`begin_rendering_views` renders {views} views into a texture with {} layers", texture.array_layers()));
+                }
+            }
+        });
+        self.begin_rendering_with(colors, depth, view_mask, "begin_rendering_views");
+    }
+
+    fn begin_rendering_with(&self, colors: &[ColorAttachment], depth: Option<DepthAttachment>, view_mask: u32, command: &str) {
+        self.validate(|v| {
+            v.begin_rendering();
+            for color in colors {
+                validation::check_texture_usage(color.texture, vk::ImageUsageFlags::COLOR_ATTACHMENT, command);
+            }
+            if let Some(depth) = &depth {
+                validation::check_texture_usage(depth.texture, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, command);
+            }
+            let mut extents = colors.iter().map(|c| c.texture.extent()).chain(depth.map(|d| d.texture.extent()));
+            if let Some(first) = extents.next() {
+                if extents.any(|e| e.width != first.width || e.height != first.height) {
+                    validation::fail(&format!("`{command}` attachments differ in extent"));
+                }
+            }
+        });
+        let extent = colors.first().map(|c| c.texture.extent())
+            .or(depth.map(|d| d.texture.extent()))
+            .unwrap_or_else(|| panic!("{command} needs at least one attachment"));
+        // Multiview renders into layers of the first mip, the default
+        // views of cubemaps and mipmapped textures can't be attachments.
+        let attachment_view = |texture: &Texture| if view_mask == 0 { texture.image_view } else { texture.mip_view(0) };
+
+        let color_attachments = colors.iter()
+            .map(|color| {
+                let clear_color = match color.load {
+                    LoadOp::Clear(clear_color) => clear_color,
+                    _ => [0.0; 4],
+                };
+                vk::RenderingAttachmentInfo::default()
+                    .image_view(attachment_view(color.texture))
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .load_op(Self::attachment_load_op(color.load))
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(vk::ClearValue { color: vk::ClearColorValue { float32: clear_color } })
+            })
+            .collect::<Vec<_>>();
+
+        let depth_attachment = depth.map(|depth| {
+            let clear_depth = match depth.load {
+                LoadOp::Clear(clear_depth) => clear_depth,
+                _ => 0.0,
+            };
+            vk::RenderingAttachmentInfo::default()
+                .image_view(attachment_view(depth.texture))
+                .image_layout(vk::ImageLayout::GENERAL)
+                .load_op(Self::attachment_load_op(depth.load))
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: clear_depth, stencil: 0 } })
+        });
+
+        let mut info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D { offset: vk::Offset2D::default(), extent: vk::Extent2D { width: extent.width, height: extent.height } })
+            .layer_count(1)
+            .view_mask(view_mask)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            info = info.depth_attachment(depth_attachment);
+        }
+
+        unsafe { self.renderer.cmd_begin_rendering(self.get_current(), &info) };
+    }
+
+    /// Starts depth only rendering into one layer of `depth`, cleared to 0.
+    pub fn begin_depth_rendering(&self, depth: &Texture, layer: u32) {
+        self.validate(|v| {
+            v.begin_rendering();
+            validation::check_texture_usage(depth, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, "begin_depth_rendering");
+        });
+        let extent = depth.extent();
+
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth.layer_view(layer))
+            .image_layout(vk::ImageLayout::GENERAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 0.0, stencil: 0 } });
+
+        let info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D { offset: vk::Offset2D::default(), extent: vk::Extent2D { width: extent.width, height: extent.height } })
+            .layer_count(1)
+            .depth_attachment(&depth_attachment);
+
+        unsafe { self.renderer.cmd_begin_rendering(self.get_current(), &info) };
+    }
+
+    /// Starts rendering into one layer of `color`, cleared to `clear_color`,
+    /// and into `depth`, cleared to 0.
+    pub fn begin_layer_rendering(&self, color: &Texture, layer: u32, depth: &Texture, clear_color: [f32; 4]) {
+        self.validate(|v| {
+            v.begin_rendering();
+            validation::check_texture_usage(color, vk::ImageUsageFlags::COLOR_ATTACHMENT, "begin_layer_rendering");
+            validation::check_texture_usage(depth, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, "begin_layer_rendering");
+        });
+        let extent = color.extent();
+
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(color.layer_view(layer))
+            .image_layout(vk::ImageLayout::GENERAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue { color: vk::ClearColorValue { float32: clear_color } });
+
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth.image_view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 0.0, stencil: 0 } });
+
+        let info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D { offset: vk::Offset2D::default(), extent: vk::Extent2D { width: extent.width, height: extent.height } })
+            .layer_count(1)
+            .color_attachments(std::slice::from_ref(&color_attachment))
+            .depth_attachment(&depth_attachment);
+
+        unsafe { self.renderer.cmd_begin_rendering(self.get_current(), &info) };
+    }
+
+    pub fn end_rendering(&self) {
+        self.validate(|v| v.end_rendering());
+        unsafe { self.renderer.cmd_end_rendering(self.get_current()) };
+    }
+
+    /// Sets viewport 0 of pipelines created with `DynamicState::Viewport`.
+    pub fn set_viewport(&self, viewport: vk::Viewport) {
+        self.set_viewports(0, &[viewport]);
+    }
+
+    pub fn set_viewports(&self, first_viewport: u32, viewports: &[vk::Viewport]) {
+        self.validate(|v| v.command("set_viewports"));
+        unsafe { self.renderer.device.cmd_set_viewport(self.get_current(), first_viewport, viewports) };
+    }
+
+    /// Sets scissor 0 of pipelines created with `DynamicState::Scissor`.
+    pub fn set_scissor(&self, scissor: vk::Rect2D) {
+        self.set_scissors(0, &[scissor]);
+    }
+
+    pub fn set_scissors(&self, first_scissor: u32, scissors: &[vk::Rect2D]) {
+        self.validate(|v| v.command("set_scissors"));
+        unsafe { self.renderer.device.cmd_set_scissor(self.get_current(), first_scissor, scissors) };
+    }
+
+    /// Clears `rect` of color attachment `attachment` of the current
+    /// rendering, leaving the rest of it untouched.
+    pub fn clear_color_attachment(&self, attachment: u32, rect: vk::Rect2D, color: [f32; 4]) {
+        self.validate(|v| v.inside_rendering("clear_color_attachment"));
+        let attachments = [vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            color_attachment: attachment,
+            clear_value: vk::ClearValue { color: vk::ClearColorValue { float32: color } },
+        }];
+        let rects = [vk::ClearRect { rect, base_array_layer: 0, layer_count: 1 }];
+        unsafe { self.renderer.device.cmd_clear_attachments(self.get_current(), &attachments, &rects) };
+    }
+
+    /// Depth bias of pipelines created with `DynamicState::DepthBias`.
+    /// `clamp` other than 0 needs the `depthBiasClamp` feature.
+    pub fn set_depth_bias(&self, constant_factor: f32, clamp: f32, slope_factor: f32) {
+        self.validate(|v| v.command("set_depth_bias"));
+        unsafe { self.renderer.device.cmd_set_depth_bias(self.get_current(), constant_factor, clamp, slope_factor) };
+    }
+
+    /// Blend constants of pipelines created with `DynamicState::BlendConstants`.
+    pub fn set_blend_constants(&self, constants: [f32; 4]) {
+        self.validate(|v| v.command("set_blend_constants"));
+        unsafe { self.renderer.device.cmd_set_blend_constants(self.get_current(), &constants) };
+    }
+
+    pub fn draw(&self, vertex_count: u32, instance_count: u32, first_vertex: u32, first_instance: u32) {
+        self.validate(|v| v.draw());
+        unsafe { self.renderer.device.cmd_draw(self.get_current(), vertex_count, instance_count, first_vertex, first_instance) };
+        self.renderer.frame_counters.draw(self.topology, vertex_count, instance_count);
+    }
+
+    pub fn draw_indexed(&self, index_count: u32, instance_count: u32, first_index: u32, vertex_offset: i32, first_instance: u32) {
+        self.validate(|v| v.draw());
+        unsafe { self.renderer.device.cmd_draw_indexed(self.get_current(), index_count, instance_count, first_index, vertex_offset, first_instance) };
+        self.renderer.frame_counters.draw(self.topology, index_count, instance_count);
+    }
+
+    /// Draws with `vk::DrawIndirectCommand`s read from `buffer`, which needs
+    /// `INDIRECT_BUFFER` usage. The arguments are typically written by a
+    /// compute shader recorded earlier, followed by a `memory_barrier`.
+    pub fn draw_indirect(&mut self, buffer: Arc<Buffer>, offset: u64, draw_count: u32, stride: u32) {
+        self.validate(|v| {
+            v.draw();
+            validation::check_buffer_usage(&buffer, vk::BufferUsageFlags::INDIRECT_BUFFER, "draw_indirect");
+        });
+        unsafe { self.renderer.device.cmd_draw_indirect(self.get_current(), buffer.buffer, offset, draw_count, stride) };
+        self.renderer.frame_counters.indirect_draw(draw_count);
+        self.retain(buffer);
+    }
+
+    /// Indexed `draw_indirect`, with `vk::DrawIndexedIndirectCommand`s.
+    pub fn draw_indexed_indirect(&mut self, buffer: Arc<Buffer>, offset: u64, draw_count: u32, stride: u32) {
+        self.validate(|v| {
+            v.draw();
+            validation::check_buffer_usage(&buffer, vk::BufferUsageFlags::INDIRECT_BUFFER, "draw_indexed_indirect");
+        });
+        unsafe { self.renderer.device.cmd_draw_indexed_indirect(self.get_current(), buffer.buffer, offset, draw_count, stride) };
+        self.renderer.frame_counters.indirect_draw(draw_count);
+        self.retain(buffer);
+    }
+}
+
+impl Drop for CommandList {
+    fn drop(&mut self) {
+        let _ = self.renderer.wait_idle();
+        unsafe {
+            self.renderer.device.destroy_command_pool(self.command_pool, None);
+        }
     }
 }
@@ -0,0 +1,186 @@
+use std::ptr;
+use std::sync::Mutex;
+
+use ash::{Device, Instance, vk};
+use ash::ext::descriptor_buffer;
+use vk_mem::{Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, Allocator, MemoryUsage};
+
+/// Size of the buffer all descriptor sets are placed in, roughly what the
+/// descriptor pool of the pool backend holds.
+const HEAP_SIZE: u64 = 4 << 20;
+
+/// Descriptor data fetched with `vkGetDescriptorEXT`.
+pub(crate) enum HeapDescriptor {
+    UniformBuffer { address: vk::DeviceAddress, range: u64 },
+    StorageBuffer { address: vk::DeviceAddress, range: u64 },
+    UniformTexelBuffer { address: vk::DeviceAddress, range: u64, format: vk::Format },
+    StorageTexelBuffer { address: vk::DeviceAddress, range: u64, format: vk::Format },
+    StorageImage(vk::ImageView),
+    CombinedImageSampler(vk::ImageView, vk::Sampler),
+    #[cfg(feature = "ray-tracing")]
+    AccelerationStructure(vk::DeviceAddress),
+}
+
+/// Backs descriptor sets with `VK_EXT_descriptor_buffer`. Every set is a
+/// range of a single host visible buffer, bound once per command buffer,
+/// and selected by offset instead of allocating `vk::DescriptorSet`s.
+pub(crate) struct DescriptorHeap {
+    pub(crate) loader: descriptor_buffer::Device,
+    pub(crate) address: vk::DeviceAddress,
+    properties: vk::PhysicalDeviceDescriptorBufferPropertiesEXT<'static>,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped: *mut u8,
+    /// Free ranges as `(offset, size)`, sorted by offset.
+    free: Mutex<Vec<(u64, u64)>>,
+}
+
+// Sets occupy disjoint ranges of the mapped memory, and a set is only
+// written for the frame slot the GPU is done with.
+unsafe impl Send for DescriptorHeap {}
+unsafe impl Sync for DescriptorHeap {}
+
+pub(crate) fn is_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut descriptor_buffer_features = vk::PhysicalDeviceDescriptorBufferFeaturesEXT::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::default()
+        .push_next(&mut descriptor_buffer_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+    descriptor_buffer_features.descriptor_buffer == vk::TRUE
+}
+
+impl DescriptorHeap {
+    pub(crate) fn new(instance: &Instance, device: &Device, physical_device: vk::PhysicalDevice, allocator: &Allocator) -> Self {
+        let loader = descriptor_buffer::Device::new(instance, device);
+
+        let properties = {
+            let mut descriptor_buffer_properties = vk::PhysicalDeviceDescriptorBufferPropertiesEXT::default();
+            let mut properties2 = vk::PhysicalDeviceProperties2::default()
+                .push_next(&mut descriptor_buffer_properties);
+            unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+            descriptor_buffer_properties.p_next = ptr::null_mut();
+            descriptor_buffer_properties
+        };
+
+        let buffer_create_info = vk::BufferCreateInfo::default()
+            .size(HEAP_SIZE)
+            .usage(vk::BufferUsageFlags::RESOURCE_DESCRIPTOR_BUFFER_EXT
+                | vk::BufferUsageFlags::SAMPLER_DESCRIPTOR_BUFFER_EXT
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let allocation_info = AllocationCreateInfo {
+            usage: MemoryUsage::Auto,
+            flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE | AllocationCreateFlags::MAPPED,
+            ..Default::default()
+        };
+        let (buffer, allocation) = unsafe { allocator.create_buffer(&buffer_create_info, &allocation_info).unwrap() };
+        let mapped = allocator.get_allocation_info(&allocation).mapped_data as *mut u8;
+
+        let address = {
+            let info = vk::BufferDeviceAddressInfo::default().buffer(buffer);
+            unsafe { device.get_buffer_device_address(&info) }
+        };
+
+        Self {
+            loader,
+            address,
+            properties,
+            buffer,
+            allocation,
+            mapped,
+            free: Mutex::new(vec![(0, HEAP_SIZE)]),
+        }
+    }
+
+    /// Space taken by a set of `layout`, padded so consecutive sets keep
+    /// the required offset alignment.
+    pub(crate) fn set_size(&self, layout: vk::DescriptorSetLayout) -> u64 {
+        let size = unsafe { self.loader.get_descriptor_set_layout_size(layout) };
+        size.next_multiple_of(self.properties.descriptor_buffer_offset_alignment).max(self.properties.descriptor_buffer_offset_alignment)
+    }
+
+    pub(crate) fn binding_offset(&self, layout: vk::DescriptorSetLayout, binding: u32) -> u64 {
+        unsafe { self.loader.get_descriptor_set_layout_binding_offset(layout, binding) }
+    }
+
+    /// First fit, panics when the heap is exhausted like the descriptor
+    /// pool does.
+    pub(crate) fn allocate(&self, size: u64) -> u64 {
+        let mut free = self.free.lock().unwrap();
+        let idx = free.iter().position(|&(_, s)| s >= size).expect("Descriptor heap exhausted");
+        let (offset, available) = free[idx];
+        if available == size {
+            free.remove(idx);
+        } else {
+            free[idx] = (offset + size, available - size);
+        }
+        offset
+    }
+
+    pub(crate) fn free(&self, offset: u64, size: u64) {
+        let mut free = self.free.lock().unwrap();
+        let idx = free.partition_point(|&(o, _)| o < offset);
+        free.insert(idx, (offset, size));
+
+        // Merge with the following and then the preceding range.
+        if idx + 1 < free.len() && free[idx].0 + free[idx].1 == free[idx + 1].0 {
+            free[idx].1 += free[idx + 1].1;
+            free.remove(idx + 1);
+        }
+        if idx > 0 && free[idx - 1].0 + free[idx - 1].1 == free[idx].0 {
+            free[idx - 1].1 += free[idx].1;
+            free.remove(idx);
+        }
+    }
+
+    /// Writes a descriptor at `offset`, the set offset plus the binding
+    /// offset.
+    pub(crate) fn write(&self, allocator: &Allocator, offset: u64, descriptor: HeapDescriptor) {
+        let address_info;
+        let image_info;
+        let (ty, data, size) = match descriptor {
+            HeapDescriptor::UniformBuffer { address, range } => {
+                address_info = vk::DescriptorAddressInfoEXT::default().address(address).range(range);
+                (vk::DescriptorType::UNIFORM_BUFFER, vk::DescriptorDataEXT { p_uniform_buffer: &address_info }, self.properties.uniform_buffer_descriptor_size)
+            }
+            HeapDescriptor::StorageBuffer { address, range } => {
+                address_info = vk::DescriptorAddressInfoEXT::default().address(address).range(range);
+                (vk::DescriptorType::STORAGE_BUFFER, vk::DescriptorDataEXT { p_storage_buffer: &address_info }, self.properties.storage_buffer_descriptor_size)
+            }
+            HeapDescriptor::UniformTexelBuffer { address, range, format } => {
+                address_info = vk::DescriptorAddressInfoEXT::default().address(address).range(range).format(format);
+                (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, vk::DescriptorDataEXT { p_uniform_texel_buffer: &address_info }, self.properties.uniform_texel_buffer_descriptor_size)
+            }
+            HeapDescriptor::StorageTexelBuffer { address, range, format } => {
+                address_info = vk::DescriptorAddressInfoEXT::default().address(address).range(range).format(format);
+                (vk::DescriptorType::STORAGE_TEXEL_BUFFER, vk::DescriptorDataEXT { p_storage_texel_buffer: &address_info }, self.properties.storage_texel_buffer_descriptor_size)
+            }
+            HeapDescriptor::StorageImage(image_view) => {
+                image_info = vk::DescriptorImageInfo::default()
+                    .image_view(image_view)
+                    .image_layout(vk::ImageLayout::GENERAL);
+                (vk::DescriptorType::STORAGE_IMAGE, vk::DescriptorDataEXT { p_storage_image: &image_info }, self.properties.storage_image_descriptor_size)
+            }
+            HeapDescriptor::CombinedImageSampler(image_view, sampler) => {
+                image_info = vk::DescriptorImageInfo::default()
+                    .image_view(image_view)
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .sampler(sampler);
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::DescriptorDataEXT { p_combined_image_sampler: &image_info }, self.properties.combined_image_sampler_descriptor_size)
+            }
+            #[cfg(feature = "ray-tracing")]
+            HeapDescriptor::AccelerationStructure(address) => {
+                (vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, vk::DescriptorDataEXT { acceleration_structure: address }, self.properties.acceleration_structure_descriptor_size)
+            }
+        };
+
+        let info = vk::DescriptorGetInfoEXT::default().ty(ty).data(data);
+        let descriptor = unsafe { std::slice::from_raw_parts_mut(self.mapped.add(offset as usize), size) };
+        unsafe { self.loader.get_descriptor(&info, descriptor) };
+        allocator.flush_allocation(&self.allocation, offset, size as u64).unwrap();
+    }
+
+    /// Must be called before the allocator and device are destroyed.
+    pub(crate) fn destroy(&mut self, allocator: &Allocator) {
+        unsafe { allocator.destroy_buffer(self.buffer, &mut self.allocation) };
+    }
+}
@@ -1,15 +1,29 @@
+use std::any::Any;
 use std::ptr;
 use std::sync::Arc;
 
 use ash::vk;
 
 use crate::render::hal::{BindingType, DescriptorSetLayoutCreateInfo, ShaderStages};
+#[cfg(feature = "ray-tracing")]
+use crate::render::hal::vulkan::acceleration_structure::AccelerationStructure;
+use crate::render::hal::vulkan::buffer::{Buffer, BufferView};
+use crate::render::hal::vulkan::descriptor_buffer::HeapDescriptor;
 use crate::render::hal::vulkan::FRAME_OVERLAP;
 use crate::render::hal::vulkan::image::Texture;
 use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::hal::vulkan::validation;
 
 pub struct DescriptorSetLayout {
     pub(crate) layout: vk::DescriptorSetLayout,
+    /// Created with the push descriptor flag, only when the extension is
+    /// supported.
+    pub(crate) push_descriptor: bool,
+    /// Space a set takes in the descriptor heap, 0 with the pool backend.
+    heap_size: u64,
+    /// Binding numbers and types, for `validation`.
+    pub(crate) bindings: Vec<(u32, vk::DescriptorType)>,
 
     renderer: Arc<Renderer>,
 }
@@ -20,10 +34,14 @@ fn convert_binding_type(binding: BindingType) -> vk::DescriptorType {
         BindingType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
         BindingType::Texture => vk::DescriptorType::STORAGE_IMAGE,
         BindingType::Sampler => vk::DescriptorType::SAMPLER,
+        BindingType::SampledTexture => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        BindingType::UniformTexelBuffer => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+        BindingType::StorageTexelBuffer => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+        BindingType::AccelerationStructure => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
     }
 }
 
-fn convert_shader_stage(stage: ShaderStages) -> vk::ShaderStageFlags {
+pub(crate) fn convert_shader_stage(stage: ShaderStages) -> vk::ShaderStageFlags {
     let mut flags = vk::ShaderStageFlags::empty();
     if stage.contains(ShaderStages::Vertex) {
         flags |= vk::ShaderStageFlags::VERTEX;
@@ -34,6 +52,15 @@ fn convert_shader_stage(stage: ShaderStages) -> vk::ShaderStageFlags {
     if stage.contains(ShaderStages::Compute) {
         flags |= vk::ShaderStageFlags::COMPUTE;
     }
+    if stage.contains(ShaderStages::Geometry) {
+        flags |= vk::ShaderStageFlags::GEOMETRY;
+    }
+    if stage.contains(ShaderStages::TessellationControl) {
+        flags |= vk::ShaderStageFlags::TESSELLATION_CONTROL;
+    }
+    if stage.contains(ShaderStages::TessellationEvaluation) {
+        flags |= vk::ShaderStageFlags::TESSELLATION_EVALUATION;
+    }
     flags
 }
 
@@ -50,7 +77,14 @@ impl DescriptorSetLayout {
             }
         }).collect::<Vec<_>>();
 
-        let flags = vk::DescriptorSetLayoutCreateFlags::default();
+        let push_descriptor = create_info.push_descriptor && renderer.push_descriptors_supported();
+        let flags = if push_descriptor {
+            vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+        } else if renderer.descriptor_heap.is_some() {
+            vk::DescriptorSetLayoutCreateFlags::DESCRIPTOR_BUFFER_EXT
+        } else {
+            vk::DescriptorSetLayoutCreateFlags::default()
+        };
 
         let layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
             .bindings(&bindings)
@@ -58,7 +92,11 @@ impl DescriptorSetLayout {
 
         let layout = unsafe { renderer.device.create_descriptor_set_layout(&layout_create_info, None).unwrap() };
 
-        Arc::new(DescriptorSetLayout { layout, renderer })
+        let heap_size = renderer.descriptor_heap.as_ref().map_or(0, |heap| heap.set_size(layout));
+
+        let bindings = bindings.iter().map(|b| (b.binding, b.descriptor_type)).collect();
+
+        Arc::new(DescriptorSetLayout { layout, push_descriptor, heap_size, bindings, renderer })
     }
 }
 
@@ -68,48 +106,341 @@ impl Drop for DescriptorSetLayout {
     }
 }
 
+/// A set in either descriptor backend.
+#[derive(Clone, Copy)]
+pub(crate) enum SetHandle {
+    Pool(vk::DescriptorSet),
+    /// Offset of the set in the descriptor heap.
+    Heap(u64),
+}
+
+fn allocate_set(renderer: &Renderer, layout: &DescriptorSetLayout) -> SetHandle {
+    renderer.frame_counters.descriptor_set_allocation();
+    if let Some(heap) = &renderer.descriptor_heap {
+        return SetHandle::Heap(heap.allocate(layout.heap_size));
+    }
+
+    let layouts = [layout.layout];
+    let pool = renderer.descriptor_pool.lock().unwrap();
+    let alloc_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(*pool)
+        .set_layouts(&layouts);
+    SetHandle::Pool(unsafe { renderer.device.allocate_descriptor_sets(&alloc_info).unwrap()[0] })
+}
+
+fn free_set(renderer: &Renderer, layout: &DescriptorSetLayout, set: SetHandle) {
+    match set {
+        SetHandle::Pool(set) => {
+            let pool = renderer.descriptor_pool.lock().unwrap();
+            unsafe { renderer.device.free_descriptor_sets(*pool, &[set]).unwrap(); }
+        }
+        SetHandle::Heap(offset) => renderer.descriptor_heap.as_ref().unwrap().free(offset, layout.heap_size),
+    }
+}
+
+enum Descriptor<'a> {
+    Buffer(vk::DescriptorType, &'a Buffer),
+    TexelBuffer(vk::DescriptorType, &'a BufferView),
+    StorageImage(vk::ImageView),
+    CombinedImageSampler(vk::ImageView, &'a Sampler),
+    #[cfg(feature = "ray-tracing")]
+    AccelerationStructure(&'a AccelerationStructure),
+}
+
+fn write_descriptor(renderer: &Renderer, layout: &DescriptorSetLayout, set: SetHandle, binding: u32, descriptor: Descriptor) {
+    match set {
+        SetHandle::Pool(set) => {
+            let buffer_info;
+            let image_info;
+            let texel_buffer_view;
+            #[cfg(feature = "ray-tracing")]
+            let acceleration_structures;
+            #[cfg(feature = "ray-tracing")]
+            let mut acceleration_structure_info;
+            let write = vk::WriteDescriptorSet::default()
+                .dst_binding(binding)
+                .dst_set(set)
+                .descriptor_count(1);
+            let write = match descriptor {
+                Descriptor::Buffer(descriptor_type, buffer) => {
+                    buffer_info = [vk::DescriptorBufferInfo::default()
+                        .buffer(buffer.buffer)
+                        .offset(0)
+                        .range(vk::WHOLE_SIZE)];
+                    write.descriptor_type(descriptor_type).buffer_info(&buffer_info)
+                }
+                Descriptor::TexelBuffer(descriptor_type, view) => {
+                    texel_buffer_view = [view.view];
+                    write.descriptor_type(descriptor_type).texel_buffer_view(&texel_buffer_view)
+                }
+                Descriptor::StorageImage(image_view) => {
+                    image_info = [vk::DescriptorImageInfo::default()
+                        .image_view(image_view)
+                        .image_layout(vk::ImageLayout::GENERAL)];
+                    write.descriptor_type(vk::DescriptorType::STORAGE_IMAGE).image_info(&image_info)
+                }
+                Descriptor::CombinedImageSampler(image_view, sampler) => {
+                    image_info = [vk::DescriptorImageInfo::default()
+                        .image_view(image_view)
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .sampler(sampler.sampler)];
+                    write.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER).image_info(&image_info)
+                }
+                #[cfg(feature = "ray-tracing")]
+                Descriptor::AccelerationStructure(acceleration_structure) => {
+                    acceleration_structures = [acceleration_structure.handle];
+                    acceleration_structure_info = vk::WriteDescriptorSetAccelerationStructureKHR::default()
+                        .acceleration_structures(&acceleration_structures);
+                    write.descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR).push_next(&mut acceleration_structure_info)
+                }
+            };
+            unsafe { renderer.device.update_descriptor_sets(&[write], &[]); }
+        }
+        SetHandle::Heap(offset) => {
+            let heap = renderer.descriptor_heap.as_ref().unwrap();
+            let descriptor = match descriptor {
+                Descriptor::Buffer(vk::DescriptorType::UNIFORM_BUFFER, buffer) => HeapDescriptor::UniformBuffer { address: buffer.device_address(), range: buffer.size() },
+                Descriptor::Buffer(_, buffer) => HeapDescriptor::StorageBuffer { address: buffer.device_address(), range: buffer.size() },
+                Descriptor::TexelBuffer(vk::DescriptorType::UNIFORM_TEXEL_BUFFER, view) => HeapDescriptor::UniformTexelBuffer { address: view.buffer.device_address() + view.offset, range: view.range, format: view.format },
+                Descriptor::TexelBuffer(_, view) => HeapDescriptor::StorageTexelBuffer { address: view.buffer.device_address() + view.offset, range: view.range, format: view.format },
+                Descriptor::StorageImage(image_view) => HeapDescriptor::StorageImage(image_view),
+                Descriptor::CombinedImageSampler(image_view, sampler) => HeapDescriptor::CombinedImageSampler(image_view, sampler.sampler),
+                #[cfg(feature = "ray-tracing")]
+                Descriptor::AccelerationStructure(acceleration_structure) => HeapDescriptor::AccelerationStructure(acceleration_structure.address),
+            };
+            heap.write(&renderer.allocator, offset + heap.binding_offset(layout.layout, binding), descriptor);
+        }
+    }
+}
+
 pub struct DescriptorSet {
-    descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_sets: Vec<SetHandle>,
 
     renderer: Arc<Renderer>,
-    layout: Arc<DescriptorSetLayout>,
+    pub(crate) layout: Arc<DescriptorSetLayout>,
 }
 
 impl DescriptorSet {
     pub fn new(renderer: Arc<Renderer>, layout: Arc<DescriptorSetLayout>) -> Arc<Self> {
-        let layouts = (0..FRAME_OVERLAP)
-            .map(|_| layout.layout)
-            .collect::<Vec<_>>();
-        let alloc_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(renderer.descriptor_pool)
-            .set_layouts(&layouts);
-        let descriptor_sets = unsafe { renderer.device.allocate_descriptor_sets(&alloc_info).unwrap() };
+        let descriptor_sets = (0..FRAME_OVERLAP)
+            .map(|_| allocate_set(&renderer, &layout))
+            .collect();
 
         Arc::new(DescriptorSet { descriptor_sets, renderer, layout })
     }
 
-    pub(crate) fn get_current(&self) -> vk::DescriptorSet {
+    pub(crate) fn get_current(&self) -> SetHandle {
         self.descriptor_sets[self.renderer.current_frame()]
     }
 
     pub fn write_texture(&self, binding: u32, texture: &Texture) {
-        let img_infos = [vk::DescriptorImageInfo::default()
-            .image_view(texture.image_view)
-            .image_layout(vk::ImageLayout::GENERAL)];
+        self.validate_texture(binding, texture, vk::DescriptorType::STORAGE_IMAGE);
+        self.write(binding, Descriptor::StorageImage(texture.image_view));
+    }
+
+    /// Binds a single layer of an array texture as a 2D storage image.
+    pub fn write_texture_layer(&self, binding: u32, texture: &Texture, layer: u32) {
+        self.validate_texture(binding, texture, vk::DescriptorType::STORAGE_IMAGE);
+        self.write(binding, Descriptor::StorageImage(texture.layer_view(layer)));
+    }
+
+    /// Binds one mip level of a texture as a storage image, cubemaps as a
+    /// 2D array of their faces.
+    pub fn write_texture_mip(&self, binding: u32, texture: &Texture, level: u32) {
+        self.validate_texture(binding, texture, vk::DescriptorType::STORAGE_IMAGE);
+        self.write(binding, Descriptor::StorageImage(texture.mip_view(level)));
+    }
+
+    pub fn write_sampled_texture(&self, binding: u32, texture: &Texture, sampler: &Sampler) {
+        self.validate_texture(binding, texture, vk::DescriptorType::COMBINED_IMAGE_SAMPLER);
+        self.write(binding, Descriptor::CombinedImageSampler(texture.image_view, sampler));
+    }
+
+    pub fn write_uniform_buffer(&self, binding: u32, buffer: &Buffer) {
+        self.validate_buffer(binding, buffer, vk::DescriptorType::UNIFORM_BUFFER);
+        self.write(binding, Descriptor::Buffer(vk::DescriptorType::UNIFORM_BUFFER, buffer));
+    }
+
+    pub fn write_storage_buffer(&self, binding: u32, buffer: &Buffer) {
+        self.validate_buffer(binding, buffer, vk::DescriptorType::STORAGE_BUFFER);
+        self.write(binding, Descriptor::Buffer(vk::DescriptorType::STORAGE_BUFFER, buffer));
+    }
+
+    pub fn write_uniform_texel_buffer(&self, binding: u32, view: &BufferView) {
+        self.validate_buffer(binding, &view.buffer, vk::DescriptorType::UNIFORM_TEXEL_BUFFER);
+        self.write(binding, Descriptor::TexelBuffer(vk::DescriptorType::UNIFORM_TEXEL_BUFFER, view));
+    }
+
+    pub fn write_storage_texel_buffer(&self, binding: u32, view: &BufferView) {
+        self.validate_buffer(binding, &view.buffer, vk::DescriptorType::STORAGE_TEXEL_BUFFER);
+        self.write(binding, Descriptor::TexelBuffer(vk::DescriptorType::STORAGE_TEXEL_BUFFER, view));
+    }
 
-        let writes = [vk::WriteDescriptorSet::default()
-            .dst_binding(binding)
-            .dst_set(self.get_current())
-            .descriptor_count(1)
-            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-            .image_info(&img_infos)];
+    /// Binds a top level acceleration structure for ray queries.
+    #[cfg(feature = "ray-tracing")]
+    pub fn write_acceleration_structure(&self, binding: u32, acceleration_structure: &AccelerationStructure) {
+        if self.renderer.validate_usage() {
+            validation::check_binding(&self.layout, binding, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR);
+            assert!(acceleration_structure.is_top_level(), "Only top level acceleration structures can be bound");
+        }
+        self.write(binding, Descriptor::AccelerationStructure(acceleration_structure));
+    }
 
-        unsafe { self.renderer.device.update_descriptor_sets(&writes, &[]); }
+    fn write(&self, binding: u32, descriptor: Descriptor) {
+        write_descriptor(&self.renderer, &self.layout, self.get_current(), binding, descriptor);
+    }
+
+    fn validate_texture(&self, binding: u32, texture: &Texture, descriptor_type: vk::DescriptorType) {
+        if !self.renderer.validate_usage() {
+            return;
+        }
+        let usage = match descriptor_type {
+            vk::DescriptorType::STORAGE_IMAGE => vk::ImageUsageFlags::STORAGE,
+            _ => vk::ImageUsageFlags::SAMPLED,
+        };
+        validation::check_binding(&self.layout, binding, descriptor_type);
+        validation::check_texture_usage(texture, usage, &format!("Binding as {descriptor_type:?}"));
+    }
+
+    fn validate_buffer(&self, binding: u32, buffer: &Buffer, descriptor_type: vk::DescriptorType) {
+        if !self.renderer.validate_usage() {
+            return;
+        }
+        let usage = match descriptor_type {
+            vk::DescriptorType::UNIFORM_BUFFER => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::DescriptorType::UNIFORM_TEXEL_BUFFER => vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER,
+            vk::DescriptorType::STORAGE_TEXEL_BUFFER => vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER,
+            _ => vk::BufferUsageFlags::STORAGE_BUFFER,
+        };
+        validation::check_binding(&self.layout, binding, descriptor_type);
+        validation::check_buffer_usage(buffer, usage, &format!("Binding as {descriptor_type:?}"));
     }
 }
 
 impl Drop for DescriptorSet {
     fn drop(&mut self) {
-        unsafe { self.renderer.device.free_descriptor_sets(self.renderer.descriptor_pool, &self.descriptor_sets).unwrap(); }
+        for &set in &self.descriptor_sets {
+            free_set(&self.renderer, &self.layout, set);
+        }
+    }
+}
+
+/// A descriptor recorded with `CommandList::push_descriptor_set`. The
+/// resources are kept alive by the command list.
+pub enum DescriptorWrite {
+    UniformBuffer { binding: u32, buffer: Arc<Buffer> },
+    StorageBuffer { binding: u32, buffer: Arc<Buffer> },
+    UniformTexelBuffer { binding: u32, view: Arc<BufferView> },
+    StorageTexelBuffer { binding: u32, view: Arc<BufferView> },
+    Texture { binding: u32, texture: Arc<Texture> },
+    SampledTexture { binding: u32, texture: Arc<Texture>, sampler: Arc<Sampler> },
+}
+
+impl DescriptorWrite {
+    pub(crate) fn resources(&self) -> Vec<Arc<dyn Any + Send + Sync>> {
+        match self {
+            DescriptorWrite::UniformBuffer { buffer, .. } | DescriptorWrite::StorageBuffer { buffer, .. } => vec![buffer.clone()],
+            DescriptorWrite::UniformTexelBuffer { view, .. } | DescriptorWrite::StorageTexelBuffer { view, .. } => vec![view.clone()],
+            DescriptorWrite::Texture { texture, .. } => vec![texture.clone()],
+            DescriptorWrite::SampledTexture { texture, sampler, .. } => vec![texture.clone(), sampler.clone()],
+        }
     }
-}
\ No newline at end of file
+
+    fn descriptor(&self) -> (u32, Descriptor<'_>) {
+        match self {
+            DescriptorWrite::UniformBuffer { binding, buffer } => (*binding, Descriptor::Buffer(vk::DescriptorType::UNIFORM_BUFFER, buffer)),
+            DescriptorWrite::StorageBuffer { binding, buffer } => (*binding, Descriptor::Buffer(vk::DescriptorType::STORAGE_BUFFER, buffer)),
+            DescriptorWrite::UniformTexelBuffer { binding, view } => (*binding, Descriptor::TexelBuffer(vk::DescriptorType::UNIFORM_TEXEL_BUFFER, view)),
+            DescriptorWrite::StorageTexelBuffer { binding, view } => (*binding, Descriptor::TexelBuffer(vk::DescriptorType::STORAGE_TEXEL_BUFFER, view)),
+            DescriptorWrite::Texture { binding, texture } => (*binding, Descriptor::StorageImage(texture.image_view)),
+            DescriptorWrite::SampledTexture { binding, texture, sampler } => (*binding, Descriptor::CombinedImageSampler(texture.image_view, sampler)),
+        }
+    }
+}
+
+/// Converts `writes` and passes them to `f`. `dst_set` is left null, for
+/// push descriptors.
+pub(crate) fn with_vk_writes<R>(writes: &[DescriptorWrite], f: impl FnOnce(&[vk::WriteDescriptorSet]) -> R) -> R {
+    let buffer_infos = writes.iter().map(|w| match w {
+        DescriptorWrite::UniformBuffer { buffer, .. } | DescriptorWrite::StorageBuffer { buffer, .. } => vk::DescriptorBufferInfo::default()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE),
+        _ => vk::DescriptorBufferInfo::default(),
+    }).collect::<Vec<_>>();
+
+    let image_infos = writes.iter().map(|w| match w {
+        DescriptorWrite::Texture { texture, .. } => vk::DescriptorImageInfo::default()
+            .image_view(texture.image_view)
+            .image_layout(vk::ImageLayout::GENERAL),
+        DescriptorWrite::SampledTexture { texture, sampler, .. } => vk::DescriptorImageInfo::default()
+            .image_view(texture.image_view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .sampler(sampler.sampler),
+        _ => vk::DescriptorImageInfo::default(),
+    }).collect::<Vec<_>>();
+
+    let texel_buffer_views = writes.iter().map(|w| match w {
+        DescriptorWrite::UniformTexelBuffer { view, .. } | DescriptorWrite::StorageTexelBuffer { view, .. } => view.view,
+        _ => vk::BufferView::null(),
+    }).collect::<Vec<_>>();
+
+    let vk_writes = writes.iter().enumerate().map(|(i, w)| {
+        let write = vk::WriteDescriptorSet::default().descriptor_count(1);
+        match w {
+            DescriptorWrite::UniformBuffer { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(&buffer_infos[i..i + 1]),
+            DescriptorWrite::StorageBuffer { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_infos[i..i + 1]),
+            DescriptorWrite::UniformTexelBuffer { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::UNIFORM_TEXEL_BUFFER)
+                .texel_buffer_view(&texel_buffer_views[i..i + 1]),
+            DescriptorWrite::StorageTexelBuffer { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_TEXEL_BUFFER)
+                .texel_buffer_view(&texel_buffer_views[i..i + 1]),
+            DescriptorWrite::Texture { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&image_infos[i..i + 1]),
+            DescriptorWrite::SampledTexture { binding, .. } => write
+                .dst_binding(*binding)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos[i..i + 1]),
+        }
+    }).collect::<Vec<_>>();
+
+    f(&vk_writes)
+}
+
+/// Single use set backing `push_descriptor_set` without the extension,
+/// retained by the command list and freed once the frame slot is reset.
+pub(crate) struct TransientDescriptorSet {
+    pub(crate) set: SetHandle,
+
+    renderer: Arc<Renderer>,
+    layout: Arc<DescriptorSetLayout>,
+}
+
+impl TransientDescriptorSet {
+    pub(crate) fn new(renderer: Arc<Renderer>, layout: Arc<DescriptorSetLayout>, writes: &[DescriptorWrite]) -> Self {
+        let set = allocate_set(&renderer, &layout);
+        for write in writes {
+            let (binding, descriptor) = write.descriptor();
+            write_descriptor(&renderer, &layout, set, binding, descriptor);
+        }
+
+        Self { set, renderer, layout }
+    }
+}
+
+impl Drop for TransientDescriptorSet {
+    fn drop(&mut self) {
+        free_set(&self.renderer, &self.layout, self.set);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+
+use ash::{Device, Instance, vk};
+use ash::amd::buffer_marker;
+use ash::prelude::VkResult;
+use vk_mem::{Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, Allocator, MemoryUsage};
+
+use crate::render::hal::{Error, QueueType, Result};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::util::log_target;
+
+/// Checkpoint labels are interned, the marker passed to the driver is the
+/// label index, so no pointers into Rust strings are handed to the GPU.
+#[derive(Default)]
+pub(crate) struct CheckpointLabels {
+    labels: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl CheckpointLabels {
+    fn intern(&mut self, label: &str) -> usize {
+        if let Some(&idx) = self.indices.get(label) {
+            return idx;
+        }
+        self.labels.push(label.to_string());
+        self.indices.insert(label.to_string(), self.labels.len());
+        self.labels.len()
+    }
+
+    fn get(&self, marker: usize) -> &str {
+        marker.checked_sub(1)
+            .and_then(|idx| self.labels.get(idx))
+            .map(String::as_str)
+            .unwrap_or("<unknown>")
+    }
+}
+
+/// `VK_AMD_buffer_marker` stand-in for checkpoints. Every checkpoint writes
+/// its marker into a host visible buffer twice, once its queue started the
+/// commands before it and once it finished them. Each queue has its own
+/// pair of slots.
+pub(crate) struct BufferMarkers {
+    loader: buffer_marker::Device,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped: *const u32,
+}
+
+// The mapped memory is only written by the GPU and read after device loss.
+unsafe impl Send for BufferMarkers {}
+unsafe impl Sync for BufferMarkers {}
+
+const MARKER_QUEUES: [QueueType; 2] = [QueueType::Graphics, QueueType::Compute];
+
+fn marker_slots(queue: QueueType) -> (u64, u64) {
+    let base = match queue {
+        QueueType::Graphics => 0,
+        QueueType::Compute => 2,
+    };
+    (base, base + 1)
+}
+
+impl BufferMarkers {
+    pub(crate) fn new(instance: &Instance, device: &Device, allocator: &Allocator) -> Self {
+        let loader = buffer_marker::Device::new(instance, device);
+
+        let buffer_create_info = vk::BufferCreateInfo::default()
+            .size(MARKER_QUEUES.len() as u64 * 2 * size_of::<u32>() as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST);
+        // Coherent, so the markers written before the device was lost
+        // can be read without a flush.
+        let allocation_info = AllocationCreateInfo {
+            usage: MemoryUsage::Auto,
+            flags: AllocationCreateFlags::HOST_ACCESS_RANDOM | AllocationCreateFlags::MAPPED,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ..Default::default()
+        };
+        let (buffer, allocation) = unsafe { allocator.create_buffer(&buffer_create_info, &allocation_info).unwrap() };
+        let mapped = allocator.get_allocation_info(&allocation).mapped_data as *mut u32;
+        unsafe { std::ptr::write_bytes(mapped, 0, MARKER_QUEUES.len() * 2) };
+
+        Self { loader, buffer, allocation, mapped }
+    }
+
+    fn write(&self, command_buffer: vk::CommandBuffer, queue: QueueType, marker: u32) {
+        let (started, finished) = marker_slots(queue);
+        let size = size_of::<u32>() as u64;
+        unsafe {
+            self.loader.cmd_write_buffer_marker(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, self.buffer, started * size, marker);
+            self.loader.cmd_write_buffer_marker(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, self.buffer, finished * size, marker);
+        }
+    }
+
+    /// Last started and finished marker of `queue`.
+    fn read(&self, queue: QueueType) -> (u32, u32) {
+        let (started, finished) = marker_slots(queue);
+        unsafe {
+            let read = |slot: u64| std::ptr::read_volatile(self.mapped.add(slot as usize));
+            (read(started), read(finished))
+        }
+    }
+
+    /// Must be called before the allocator and device are destroyed.
+    pub(crate) fn destroy(&mut self, allocator: &Allocator) {
+        unsafe { allocator.destroy_buffer(self.buffer, &mut self.allocation) };
+    }
+}
+
+impl Renderer {
+    /// Once the device is lost every further call fails with
+    /// `Error::DeviceLost`. To recover, drop every object created from this
+    /// renderer (a `ResourceRegistry::clear` helps with that), create a new
+    /// `Renderer` for `window()` and recreate the resources.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// Checkpoints the GPU last passed before the device was lost, when
+    /// `RendererCreateInfo::gpu_crash_diagnostics` was enabled and supported.
+    pub fn device_lost_report(&self) -> Option<String> {
+        self.device_lost_report.lock().unwrap().clone()
+    }
+
+    pub fn is_crash_diagnostics_enabled(&self) -> bool {
+        self.checkpoints_loader.is_some() || self.buffer_markers.is_some()
+    }
+
+    pub(crate) fn set_checkpoint(&self, command_buffer: vk::CommandBuffer, queue: QueueType, label: &str) {
+        if !self.is_crash_diagnostics_enabled() {
+            return;
+        }
+
+        let marker = self.checkpoint_labels.lock().unwrap().intern(label);
+        if let Some(loader) = &self.checkpoints_loader {
+            unsafe { loader.cmd_set_checkpoint(command_buffer, marker as *const c_void) };
+        } else if let Some(markers) = &self.buffer_markers {
+            markers.write(command_buffer, queue, marker as u32);
+        }
+    }
+
+    /// Converts a Vulkan result, recording device loss on the way.
+    pub(crate) fn check<T>(&self, res: VkResult<T>) -> Result<T> {
+        match res {
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                if !self.device_lost.swap(true, Ordering::AcqRel) {
+                    let report = self.collect_checkpoints();
+                    log::error!(target: log_target::VULKAN, "{report}");
+                    *self.device_lost_report.lock().unwrap() = Some(report);
+                }
+                Err(Error::DeviceLost)
+            }
+            Err(err) => Err(err.into()),
+            Ok(value) => Ok(value),
+        }
+    }
+
+    fn collect_checkpoints(&self) -> String {
+        let mut report = String::from("Device lost\n");
+
+        if let Some(markers) = &self.buffer_markers {
+            let labels = self.checkpoint_labels.lock().unwrap();
+            for queue in MARKER_QUEUES {
+                if queue == QueueType::Compute && self.compute_queue.is_none() {
+                    continue;
+                }
+                let (started, finished) = markers.read(queue);
+                writeln!(report, "{queue:?} queue started: {}", labels.get(started as usize)).unwrap();
+                writeln!(report, "{queue:?} queue finished: {}", labels.get(finished as usize)).unwrap();
+            }
+            return report;
+        }
+
+        let Some(loader) = &self.checkpoints_loader else {
+            report.push_str("GPU crash diagnostics are not enabled\n");
+            return report;
+        };
+
+        let queues = [("Graphics", Some(&self.graphics_queue)), ("Compute", self.compute_queue.as_ref()), ("Present", self.present_queue.as_ref())];
+        for (name, queue) in queues {
+            let Some(queue) = queue else {
+                continue;
+            };
+            let queue = *queue.lock().unwrap();
+            let checkpoints = unsafe {
+                let len = loader.get_queue_checkpoint_data_len(queue);
+                let mut checkpoints = vec![vk::CheckpointDataNV::default(); len];
+                loader.get_queue_checkpoint_data(queue, &mut checkpoints);
+                checkpoints
+            };
+
+            let labels = self.checkpoint_labels.lock().unwrap();
+            for checkpoint in checkpoints {
+                let label = labels.get(checkpoint.p_checkpoint_marker as usize);
+                writeln!(report, "{name} queue {:?}: {label}", checkpoint.stage).unwrap();
+            }
+        }
+
+        report
+    }
+}
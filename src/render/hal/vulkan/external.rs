@@ -0,0 +1,140 @@
+#[cfg(not(windows))]
+use std::os::fd::{FromRawFd, OwnedFd};
+#[cfg(windows)]
+use std::os::windows::io::{FromRawHandle, OwnedHandle};
+
+use ash::{Device, Instance, vk};
+#[cfg(not(windows))]
+use ash::khr::{external_memory_fd, external_semaphore_fd};
+#[cfg(windows)]
+use ash::khr::{external_memory_win32, external_semaphore_win32};
+use ash::prelude::VkResult;
+
+/// Handle to memory or a semaphore exported to another API or process. The
+/// importer takes ownership, otherwise it's closed when dropped.
+#[cfg(not(windows))]
+pub type ExternalHandle = OwnedFd;
+#[cfg(windows)]
+pub type ExternalHandle = OwnedHandle;
+
+/// What exported memory is imported as, e.g. `cudaExternalMemoryHandleTypeOpaqueFd`
+/// or `GL_HANDLE_TYPE_OPAQUE_FD_EXT`.
+#[cfg(not(windows))]
+pub const MEMORY_HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags = vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD;
+#[cfg(windows)]
+pub const MEMORY_HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags = vk::ExternalMemoryHandleTypeFlags::OPAQUE_WIN32;
+
+#[cfg(not(windows))]
+pub const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags = vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD;
+#[cfg(windows)]
+pub const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags = vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32;
+
+/// Extensions needed for `Capabilities::ExternalMemory`.
+#[cfg(not(windows))]
+pub(crate) fn get_device_extensions() -> [&'static std::ffi::CStr; 2] {
+    [external_memory_fd::NAME, external_semaphore_fd::NAME]
+}
+
+/// Extensions needed for `Capabilities::ExternalMemory`.
+#[cfg(windows)]
+pub(crate) fn get_device_extensions() -> [&'static std::ffi::CStr; 2] {
+    [external_memory_win32::NAME, external_semaphore_win32::NAME]
+}
+
+/// `VK_KHR_external_memory` and `VK_KHR_external_semaphore` are core since
+/// Vulkan 1.1, the platform handle extensions come from
+/// `get_device_extensions`.
+pub(crate) fn is_supported(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let info = vk::PhysicalDeviceExternalSemaphoreInfo::default().handle_type(SEMAPHORE_HANDLE_TYPE);
+    let mut properties = vk::ExternalSemaphoreProperties::default();
+    unsafe { instance.get_physical_device_external_semaphore_properties(physical_device, &info, &mut properties) };
+    properties.external_semaphore_features.contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE)
+}
+
+/// Whether images created with `image_info` can be exported, `image_info`
+/// is checked without its `p_next` chain.
+pub(crate) fn is_image_exportable(instance: &Instance, physical_device: vk::PhysicalDevice, image_info: &vk::ImageCreateInfo) -> bool {
+    let mut external_info = vk::PhysicalDeviceExternalImageFormatInfo::default().handle_type(MEMORY_HANDLE_TYPE);
+    let format_info = vk::PhysicalDeviceImageFormatInfo2::default()
+        .format(image_info.format)
+        .ty(image_info.image_type)
+        .tiling(image_info.tiling)
+        .usage(image_info.usage)
+        .flags(image_info.flags)
+        .push_next(&mut external_info);
+    let mut external_properties = vk::ExternalImageFormatProperties::default();
+    let mut properties = vk::ImageFormatProperties2::default().push_next(&mut external_properties);
+    let supported = unsafe { instance.get_physical_device_image_format_properties2(physical_device, &format_info, &mut properties) };
+    supported.is_ok() && external_properties.external_memory_properties.external_memory_features.contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE)
+}
+
+/// Memory of a texture exported with `Texture::export_memory`. The texture
+/// has a dedicated allocation, importers must be told so, e.g. with
+/// `cudaExternalMemoryDedicated`.
+pub struct ExternalMemory {
+    pub handle: ExternalHandle,
+    pub handle_type: vk::ExternalMemoryHandleTypeFlags,
+    /// Of the whole allocation, in bytes.
+    pub size: u64,
+    /// Where the image starts in the allocation.
+    pub offset: u64,
+}
+
+/// Loaders of the platform handle extensions, present when
+/// `Capabilities::ExternalMemory` is.
+pub(crate) struct ExternalSupport {
+    #[cfg(not(windows))]
+    memory: external_memory_fd::Device,
+    #[cfg(not(windows))]
+    semaphore: external_semaphore_fd::Device,
+    #[cfg(windows)]
+    memory: external_memory_win32::Device,
+    #[cfg(windows)]
+    semaphore: external_semaphore_win32::Device,
+}
+
+impl ExternalSupport {
+    #[cfg(not(windows))]
+    pub(crate) fn new(instance: &Instance, device: &Device) -> Self {
+        Self {
+            memory: external_memory_fd::Device::new(instance, device),
+            semaphore: external_semaphore_fd::Device::new(instance, device),
+        }
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn new(instance: &Instance, device: &Device) -> Self {
+        Self {
+            memory: external_memory_win32::Device::new(instance, device),
+            semaphore: external_semaphore_win32::Device::new(instance, device),
+        }
+    }
+
+    #[cfg(not(windows))]
+    pub(crate) unsafe fn export_memory(&self, memory: vk::DeviceMemory) -> VkResult<ExternalHandle> {
+        let info = vk::MemoryGetFdInfoKHR::default().memory(memory).handle_type(MEMORY_HANDLE_TYPE);
+        let fd = self.memory.get_memory_fd(&info)?;
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+
+    #[cfg(windows)]
+    pub(crate) unsafe fn export_memory(&self, memory: vk::DeviceMemory) -> VkResult<ExternalHandle> {
+        let info = vk::MemoryGetWin32HandleInfoKHR::default().memory(memory).handle_type(MEMORY_HANDLE_TYPE);
+        let handle = self.memory.get_memory_win32_handle(&info)?;
+        Ok(OwnedHandle::from_raw_handle(handle as _))
+    }
+
+    #[cfg(not(windows))]
+    pub(crate) unsafe fn export_semaphore(&self, semaphore: vk::Semaphore) -> VkResult<ExternalHandle> {
+        let info = vk::SemaphoreGetFdInfoKHR::default().semaphore(semaphore).handle_type(SEMAPHORE_HANDLE_TYPE);
+        let fd = self.semaphore.get_semaphore_fd(&info)?;
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+
+    #[cfg(windows)]
+    pub(crate) unsafe fn export_semaphore(&self, semaphore: vk::Semaphore) -> VkResult<ExternalHandle> {
+        let info = vk::SemaphoreGetWin32HandleInfoKHR::default().semaphore(semaphore).handle_type(SEMAPHORE_HANDLE_TYPE);
+        let handle = self.semaphore.get_semaphore_win32_handle(&info)?;
+        Ok(OwnedHandle::from_raw_handle(handle as _))
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use crate::render::hal::{CommandListCreateInfo, QueueType, Result};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sync::Fence;
+
+/// Command list and fence of the frames presented to the swapchain.
+///
+/// ```ignore
+/// let mut frames = FrameContext::new(renderer.clone());
+/// loop {
+///     let mut frame = frames.begin_frame()?;
+///     pass.record(frame.command_list(), &texture);
+///     frame.end()?;
+/// }
+/// ```
+///
+/// Kept apart from the `Renderer`, the command list holds a reference to
+/// the renderer.
+pub struct FrameContext {
+    command_list: CommandList,
+    fence: Fence,
+    renderer: Arc<Renderer>,
+}
+
+impl FrameContext {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        let command_list = {
+            let create_info = CommandListCreateInfo { queue: QueueType::Graphics };
+            CommandList::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            command_list,
+            fence: Fence::new(renderer.clone()),
+            renderer,
+        }
+    }
+
+    /// Waits until the frame slot is free again, acquires the next
+    /// swapchain image and starts recording.
+    pub fn begin_frame(&mut self) -> Result<Frame<'_>> {
+        self.fence.wait()?;
+        self.renderer.start_frame()?;
+        // Reset after the acquire succeeded, an unsignaled fence would
+        // block the next `begin_frame` forever otherwise.
+        self.fence.reset()?;
+
+        self.command_list.reset();
+        self.command_list.begin();
+
+        Ok(Frame { context: self, ended: false })
+    }
+}
+
+/// A frame being recorded. Ending it submits the command list and presents
+/// the swapchain image. A frame dropped without `end` is ended as well,
+/// the acquired image has to be presented.
+pub struct Frame<'a> {
+    context: &'a mut FrameContext,
+    ended: bool,
+}
+
+impl Frame<'_> {
+    pub fn command_list(&mut self) -> &mut CommandList {
+        &mut self.context.command_list
+    }
+
+    /// Signaled when the frame's commands completed. Reset by the
+    /// `begin_frame` reusing its slot.
+    pub fn fence(&self) -> &Fence {
+        &self.context.fence
+    }
+
+    pub fn renderer(&self) -> &Arc<Renderer> {
+        &self.context.renderer
+    }
+
+    pub fn end(mut self) -> Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.ended = true;
+
+        let context = &mut *self.context;
+        context.command_list.end();
+        context.renderer.submit_frame(&context.command_list, &context.fence)?;
+        context.renderer.present()
+    }
+}
+
+impl Drop for Frame<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.finish();
+        }
+    }
+}
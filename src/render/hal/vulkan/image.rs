@@ -1,9 +1,15 @@
 use std::sync::Arc;
 
 use ash::vk;
-use vk_mem::{Alloc, Allocation, AllocationCreateInfo, MemoryUsage};
+use vk_mem::{Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, MemoryUsage};
 
+use crate::render::hal::{BufferCreateInfo, BufferTextureCopy, CommandListCreateInfo, Error, MemoryLocation, PoolResources, QueueType, Result, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::external::{self, ExternalMemory, MEMORY_HANDLE_TYPE};
+use crate::render::hal::vulkan::memory::{MemoryPool, ResourceKind};
 use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sync::Fence;
 
 pub trait Image {
     unsafe fn get_image_view(&self) -> vk::ImageView;
@@ -18,57 +24,321 @@ pub struct Framebuffer {
 pub struct Texture {
     pub(super) image: vk::Image,
     pub(super) image_view: vk::ImageView,
+    pub(super) layer_views: Vec<vk::ImageView>,
+    pub(super) mip_views: Vec<vk::ImageView>,
     pub(super) allocation: Allocation,
     pub(super) extent: vk::Extent3D,
     pub(super) format: vk::Format,
+    pub(super) array_layers: u32,
+    pub(super) mip_levels: u32,
+    pub(super) aspect: vk::ImageAspectFlags,
+    pub(super) usage: vk::ImageUsageFlags,
+    tracking_id: Option<u64>,
+    /// Kept alive until the allocation is freed.
+    pool: Option<Arc<MemoryPool>>,
     renderer: Arc<Renderer>,
 }
 
 impl Texture {
-    pub fn new(renderer: Arc<Renderer>, format: vk::Format, extent: vk::Extent3D, usage: vk::ImageUsageFlags, aspect_flags: vk::ImageAspectFlags) -> Self {
-        let image_create_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(format)
-            .extent(extent)
-            .mip_levels(1)
-            .array_layers(1)
+    pub fn new(renderer: Arc<Renderer>, create_info: TextureCreateInfo) -> Self {
+        Self::create(renderer, create_info, None, false)
+    }
+
+    /// Allocates the texture from `pool`, which must hold textures.
+    pub fn new_in(renderer: Arc<Renderer>, create_info: TextureCreateInfo, pool: &Arc<MemoryPool>) -> Self {
+        assert!(matches!(pool.resources(), PoolResources::Textures { .. }), "Texture created in a buffer memory pool");
+        Self::create(renderer, create_info, Some(pool.clone()), false)
+    }
+
+    /// Texture in its own allocation that other APIs and processes can
+    /// import with the handle from `export_memory`, e.g. to run CUDA kernels
+    /// on it or draw it in a GL or DX tool without copies. Needs
+    /// `Capabilities::ExternalMemory`. Access from both sides has to be
+    /// ordered with exported semaphores, see `Semaphore::new_exportable`
+    /// and `CommandList::release_to_external`.
+    pub fn new_exportable(renderer: Arc<Renderer>, create_info: TextureCreateInfo) -> Result<Self> {
+        assert!(renderer.external.is_some(), "Exportable textures need Capabilities::ExternalMemory");
+        let image_info = Self::image_create_info(&create_info);
+        if !external::is_image_exportable(&renderer.render_instance.instance, renderer.physical_device, &image_info) {
+            return Err(Error::Backend(format!("Textures of format {:?} and usage {:?} can't be exported", create_info.format, create_info.usage)));
+        }
+
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::default().handle_types(MEMORY_HANDLE_TYPE);
+        let pool = MemoryPool::exportable(renderer.clone(), &image_info.push_next(&mut external_info))?;
+        Ok(Self::create(renderer, create_info, Some(pool), true))
+    }
+
+    fn image_create_info(create_info: &TextureCreateInfo) -> vk::ImageCreateInfo<'static> {
+        let flags = if create_info.kind == TextureKind::Cube {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+
+        let image_type = if create_info.kind == TextureKind::D3 {
+            vk::ImageType::TYPE_3D
+        } else {
+            vk::ImageType::TYPE_2D
+        };
+
+        vk::ImageCreateInfo::default()
+            .flags(flags)
+            .image_type(image_type)
+            .format(create_info.format)
+            .extent(create_info.extent)
+            .mip_levels(create_info.mip_levels)
+            .array_layers(create_info.array_layers)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(usage);
+            .usage(create_info.usage)
+    }
+
+    fn create(renderer: Arc<Renderer>, create_info: TextureCreateInfo, pool: Option<Arc<MemoryPool>>, exportable: bool) -> Self {
+        assert!(create_info.kind != TextureKind::D3 || create_info.array_layers == 1, "3D textures can't have array layers");
+        let image_create_info = Self::image_create_info(&create_info);
+        let TextureCreateInfo { format, extent, usage, aspect, array_layers, mip_levels, kind } = create_info;
+
+        let mut external_info = vk::ExternalMemoryImageCreateInfo::default().handle_types(MEMORY_HANDLE_TYPE);
+        let image_create_info = if exportable {
+            image_create_info.push_next(&mut external_info)
+        } else {
+            image_create_info
+        };
 
-        let allocation_info = AllocationCreateInfo {
+        let mut allocation_info = Self::allocation_info();
+        if exportable {
+            // Importers need the image at a known place in memory of its own.
+            allocation_info.flags |= AllocationCreateFlags::DEDICATED_MEMORY;
+        }
+        let (image, allocation) = unsafe {
+            match &pool {
+                Some(pool) => pool.pool.create_image(&image_create_info, &allocation_info).unwrap(),
+                None => renderer.allocator.create_image(&image_create_info, &allocation_info).unwrap(),
+            }
+        };
+        let size = renderer.allocator.get_allocation_info(&allocation).size;
+        renderer.texture_memory.add(size);
+        let tracking_id = renderer.resource_tracker.as_ref().map(|t| t.track(ResourceKind::Texture, size));
+
+        let array_view_type = match (kind, array_layers) {
+            (TextureKind::D3, _) => vk::ImageViewType::TYPE_3D,
+            (_, 1) => vk::ImageViewType::TYPE_2D,
+            (_, _) => vk::ImageViewType::TYPE_2D_ARRAY,
+        };
+
+        let view_type = match (kind, array_layers) {
+            (TextureKind::Cube, 6) => vk::ImageViewType::CUBE,
+            (TextureKind::Cube, _) => vk::ImageViewType::CUBE_ARRAY,
+            (_, _) => array_view_type,
+        };
+
+        let image_view = Self::create_view(&renderer, image, view_type, format, aspect, 0, mip_levels, 0, array_layers);
+
+        let layer_views = if array_layers > 1 {
+            (0..array_layers)
+                .map(|layer| Self::create_view(&renderer, image, vk::ImageViewType::TYPE_2D, format, aspect, 0, mip_levels, layer, 1))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // Storage image views can only cover a single mip level.
+        let mip_views = if kind == TextureKind::Cube || mip_levels > 1 {
+            (0..mip_levels)
+                .map(|level| Self::create_view(&renderer, image, array_view_type, format, aspect, level, 1, 0, array_layers))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Texture { image, image_view, layer_views, mip_views, allocation, extent, format, array_layers, mip_levels, aspect, usage, tracking_id, pool, renderer }
+    }
+
+    pub(super) fn allocation_info() -> AllocationCreateInfo {
+        AllocationCreateInfo {
             usage: MemoryUsage::AutoPreferDevice,
             required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
             ..Default::default()
-        };
-
-        let (image, allocation) = unsafe { renderer.allocator.create_image(&image_create_info, &allocation_info).unwrap() };
+        }
+    }
 
+    #[allow(clippy::too_many_arguments)]
+    fn create_view(renderer: &Renderer, image: vk::Image, view_type: vk::ImageViewType, format: vk::Format, aspect: vk::ImageAspectFlags, base_mip: u32, mip_levels: u32, base_layer: u32, layer_count: u32) -> vk::ImageView {
         let imageview_create_info = vk::ImageViewCreateInfo::default()
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(view_type)
             .image(image)
             .format(format)
             .subresource_range(
                 vk::ImageSubresourceRange::default()
-                    .base_mip_level(0)
-                    .level_count(1)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .aspect_mask(aspect_flags)
+                    .base_mip_level(base_mip)
+                    .level_count(mip_levels)
+                    .base_array_layer(base_layer)
+                    .layer_count(layer_count)
+                    .aspect_mask(aspect)
             );
 
-        let image_view = unsafe { renderer.device.create_image_view(&imageview_create_info, None).unwrap() };
+        unsafe { renderer.device.create_image_view(&imageview_create_info, None).unwrap() }
+    }
+
+    /// Names the texture in debug tools and the live resources report.
+    pub fn set_name(&self, name: &str) {
+        self.renderer.set_object_name(self.image, name);
+        if let (Some(tracker), Some(id)) = (&self.renderer.resource_tracker, self.tracking_id) {
+            tracker.set_name(id, name);
+        }
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// View of a single layer, used when rendering into one slice of an array texture.
+    pub(crate) fn layer_view(&self, layer: u32) -> vk::ImageView {
+        if self.layer_views.is_empty() {
+            assert_eq!(layer, 0);
+            self.image_view
+        } else {
+            self.layer_views[layer as usize]
+        }
+    }
+
+    /// View of a single mip level covering all layers, used when writing
+    /// into a mip level or cubemap from a compute shader.
+    pub(crate) fn mip_view(&self, level: u32) -> vk::ImageView {
+        if self.mip_views.is_empty() {
+            assert_eq!(level, 0);
+            self.image_view
+        } else {
+            self.mip_views[level as usize]
+        }
+    }
+
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// The pool the texture was allocated from with `new_in`.
+    pub fn memory_pool(&self) -> Option<&Arc<MemoryPool>> {
+        self.pool.as_ref()
+    }
 
-        Texture { image, image_view, allocation, extent, format, renderer }
+    /// A new handle to the memory of a texture created with
+    /// `new_exportable`. The texture has to outlive the importer's use of
+    /// the memory.
+    pub fn export_memory(&self) -> Result<ExternalMemory> {
+        let external = self.renderer.external.as_ref().expect("Exportable textures need Capabilities::ExternalMemory");
+        let info = self.renderer.allocator.get_allocation_info(&self.allocation);
+        let handle = unsafe { external.export_memory(info.device_memory)? };
+        Ok(ExternalMemory { handle, handle_type: MEMORY_HANDLE_TYPE, size: info.size, offset: info.offset })
     }
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
+        for &v in self.layer_views.iter().chain(&self.mip_views) {
+            unsafe { self.renderer.device.destroy_image_view(v, None); }
+        }
         unsafe { self.renderer.device.destroy_image_view(self.image_view, None); }
+        if let (Some(tracker), Some(id)) = (&self.renderer.resource_tracker, self.tracking_id) {
+            tracker.untrack(id);
+        }
+        self.renderer.texture_memory.remove(self.renderer.allocator.get_allocation_info(&self.allocation).size);
         unsafe { self.renderer.allocator.destroy_image(self.image, &mut self.allocation) };
     }
 }
+/// Bytes per texel of the uncompressed color and single aspect depth
+/// formats, `None` for the rest.
+fn texel_size(format: vk::Format) -> Option<u64> {
+    let size = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB | vk::Format::R8_UINT => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT | vk::Format::R16_UINT
+        | vk::Format::R16_UNORM | vk::Format::D16_UNORM => 2,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB | vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32 | vk::Format::R16G16_SFLOAT | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT | vk::Format::D32_SFLOAT => 4,
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_UNORM | vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_UINT => 16,
+        _ => return None,
+    };
+    Some(size)
+}
+
+/// Records with `record` into a one-shot command list and waits for it to
+/// complete.
+fn submit_blocking(renderer: &Arc<Renderer>, record: impl FnOnce(&CommandList)) -> Result<()> {
+    let command_list = CommandList::new(renderer.clone(), CommandListCreateInfo { queue: QueueType::Graphics });
+    let fence = Fence::new(renderer.clone());
+    fence.reset()?;
+
+    command_list.begin();
+    record(&command_list);
+    command_list.end();
+
+    renderer.submit(&command_list, &[], &[], &fence)?;
+    fence.wait()
+}
+
+/// Copies all layers of mip 0 of `src` into `dst` through host memory, for
+/// textures of renderers on different adapters, e.g. results baked on the
+/// discrete GPU previewed on the integrated one. Both must be in `GENERAL`
+/// layout with the same format, extent and layer count, `src` with
+/// `TRANSFER_SRC` and `dst` with `TRANSFER_DST` usage. Blocks until both
+/// copies completed, meant for tools and not for every frame.
+pub fn copy_between_devices(src: &Texture, dst: &Texture) -> Result<()> {
+    assert_eq!(src.format, dst.format, "Textures copied between devices must have the same format");
+    assert_eq!(src.extent, dst.extent, "Textures copied between devices must have the same extent");
+    assert_eq!(src.array_layers, dst.array_layers, "Textures copied between devices must have the same layer count");
+    assert!(src.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC), "The source texture needs TRANSFER_SRC usage");
+    assert!(dst.usage.contains(vk::ImageUsageFlags::TRANSFER_DST), "The destination texture needs TRANSFER_DST usage");
+
+    let texel_size = texel_size(src.format)
+        .ok_or_else(|| Error::Backend(format!("Textures of format {:?} can't be copied between devices", src.format)))?;
+    let size = src.extent.width as u64 * src.extent.height as u64 * src.extent.depth as u64
+        * src.array_layers as u64 * texel_size;
+    let mut data = vec![0; size as usize];
+
+    let readback = {
+        let create_info = BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            location: MemoryLocation::GpuToCpu,
+        };
+        Buffer::new(src.renderer.clone(), create_info)
+    };
+    readback.set_name("cross-device readback");
+    submit_blocking(&src.renderer, |command_list| {
+        command_list.copy_texture_to_buffer(src, &readback, &[BufferTextureCopy::whole(src)]);
+    })?;
+    readback.read(0, &mut data);
+
+    let mut upload = {
+        let create_info = BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            location: MemoryLocation::CpuToGpu,
+        };
+        Buffer::new(dst.renderer.clone(), create_info)
+    };
+    upload.set_name("cross-device upload");
+    upload.write(0, &data);
+    submit_blocking(&dst.renderer, |command_list| {
+        command_list.copy_buffer_to_texture(&upload, dst, &[BufferTextureCopy::whole(dst)]);
+    })
+}
+
 impl Image for Framebuffer {
     unsafe fn get_image_view(&self) -> vk::ImageView {
         self.image_view
@@ -0,0 +1,276 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ash::vk;
+use vk_mem::{Alloc, AllocatorPool, AllocatorPoolCreateFlags, PoolCreateInfo};
+
+use crate::render::hal::{HeapStats, MemoryPoolCreateInfo, MemoryStats, PoolResources, ResourceStats, Result};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::external::MEMORY_HANDLE_TYPE;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+
+/// Live count and size of one kind of resource.
+#[derive(Default)]
+pub(crate) struct ResourceCounter {
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl ResourceCounter {
+    pub(crate) fn add(&self, bytes: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remove(&self, bytes: u64) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> ResourceStats {
+        ResourceStats {
+            count: self.count.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ResourceKind {
+    Texture,
+    Buffer,
+}
+
+struct TrackedResource {
+    kind: ResourceKind,
+    name: String,
+    size: u64,
+    backtrace: Backtrace,
+}
+
+/// Registry of live allocations, only present when resource tracking is enabled.
+#[derive(Default)]
+pub(crate) struct ResourceTracker {
+    next_id: AtomicU64,
+    resources: Mutex<HashMap<u64, TrackedResource>>,
+}
+
+impl ResourceTracker {
+    pub(crate) fn track(&self, kind: ResourceKind, size: u64) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let resource = TrackedResource {
+            kind,
+            name: String::new(),
+            size,
+            backtrace: Backtrace::force_capture(),
+        };
+        self.resources.lock().unwrap().insert(id, resource);
+        id
+    }
+
+    pub(crate) fn untrack(&self, id: u64) {
+        self.resources.lock().unwrap().remove(&id);
+    }
+
+    pub(crate) fn set_name(&self, id: u64, name: &str) {
+        if let Some(resource) = self.resources.lock().unwrap().get_mut(&id) {
+            resource.name = name.to_string();
+        }
+    }
+}
+
+/// Memory blocks reserved for the textures or buffers created in it with
+/// `new_in`, see `MemoryPoolCreateInfo`. Resources keep their pool alive.
+pub struct MemoryPool {
+    pub(super) pool: AllocatorPool,
+    resources: PoolResources,
+    /// Chained to the pool's allocations, the allocator keeps the pointer.
+    _export_info: Option<Box<vk::ExportMemoryAllocateInfo<'static>>>,
+    // The allocator the pool belongs to lives as long as the renderer.
+    _renderer: Arc<Renderer>,
+}
+
+impl MemoryPool {
+    pub fn new(renderer: Arc<Renderer>, create_info: MemoryPoolCreateInfo) -> Result<Arc<Self>> {
+        let memory_type_index = match create_info.resources {
+            PoolResources::Buffers { usage, location } => {
+                let buffer_info = vk::BufferCreateInfo::default()
+                    .size(1)
+                    .usage(Buffer::usage(&renderer, usage))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                unsafe { renderer.allocator.find_memory_type_index_for_buffer_info(&buffer_info, &Buffer::allocation_info(location))? }
+            }
+            PoolResources::Textures { format, usage } => {
+                let image_info = vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(vk::Extent3D { width: 1, height: 1, depth: 1 })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage);
+                unsafe { renderer.allocator.find_memory_type_index_for_image_info(image_info, &Texture::allocation_info())? }
+            }
+        };
+
+        let mut flags = AllocatorPoolCreateFlags::empty();
+        if create_info.linear {
+            flags |= AllocatorPoolCreateFlags::LINEAR_ALGORITHM;
+        }
+
+        let pool_info = PoolCreateInfo {
+            memory_type_index,
+            flags,
+            block_size: create_info.block_size,
+            min_block_count: create_info.min_block_count,
+            max_block_count: create_info.max_block_count,
+            ..Default::default()
+        };
+        let pool = renderer.allocator.create_pool(&pool_info)?;
+
+        Ok(Arc::new(Self { pool, resources: create_info.resources, _export_info: None, _renderer: renderer }))
+    }
+
+    /// Pool for a single texture created with `image_info` whose memory
+    /// can be exported, see `Texture::new_exportable`.
+    pub(crate) fn exportable(renderer: Arc<Renderer>, image_info: &vk::ImageCreateInfo) -> Result<Arc<Self>> {
+        let memory_type_index = unsafe { renderer.allocator.find_memory_type_index_for_image_info(*image_info, &Texture::allocation_info())? };
+        let export_info = Box::new(vk::ExportMemoryAllocateInfo::default().handle_types(MEMORY_HANDLE_TYPE));
+        let pool_info = PoolCreateInfo {
+            memory_type_index,
+            memory_allocate_next: &*export_info as *const vk::ExportMemoryAllocateInfo as *const c_void,
+            ..Default::default()
+        };
+        let pool = renderer.allocator.create_pool(&pool_info)?;
+
+        let resources = PoolResources::Textures { format: image_info.format, usage: image_info.usage };
+        Ok(Arc::new(Self { pool, resources, _export_info: Some(export_info), _renderer: renderer }))
+    }
+
+    /// Names the pool in allocator statistics.
+    pub fn set_name(&self, name: &str) {
+        let name = CString::new(name).unwrap();
+        self.pool.set_name(Some(&name));
+    }
+
+    pub fn resources(&self) -> PoolResources {
+        self.resources
+    }
+
+    /// Live allocations in the pool.
+    pub fn stats(&self) -> ResourceStats {
+        let stats = self.pool.get_statistics().unwrap();
+        ResourceStats {
+            count: stats.allocationCount as u64,
+            bytes: stats.allocationBytes,
+        }
+    }
+}
+
+pub type BudgetCallback = Box<dyn Fn(usize, &HeapStats) + Send + Sync>;
+
+/// Invoked once every time the usage of a heap rises above `threshold`
+/// (a fraction of the budget); re-armed when it drops back below.
+pub(crate) struct BudgetWatch {
+    threshold: f32,
+    callback: BudgetCallback,
+    over_budget: Vec<bool>,
+}
+
+impl Renderer {
+    fn heap_stats(&self) -> Vec<HeapStats> {
+        let budgets = self.allocator.get_heap_budgets().unwrap();
+        let memory_properties = unsafe { self.allocator.get_memory_properties() };
+
+        budgets.iter().enumerate().map(|(idx, budget)| {
+            let heap = memory_properties.memory_heaps[idx];
+            HeapStats {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                budget: budget.budget,
+                usage: budget.usage,
+                block_bytes: budget.statistics.blockBytes,
+                allocation_bytes: budget.statistics.allocationBytes,
+                block_count: budget.statistics.blockCount,
+                allocation_count: budget.statistics.allocationCount,
+            }
+        }).collect()
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        let total = self.allocator.calculate_statistics().unwrap().total.statistics;
+
+        MemoryStats {
+            heaps: self.heap_stats(),
+            textures: self.texture_memory.stats(),
+            buffers: self.buffer_memory.stats(),
+            total_allocation_count: total.allocationCount,
+            total_allocation_bytes: total.allocationBytes,
+        }
+    }
+
+    /// Human readable report of every live texture and buffer, largest
+    /// first. Empty unless `RendererCreateInfo::track_resources` was set.
+    pub fn dump_live_resources(&self) -> String {
+        let mut report = String::new();
+
+        let Some(tracker) = &self.resource_tracker else {
+            return report;
+        };
+
+        let resources = tracker.resources.lock().unwrap();
+        let mut live = resources.values().collect::<Vec<_>>();
+        live.sort_by_key(|r| std::cmp::Reverse(r.size));
+
+        let total: u64 = live.iter().map(|r| r.size).sum();
+        writeln!(report, "{} live resources, {} bytes", live.len(), total).unwrap();
+
+        for resource in live {
+            let name = if resource.name.is_empty() { "<unnamed>" } else { &resource.name };
+            writeln!(report, "\n{:?} {name}: {} bytes\n{}", resource.kind, resource.size, resource.backtrace).unwrap();
+        }
+
+        report
+    }
+
+    pub fn is_memory_budget_supported(&self) -> bool {
+        self.memory_budget_supported
+    }
+
+    /// Registers a callback invoked with the heap index and its stats when
+    /// the heap usage exceeds `threshold` (e.g. `0.9`) of its budget.
+    /// Budgets are checked once per presented frame.
+    pub fn add_budget_callback(&self, threshold: f32, callback: BudgetCallback) {
+        self.budget_watches.lock().unwrap().push(BudgetWatch {
+            threshold,
+            callback,
+            over_budget: Vec::new(),
+        });
+    }
+
+    pub(crate) fn check_budgets(&self) {
+        let mut watches = self.budget_watches.lock().unwrap();
+        if watches.is_empty() {
+            return;
+        }
+
+        let heaps = self.heap_stats();
+        for watch in watches.iter_mut() {
+            watch.over_budget.resize(heaps.len(), false);
+
+            for (idx, heap) in heaps.iter().enumerate() {
+                let over = heap.budget > 0 && heap.usage as f64 > heap.budget as f64 * watch.threshold as f64;
+                if over && !watch.over_budget[idx] {
+                    (watch.callback)(idx, heap);
+                }
+                watch.over_budget[idx] = over;
+            }
+        }
+    }
+}
@@ -1,11 +1,22 @@
 pub mod renderer;
+pub mod buffer;
 pub mod image;
 pub mod command_list;
 pub mod sync;
+pub mod frame;
 pub mod descriptor_set;
+pub(crate) mod descriptor_buffer;
 pub mod shader;
 pub mod pipeline;
+pub mod memory;
+pub mod sampler;
+pub mod diagnostics;
+#[cfg(feature = "ray-tracing")]
+pub mod acceleration_structure;
+pub mod external;
+pub(crate) mod validation;
+pub(crate) mod stats;
 
-const FRAME_OVERLAP: usize = 2;
+pub const FRAME_OVERLAP: usize = 2;
 
 
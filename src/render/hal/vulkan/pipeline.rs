@@ -1,12 +1,22 @@
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
 
 use ash::vk;
 
-use crate::render::hal::{ComputePipelineCreateInfo, PipelineLayoutCreateInfo};
-use crate::render::hal::vulkan::descriptor_set::DescriptorSetLayout;
+use crate::render::hal::{BlendMode, Capabilities, ComputePipelineCreateInfo, DynamicState, Error, GraphicsPipelineCreateInfo, PipelineLayoutCreateInfo, Result, ShaderStages};
+use crate::render::hal::vulkan::descriptor_set::{convert_shader_stage, DescriptorSetLayout};
 use crate::render::hal::vulkan::renderer::Renderer;
 use crate::render::hal::vulkan::shader::Shader;
 
+fn pipeline_create_flags(renderer: &Renderer) -> vk::PipelineCreateFlags {
+    if renderer.descriptor_heap.is_some() {
+        vk::PipelineCreateFlags::DESCRIPTOR_BUFFER_EXT
+    } else {
+        vk::PipelineCreateFlags::empty()
+    }
+}
+
 pub struct PipelineLayout {
     pub(crate) layout: vk::PipelineLayout,
 
@@ -18,13 +28,29 @@ impl PipelineLayout {
     pub fn new(renderer: Arc<Renderer>, create_info: PipelineLayoutCreateInfo) -> Arc<Self> {
         let sets = create_info.sets.iter().map(|s| s.layout)
             .collect::<Vec<_>>();
+        let push_constant_ranges = create_info.push_constant_ranges.iter().map(|r| {
+            vk::PushConstantRange::default()
+                .stage_flags(convert_shader_stage(r.stage))
+                .offset(r.offset)
+                .size(r.size)
+        }).collect::<Vec<_>>();
         let info = vk::PipelineLayoutCreateInfo::default()
-            .set_layouts(&sets);
+            .set_layouts(&sets)
+            .push_constant_ranges(&push_constant_ranges);
 
         let layout = unsafe { renderer.device.create_pipeline_layout(&info, None).unwrap() };
 
         Arc::new(PipelineLayout { layout, renderer, descriptor_sets: create_info.sets })
     }
+
+    pub(crate) fn set_layout(&self, index: u32) -> &Arc<DescriptorSetLayout> {
+        &self.descriptor_sets[index as usize]
+    }
+
+    /// Bindings of every set, for `validation`.
+    pub(crate) fn set_bindings(&self) -> Vec<Vec<(u32, vk::DescriptorType)>> {
+        self.descriptor_sets.iter().map(|set| set.bindings.clone()).collect()
+    }
 }
 
 impl Drop for PipelineLayout {
@@ -37,24 +63,69 @@ pub struct ComputePipeline {
     pub(crate) pipeline: vk::Pipeline,
 
     renderer: Arc<Renderer>,
-    _layout: Arc<PipelineLayout>,
+    pub(crate) layout: Arc<PipelineLayout>,
+    local_size: [u32; 3],
     _shader: Arc<Shader>,
 }
 
 impl ComputePipeline {
+    /// Compiles the pipeline on the calling thread, which can take a while
+    /// on a cold pipeline cache. See `new_async`.
     pub fn new(renderer: Arc<Renderer>, create_info: ComputePipelineCreateInfo) -> Arc<Self> {
+        Self::try_new(renderer, create_info).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like `new`, but returns an error when the device lacks the required
+    /// subgroup operations or, in debug builds, when the shader's local
+    /// size exceeds the device limits.
+    pub fn try_new(renderer: Arc<Renderer>, create_info: ComputePipelineCreateInfo) -> Result<Arc<Self>> {
+        crate::trace_span!("ComputePipeline::new");
+        let capabilities = renderer.device_capabilities();
+        if !create_info.subgroup_operations.is_empty() && !capabilities.subgroup_stages.contains(ShaderStages::Compute) {
+            return Err(Error::Backend("Subgroup operations aren't supported in compute shaders by the device".to_string()));
+        }
+        let missing = create_info.subgroup_operations - capabilities.subgroup_operations;
+        if !missing.is_empty() {
+            return Err(Error::Backend(format!("Subgroup operations {missing:?} aren't supported by the device")));
+        }
+
+        let local_size = create_info.shader.local_size.unwrap_or([1, 1, 1]);
+        if cfg!(debug_assertions) {
+            let limits = &renderer.limits;
+            let invocations = local_size.iter().map(|&s| s as u64).product::<u64>();
+            if local_size.iter().zip(limits.max_compute_work_group_size).any(|(&size, max)| size > max)
+                || invocations > limits.max_compute_work_group_invocations as u64 {
+                return Err(Error::Backend(format!(
+                    "Local size {local_size:?} exceeds the device limits of {:?} and {} invocations",
+                    limits.max_compute_work_group_size, limits.max_compute_work_group_invocations)));
+            }
+        }
+
         let shader_stage = vk::PipelineShaderStageCreateInfo::default()
             .stage(vk::ShaderStageFlags::COMPUTE)
             .module(create_info.shader.shader)
             .name(create_info.entrypoint);
 
         let pipeline_infos = [vk::ComputePipelineCreateInfo::default()
+            .flags(pipeline_create_flags(&renderer))
             .layout(create_info.pipeline_layout.layout)
             .stage(shader_stage)];
 
-        let pipeline = unsafe { renderer.device.create_compute_pipelines(vk::PipelineCache::null(), &pipeline_infos, None).unwrap()[0] };
+        let pipeline = unsafe { renderer.device.create_compute_pipelines(renderer.pipeline_cache, &pipeline_infos, None) }
+            .map_err(|(_, e)| Error::Backend(format!("Failed to compile compute pipeline: {e}")))?[0];
 
-        Arc::new(ComputePipeline { pipeline, renderer, _layout: create_info.pipeline_layout, _shader: create_info.shader })
+        Ok(Arc::new(ComputePipeline { pipeline, renderer, layout: create_info.pipeline_layout, local_size, _shader: create_info.shader }))
+    }
+
+    /// Compiles the pipeline on the renderer's compile threads.
+    pub fn new_async(renderer: Arc<Renderer>, create_info: ComputePipelineCreateInfo) -> AsyncPipeline<Self> {
+        let compiler = renderer.pipeline_compiler.clone();
+        compiler.spawn(move || Self::try_new(renderer, create_info))
+    }
+
+    /// Invocations per workgroup, as declared by the shader.
+    pub fn local_size(&self) -> [u32; 3] {
+        self.local_size
     }
 }
 
@@ -62,4 +133,316 @@ impl Drop for ComputePipeline {
     fn drop(&mut self) {
         unsafe { self.renderer.device.destroy_pipeline(self.pipeline, None) };
     }
-}
\ No newline at end of file
+}
+
+fn color_blend_attachment(blend: BlendMode) -> vk::PipelineColorBlendAttachmentState {
+    let state = vk::PipelineColorBlendAttachmentState::default()
+        .color_write_mask(vk::ColorComponentFlags::RGBA);
+
+    match blend {
+        BlendMode::Opaque => state,
+        BlendMode::Alpha => state
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD),
+        BlendMode::Additive => state
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD),
+    }
+}
+
+pub struct GraphicsPipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    pub(crate) layout: Arc<PipelineLayout>,
+    pub(crate) topology: vk::PrimitiveTopology,
+
+    renderer: Arc<Renderer>,
+    _shaders: Vec<Arc<Shader>>,
+}
+
+impl GraphicsPipeline {
+    /// Compiles the pipeline on the calling thread, which can take a while
+    /// on a cold pipeline cache. See `new_async`.
+    pub fn new(renderer: Arc<Renderer>, create_info: GraphicsPipelineCreateInfo) -> Arc<Self> {
+        Self::try_new(renderer, create_info).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like `new`, but returns an error when the device lacks geometry or
+    /// tessellation shaders used by the pipeline, or fails to compile it.
+    pub fn try_new(renderer: Arc<Renderer>, create_info: GraphicsPipelineCreateInfo) -> Result<Arc<Self>> {
+        crate::trace_span!("GraphicsPipeline::new");
+        let raster = create_info.raster;
+        if create_info.geometry_shader.is_some() && !renderer.capabilities().contains(Capabilities::GeometryShader) {
+            return Err(Error::Backend("Geometry shaders aren't supported by the device".to_string()));
+        }
+        if create_info.tessellation.is_some() && !renderer.capabilities().contains(Capabilities::TessellationShader) {
+            return Err(Error::Backend("Tessellation shaders aren't supported by the device".to_string()));
+        }
+
+        let mut shader_stages = vec![
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(create_info.vertex_shader.shader)
+                .name(create_info.vertex_entrypoint),
+        ];
+        if let Some(fragment_shader) = &create_info.fragment_shader {
+            shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_shader.shader)
+                .name(create_info.fragment_entrypoint));
+        }
+        if let Some(tessellation) = &create_info.tessellation {
+            shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+                .module(tessellation.control_shader.shader)
+                .name(c"main"));
+            shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+                .module(tessellation.evaluation_shader.shader)
+                .name(c"main"));
+        }
+        if let Some(geometry_shader) = &create_info.geometry_shader {
+            shader_stages.push(vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::GEOMETRY)
+                .module(geometry_shader.shader)
+                .name(c"main"));
+        }
+
+        let vertex_bindings = create_info.vertex_layout.strides.iter().enumerate()
+            .map(|(binding, &stride)| vk::VertexInputBindingDescription {
+                binding: binding as u32,
+                stride,
+                input_rate: if create_info.vertex_layout.instance_bindings.contains(&(binding as u32)) {
+                    vk::VertexInputRate::INSTANCE
+                } else {
+                    vk::VertexInputRate::VERTEX
+                },
+            })
+            .collect::<Vec<_>>();
+        let vertex_attributes = create_info.vertex_layout.attributes.iter()
+            .flat_map(|a| {
+                let location_size = a.semantic.components() / a.semantic.location_count() * 4;
+                (0..a.semantic.location_count()).map(move |i| vk::VertexInputAttributeDescription {
+                    location: a.semantic.location() + i,
+                    binding: a.binding,
+                    format: a.semantic.format(),
+                    offset: a.offset + i * location_size,
+                })
+            })
+            .collect::<Vec<_>>();
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&vertex_bindings)
+            .vertex_attribute_descriptions(&vertex_attributes);
+
+        let topology = if create_info.tessellation.is_some() { vk::PrimitiveTopology::PATCH_LIST } else { raster.topology };
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(topology);
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo::default()
+            .patch_control_points(create_info.tessellation.as_ref().map_or(0, |t| t.patch_control_points));
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: create_info.extent.width as f32,
+            height: create_info.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D { offset: vk::Offset2D::default(), extent: create_info.extent }];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewports(&viewports)
+            .scissors(&scissors);
+
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(raster.cull_mode)
+            .front_face(raster.front_face)
+            .depth_bias_enable(raster.dynamic.contains(DynamicState::DepthBias))
+            .line_width(1.0);
+
+        let multisample = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(raster.depth_test)
+            .depth_write_enable(raster.depth_write)
+            .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL);
+
+        let dynamic_states = [
+            (DynamicState::Viewport, vk::DynamicState::VIEWPORT),
+            (DynamicState::Scissor, vk::DynamicState::SCISSOR),
+            (DynamicState::DepthBias, vk::DynamicState::DEPTH_BIAS),
+            (DynamicState::BlendConstants, vk::DynamicState::BLEND_CONSTANTS),
+        ].into_iter()
+            .filter(|(flag, _)| raster.dynamic.contains(*flag))
+            .map(|(_, state)| state)
+            .collect::<Vec<_>>();
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&dynamic_states);
+
+        let blend_attachments = create_info.color_formats.iter()
+            .map(|_| color_blend_attachment(raster.blend))
+            .collect::<Vec<_>>();
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&blend_attachments);
+
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .view_mask(create_info.view_mask)
+            .color_attachment_formats(&create_info.color_formats)
+            .depth_attachment_format(create_info.depth_format);
+
+        let pipeline_infos = [vk::GraphicsPipelineCreateInfo::default()
+            .flags(pipeline_create_flags(&renderer))
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .tessellation_state(&tessellation_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(create_info.pipeline_layout.layout)
+            .push_next(&mut rendering)];
+
+        let pipeline = unsafe { renderer.device.create_graphics_pipelines(renderer.pipeline_cache, &pipeline_infos, None) }
+            .map_err(|(_, e)| Error::Backend(format!("Failed to compile graphics pipeline: {e}")))?[0];
+
+        Ok(Arc::new(GraphicsPipeline {
+            pipeline,
+            layout: create_info.pipeline_layout,
+            topology,
+            renderer,
+            _shaders: [Some(create_info.vertex_shader), create_info.fragment_shader, create_info.geometry_shader].into_iter().flatten()
+                .chain(create_info.tessellation.into_iter().flat_map(|t| [t.control_shader, t.evaluation_shader]))
+                .collect(),
+        }))
+    }
+
+    /// Compiles the pipeline on the renderer's compile threads.
+    pub fn new_async(renderer: Arc<Renderer>, create_info: GraphicsPipelineCreateInfo) -> AsyncPipeline<Self> {
+        let compiler = renderer.pipeline_compiler.clone();
+        compiler.spawn(move || Self::try_new(renderer, create_info))
+    }
+
+    pub fn layout(&self) -> Arc<PipelineLayout> {
+        self.layout.clone()
+    }
+}
+
+impl Drop for GraphicsPipeline {
+    fn drop(&mut self) {
+        unsafe { self.renderer.device.destroy_pipeline(self.pipeline, None) };
+    }
+}
+
+struct AsyncPipelineInner<T> {
+    pipeline: Mutex<Option<Result<Arc<T>>>>,
+    ready: Condvar,
+}
+
+/// Pipeline that is being compiled in the background. Check `get` before
+/// binding it, or block on `wait` when it's needed right away. Both return
+/// the error when compilation failed.
+pub struct AsyncPipeline<T> {
+    inner: Arc<AsyncPipelineInner<T>>,
+}
+
+impl<T> Clone for AsyncPipeline<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<T> AsyncPipeline<T> {
+    fn new() -> Self {
+        Self { inner: Arc::new(AsyncPipelineInner { pipeline: Mutex::new(None), ready: Condvar::new() }) }
+    }
+
+    fn resolve(&self, pipeline: Result<Arc<T>>) {
+        *self.inner.pipeline.lock().unwrap() = Some(pipeline);
+        self.inner.ready.notify_all();
+    }
+
+    /// Whether compilation finished, successfully or not.
+    pub fn is_ready(&self) -> bool {
+        self.inner.pipeline.lock().unwrap().is_some()
+    }
+
+    pub fn get(&self) -> Option<Result<Arc<T>>> {
+        self.inner.pipeline.lock().unwrap().clone()
+    }
+
+    pub fn wait(&self) -> Result<Arc<T>> {
+        let guard = self.inner.ready.wait_while(self.inner.pipeline.lock().unwrap(), |p| p.is_none()).unwrap();
+        guard.clone().unwrap()
+    }
+}
+
+type CompileJob = Box<dyn FnOnce() + Send>;
+
+/// Pool of threads compiling pipelines. Jobs hold the renderer alive, the
+/// threads exit once the renderer drops the last sender.
+#[derive(Clone)]
+pub(crate) struct PipelineCompiler {
+    sender: Option<Arc<Mutex<mpsc::Sender<CompileJob>>>>,
+}
+
+impl PipelineCompiler {
+    pub(crate) fn new(threads: usize) -> Self {
+        if threads == 0 {
+            return Self { sender: None };
+        }
+
+        let (sender, receiver) = mpsc::channel::<CompileJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("pipeline-compiler-{i}"))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .unwrap();
+        }
+
+        Self { sender: Some(Arc::new(Mutex::new(sender))) }
+    }
+
+    fn spawn<T: Send + Sync + 'static>(&self, compile: impl FnOnce() -> Result<Arc<T>> + Send + 'static) -> AsyncPipeline<T> {
+        let handle = AsyncPipeline::new();
+        match &self.sender {
+            Some(sender) => {
+                let result = handle.clone();
+                sender.lock().unwrap().send(Box::new(move || result.resolve(catch_compile_panic(compile)))).unwrap();
+            }
+            None => handle.resolve(compile()),
+        }
+        handle
+    }
+}
+
+/// Keeps the compile thread alive and the waiters woken when compilation
+/// panics, e.g. on an out of memory error.
+fn catch_compile_panic<T>(compile: impl FnOnce() -> Result<Arc<T>>) -> Result<Arc<T>> {
+    panic::catch_unwind(AssertUnwindSafe(compile)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::Backend(format!("Pipeline compilation panicked: {message}")))
+    })
+}
@@ -1,59 +1,145 @@
 use std::borrow::Cow;
-use std::cell::Cell;
+use std::collections::HashMap;
 use std::ffi;
 use std::ffi::{c_char, c_void, CStr};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
 
 use ash::{Device, Entry, Instance, vk};
-use ash::ext::debug_utils;
-use ash::khr::{surface, swapchain};
-use vk_mem::{Allocator, AllocatorCreateInfo};
+use ash::amd::buffer_marker;
+use ash::ext::{debug_utils, descriptor_buffer, full_screen_exclusive, memory_budget, swapchain_colorspace};
+use ash::khr::{copy_commands2, dynamic_rendering, get_surface_capabilities2, portability_enumeration, portability_subset, push_descriptor, surface, swapchain, swapchain_mutable_format, synchronization2};
+use ash::nv::device_diagnostic_checkpoints;
+use ash::prelude::VkResult;
+use vk_mem::{Allocator, AllocatorCreateFlags, AllocatorCreateInfo};
 use winit::error::OsError;
 use winit::raw_window_handle::{HandleError, HasDisplayHandle, HasWindowHandle};
 use winit::window::Window;
 
-use crate::render::hal::{Error, RendererCreateInfo, Result};
+#[cfg(feature = "renderdoc")]
+use crate::render::debug::renderdoc::RenderDoc;
+use crate::render::hal::{AdapterInfo, ApiVersion, Capabilities, ColorEncoding, DescriptorBackend, DeviceCapabilities, Error, FrameStats, Limits, PresentMode, QueueType, RendererCreateInfo, Result, ShaderStages, SubgroupOperations};
+#[cfg(feature = "ray-tracing")]
+use crate::render::hal::vulkan::acceleration_structure::{self, RayTracingSupport};
+use crate::render::hal::vulkan::external::{self, ExternalSupport};
 use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_buffer::{self as descriptor_heap, DescriptorHeap};
+use crate::render::hal::vulkan::diagnostics::{BufferMarkers, CheckpointLabels};
 use crate::render::hal::vulkan::FRAME_OVERLAP;
-use crate::render::hal::vulkan::sync::{Fence, Semaphore};
+use crate::render::hal::vulkan::memory::{BudgetWatch, ResourceCounter, ResourceTracker};
+use crate::render::hal::vulkan::pipeline::PipelineCompiler;
+use crate::render::hal::vulkan::stats::FrameCounters;
+use crate::render::hal::vulkan::sync::{timeout_ns, Fence, Semaphore};
+use crate::render::util::log_target;
 
 pub struct Renderer {
     pub(crate) entry: Entry,
-    pub(crate) instance: Instance,
-    pub(crate) debug_utils_loader: debug_utils::Instance,
-    pub(crate) debug_callback: vk::DebugUtilsMessengerEXT,
+    pub(crate) debug_utils_device: debug_utils::Device,
 
     pub(crate) physical_device: vk::PhysicalDevice,
 
     pub(crate) present_family_idx: u32,
     pub(crate) graphics_family_idx: u32,
-    pub(crate) present_queue: vk::Queue,
-    pub(crate) graphics_queue: vk::Queue,
+    /// Queue access has to be externally synchronized, submissions and
+    /// presentation from different threads are serialized by this lock.
+    pub(crate) graphics_queue: Mutex<vk::Queue>,
+    /// `None` when the graphics family presents, see `present_queue()`.
+    pub(crate) present_queue: Option<Mutex<vk::Queue>>,
+    /// Family without graphics support, for `QueueType::Compute`.
+    pub(crate) compute_family_idx: Option<u32>,
+    pub(crate) compute_queue: Option<Mutex<vk::Queue>>,
 
     pub(crate) surface_loader: surface::Instance,
     pub(crate) surface: vk::SurfaceKHR,
 
     pub(crate) swapchain_loader: swapchain::Device,
-    pub(crate) swapchain: vk::SwapchainKHR,
-    pub(crate) swapchain_images: Vec<vk::Image>,
-    pub(crate) swapchain_imageviews: Vec<vk::ImageView>,
+    /// `None` without `VK_EXT_full_screen_exclusive`.
+    full_screen_exclusive_loader: Option<full_screen_exclusive::Device>,
+    swapchain_desc: SwapchainDesc,
+    /// Locked before `swapchain_sync` where both are needed.
+    swapchain: Mutex<SwapchainState>,
+    /// Swapchain images have `TRANSFER_SRC` usage and can be read back.
+    swapchain_capturable: bool,
+    swapchain_sync: Mutex<SwapchainSync>,
 
     pub(crate) device: Device,
 
-    pub(crate) command_pool: vk::CommandPool,
-
-    pub(crate) allocator: Allocator,
-
-    pub(crate) descriptor_pool: vk::DescriptorPool,
-
-    window: Arc<Window>,
-
-    frame_number: Cell<usize>,
-    swapchain_image_idx: Cell<u32>,
+    /// VMA synchronizes internally, the allocator is safe to use from any thread.
+    pub(crate) allocator: Arc<Allocator>,
+
+    pub(crate) descriptor_pool: Mutex<vk::DescriptorPool>,
+    pub(crate) pipeline_cache: vk::PipelineCache,
+    pub(crate) pipeline_compiler: PipelineCompiler,
+
+    pub(crate) memory_budget_supported: bool,
+    portability_subset_enabled: bool,
+    geometry_shader_enabled: bool,
+    tessellation_shader_enabled: bool,
+    multiview_enabled: bool,
+    device_capabilities: DeviceCapabilities,
+    validate_usage: bool,
+    /// In nanoseconds, `u64::MAX` waits forever.
+    pub(crate) fence_timeout: u64,
+    acquire_timeout: u64,
+    /// Nanoseconds per timestamp query tick.
+    #[cfg(feature = "debug")]
+    pub(crate) timestamp_period: f32,
+    pub(crate) limits: vk::PhysicalDeviceLimits,
+    pub(crate) texture_memory: ResourceCounter,
+    pub(crate) buffer_memory: ResourceCounter,
+    pub(crate) budget_watches: Mutex<Vec<BudgetWatch>>,
+    pub(crate) resource_tracker: Option<ResourceTracker>,
+    pub(crate) frame_counters: FrameCounters,
+    /// Counts of the last presented frame.
+    frame_stats: Mutex<FrameStats>,
+
+    pub(crate) device_lost: AtomicBool,
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc>,
+    #[cfg(feature = "renderdoc")]
+    capture_on_validation_error: bool,
+    /// Validation error count at the last present.
+    #[cfg(feature = "renderdoc")]
+    presented_validation_errors: AtomicU32,
+    pub(crate) device_lost_report: Mutex<Option<String>>,
+    pub(crate) checkpoints_loader: Option<device_diagnostic_checkpoints::Device>,
+    /// `VK_AMD_buffer_marker`, used when checkpoints are missing.
+    pub(crate) buffer_markers: Option<BufferMarkers>,
+    pub(crate) checkpoint_labels: Mutex<CheckpointLabels>,
+
+    /// `None` when `VK_KHR_push_descriptor` is missing.
+    pub(crate) push_descriptor_loader: Option<push_descriptor::Device>,
+    /// Set with `DescriptorBackend::Buffer`.
+    pub(crate) descriptor_heap: Option<DescriptorHeap>,
+    /// Set with `Capabilities::RayQuery`.
+    #[cfg(feature = "ray-tracing")]
+    pub(crate) ray_tracing: Option<RayTracingSupport>,
+    /// Present with `Capabilities::ExternalMemory`.
+    pub(crate) external: Option<ExternalSupport>,
+
+    api_version: ApiVersion,
+    /// Set on `ApiVersion::Vulkan12`, see `TierLoaders`.
+    tier_loaders: Option<TierLoaders>,
+
+    /// `None` for headless renderers, which have no surface and swapchain.
+    window: Option<Arc<Window>>,
+
+    frame_number: AtomicUsize,
+    swapchain_image_idx: AtomicU32,
+    frame_index: AtomicU64,
+
+    /// Last, so the instance outlives everything else. Destroyed with the
+    /// last renderer using it.
+    pub(crate) render_instance: Arc<RenderInstance>,
 }
 impl From<vk::Result> for Error {
     fn from(res: vk::Result) -> Self {
-        Error::Backend(format!("Vulkan error: {}", res))
+        match res {
+            vk::Result::ERROR_DEVICE_LOST => Error::DeviceLost,
+            vk::Result::TIMEOUT | vk::Result::NOT_READY => Error::Timeout,
+            _ => Error::Backend(format!("Vulkan error: {}", res)),
+        }
     }
 }
 
@@ -69,33 +155,118 @@ impl From<HandleError> for Error {
     }
 }
 
-fn get_enabled_layers() -> Vec<*const c_char> {
-    let enabled_layers = [c"VK_LAYER_KHRONOS_validation"];
+fn get_enabled_layers(info: &RendererCreateInfo) -> Vec<*const c_char> {
+    let mut enabled_layers = Vec::new();
+    if info.validation_layers {
+        enabled_layers.push(c"VK_LAYER_KHRONOS_validation");
+    }
     enabled_layers
         .iter()
         .map(|raw_name| raw_name.as_ptr())
         .collect()
 }
 
-fn get_enabled_extensions(window: &Window) -> Vec<*const c_char> {
-    let mut res = ash_window::enumerate_required_extensions(window.display_handle()
-        .expect("Failed to get winow handle").as_raw())
-        .unwrap()
-        .to_vec();
+fn is_instance_extension_supported(entry: &Entry, name: &CStr) -> bool {
+    let extension_props = unsafe { entry.enumerate_instance_extension_properties(None).unwrap() };
+    extension_props.iter().any(|ext| {
+        let ext_name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+        ext_name == name
+    })
+}
+
+/// `VK_EXT_full_screen_exclusive` only exists on Windows and needs
+/// `VK_KHR_get_surface_capabilities2` on the instance.
+fn is_full_screen_exclusive_available(entry: &Entry, window: Option<&Window>) -> bool {
+    cfg!(windows) && window.is_some() && is_instance_extension_supported(entry, get_surface_capabilities2::NAME)
+}
+
+fn get_enabled_extensions(entry: &Entry, window: Option<&Window>, info: &RendererCreateInfo) -> Vec<*const c_char> {
+    let mut res = match window {
+        Some(window) => ash_window::enumerate_required_extensions(window.display_handle()
+            .expect("Failed to get winow handle").as_raw())
+            .unwrap()
+            .to_vec(),
+        None => Vec::new(),
+    };
 
     res.push(debug_utils::NAME.as_ptr());
+
+    if window.is_some() && info.prefer_hdr && is_instance_extension_supported(entry, swapchain_colorspace::NAME) {
+        res.push(swapchain_colorspace::NAME.as_ptr());
+    }
+
+    if is_full_screen_exclusive_available(entry, window) {
+        res.push(get_surface_capabilities2::NAME.as_ptr());
+    }
+
+    // Portability implementations like MoltenVK are only enumerated when
+    // asked for.
+    if is_instance_extension_supported(entry, portability_enumeration::NAME) {
+        res.push(portability_enumeration::NAME.as_ptr());
+    }
     res
 }
 
+/// Times a message id is logged before further ones are dropped, a
+/// validation error in a loop otherwise floods the log every frame.
+const MAX_REPEATED_MESSAGES: u32 = 10;
+
+/// State of the debug callback, passed as its user data. The callback may
+/// run on any thread calling into Vulkan.
+#[derive(Default)]
+struct DebugMessages {
+    validation_errors: AtomicU32,
+    /// How often each message id was reported.
+    repeats: Mutex<HashMap<i32, u32>>,
+}
+
+fn log_level(message_severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> log::Level {
+    if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Debug
+    } else {
+        log::Level::Trace
+    }
+}
+
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
+    let messages = (user_data as *const DebugMessages).as_ref();
+    if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        && message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        if let Some(messages) = messages {
+            messages.validation_errors.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    let level = log_level(message_severity);
+    if !log::log_enabled!(target: log_target::VULKAN, level) {
+        return vk::FALSE;
+    }
+
     let callback_data = *p_callback_data;
     let message_id_number = callback_data.message_id_number;
 
+    // Loader and driver messages share id 0, only validation ids repeat.
+    let repeats = match messages.map(|messages| messages.repeats.lock()) {
+        Some(Ok(mut repeats)) if message_id_number != 0 => {
+            let count = repeats.entry(message_id_number).or_insert(0);
+            *count += 1;
+            *count
+        }
+        _ => 1,
+    };
+    if repeats > MAX_REPEATED_MESSAGES {
+        return vk::FALSE;
+    }
+
     let message_id_name = if callback_data.p_message_id_name.is_null() {
         Cow::from("")
     } else {
@@ -108,8 +279,11 @@ unsafe extern "system" fn vulkan_debug_callback(
         ffi::CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
-    println!(
-        "{message_severity:?}:\n{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n",
+    let suppressed = if repeats == MAX_REPEATED_MESSAGES { " (repeated, further occurrences are suppressed)" } else { "" };
+    log::log!(
+        target: log_target::VULKAN,
+        level,
+        "{message_type:?} [{message_id_name} ({message_id_number})] : {message}{suppressed}",
     );
 
     vk::FALSE
@@ -119,6 +293,92 @@ struct SelectedPhysicalDevice {
     physical_device: vk::PhysicalDevice,
     graphics_family_idx: u32,
     present_family_idx: u32,
+    api_version: ApiVersion,
+}
+
+/// Acquire and present semaphores of each swapchain image. Indexing them by
+/// frame slot instead reuses semaphores still pending on a presentation
+/// when the swapchain has more images than `FRAME_OVERLAP`.
+struct SwapchainSync {
+    /// Signaled by the acquire of the image, waited on by `submit_frame`.
+    image_available: Vec<vk::Semaphore>,
+    /// Signaled by `submit_frame`, waited on by the presentation.
+    render_finished: Vec<vk::Semaphore>,
+    /// The image index isn't known before acquiring, so the acquire signals
+    /// the spare semaphore, which is then swapped with the image's one. The
+    /// image's previous semaphore was waited on by the submission that
+    /// rendered the image's last presentation, so it's free again.
+    spare: vk::Semaphore,
+}
+
+impl SwapchainSync {
+    unsafe fn new(device: &Device, image_count: usize) -> Result<Self> {
+        let create = || device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None);
+        Ok(Self {
+            image_available: (0..image_count).map(|_| create()).collect::<ash::prelude::VkResult<_>>()?,
+            render_finished: (0..image_count).map(|_| create()).collect::<ash::prelude::VkResult<_>>()?,
+            spare: if image_count > 0 { create()? } else { vk::Semaphore::null() },
+        })
+    }
+
+    unsafe fn destroy(&self, device: &Device) {
+        for &semaphore in self.image_available.iter().chain(&self.render_finished) {
+            device.destroy_semaphore(semaphore, None);
+        }
+        device.destroy_semaphore(self.spare, None);
+    }
+}
+
+/// What the swapchain is created for and with, kept to recreate it.
+#[derive(Clone, Copy)]
+struct SwapchainDesc {
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    format: vk::SurfaceFormatKHR,
+    /// Format of the image views, the `*_SRGB` twin of a `UNORM` format
+    /// presented as sRGB where the swapchain can be created mutable.
+    view_format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    /// Requested count, clamped to the surface capabilities.
+    image_count: u32,
+    graphics_family_idx: u32,
+    present_family_idx: u32,
+}
+
+/// The swapchain and the settings `start_frame` may recreate it with.
+/// Headless renderers have a null swapchain without images.
+struct SwapchainState {
+    handle: vk::SwapchainKHR,
+    /// The headless extent without a window.
+    extent: vk::Extent2D,
+    images: Vec<vk::Image>,
+    image_views: Vec<vk::ImageView>,
+    /// Mode the swapchain was created with after falling back.
+    present_mode: PresentMode,
+    /// Mode last asked for, which `present_mode` may differ from.
+    requested_present_mode: PresentMode,
+    render_scale: f32,
+    /// Asked for with `set_fullscreen_exclusive`, whether or not the
+    /// display could be acquired.
+    fullscreen_exclusive: bool,
+    /// Set by `set_present_mode`, `set_render_scale` and
+    /// `set_fullscreen_exclusive`, applied by the next `start_frame`.
+    pending_present_mode: Option<PresentMode>,
+    pending_render_scale: Option<f32>,
+    pending_fullscreen_exclusive: Option<bool>,
+}
+
+/// Extension loaders for the Vulkan 1.3 core commands on
+/// `ApiVersion::Vulkan12`, where the core entry points are missing.
+struct TierLoaders {
+    dynamic_rendering: dynamic_rendering::Device,
+    synchronization2: synchronization2::Device,
+    copy_commands2: copy_commands2::Device,
+}
+
+/// Extensions providing the Vulkan 1.3 core commands on `ApiVersion::Vulkan12`.
+fn get_tier_device_extensions() -> [&'static CStr; 3] {
+    [dynamic_rendering::NAME, synchronization2::NAME, copy_commands2::NAME]
 }
 
 fn get_required_device_extensions() -> [&'static CStr; 1] {
@@ -148,24 +408,100 @@ fn check_required_extensions(instance: &Instance, device: vk::PhysicalDevice) ->
     true
 }
 
-fn check_required_features(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+fn is_device_extension_supported(instance: &Instance, device: vk::PhysicalDevice, name: &CStr) -> bool {
+    let extension_props = unsafe {
+        instance
+            .enumerate_device_extension_properties(device)
+            .unwrap()
+    };
+
+    extension_props.iter().any(|ext| {
+        let ext_name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+        ext_name == name
+    })
+}
+
+/// Highest tier up to `max_api_version` the device supports the required
+/// features on.
+fn select_api_version(instance: &Instance, device: vk::PhysicalDevice, max_api_version: ApiVersion) -> Option<ApiVersion> {
+    let device_version = unsafe { instance.get_physical_device_properties(device).api_version };
+    if device_version < vk::API_VERSION_1_2 {
+        return None;
+    }
+
     let features = unsafe { instance.get_physical_device_features(device) };
-    let mut features2 = vk::PhysicalDeviceFeatures2::default();
     let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
-    let mut features13 = vk::PhysicalDeviceVulkan13Features::default();
-    features2.p_next = &mut features12 as *mut _ as *mut c_void;
-    features12.p_next = &mut features13 as *mut _ as *mut c_void;
+    unsafe { instance.get_physical_device_features2(device, &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut features12)) };
+    if features.sampler_anisotropy != vk::TRUE
+        || features12.buffer_device_address != vk::TRUE
+        || features12.descriptor_indexing != vk::TRUE {
+        return None;
+    }
 
+    if max_api_version >= ApiVersion::Vulkan13 && device_version >= vk::API_VERSION_1_3 {
+        let mut features13 = vk::PhysicalDeviceVulkan13Features::default();
+        unsafe { instance.get_physical_device_features2(device, &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut features13)) };
+        if features13.dynamic_rendering == vk::TRUE && features13.synchronization2 == vk::TRUE {
+            return Some(ApiVersion::Vulkan13);
+        }
+    }
+
+    if !get_tier_device_extensions().iter().all(|name| is_device_extension_supported(instance, device, name)) {
+        return None;
+    }
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::default()
+        .push_next(&mut dynamic_rendering_features)
+        .push_next(&mut synchronization2_features);
     unsafe { instance.get_physical_device_features2(device, &mut features2) };
+    (dynamic_rendering_features.dynamic_rendering == vk::TRUE && synchronization2_features.synchronization2 == vk::TRUE)
+        .then_some(ApiVersion::Vulkan12)
+}
 
-    features.sampler_anisotropy == vk::TRUE
-        && features12.buffer_device_address == vk::TRUE
-        && features12.descriptor_indexing == vk::TRUE
-        && features13.dynamic_rendering == vk::TRUE
-        && features13.synchronization2 == vk::TRUE
+fn query_device_capabilities(instance: &Instance, device: vk::PhysicalDevice) -> DeviceCapabilities {
+    let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+    let mut multiview = vk::PhysicalDeviceMultiviewProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::default()
+        .push_next(&mut subgroup)
+        .push_next(&mut multiview);
+    unsafe { instance.get_physical_device_properties2(device, &mut properties2) };
+
+    let stages = [
+        (vk::ShaderStageFlags::VERTEX, ShaderStages::Vertex),
+        (vk::ShaderStageFlags::FRAGMENT, ShaderStages::Fragment),
+        (vk::ShaderStageFlags::COMPUTE, ShaderStages::Compute),
+        (vk::ShaderStageFlags::GEOMETRY, ShaderStages::Geometry),
+        (vk::ShaderStageFlags::TESSELLATION_CONTROL, ShaderStages::TessellationControl),
+        (vk::ShaderStageFlags::TESSELLATION_EVALUATION, ShaderStages::TessellationEvaluation),
+    ];
+    let operations = [
+        (vk::SubgroupFeatureFlags::BASIC, SubgroupOperations::Basic),
+        (vk::SubgroupFeatureFlags::VOTE, SubgroupOperations::Vote),
+        (vk::SubgroupFeatureFlags::ARITHMETIC, SubgroupOperations::Arithmetic),
+        (vk::SubgroupFeatureFlags::BALLOT, SubgroupOperations::Ballot),
+        (vk::SubgroupFeatureFlags::SHUFFLE, SubgroupOperations::Shuffle),
+        (vk::SubgroupFeatureFlags::SHUFFLE_RELATIVE, SubgroupOperations::ShuffleRelative),
+        (vk::SubgroupFeatureFlags::CLUSTERED, SubgroupOperations::Clustered),
+        (vk::SubgroupFeatureFlags::QUAD, SubgroupOperations::Quad),
+    ];
+
+    DeviceCapabilities {
+        subgroup_size: subgroup.subgroup_size,
+        subgroup_stages: stages.into_iter()
+            .filter(|(flag, _)| subgroup.supported_stages.contains(*flag))
+            .fold(ShaderStages::empty(), |stages, (_, stage)| stages | stage),
+        subgroup_operations: operations.into_iter()
+            .filter(|(flag, _)| subgroup.supported_operations.contains(*flag))
+            .fold(SubgroupOperations::empty(), |operations, (_, operation)| operations | operation),
+        max_multiview_views: multiview.max_multiview_view_count,
+    }
 }
 
-unsafe fn find_queue_families(instance: &Instance, surface_loader: &surface::Instance, surface: vk::SurfaceKHR, device: vk::PhysicalDevice) -> Option<(u32, u32)> {
+/// Surface the device has to present to, `None` for headless renderers.
+type Presentation<'a> = Option<(&'a surface::Instance, vk::SurfaceKHR)>;
+
+unsafe fn find_queue_families(instance: &Instance, presentation: Presentation, device: vk::PhysicalDevice) -> Option<(u32, u32)> {
     unsafe {
         let props = instance.get_physical_device_queue_family_properties(device);
 
@@ -174,42 +510,242 @@ unsafe fn find_queue_families(instance: &Instance, surface_loader: &surface::Ins
         for (idx, family) in props.iter().enumerate() {
             let idx = idx as u32;
 
-            if graphics.is_none() && family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                graphics = Some(idx);
-                continue;
+            let graphics_support = family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+            // Nothing is presented when headless, the graphics queue stands in.
+            let present_support = match presentation {
+                Some((surface_loader, surface)) => surface_loader.get_physical_device_surface_support(device, idx, surface).unwrap(),
+                None => graphics_support,
+            };
+
+            // One family doing both avoids sharing swapchain images between
+            // queue families.
+            if graphics_support && present_support {
+                return Some((idx, idx));
             }
 
-            let present_support = surface_loader.get_physical_device_surface_support(device, idx, surface).unwrap();
+            if graphics.is_none() && graphics_support {
+                graphics = Some(idx);
+            }
 
             if present.is_none() && present_support {
                 present = Some(idx);
             }
         }
 
-        if let (Some(g), Some(p)) = (graphics, present) {
-            Some((g, p))
-        } else {
-            None
-        }
+        graphics.zip(present)
     }
 }
-unsafe fn select_physical_device(instance: &Instance, surface_loader: &surface::Instance, surface: vk::SurfaceKHR) -> Result<SelectedPhysicalDevice> {
+/// A family with compute but no graphics support, which usually runs
+/// alongside the graphics queue.
+unsafe fn find_compute_family(instance: &Instance, device: vk::PhysicalDevice) -> Option<u32> {
+    let props = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    props.iter()
+        .position(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE) && !family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .map(|idx| idx as u32)
+}
+
+unsafe fn select_physical_device(instance: &Instance, presentation: Presentation, max_api_version: ApiVersion, adapter: Option<usize>) -> Result<SelectedPhysicalDevice> {
     let devices = instance
         .enumerate_physical_devices()?;
 
-    Ok(devices
+    let select = |physical_device| {
+        if presentation.is_some() && !check_required_extensions(instance, physical_device) {
+            return None;
+        }
+        let api_version = select_api_version(instance, physical_device, max_api_version)?;
+
+        find_queue_families(instance, presentation, physical_device)
+            .map(|(graphics_family_idx, present_family_idx)| SelectedPhysicalDevice { physical_device, graphics_family_idx, present_family_idx, api_version })
+    };
+
+    if let Some(adapter) = adapter {
+        let &physical_device = devices
+            .get(adapter)
+            .ok_or_else(|| Error::Backend(format!("There's no adapter {adapter}, {} found", devices.len())))?;
+        return select(physical_device)
+            .ok_or_else(|| Error::Backend(format!("Adapter {adapter} doesn't support the required Vulkan 1.2 features")));
+    }
+
+    let candidates = devices
         .iter()
-        .find_map(|&physical_device| {
-            if !check_required_extensions(instance, physical_device) || !check_required_features(instance, physical_device) {
-                return None;
-            }
+        .filter_map(|&physical_device| select(physical_device));
 
-            if let Some((graphics_family_idx, present_family_idx)) = find_queue_families(instance, surface_loader, surface, physical_device) {
-                Some(SelectedPhysicalDevice { physical_device, graphics_family_idx, present_family_idx })
-            } else {
-                None
-            }
-        }).expect("Couldn't find suitable device."))
+    // The first device of the highest tier, enumeration order otherwise.
+    candidates
+        .rev()
+        .max_by_key(|device| device.api_version)
+        .ok_or_else(|| Error::Backend("No device supports the required Vulkan 1.2 features".to_string()))
+}
+
+unsafe fn select_surface_format(surface_loader: &surface::Instance, physical_device: vk::PhysicalDevice, surface: vk::SurfaceKHR, prefer_hdr: bool) -> Result<vk::SurfaceFormatKHR> {
+    let formats = surface_loader.get_physical_device_surface_formats(physical_device, surface)?;
+
+    let hdr_candidates = [
+        (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+    ];
+    let sdr_candidates = [
+        (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        (vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+        (vk::Format::R8G8B8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+    ];
+
+    let candidates = if prefer_hdr {
+        hdr_candidates.iter().chain(sdr_candidates.iter()).collect::<Vec<_>>()
+    } else {
+        sdr_candidates.iter().collect::<Vec<_>>()
+    };
+
+    let selected = candidates.iter().find_map(|&&(format, color_space)| {
+        formats.iter().find(|f| f.format == format && f.color_space == color_space).copied()
+    });
+
+    if let Some(selected) = selected {
+        return Ok(selected);
+    }
+    let fallback = formats.first().copied().ok_or_else(|| Error::Backend("Surface doesn't report any formats".to_string()))?;
+    log::warn!(target: log_target::SWAPCHAIN, "The surface supports none of the preferred formats, falling back to {:?} in {:?}", fallback.format, fallback.color_space);
+    Ok(fallback)
+}
+
+/// The `*_SRGB` twin of a `UNORM` format presented as sRGB, so rendering
+/// through the image views encodes on write. The swapchain has to be
+/// created with `MUTABLE_FORMAT` for views in another format.
+fn select_swapchain_view_format(format: vk::SurfaceFormatKHR, mutable_format_supported: bool) -> vk::Format {
+    if mutable_format_supported && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+        ColorEncoding::Srgb.apply(format.format)
+    } else {
+        format.format
+    }
+}
+
+fn select_swapchain_extent(capabilities: &vk::SurfaceCapabilitiesKHR, window: &Window) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+
+    let size = window.inner_size();
+    vk::Extent2D {
+        width: size.width.clamp(capabilities.min_image_extent.width, capabilities.max_image_extent.width),
+        height: size.height.clamp(capabilities.min_image_extent.height, capabilities.max_image_extent.height),
+    }
+}
+
+/// `requested` when supported, otherwise the closest supported mode.
+/// FIFO is always available.
+unsafe fn select_present_mode(surface_loader: &surface::Instance, physical_device: vk::PhysicalDevice, surface: vk::SurfaceKHR, requested: PresentMode) -> Result<PresentMode> {
+    let supported = surface_loader.get_physical_device_surface_present_modes(physical_device, surface)?;
+    let candidates: &[PresentMode] = match requested {
+        PresentMode::Fifo => &[],
+        PresentMode::FifoRelaxed => &[PresentMode::FifoRelaxed],
+        PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+    };
+    Ok(candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(&mode.to_vk()))
+        .unwrap_or(PresentMode::Fifo))
+}
+
+/// The monitor `window` is on, to take exclusive control of with
+/// `VK_EXT_full_screen_exclusive`.
+#[cfg(windows)]
+fn exclusive_monitor(window: &Window) -> Option<vk::HMONITOR> {
+    use winit::platform::windows::MonitorHandleExtWindows;
+    window.current_monitor().map(|monitor| monitor.hmonitor() as vk::HMONITOR)
+}
+
+#[cfg(not(windows))]
+fn exclusive_monitor(_window: &Window) -> Option<vk::HMONITOR> {
+    None
+}
+
+/// Creates a swapchain for `window`, retiring `old_swapchain` unless it's
+/// null. With `exclusive_monitor` the application controls exclusive
+/// fullscreen on it, see `Renderer::acquire_full_screen_exclusive`.
+/// Returns it with its extent, images and actual present mode.
+unsafe fn create_swapchain(
+    surface_loader: &surface::Instance,
+    swapchain_loader: &swapchain::Device,
+    desc: &SwapchainDesc,
+    window: &Window,
+    present_mode: PresentMode,
+    exclusive_monitor: Option<vk::HMONITOR>,
+    old_swapchain: vk::SwapchainKHR,
+) -> Result<(vk::SwapchainKHR, vk::Extent2D, Vec<vk::Image>, PresentMode)> {
+    let surface_capabilities = surface_loader.get_physical_device_surface_capabilities(desc.physical_device, desc.surface)?;
+    let extent = select_swapchain_extent(&surface_capabilities, window);
+    let image_count = select_swapchain_image_count(&surface_capabilities, desc.image_count);
+    let requested_present_mode = present_mode;
+    let present_mode = select_present_mode(surface_loader, desc.physical_device, desc.surface, present_mode)?;
+    if present_mode != requested_present_mode {
+        log::warn!(target: log_target::SWAPCHAIN, "{requested_present_mode:?} presentation isn't supported, falling back to {present_mode:?}");
+    }
+
+    // Images written by the graphics queue and presented by another family
+    // are shared instead of transferring ownership every frame.
+    let queue_family_indices = [desc.graphics_family_idx, desc.present_family_idx];
+    let sharing_mode = if desc.present_family_idx == desc.graphics_family_idx {
+        vk::SharingMode::EXCLUSIVE
+    } else {
+        vk::SharingMode::CONCURRENT
+    };
+
+    let mut create_info = vk::SwapchainCreateInfoKHR::default()
+        .surface(desc.surface)
+        .min_image_count(image_count)
+        .image_color_space(desc.format.color_space)
+        .image_format(desc.format.format)
+        .image_extent(extent)
+        .image_usage(desc.usage)
+        .image_sharing_mode(sharing_mode)
+        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        .present_mode(present_mode.to_vk())
+        .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+        .clipped(true)
+        .image_array_layers(1)
+        .old_swapchain(old_swapchain);
+    if sharing_mode == vk::SharingMode::CONCURRENT {
+        create_info = create_info.queue_family_indices(&queue_family_indices);
+    }
+    let view_formats = [desc.format.format, desc.view_format];
+    let mut format_list = vk::ImageFormatListCreateInfo::default().view_formats(&view_formats);
+    if desc.view_format != desc.format.format {
+        create_info = create_info
+            .flags(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+            .push_next(&mut format_list);
+    }
+    let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::default()
+        .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+    let mut full_screen_exclusive_monitor = vk::SurfaceFullScreenExclusiveWin32InfoEXT::default();
+    if let Some(monitor) = exclusive_monitor {
+        full_screen_exclusive_monitor = full_screen_exclusive_monitor.hmonitor(monitor);
+        create_info = create_info
+            .push_next(&mut full_screen_exclusive_info)
+            .push_next(&mut full_screen_exclusive_monitor);
+    }
+
+    let swapchain = swapchain_loader.create_swapchain(&create_info, None)?;
+    let images = swapchain_loader.get_swapchain_images(swapchain)?;
+    log::debug!(
+        target: log_target::SWAPCHAIN,
+        "Created a {}x{} swapchain with {} images, {:?} and {present_mode:?}",
+        extent.width,
+        extent.height,
+        images.len(),
+        desc.format.format);
+    Ok((swapchain, extent, images, present_mode))
+}
+
+fn select_swapchain_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, desired: u32) -> u32 {
+    let count = desired.max(capabilities.min_image_count);
+    // max_image_count of 0 means there's no upper limit
+    if capabilities.max_image_count > 0 {
+        count.min(capabilities.max_image_count)
+    } else {
+        count
+    }
 }
 
 fn create_swapchain_image_views(
@@ -252,32 +788,68 @@ fn create_image_view(
     unsafe { Ok(device.create_image_view(&create_info, None)?) }
 }
 
-impl Renderer {
-    pub fn new(window: Arc<Window>, info: RendererCreateInfo) -> Result<Arc<Self>> {
-        unsafe {
-            let entry = Entry::linked();
+/// Creates an instance with the extensions presenting to `window` needs,
+/// or none of the surface extensions when headless.
+unsafe fn create_instance(entry: &Entry, window: Option<&Window>, info: &RendererCreateInfo) -> Result<Instance> {
+    let instance_version = entry.try_enumerate_instance_version()?.unwrap_or(vk::API_VERSION_1_0);
+    if instance_version < vk::API_VERSION_1_2 {
+        return Err(Error::Backend("The Vulkan loader doesn't support Vulkan 1.2".to_string()));
+    }
 
-            let instance = {
-                let app_info = vk::ApplicationInfo::default()
-                    .engine_name(c"Patoka Engine")
-                    .application_name(c"Patoka App")
-                    .application_version(vk::make_api_version(0, 1, 0, 0))
-                    .engine_version(vk::make_api_version(0, 1, 0, 0))
-                    .api_version(vk::make_api_version(0, 1, 3, 0));
+    let instance = {
+        let app_info = vk::ApplicationInfo::default()
+            .engine_name(c"Patoka Engine")
+            .application_name(c"Patoka App")
+            .application_version(vk::make_api_version(0, 1, 0, 0))
+            .engine_version(vk::make_api_version(0, 1, 0, 0))
+            .api_version(info.max_api_version.to_vk().min(instance_version));
+
+        let mut create_flags = vk::InstanceCreateFlags::default();
+        if is_instance_extension_supported(entry, portability_enumeration::NAME) {
+            create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+        }
 
-                let create_flags = vk::InstanceCreateFlags::default();
+        let enabled_layers = get_enabled_layers(info);
+        let enabled_extensions = get_enabled_extensions(entry, window, info);
 
-                let enabled_layers = get_enabled_layers();
-                let enabled_extensions = get_enabled_extensions(&window);
+        let create_info = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_layer_names(&enabled_layers)
+            .enabled_extension_names(&enabled_extensions)
+            .flags(create_flags);
 
-                let create_info = vk::InstanceCreateInfo::default()
-                    .application_info(&app_info)
-                    .enabled_layer_names(&enabled_layers)
-                    .enabled_extension_names(&enabled_extensions)
-                    .flags(create_flags);
+        entry.create_instance(&create_info, None)?
+    };
 
-                entry.create_instance(&create_info, None)?
-            };
+    Ok(instance)
+}
+
+/// A Vulkan instance, shared by renderers on different adapters in one
+/// process, e.g. by a tool previewing on the integrated GPU while baking on
+/// the discrete one, see `Renderer::with_instance`. `Renderer::new` creates
+/// one per renderer.
+pub struct RenderInstance {
+    pub(crate) entry: Entry,
+    pub(crate) instance: Instance,
+    debug_utils_loader: debug_utils::Instance,
+    debug_callback: vk::DebugUtilsMessengerEXT,
+    /// Updated by the debug callback. The messenger is destroyed in `drop`,
+    /// before the box is freed.
+    debug_messages: Box<DebugMessages>,
+    /// Created with the surface extensions a window needs.
+    presentable: bool,
+    full_screen_exclusive_available: bool,
+}
+
+impl RenderInstance {
+    /// Enables the surface extensions presenting to windows like `window`
+    /// needs, headless renderers only without one. Only the instance level
+    /// settings of `info` are used: validation layers, HDR color spaces and
+    /// `max_api_version`.
+    pub fn new(window: Option<&Window>, info: &RendererCreateInfo) -> Result<Arc<Self>> {
+        unsafe {
+            let entry = Entry::linked();
+            let instance = create_instance(&entry, window, info)?;
 
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
                 .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
@@ -288,47 +860,281 @@ impl Renderer {
                     | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                     | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
                 .pfn_user_callback(Some(vulkan_debug_callback));
+            // Boxed so the address handed to the callback stays valid.
+            let debug_messages = Box::<DebugMessages>::default();
+            let debug_info = debug_info.user_data(&*debug_messages as *const DebugMessages as *mut c_void);
 
             let debug_utils_loader = debug_utils::Instance::new(&entry, &instance);
             let debug_callback = debug_utils_loader
                 .create_debug_utils_messenger(&debug_info, None)?;
 
-            let surface = ash_window::create_surface(
-                &entry,
-                &instance,
-                window.display_handle()?.as_raw(),
-                window.window_handle()?.as_raw(),
-                None,
-            )?;
+            let full_screen_exclusive_available = is_full_screen_exclusive_available(&entry, window);
+            Ok(Arc::new(Self {
+                entry,
+                instance,
+                debug_utils_loader,
+                debug_callback,
+                debug_messages,
+                presentable: window.is_some(),
+                full_screen_exclusive_available,
+            }))
+        }
+    }
 
-            let surface_loader = surface::Instance::new(&entry, &instance);
+    /// Physical devices in the order `RendererCreateInfo::adapter` indexes
+    /// them.
+    pub fn enumerate_adapters(&self) -> Result<Vec<AdapterInfo>> {
+        let devices = unsafe { self.instance.enumerate_physical_devices()? };
+        Ok(devices
+            .into_iter()
+            .map(|device| {
+                let properties = unsafe { self.instance.get_physical_device_properties(device) };
+                AdapterInfo {
+                    name: properties.device_name_as_c_str().unwrap_or_default().to_string_lossy().into_owned(),
+                    device_type: properties.device_type,
+                    api_version: select_api_version(&self.instance, device, ApiVersion::Vulkan13),
+                }
+            })
+            .collect())
+    }
+
+    /// Validation errors reported so far by all renderers on the instance.
+    pub fn validation_error_count(&self) -> u32 {
+        self.debug_messages.validation_errors.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for RenderInstance {
+    fn drop(&mut self) {
+        unsafe {
+            self.debug_utils_loader.destroy_debug_utils_messenger(self.debug_callback, None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+impl Renderer {
+    pub fn new(window: Arc<Window>, info: RendererCreateInfo) -> Result<Arc<Self>> {
+        let render_instance = RenderInstance::new(Some(&window), &info)?;
+        Self::create(render_instance, Some(window), vk::Extent2D::default(), info)
+    }
+
+    /// Renderer without a window, for compute work, tests and tools. There
+    /// is no swapchain, `start_frame`, `submit_frame`, `present` and
+    /// framebuffer copies are unavailable. `extent` takes the place of the
+    /// swapchain extent in `render_extent`.
+    pub fn new_headless(info: RendererCreateInfo, extent: vk::Extent2D) -> Result<Arc<Self>> {
+        let render_instance = RenderInstance::new(None, &info)?;
+        Self::create(render_instance, None, extent, info)
+    }
+
+    /// Like `new` on an existing instance, which must have been created
+    /// with a window. Several renderers on one instance pick their
+    /// adapters with `RendererCreateInfo::adapter`, resources can't be
+    /// shared between them, see `image::copy_between_devices`.
+    pub fn with_instance(render_instance: Arc<RenderInstance>, window: Arc<Window>, info: RendererCreateInfo) -> Result<Arc<Self>> {
+        if !render_instance.presentable {
+            return Err(Error::Backend("The instance was created without a window and can't present".to_string()));
+        }
+        Self::create(render_instance, Some(window), vk::Extent2D::default(), info)
+    }
+
+    /// Like `new_headless` on an existing instance, see `with_instance`.
+    pub fn headless_with_instance(render_instance: Arc<RenderInstance>, info: RendererCreateInfo, extent: vk::Extent2D) -> Result<Arc<Self>> {
+        Self::create(render_instance, None, extent, info)
+    }
+
+    /// Physical devices in the order `RendererCreateInfo::adapter` indexes
+    /// them.
+    pub fn enumerate_adapters() -> Result<Vec<AdapterInfo>> {
+        RenderInstance::new(None, &RendererCreateInfo::default())?.enumerate_adapters()
+    }
 
-            let SelectedPhysicalDevice { physical_device, graphics_family_idx, present_family_idx } = select_physical_device(&instance, &surface_loader, surface)?;
+    fn create(render_instance: Arc<RenderInstance>, window: Option<Arc<Window>>, headless_extent: vk::Extent2D, info: RendererCreateInfo) -> Result<Arc<Self>> {
+        unsafe {
+            #[cfg(feature = "renderdoc")]
+            let renderdoc = RenderDoc::load();
+
+            let entry = render_instance.entry.clone();
+            let instance = render_instance.instance.clone();
+
+            let surface = match &window {
+                Some(window) => ash_window::create_surface(
+                    &entry,
+                    &instance,
+                    window.display_handle()?.as_raw(),
+                    window.window_handle()?.as_raw(),
+                    None,
+                )?,
+                None => vk::SurfaceKHR::null(),
+            };
+
+            let surface_loader = surface::Instance::new(&entry, &instance);
+            let presentation = window.is_some().then_some((&surface_loader, surface));
+
+            let SelectedPhysicalDevice { physical_device, graphics_family_idx, present_family_idx, api_version } = select_physical_device(&instance, presentation, info.max_api_version, info.adapter)?;
+            let compute_family_idx = find_compute_family(&instance, physical_device);
+
+            let memory_budget_supported = is_device_extension_supported(&instance, physical_device, memory_budget::NAME);
+            let properties = instance.get_physical_device_properties(physical_device);
+            log::info!(
+                target: log_target::VULKAN,
+                "Using {} ({:?}) on {:?}",
+                properties.device_name_as_c_str().unwrap_or_default().to_string_lossy(),
+                properties.device_type,
+                api_version);
+            let limits = properties.limits;
+            let device_capabilities = query_device_capabilities(&instance, physical_device);
+            let descriptor_buffer_enabled = info.descriptor_backend == DescriptorBackend::Buffer
+                && is_device_extension_supported(&instance, physical_device, descriptor_buffer::NAME)
+                && descriptor_heap::is_supported(&instance, physical_device);
+            // Pushing into descriptor buffer layouts needs another feature,
+            // push descriptors fall back to transient sets instead.
+            let push_descriptor_supported = !descriptor_buffer_enabled
+                && is_device_extension_supported(&instance, physical_device, push_descriptor::NAME);
+            // Must be enabled when present.
+            let portability_subset_enabled = is_device_extension_supported(&instance, physical_device, portability_subset::NAME);
+            let checkpoints_enabled = info.gpu_crash_diagnostics
+                && is_device_extension_supported(&instance, physical_device, device_diagnostic_checkpoints::NAME);
+            let buffer_markers_enabled = info.gpu_crash_diagnostics
+                && !checkpoints_enabled
+                && is_device_extension_supported(&instance, physical_device, buffer_marker::NAME);
+            let supported_features = instance.get_physical_device_features(physical_device);
+            let geometry_shader_enabled = supported_features.geometry_shader == vk::TRUE;
+            let tessellation_shader_enabled = supported_features.tessellation_shader == vk::TRUE;
+            let multiview_enabled = {
+                let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::default();
+                instance.get_physical_device_features2(physical_device, &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut multiview_features));
+                multiview_features.multiview == vk::TRUE
+            };
+            let swapchain_mutable_format_enabled = window.is_some()
+                && is_device_extension_supported(&instance, physical_device, swapchain_mutable_format::NAME);
+            let full_screen_exclusive_enabled = window.is_some()
+                && render_instance.full_screen_exclusive_available
+                && is_device_extension_supported(&instance, physical_device, full_screen_exclusive::NAME);
+            let external_memory_enabled = external::get_device_extensions().iter().all(|name| is_device_extension_supported(&instance, physical_device, name))
+                && external::is_supported(&instance, physical_device);
+            #[cfg(not(feature = "ray-tracing"))]
+            let ray_query_enabled = false;
+            #[cfg(feature = "ray-tracing")]
+            let ray_query_enabled = acceleration_structure::get_device_extensions().iter().all(|name| is_device_extension_supported(&instance, physical_device, name))
+                && acceleration_structure::is_supported(&instance, physical_device);
 
             let device = {
-                let device_extension_names_raw = [
-                    swapchain::NAME.as_ptr(),
-                ];
+                let mut device_extension_names_raw = Vec::new();
+
+                if window.is_some() {
+                    device_extension_names_raw.push(swapchain::NAME.as_ptr());
+                }
+
+                if swapchain_mutable_format_enabled {
+                    device_extension_names_raw.push(swapchain_mutable_format::NAME.as_ptr());
+                }
+
+                if full_screen_exclusive_enabled {
+                    device_extension_names_raw.push(full_screen_exclusive::NAME.as_ptr());
+                }
+
+                if memory_budget_supported {
+                    device_extension_names_raw.push(memory_budget::NAME.as_ptr());
+                }
+
+                if push_descriptor_supported {
+                    device_extension_names_raw.push(push_descriptor::NAME.as_ptr());
+                }
+
+                if descriptor_buffer_enabled {
+                    device_extension_names_raw.push(descriptor_buffer::NAME.as_ptr());
+                }
+
+                if checkpoints_enabled {
+                    device_extension_names_raw.push(device_diagnostic_checkpoints::NAME.as_ptr());
+                }
+
+                if buffer_markers_enabled {
+                    device_extension_names_raw.push(buffer_marker::NAME.as_ptr());
+                }
+
+                #[cfg(feature = "ray-tracing")]
+                if ray_query_enabled {
+                    device_extension_names_raw.extend(acceleration_structure::get_device_extensions().map(CStr::as_ptr));
+                }
+
+                if external_memory_enabled {
+                    device_extension_names_raw.extend(external::get_device_extensions().map(CStr::as_ptr));
+                }
+
+                if api_version == ApiVersion::Vulkan12 {
+                    device_extension_names_raw.extend(get_tier_device_extensions().map(CStr::as_ptr));
+                }
+
+                if portability_subset_enabled {
+                    device_extension_names_raw.push(portability_subset::NAME.as_ptr());
+                }
 
                 let features = vk::PhysicalDeviceFeatures {
-                    shader_clip_distance: 1,
+                    shader_clip_distance: supported_features.shader_clip_distance,
+                    geometry_shader: supported_features.geometry_shader,
+                    tessellation_shader: supported_features.tessellation_shader,
                     ..Default::default()
                 };
 
                 let mut features2 = vk::PhysicalDeviceFeatures2::default()
                     .features(features);
+                let mut features11 = vk::PhysicalDeviceVulkan11Features::default()
+                    .multiview(multiview_enabled);
+                features2 = features2.push_next(&mut features11);
                 let mut features12 = vk::PhysicalDeviceVulkan12Features::default()
                     .descriptor_indexing(true)
                     .buffer_device_address(true);
+                features2 = features2.push_next(&mut features12);
                 let mut features13 = vk::PhysicalDeviceVulkan13Features::default()
                     .synchronization2(true)
                     .dynamic_rendering(true);
-                features2.p_next = &mut features12 as *mut _ as *mut c_void;
-                features12.p_next = &mut features13 as *mut _ as *mut c_void;
+                let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default()
+                    .dynamic_rendering(true);
+                let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default()
+                    .synchronization2(true);
+                match api_version {
+                    ApiVersion::Vulkan13 => {
+                        features2 = features2.push_next(&mut features13);
+                    }
+                    ApiVersion::Vulkan12 => {
+                        features2 = features2
+                            .push_next(&mut dynamic_rendering_features)
+                            .push_next(&mut synchronization2_features);
+                    }
+                }
+                let mut descriptor_buffer_features = vk::PhysicalDeviceDescriptorBufferFeaturesEXT::default()
+                    .descriptor_buffer(true);
+                if descriptor_buffer_enabled {
+                    features2 = features2.push_next(&mut descriptor_buffer_features);
+                }
+                let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
+                    .acceleration_structure(true);
+                let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default()
+                    .ray_query(true);
+                if ray_query_enabled {
+                    features2 = features2
+                        .push_next(&mut acceleration_structure_features)
+                        .push_next(&mut ray_query_features);
+                }
+                // Everything the implementation supports, the renderer avoids
+                // what it doesn't.
+                let mut portability_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+                if portability_subset_enabled {
+                    instance.get_physical_device_features2(physical_device, &mut vk::PhysicalDeviceFeatures2::default().push_next(&mut portability_features));
+                    portability_features.p_next = std::ptr::null_mut();
+                    features2 = features2.push_next(&mut portability_features);
+                }
 
                 let priorities = [1.0];
 
-                let queue_infos: Vec<_> = [graphics_family_idx, present_family_idx].iter().map(|&idx| vk::DeviceQueueCreateInfo::default()
+                let mut queue_families = vec![graphics_family_idx, present_family_idx];
+                queue_families.extend(compute_family_idx);
+                queue_families.sort_unstable();
+                queue_families.dedup();
+                let queue_infos: Vec<_> = queue_families.iter().map(|&idx| vk::DeviceQueueCreateInfo::default()
                     .queue_family_index(idx)
                     .queue_priorities(&priorities)
                 ).collect();
@@ -342,53 +1148,116 @@ impl Renderer {
                     .unwrap()
             };
 
-            let present_queue = device.get_device_queue(present_family_idx, 0);
+            let present_queue = (present_family_idx != graphics_family_idx).then(|| Mutex::new(device.get_device_queue(present_family_idx, 0)));
             let graphics_queue = device.get_device_queue(graphics_family_idx, 0);
+            let compute_queue = compute_family_idx.map(|idx| Mutex::new(device.get_device_queue(idx, 0)));
 
             let swapchain_loader = swapchain::Device::new(&instance, &device);
-
-            let swapchain = {
-                let create_info = vk::SwapchainCreateInfoKHR::default()
-                    .surface(surface)
-                    .min_image_count(3)
-                    .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
-                    .image_format(vk::Format::B8G8R8A8_UNORM)
-                    .image_extent(vk::Extent2D {
-                        width: 800,
-                        height: 600,
-                    })
-                    .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
-                    .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-                    .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                    .present_mode(vk::PresentModeKHR::FIFO)
-                    .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-                    .clipped(true)
-                    .image_array_layers(1);
-
-                swapchain_loader
-                    .create_swapchain(&create_info, None)
-                    .unwrap()
+            let full_screen_exclusive_loader = full_screen_exclusive_enabled.then(|| full_screen_exclusive::Device::new(&instance, &device));
+            let debug_utils_device = debug_utils::Device::new(&instance, &device);
+            let push_descriptor_loader = push_descriptor_supported.then(|| push_descriptor::Device::new(&instance, &device));
+            let checkpoints_loader = checkpoints_enabled.then(|| device_diagnostic_checkpoints::Device::new(&instance, &device));
+            let tier_loaders = (api_version == ApiVersion::Vulkan12).then(|| TierLoaders {
+                dynamic_rendering: dynamic_rendering::Device::new(&instance, &device),
+                synchronization2: synchronization2::Device::new(&instance, &device),
+                copy_commands2: copy_commands2::Device::new(&instance, &device),
+            });
+
+            let (swapchain_desc, swapchain_capturable) = match &window {
+                Some(_) => {
+                    let format = select_surface_format(&surface_loader, physical_device, surface, info.prefer_hdr)?;
+                    let view_format = select_swapchain_view_format(format, swapchain_mutable_format_enabled);
+                    if ColorEncoding::of_format(format.format) == ColorEncoding::Linear
+                        && ColorEncoding::has_srgb_variant(format.format)
+                        && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+                        log::info!(
+                            target: log_target::SWAPCHAIN,
+                            "Presenting {:?} as sRGB, copies to the swapchain images have to be sRGB encoded already",
+                            format.format);
+                    }
+                    let surface_capabilities = surface_loader.get_physical_device_surface_capabilities(physical_device, surface)?;
+                    let swapchain_capturable = surface_capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_SRC);
+                    let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+                    if swapchain_capturable {
+                        usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+                    }
+                    let desc = SwapchainDesc {
+                        physical_device,
+                        surface,
+                        format,
+                        view_format,
+                        usage,
+                        image_count: info.swapchain_images,
+                        graphics_family_idx,
+                        present_family_idx,
+                    };
+                    (desc, swapchain_capturable)
+                }
+                None => {
+                    let desc = SwapchainDesc {
+                        physical_device,
+                        surface,
+                        format: vk::SurfaceFormatKHR::default(),
+                        view_format: vk::Format::UNDEFINED,
+                        usage: vk::ImageUsageFlags::empty(),
+                        image_count: 0,
+                        graphics_family_idx,
+                        present_family_idx,
+                    };
+                    (desc, false)
+                }
+            };
+            let (swapchain, swapchain_extent, swapchain_images, present_mode) = match &window {
+                Some(window) => create_swapchain(&surface_loader, &swapchain_loader, &swapchain_desc, window, info.present_mode, None, vk::SwapchainKHR::null())?,
+                None => (vk::SwapchainKHR::null(), headless_extent, Vec::new(), info.present_mode),
+            };
+            let swapchain_imageviews = create_swapchain_image_views(&device, &swapchain_images, swapchain_desc.view_format);
+            let swapchain_sync = SwapchainSync::new(&device, swapchain_images.len())?;
+            let swapchain = SwapchainState {
+                handle: swapchain,
+                extent: swapchain_extent,
+                images: swapchain_images,
+                image_views: swapchain_imageviews,
+                present_mode,
+                requested_present_mode: info.present_mode,
+                render_scale: info.render_scale,
+                fullscreen_exclusive: false,
+                pending_present_mode: None,
+                pending_render_scale: None,
+                pending_fullscreen_exclusive: None,
             };
 
-            let swapchain_images = swapchain_loader.get_swapchain_images(swapchain)?;
-            let swapchain_imageviews = create_swapchain_image_views(&device, &swapchain_images, vk::Format::B8G8R8A8_UNORM);
-
-            let command_pool = {
-                let create_info = vk::CommandPoolCreateInfo::default()
-                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                    .queue_family_index(graphics_family_idx);
-                device.create_command_pool(&create_info, None)?
+            let allocator = {
+                let mut create_info = AllocatorCreateInfo::new(&instance, &device, physical_device);
+                create_info.vulkan_api_version = api_version.to_vk();
+                if memory_budget_supported {
+                    create_info.flags |= AllocatorCreateFlags::EXT_MEMORY_BUDGET;
+                }
+                create_info.flags |= AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
+                create_info.preferred_large_heap_block_size = info.memory_block_size;
+                Arc::new(Allocator::new(create_info).unwrap())
             };
 
-            let allocator = Allocator::new(AllocatorCreateInfo::new(&instance, &device, physical_device)).unwrap();
+            let descriptor_heap = descriptor_buffer_enabled.then(|| DescriptorHeap::new(&instance, &device, physical_device, &allocator));
+            let buffer_markers = buffer_markers_enabled.then(|| BufferMarkers::new(&instance, &device, &allocator));
+            #[cfg(feature = "ray-tracing")]
+            let ray_tracing = ray_query_enabled.then(|| RayTracingSupport::new(&instance, &device, physical_device));
+            let external = external_memory_enabled.then(|| ExternalSupport::new(&instance, &device));
 
             let descriptor_pool = {
-                let pool_sizes = [
+                let mut pool_sizes = vec![
                     vk::DescriptorPoolSize { ty: vk::DescriptorType::UNIFORM_BUFFER, descriptor_count: 4096 },
                     vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_BUFFER, descriptor_count: 4096 },
+                    vk::DescriptorPoolSize { ty: vk::DescriptorType::UNIFORM_TEXEL_BUFFER, descriptor_count: 1024 },
+                    vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_TEXEL_BUFFER, descriptor_count: 1024 },
                     vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLED_IMAGE, descriptor_count: 4096 },
+                    vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_IMAGE, descriptor_count: 4096 },
                     vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLER, descriptor_count: 4096 },
+                    vk::DescriptorPoolSize { ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER, descriptor_count: 4096 },
                 ];
+                if ray_query_enabled {
+                    pool_sizes.push(vk::DescriptorPoolSize { ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, descriptor_count: 256 });
+                }
 
                 let create_info = vk::DescriptorPoolCreateInfo::default()
                     .pool_sizes(&pool_sizes)
@@ -398,85 +1267,604 @@ impl Renderer {
                 device.create_descriptor_pool(&create_info, None).unwrap()
             };
 
+            let pipeline_cache = {
+                let create_info = vk::PipelineCacheCreateInfo::default()
+                    .initial_data(&info.pipeline_cache_data);
+
+                match device.create_pipeline_cache(&create_info, None) {
+                    Ok(cache) => cache,
+                    // Stale or corrupt data from disk, start with an empty cache.
+                    Err(_) => device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)?,
+                }
+            };
+
             Ok(Arc::new(Self {
                 entry,
-                instance,
                 device,
                 surface_loader,
                 swapchain_loader,
-                debug_utils_loader,
-                debug_callback,
+                full_screen_exclusive_loader,
+                debug_utils_device,
                 physical_device,
                 present_family_idx,
                 graphics_family_idx,
                 present_queue,
-                graphics_queue,
+                graphics_queue: Mutex::new(graphics_queue),
+                compute_family_idx,
+                compute_queue,
                 surface,
-                swapchain,
+                swapchain_desc,
+                swapchain: Mutex::new(swapchain),
                 window,
-                swapchain_images,
-                swapchain_imageviews,
-                command_pool,
-                frame_number: Cell::new(0),
-                swapchain_image_idx: Cell::new(0),
+                swapchain_capturable,
+                swapchain_sync: Mutex::new(swapchain_sync),
+                frame_number: AtomicUsize::new(0),
+                swapchain_image_idx: AtomicU32::new(0),
                 allocator,
-                descriptor_pool,
+                descriptor_pool: Mutex::new(descriptor_pool),
+                pipeline_cache,
+                pipeline_compiler: PipelineCompiler::new(info.pipeline_compile_threads),
+                memory_budget_supported,
+                portability_subset_enabled,
+                geometry_shader_enabled,
+                tessellation_shader_enabled,
+                multiview_enabled,
+                device_capabilities,
+                validate_usage: info.validate_usage,
+                fence_timeout: timeout_ns(info.fence_timeout),
+                acquire_timeout: timeout_ns(info.acquire_timeout),
+                #[cfg(feature = "debug")]
+                timestamp_period: limits.timestamp_period,
+                limits,
+                texture_memory: ResourceCounter::default(),
+                buffer_memory: ResourceCounter::default(),
+                budget_watches: Mutex::new(Vec::new()),
+                resource_tracker: info.track_resources.then(ResourceTracker::default),
+                frame_counters: FrameCounters::default(),
+                frame_stats: Mutex::new(FrameStats::default()),
+                device_lost: AtomicBool::new(false),
+                #[cfg(feature = "renderdoc")]
+                renderdoc,
+                #[cfg(feature = "renderdoc")]
+                capture_on_validation_error: info.capture_on_validation_error,
+                #[cfg(feature = "renderdoc")]
+                presented_validation_errors: AtomicU32::new(0),
+                device_lost_report: Mutex::new(None),
+                checkpoints_loader,
+                buffer_markers,
+                checkpoint_labels: Mutex::new(CheckpointLabels::default()),
+                push_descriptor_loader,
+                descriptor_heap,
+                #[cfg(feature = "ray-tracing")]
+                ray_tracing,
+                external,
+                api_version,
+                tier_loaders,
+                frame_index: AtomicU64::new(0),
+                render_instance,
             }))
         }
     }
 
+    pub fn swapchain_format(&self) -> vk::SurfaceFormatKHR {
+        self.swapchain_desc.format
+    }
+
+    /// Format of the swapchain image views. The `*_SRGB` twin of a `UNORM`
+    /// swapchain presented as sRGB where the device supports it, transfers
+    /// to the images still use `swapchain_format`.
+    pub fn swapchain_view_format(&self) -> vk::Format {
+        self.swapchain_desc.view_format
+    }
+
+    /// Number of images the driver actually created, which may be larger
+    /// than requested.
+    pub fn swapchain_image_count(&self) -> u32 {
+        self.swapchain.lock().unwrap().images.len() as u32
+    }
+
+    /// Whether `CommandList::copy_framebuffer_to_buffer` can read the
+    /// swapchain images back, for screenshots and video capture.
+    pub fn framebuffer_capture_supported(&self) -> bool {
+        self.swapchain_capturable
+    }
+
+    /// Work recorded for the last presented frame. Stays empty for headless
+    /// renderers, which don't present.
+    pub fn frame_stats(&self) -> FrameStats {
+        *self.frame_stats.lock().unwrap()
+    }
+
+    /// Number of validation errors reported by the validation layers so
+    /// far, by all renderers sharing the instance.
+    pub fn validation_error_count(&self) -> u32 {
+        self.render_instance.validation_error_count()
+    }
+
+    pub fn render_instance(&self) -> &Arc<RenderInstance> {
+        &self.render_instance
+    }
+
+    /// `None` unless the application runs under RenderDoc.
+    #[cfg(feature = "renderdoc")]
+    pub fn renderdoc(&self) -> Option<&RenderDoc> {
+        self.renderdoc.as_ref()
+    }
+
+    /// Captures the next presented frame with RenderDoc, returns false when
+    /// RenderDoc isn't attached.
+    #[cfg(feature = "renderdoc")]
+    pub fn trigger_capture(&self) -> bool {
+        self.capture_frames(1)
+    }
+
+    /// Captures each of the next `frames` presented frames with RenderDoc,
+    /// returns false when RenderDoc isn't attached.
+    #[cfg(feature = "renderdoc")]
+    pub fn capture_frames(&self, frames: u32) -> bool {
+        let Some(renderdoc) = &self.renderdoc else {
+            return false;
+        };
+        renderdoc.trigger_multi_frame_capture(frames);
+        true
+    }
+
+    /// Serialized pipeline cache, to be stored on disk and passed back in
+    /// `RendererCreateInfo::pipeline_cache_data` on the next run.
+    pub fn pipeline_cache_data(&self) -> Result<Vec<u8>> {
+        Ok(unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache)? })
+    }
+
+    pub fn swapchain_extent(&self) -> vk::Extent2D {
+        self.swapchain.lock().unwrap().extent
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.swapchain.lock().unwrap().render_scale
+    }
+
+    /// Changes the render scale from the next `start_frame` on, right away
+    /// on headless renderers. Render targets sized by `render_extent` have
+    /// to be recreated when it changes.
+    pub fn set_render_scale(&self, render_scale: f32) {
+        let mut state = self.swapchain.lock().unwrap();
+        if self.window.is_some() {
+            state.pending_render_scale = Some(render_scale);
+        } else {
+            state.render_scale = render_scale;
+        }
+    }
+
+    /// Size of the internal render targets, the swapchain extent scaled by
+    /// `render_scale`. The final image is scaled to the swapchain by
+    /// `copy_to_framebuffer` or an `UpscalePass`.
+    pub fn render_extent(&self) -> vk::Extent2D {
+        let state = self.swapchain.lock().unwrap();
+        let scale = |size: u32| ((size as f32 * state.render_scale).round() as u32).max(1);
+        vk::Extent2D { width: scale(state.extent.width), height: scale(state.extent.height) }
+    }
+
+    /// Mode the swapchain presents with, the requested one or its fallback
+    /// when the surface doesn't support it.
+    pub fn present_mode(&self) -> PresentMode {
+        self.swapchain.lock().unwrap().present_mode
+    }
+
+    /// Recreates the swapchain with `present_mode` in the next
+    /// `start_frame`, which waits for the GPU to go idle first. The
+    /// swapchain extent may change with it. Ignored by headless renderers.
+    pub fn set_present_mode(&self, present_mode: PresentMode) {
+        let mut state = self.swapchain.lock().unwrap();
+        state.pending_present_mode = (present_mode != state.requested_present_mode).then_some(present_mode);
+    }
+
+    /// Whether the last `set_fullscreen_exclusive` asked for exclusive
+    /// fullscreen.
+    pub fn fullscreen_exclusive(&self) -> bool {
+        self.swapchain.lock().unwrap().fullscreen_exclusive
+    }
+
+    /// `VK_EXT_full_screen_exclusive` is enabled, only Windows drivers
+    /// expose it. Elsewhere compositors present fullscreen windows directly.
+    pub fn full_screen_exclusive_supported(&self) -> bool {
+        self.full_screen_exclusive_loader.is_some()
+    }
+
+    /// Recreates the swapchain in the next `start_frame` for a window that
+    /// changed its fullscreen state, see `display::set_window_mode`. With
+    /// `exclusive` the swapchain takes exclusive control of the monitor the
+    /// window is on where `full_screen_exclusive_supported`, and takes it
+    /// again after losing it, e.g. to alt-tab. Ignored by headless
+    /// renderers.
+    pub fn set_fullscreen_exclusive(&self, exclusive: bool) {
+        self.swapchain.lock().unwrap().pending_fullscreen_exclusive = Some(exclusive);
+    }
+
+    pub fn descriptor_backend(&self) -> DescriptorBackend {
+        if self.descriptor_heap.is_some() {
+            DescriptorBackend::Buffer
+        } else {
+            DescriptorBackend::Pool
+        }
+    }
+
+    /// Tier the renderer runs on, `RendererCreateInfo::max_api_version` or
+    /// lower when the device doesn't support it.
+    pub fn api_version(&self) -> ApiVersion {
+        self.api_version
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::empty();
+        capabilities.set(Capabilities::PushDescriptor, self.push_descriptor_loader.is_some());
+        capabilities.set(Capabilities::DescriptorBuffer, self.descriptor_heap.is_some());
+        capabilities.set(Capabilities::MemoryBudget, self.memory_budget_supported);
+        capabilities.set(Capabilities::CrashDiagnostics, self.is_crash_diagnostics_enabled());
+        capabilities.set(Capabilities::FramebufferCapture, self.swapchain_capturable);
+        capabilities.set(Capabilities::Portability, self.portability_subset_enabled);
+        capabilities.set(Capabilities::GeometryShader, self.geometry_shader_enabled);
+        capabilities.set(Capabilities::TessellationShader, self.tessellation_shader_enabled);
+        #[cfg(feature = "ray-tracing")]
+        capabilities.set(Capabilities::RayQuery, self.ray_tracing.is_some());
+        capabilities.set(Capabilities::AsyncCompute, self.compute_queue.is_some());
+        capabilities.set(Capabilities::ExternalMemory, self.external.is_some());
+        capabilities.set(Capabilities::Multiview, self.multiview_enabled);
+        capabilities
+    }
+
+    pub fn device_capabilities(&self) -> DeviceCapabilities {
+        self.device_capabilities
+    }
+
+    pub fn limits(&self) -> Limits {
+        let limits = &self.limits;
+        Limits {
+            max_texture_size: limits.max_image_dimension2_d,
+            max_texture_array_layers: limits.max_image_array_layers,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment,
+            max_uniform_buffer_range: limits.max_uniform_buffer_range,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_color_attachments: limits.max_color_attachments,
+            max_compute_workgroup_count: limits.max_compute_work_group_count,
+            max_compute_workgroup_size: limits.max_compute_work_group_size,
+            max_compute_workgroup_invocations: limits.max_compute_work_group_invocations,
+            timestamp_period: limits.timestamp_period,
+        }
+    }
+
+    /// Whether `CommandList::push_descriptor_set` pushes descriptors
+    /// directly rather than falling back to transient descriptor sets.
+    pub fn push_descriptors_supported(&self) -> bool {
+        self.push_descriptor_loader.is_some()
+    }
+
+    /// Family of the queue command lists for `queue` are submitted to.
+    pub(crate) fn queue_family(&self, queue: QueueType) -> u32 {
+        match queue {
+            QueueType::Compute => self.compute_family_idx.unwrap_or(self.graphics_family_idx),
+            QueueType::Graphics => self.graphics_family_idx,
+        }
+    }
+
+    /// Attaches a debug name to a Vulkan object, visible in validation
+    /// messages and capture tools.
+    pub(crate) fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        let Ok(name) = ffi::CString::new(name) else {
+            return;
+        };
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        unsafe { self.debug_utils_device.set_debug_utils_object_name(&name_info).unwrap() };
+    }
+
+    /// See `RendererCreateInfo::validate_usage`.
+    pub(crate) fn validate_usage(&self) -> bool {
+        self.validate_usage
+    }
+
     pub(crate) fn current_frame(&self) -> usize {
-        self.frame_number.get()
+        self.frame_number.load(Ordering::Acquire)
     }
 
-    pub fn start_frame(&self, signal_semaphore: &Semaphore) {
-        unsafe {
-            let (idx, _) = self.swapchain_loader.acquire_next_image(self.swapchain, 1000000000, signal_semaphore.get_current(), vk::Fence::null()).unwrap();
-            self.swapchain_image_idx.replace(idx);
+    /// Number of frames presented so far.
+    pub(crate) fn frame_index(&self) -> u64 {
+        self.frame_index.load(Ordering::Acquire)
+    }
+
+    /// `None` for renderers created with `new_headless`.
+    pub fn window(&self) -> Option<Arc<Window>> {
+        self.window.clone()
+    }
+
+    fn check_presentable(&self) -> Result<()> {
+        match self.window {
+            Some(_) => Ok(()),
+            None => Err(Error::Backend("A headless renderer has no swapchain".to_string())),
         }
     }
 
-    fn semaphore_submit_info(semaphore: &Semaphore) -> vk::SemaphoreSubmitInfo {
+    /// Acquires the next swapchain image. The commands writing it are
+    /// submitted with `submit_frame`, which waits for the acquire.
+    pub fn start_frame(&self) -> Result<()> {
+        crate::trace_span!("Renderer::start_frame");
+        self.check_presentable()?;
+        let mut state = self.swapchain.lock().unwrap();
+        self.apply_pending_changes(&mut state)?;
+        match unsafe { self.acquire_image(&state) } {
+            // Lost to another window, the next swapchain takes it again.
+            Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                state.pending_fullscreen_exclusive = Some(state.fullscreen_exclusive);
+                self.apply_pending_changes(&mut state)?;
+                self.check(unsafe { self.acquire_image(&state) })
+            }
+            res => self.check(res),
+        }
+    }
+
+    unsafe fn acquire_image(&self, state: &SwapchainState) -> VkResult<()> {
+        let mut sync = self.swapchain_sync.lock().unwrap();
+        let (idx, _) = self.swapchain_loader.acquire_next_image(state.handle, self.acquire_timeout, sync.spare, vk::Fence::null())?;
+        let acquired = sync.spare;
+        sync.spare = std::mem::replace(&mut sync.image_available[idx as usize], acquired);
+        self.swapchain_image_idx.store(idx, Ordering::Release);
+        Ok(())
+    }
+
+    /// Takes exclusive control of the display for `swapchain`. Failing
+    /// isn't fatal, the swapchain presents through the compositor then.
+    unsafe fn acquire_full_screen_exclusive(&self, swapchain: vk::SwapchainKHR) {
+        let Some(loader) = &self.full_screen_exclusive_loader else {
+            return;
+        };
+        if let Err(err) = loader.acquire_full_screen_exclusive_mode(swapchain) {
+            log::warn!(target: log_target::SWAPCHAIN, "Failed to acquire exclusive fullscreen, presenting through the compositor: {err}");
+        }
+    }
+
+    /// Applies what `set_render_scale`, `set_present_mode` and
+    /// `set_fullscreen_exclusive` requested since the last frame. Between
+    /// frames no swapchain image is acquired, so the swapchain can be
+    /// replaced once the GPU is idle.
+    fn apply_pending_changes(&self, state: &mut SwapchainState) -> Result<()> {
+        if let Some(render_scale) = state.pending_render_scale.take() {
+            state.render_scale = render_scale;
+        }
+        let pending_present_mode = state.pending_present_mode.take();
+        let pending_fullscreen_exclusive = state.pending_fullscreen_exclusive.take();
+        if pending_present_mode.is_none() && pending_fullscreen_exclusive.is_none() {
+            return Ok(());
+        }
+        let Some(window) = &self.window else {
+            return Ok(());
+        };
+        let present_mode = pending_present_mode.unwrap_or(state.requested_present_mode);
+        let fullscreen_exclusive = pending_fullscreen_exclusive.unwrap_or(state.fullscreen_exclusive);
+        let monitor = match &self.full_screen_exclusive_loader {
+            Some(_) if fullscreen_exclusive => exclusive_monitor(window),
+            _ => None,
+        };
+
         unsafe {
-            vk::SemaphoreSubmitInfo::default()
-                .semaphore(semaphore.get_current())
-                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-                .device_index(0)
-                .value(1)
+            // Frames in flight may still render to and present the old images.
+            self.check(self.wait_idle())?;
+            let (handle, extent, images, actual_present_mode) = create_swapchain(
+                &self.surface_loader,
+                &self.swapchain_loader,
+                &self.swapchain_desc,
+                window,
+                present_mode,
+                monitor,
+                state.handle)?;
+            if monitor.is_some() {
+                self.acquire_full_screen_exclusive(handle);
+            }
+
+            for &view in &state.image_views {
+                self.device.destroy_image_view(view, None);
+            }
+            self.swapchain_loader.destroy_swapchain(state.handle, None);
+            let new_sync = SwapchainSync::new(&self.device, images.len())?;
+            std::mem::replace(&mut *self.swapchain_sync.lock().unwrap(), new_sync).destroy(&self.device);
+
+            state.image_views = create_swapchain_image_views(&self.device, &images, self.swapchain_desc.view_format);
+            state.handle = handle;
+            state.extent = extent;
+            state.images = images;
+            state.present_mode = actual_present_mode;
+            state.requested_present_mode = present_mode;
+            state.fullscreen_exclusive = fullscreen_exclusive;
         }
+        Ok(())
+    }
+
+    fn semaphore_submit_info(semaphore: vk::Semaphore) -> vk::SemaphoreSubmitInfo<'static> {
+        vk::SemaphoreSubmitInfo::default()
+            .semaphore(semaphore)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .device_index(0)
+            .value(1)
+    }
+
+    /// `vkDeviceWaitIdle` with every queue locked, it needs them externally
+    /// synchronized like submits do. Locks graphics, present, then compute.
+    pub(crate) fn wait_idle(&self) -> VkResult<()> {
+        let _graphics = self.graphics_queue.lock().unwrap();
+        let _present = self.present_queue.as_ref().map(|q| q.lock().unwrap());
+        let _compute = self.compute_queue.as_ref().map(|q| q.lock().unwrap());
+        unsafe { self.device.device_wait_idle() }
+    }
+
+    /// The graphics queue when its family can present, both share one lock
+    /// then.
+    fn present_queue(&self) -> &Mutex<vk::Queue> {
+        self.present_queue.as_ref().unwrap_or(&self.graphics_queue)
     }
 
     pub(crate) fn get_current_swapchain_img(&self) -> vk::Image {
-        self.swapchain_images[self.swapchain_image_idx.get() as usize]
+        self.swapchain.lock().unwrap().images[self.swapchain_image_idx.load(Ordering::Acquire) as usize]
+    }
+
+    pub fn submit(&self, command_list: &CommandList, wait_semaphores: &[&Semaphore], signal_semaphores: &[&Semaphore], signal_fence: &Fence) -> Result<()> {
+        crate::trace_span!("Renderer::submit");
+        let wait_semaphores = wait_semaphores.iter().map(|s| unsafe { s.get_current() }).collect::<Vec<_>>();
+        let signal_semaphores = signal_semaphores.iter().map(|s| unsafe { s.get_current() }).collect::<Vec<_>>();
+        self.submit_with(command_list, &wait_semaphores, &signal_semaphores, signal_fence)
+    }
+
+    /// Submits the commands writing the image acquired by `start_frame`,
+    /// once the acquire completed. `present` waits for them.
+    pub fn submit_frame(&self, command_list: &CommandList, signal_fence: &Fence) -> Result<()> {
+        crate::trace_span!("Renderer::submit_frame");
+        self.check_presentable()?;
+        let (image_available, render_finished) = {
+            let sync = self.swapchain_sync.lock().unwrap();
+            let idx = self.swapchain_image_idx.load(Ordering::Acquire) as usize;
+            (sync.image_available[idx], sync.render_finished[idx])
+        };
+        self.submit_with(command_list, &[image_available], &[render_finished], signal_fence)
     }
 
-    pub fn submit(&self, command_list: &CommandList, wait_semaphores: &[&Semaphore], signal_semaphores: &[&Semaphore], signal_fence: &Fence) {
+    fn submit_with(&self, command_list: &CommandList, wait_semaphores: &[vk::Semaphore], signal_semaphores: &[vk::Semaphore], signal_fence: &Fence) -> Result<()> {
         let cl_submit_infos = [vk::CommandBufferSubmitInfo::default()
             .command_buffer(command_list.get_current())
             .device_mask(0)];
 
-        let wait_semaphore_infos = wait_semaphores.iter().map(|s| Self::semaphore_submit_info(s)).collect::<Vec<_>>();
-        let signal_semaphore_infos = signal_semaphores.iter().map(|s| Self::semaphore_submit_info(s)).collect::<Vec<_>>();
+        let wait_semaphore_infos = wait_semaphores.iter().map(|&s| Self::semaphore_submit_info(s)).collect::<Vec<_>>();
+        let signal_semaphore_infos = signal_semaphores.iter().map(|&s| Self::semaphore_submit_info(s)).collect::<Vec<_>>();
 
         let submit_infos = [vk::SubmitInfo2::default()
             .wait_semaphore_infos(&wait_semaphore_infos)
             .signal_semaphore_infos(&signal_semaphore_infos)
             .command_buffer_infos(&cl_submit_infos)];
 
-        unsafe { self.device.queue_submit2(self.graphics_queue, &submit_infos, signal_fence.get_current()).unwrap() }
+        let queue = match (command_list.queue(), &self.compute_queue) {
+            (QueueType::Compute, Some(compute_queue)) => compute_queue,
+            _ => &self.graphics_queue,
+        };
+        let res = {
+            let queue = queue.lock().unwrap();
+            unsafe { self.queue_submit2(*queue, &submit_infos, signal_fence.get_current()) }
+        };
+        self.check(res)
     }
 
-    pub fn present(&self, wait_semaphore: &Semaphore) {
+    /// Presents the image acquired by `start_frame` once the commands of
+    /// `submit_frame` finished.
+    pub fn present(&self) -> Result<()> {
+        crate::trace_span!("Renderer::present", frame_index = self.frame_index());
+        self.check_presentable()?;
         unsafe {
-            let swapchains = [self.swapchain];
-            let wait_semaphores = [wait_semaphore.get_current()];
-            let image_indices = [self.swapchain_image_idx.get()];
+            let image_idx = self.swapchain_image_idx.load(Ordering::Acquire);
+            let swapchains = [self.swapchain.lock().unwrap().handle];
+            let wait_semaphores = [self.swapchain_sync.lock().unwrap().render_finished[image_idx as usize]];
+            let image_indices = [image_idx];
             let present_info = vk::PresentInfoKHR::default()
                 .swapchains(&swapchains)
                 .wait_semaphores(&wait_semaphores)
                 .image_indices(&image_indices);
-            self.swapchain_loader.queue_present(self.graphics_queue, &present_info).unwrap();
-            self.frame_number.replace((self.current_frame() + 1) % FRAME_OVERLAP);
+            let res = {
+                let queue = self.present_queue().lock().unwrap();
+                self.swapchain_loader.queue_present(*queue, &present_info)
+            };
+            match res {
+                // E.g. alt-tab, the frame is dropped and the display
+                // acquired again with the next swapchain.
+                Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                    let mut state = self.swapchain.lock().unwrap();
+                    state.pending_fullscreen_exclusive = Some(state.fullscreen_exclusive);
+                }
+                res => {
+                    self.check(res)?;
+                }
+            }
+            self.frame_number.store((self.current_frame() + 1) % FRAME_OVERLAP, Ordering::Release);
+        }
+
+        // The faulty frame is already submitted, glitches usually repeat in
+        // the next one.
+        #[cfg(feature = "renderdoc")]
+        if self.capture_on_validation_error {
+            let errors = self.validation_error_count();
+            if self.presented_validation_errors.swap(errors, Ordering::AcqRel) != errors {
+                self.trigger_capture();
+            }
+        }
+
+        let frame_index = self.frame_index.fetch_add(1, Ordering::AcqRel) + 1;
+        unsafe { self.allocator.set_current_frame_index(frame_index as u32) };
+        self.check_budgets();
+        *self.frame_stats.lock().unwrap() = self.frame_counters.end_frame(Instant::now());
+        Ok(())
+    }
+}
+
+/// The Vulkan 1.3 core commands the renderer uses, dispatched to their
+/// extension equivalents on `ApiVersion::Vulkan12`.
+impl Renderer {
+    pub(crate) unsafe fn cmd_pipeline_barrier2(&self, command_buffer: vk::CommandBuffer, dependency_info: &vk::DependencyInfo) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.cmd_pipeline_barrier2(command_buffer, dependency_info),
+            None => self.device.cmd_pipeline_barrier2(command_buffer, dependency_info),
+        }
+    }
+
+    #[cfg(feature = "debug")]
+    pub(crate) unsafe fn cmd_write_timestamp2(&self, command_buffer: vk::CommandBuffer, stage: vk::PipelineStageFlags2, query_pool: vk::QueryPool, query: u32) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.cmd_write_timestamp2(command_buffer, stage, query_pool, query),
+            None => self.device.cmd_write_timestamp2(command_buffer, stage, query_pool, query),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_set_event2(&self, command_buffer: vk::CommandBuffer, event: vk::Event, dependency_info: &vk::DependencyInfo) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.cmd_set_event2(command_buffer, event, dependency_info),
+            None => self.device.cmd_set_event2(command_buffer, event, dependency_info),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_wait_events2(&self, command_buffer: vk::CommandBuffer, events: &[vk::Event], dependency_infos: &[vk::DependencyInfo]) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.cmd_wait_events2(command_buffer, events, dependency_infos),
+            None => self.device.cmd_wait_events2(command_buffer, events, dependency_infos),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_reset_event2(&self, command_buffer: vk::CommandBuffer, event: vk::Event, stage_mask: vk::PipelineStageFlags2) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.cmd_reset_event2(command_buffer, event, stage_mask),
+            None => self.device.cmd_reset_event2(command_buffer, event, stage_mask),
+        }
+    }
+
+    unsafe fn queue_submit2(&self, queue: vk::Queue, submits: &[vk::SubmitInfo2], fence: vk::Fence) -> ash::prelude::VkResult<()> {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.synchronization2.queue_submit2(queue, submits, fence),
+            None => self.device.queue_submit2(queue, submits, fence),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_begin_rendering(&self, command_buffer: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.dynamic_rendering.cmd_begin_rendering(command_buffer, rendering_info),
+            None => self.device.cmd_begin_rendering(command_buffer, rendering_info),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.dynamic_rendering.cmd_end_rendering(command_buffer),
+            None => self.device.cmd_end_rendering(command_buffer),
+        }
+    }
+
+    pub(crate) unsafe fn cmd_blit_image2(&self, command_buffer: vk::CommandBuffer, blit_info: &vk::BlitImageInfo2) {
+        match &self.tier_loaders {
+            Some(loaders) => loaders.copy_commands2.cmd_blit_image2(command_buffer, blit_info),
+            None => self.device.cmd_blit_image2(command_buffer, blit_info),
         }
     }
 }
@@ -484,18 +1872,27 @@ impl Renderer {
 impl Drop for Renderer {
     fn drop(&mut self) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
-            self.device.destroy_command_pool(self.command_pool, None);
-            for &v in &self.swapchain_imageviews {
+            // Waiting on a lost device fails, there's nothing left to wait for.
+            let _ = self.device.device_wait_idle();
+            if let Some(heap) = &mut self.descriptor_heap {
+                heap.destroy(&self.allocator);
+            }
+            if let Some(markers) = &mut self.buffer_markers {
+                markers.destroy(&self.allocator);
+            }
+            self.device.destroy_descriptor_pool(*self.descriptor_pool.get_mut().unwrap(), None);
+            self.device.destroy_pipeline_cache(self.pipeline_cache, None);
+            let swapchain = self.swapchain.get_mut().unwrap();
+            for &v in &swapchain.image_views {
                 self.device.destroy_image_view(v, None);
             }
+            self.swapchain_sync.get_mut().unwrap().destroy(&self.device);
 
-            self.swapchain_loader.destroy_swapchain(self.swapchain, None);
-            self.surface_loader.destroy_surface(self.surface, None);
+            if self.window.is_some() {
+                self.swapchain_loader.destroy_swapchain(swapchain.handle, None);
+                self.surface_loader.destroy_surface(self.surface, None);
+            }
             self.device.destroy_device(None);
-            self.debug_utils_loader.destroy_debug_utils_messenger(self.debug_callback, None);
-            self.instance.destroy_instance(None);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::{AddressMode, Filter, SamplerCreateInfo};
+use crate::render::hal::vulkan::renderer::Renderer;
+
+pub struct Sampler {
+    pub(crate) sampler: vk::Sampler,
+
+    renderer: Arc<Renderer>,
+}
+
+impl Sampler {
+    pub fn new(renderer: Arc<Renderer>, create_info: SamplerCreateInfo) -> Arc<Self> {
+        let (filter, mipmap_mode) = match create_info.filter {
+            Filter::Nearest => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST),
+            Filter::Linear => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+        };
+
+        let address_mode = match create_info.address_mode {
+            AddressMode::Repeat => vk::SamplerAddressMode::REPEAT,
+            AddressMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            AddressMode::ClampToBorder => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+        };
+
+        let mut info = vk::SamplerCreateInfo::default()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(mipmap_mode)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        if let Some(compare_op) = create_info.compare {
+            info = info.compare_enable(true).compare_op(compare_op);
+        }
+
+        let sampler = unsafe { renderer.device.create_sampler(&info, None).unwrap() };
+
+        Arc::new(Sampler { sampler, renderer })
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe { self.renderer.device.destroy_sampler(self.sampler, None); }
+    }
+}
@@ -7,10 +7,32 @@ use crate::render::hal::vulkan::renderer::Renderer;
 
 pub struct Shader {
     pub(crate) shader: vk::ShaderModule,
+    /// `local_size` of a compute shader.
+    pub(crate) local_size: Option<[u32; 3]>,
 
     renderer: Arc<Renderer>,
 }
 
+/// Reads the `LocalSize` execution mode from SPIR-V words.
+fn parse_local_size(code: &[u32]) -> Option<[u32; 3]> {
+    const OP_EXECUTION_MODE: u32 = 16;
+    const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+    // Instructions start after the five word header.
+    let mut offset = 5;
+    while let Some(&word) = code.get(offset) {
+        let (word_count, opcode) = ((word >> 16) as usize, word & 0xffff);
+        if word_count == 0 {
+            return None;
+        }
+        if opcode == OP_EXECUTION_MODE && word_count == 6 && code.get(offset + 2) == Some(&EXECUTION_MODE_LOCAL_SIZE) {
+            return code.get(offset + 3..offset + 6).map(|size| [size[0], size[1], size[2]]);
+        }
+        offset += word_count;
+    }
+    None
+}
+
 impl Shader {
     pub fn new(renderer: Arc<Renderer>, create_info: ShaderCreateInfo) -> Arc<Self> {
         let info = vk::ShaderModuleCreateInfo::default()
@@ -18,7 +40,7 @@ impl Shader {
 
         let shader = unsafe { renderer.device.create_shader_module(&info, None).unwrap() };
 
-        Arc::new(Shader { shader, renderer })
+        Arc::new(Shader { shader, local_size: parse_local_size(create_info.code), renderer })
     }
 }
 
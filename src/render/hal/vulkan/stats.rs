@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Instant;
+
+use ash::vk;
+
+use crate::render::hal::FrameStats;
+
+/// Work recorded by all command lists, summed up per frame by
+/// `Renderer::present`.
+#[derive(Default)]
+pub(crate) struct FrameCounters {
+    draw_calls: AtomicU32,
+    dispatches: AtomicU32,
+    triangles: AtomicU64,
+    descriptor_set_allocations: AtomicU32,
+    last_present: Mutex<Option<Instant>>,
+}
+
+fn triangle_count(topology: vk::PrimitiveTopology, vertex_count: u32) -> u64 {
+    match topology {
+        vk::PrimitiveTopology::TRIANGLE_LIST => (vertex_count / 3) as u64,
+        vk::PrimitiveTopology::TRIANGLE_STRIP | vk::PrimitiveTopology::TRIANGLE_FAN => vertex_count.saturating_sub(2) as u64,
+        _ => 0,
+    }
+}
+
+impl FrameCounters {
+    pub(crate) fn draw(&self, topology: vk::PrimitiveTopology, vertex_count: u32, instance_count: u32) {
+        self.draw_calls.fetch_add(1, Ordering::Relaxed);
+        self.triangles.fetch_add(triangle_count(topology, vertex_count) * instance_count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn indirect_draw(&self, draw_count: u32) {
+        self.draw_calls.fetch_add(draw_count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dispatch(&self) {
+        self.dispatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn descriptor_set_allocation(&self) {
+        self.descriptor_set_allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts since the last call, which are reset.
+    pub(crate) fn end_frame(&self, now: Instant) -> FrameStats {
+        let last_present = self.last_present.lock().unwrap().replace(now);
+        FrameStats {
+            cpu_time: last_present.map_or_else(Default::default, |last| now.saturating_duration_since(last)),
+            draw_calls: self.draw_calls.swap(0, Ordering::Relaxed),
+            dispatches: self.dispatches.swap(0, Ordering::Relaxed),
+            triangles: self.triangles.swap(0, Ordering::Relaxed),
+            descriptor_set_allocations: self.descriptor_set_allocations.swap(0, Ordering::Relaxed),
+        }
+    }
+}
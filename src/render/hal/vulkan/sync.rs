@@ -1,10 +1,19 @@
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use ash::vk;
 
+use crate::render::hal::{Error, Result};
 use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::external::{ExternalHandle, SEMAPHORE_HANDLE_TYPE};
 use crate::render::hal::vulkan::renderer::Renderer;
 
+/// Vulkan timeout in nanoseconds, where `u64::MAX` waits forever.
+pub(crate) fn timeout_ns(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(u64::MAX, |timeout| timeout.as_nanos().min(u64::MAX as u128) as u64)
+}
+
 pub struct Semaphore {
     semaphores: [vk::Semaphore; FRAME_OVERLAP],
     renderer: Arc<Renderer>,
@@ -12,7 +21,19 @@ pub struct Semaphore {
 
 impl Semaphore {
     pub fn new(renderer: Arc<Renderer>) -> Self {
-        let info = vk::SemaphoreCreateInfo::default();
+        Self::create(renderer, vk::SemaphoreCreateInfo::default())
+    }
+
+    /// Semaphores other APIs and processes can import with the handles from
+    /// `export`, to order their access to exported textures with patoka's
+    /// submissions. Needs `Capabilities::ExternalMemory`.
+    pub fn new_exportable(renderer: Arc<Renderer>) -> Self {
+        assert!(renderer.external.is_some(), "Exportable semaphores need Capabilities::ExternalMemory");
+        let mut export_info = vk::ExportSemaphoreCreateInfo::default().handle_types(SEMAPHORE_HANDLE_TYPE);
+        Self::create(renderer, vk::SemaphoreCreateInfo::default().push_next(&mut export_info))
+    }
+
+    fn create(renderer: Arc<Renderer>, info: vk::SemaphoreCreateInfo) -> Self {
         let semaphores = (0..FRAME_OVERLAP)
             .map(|_| unsafe { renderer.device.create_semaphore(&info, None).unwrap() })
             .collect::<Vec<vk::Semaphore>>()
@@ -26,6 +47,17 @@ impl Semaphore {
     pub(crate) unsafe fn get_current(&self) -> vk::Semaphore {
         self.semaphores[self.renderer.current_frame()]
     }
+
+    /// New handles to the semaphores of a semaphore created with
+    /// `new_exportable`, one per frame slot, indexed by
+    /// `Renderer::current_frame`.
+    pub fn export(&self) -> Result<[ExternalHandle; FRAME_OVERLAP]> {
+        let external = self.renderer.external.as_ref().expect("Exportable semaphores need Capabilities::ExternalMemory");
+        let handles = self.semaphores.iter()
+            .map(|&semaphore| unsafe { external.export_semaphore(semaphore) })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(handles.try_into().unwrap())
+    }
 }
 
 impl Drop for Semaphore {
@@ -68,13 +100,106 @@ impl Fence {
         self.fences[self.renderer.current_frame()]
     }
 
-    pub fn wait(&self) {
+    /// Waits for `RendererCreateInfo::fence_timeout`, `Error::Timeout` when
+    /// the fence isn't signaled by then.
+    pub fn wait(&self) -> Result<()> {
+        crate::trace_span!("Fence::wait");
+        self.wait_ns(self.renderer.fence_timeout)
+    }
+
+    /// Like `wait` with a different timeout, `None` waits forever.
+    pub fn wait_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        crate::trace_span!("Fence::wait");
+        self.wait_ns(timeout_ns(timeout))
+    }
+
+    fn wait_ns(&self, timeout: u64) -> Result<()> {
         let frame = self.renderer.current_frame();
-        unsafe { self.renderer.device.wait_for_fences(&self.fences[frame..frame + 1], true, 1000000000).unwrap(); }
+        self.renderer.check(unsafe { self.renderer.device.wait_for_fences(&self.fences[frame..frame + 1], true, timeout) })
     }
 
-    pub fn reset(&self) {
+    /// Whether the fence of the current frame slot is signaled, without
+    /// waiting.
+    pub fn is_signaled(&self) -> Result<bool> {
+        self.renderer.check(unsafe { self.renderer.device.get_fence_status(self.get_current()) })
+    }
+
+    pub fn reset(&self) -> Result<()> {
         let frame = self.renderer.current_frame();
-        unsafe { self.renderer.device.reset_fences(&self.fences[frame..frame + 1]).unwrap(); }
+        self.renderer.check(unsafe { self.renderer.device.reset_fences(&self.fences[frame..frame + 1]) })
+    }
+}
+/// Split barrier within one queue: `CommandList::set_event` after the
+/// commands producing a result, `CommandList::wait_event` before the first
+/// command consuming it. Commands recorded in between overlap with the
+/// producers, unlike with `CommandList::memory_barrier`.
+pub struct Event {
+    events: [vk::Event; FRAME_OVERLAP],
+    renderer: Arc<Renderer>,
+}
+
+impl Event {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        // Only set and waited on by command buffers, never by the host.
+        let info = vk::EventCreateInfo::default()
+            .flags(vk::EventCreateFlags::DEVICE_ONLY);
+
+        let events = std::array::from_fn(|_| unsafe { renderer.device.create_event(&info, None).unwrap() });
+        Self {
+            events,
+            renderer,
+        }
     }
-}
\ No newline at end of file
+
+    pub(crate) unsafe fn get_current(&self) -> vk::Event {
+        self.events[self.renderer.current_frame()]
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        for e in self.events {
+            unsafe { self.renderer.device.destroy_event(e, None) }
+        }
+    }
+}
+
+/// Event set by a command buffer and observed by the host, independent of
+/// the frame slots, see `CommandList::signal_host`. Set once, a new one is
+/// created for the next signal.
+pub(crate) struct HostEvent {
+    pub(crate) event: vk::Event,
+    renderer: Arc<Renderer>,
+}
+
+impl HostEvent {
+    pub(crate) fn new(renderer: Arc<Renderer>) -> Self {
+        let info = vk::EventCreateInfo::default();
+        let event = unsafe { renderer.device.create_event(&info, None).unwrap() };
+        Self { event, renderer }
+    }
+
+    pub(crate) fn is_set(&self) -> Result<bool> {
+        self.renderer.check(unsafe { self.renderer.device.get_event_status(self.event) })
+    }
+
+    /// Polls until the event is set, `Error::Timeout` after
+    /// `RendererCreateInfo::fence_timeout`. Events can't be waited on by
+    /// the host.
+    pub(crate) fn wait(&self) -> Result<()> {
+        let start = Instant::now();
+        while !self.is_set()? {
+            if start.elapsed().as_nanos() >= self.renderer.fence_timeout as u128 {
+                return Err(Error::Timeout);
+            }
+            thread::yield_now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HostEvent {
+    fn drop(&mut self) {
+        unsafe { self.renderer.device.destroy_event(self.event, None) }
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use ash::vk;
+
+use crate::render::hal::ColorEncoding;
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::descriptor_set::DescriptorSetLayout;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::util::log_target;
+
+/// Reports a misuse of the HAL. Panics, like the asserts guarding the API,
+/// the backtrace points at the offending call.
+pub(crate) fn fail(message: &str) -> ! {
+    panic!("HAL validation: {message}")
+}
+
+pub(crate) fn check_texture_usage(texture: &Texture, usage: vk::ImageUsageFlags, operation: &str) {
+    if !texture.usage.contains(usage) {
+        fail(&format!("{operation} needs a texture with {usage:?} usage, it was created with {:?}", texture.usage));
+    }
+}
+
+pub(crate) fn check_buffer_usage(buffer: &Buffer, usage: vk::BufferUsageFlags, operation: &str) {
+    if !buffer.usage.contains(usage) {
+        fail(&format!("{operation} needs a buffer with {usage:?} usage, it was created with {:?}", buffer.usage));
+    }
+}
+
+pub(crate) fn check_binding(layout: &DescriptorSetLayout, binding: u32, descriptor_type: vk::DescriptorType) {
+    match layout.bindings.iter().find(|(b, _)| *b == binding) {
+        None => fail(&format!("Binding {binding} doesn't exist in the descriptor set layout")),
+        Some((_, expected)) if *expected != descriptor_type => {
+            fail(&format!("Writing a {descriptor_type:?} to binding {binding}, which is a {expected:?}"))
+        }
+        Some(_) => {}
+    }
+}
+
+/// Why copying `source` to a swapchain in `target` most likely applies
+/// the sRGB curve twice or not at all. Float sources may hold linear or
+/// encoded color and aren't checked.
+fn gamma_mismatch(source: vk::Format, target: vk::SurfaceFormatKHR) -> Option<&'static str> {
+    if target.color_space != vk::ColorSpaceKHR::SRGB_NONLINEAR || !ColorEncoding::has_srgb_variant(source) {
+        return None;
+    }
+    match (ColorEncoding::of_format(source), ColorEncoding::of_format(target.format)) {
+        (ColorEncoding::Linear, ColorEncoding::Srgb) => {
+            Some("8 bit UNORM color is usually sRGB encoded already and gets encoded again, the image looks washed out")
+        }
+        (ColorEncoding::Srgb, ColorEncoding::Linear) => {
+            Some("the texels are decoded to linear and presented as sRGB without encoding, the image looks too dark")
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct State {
+    recording: bool,
+    rendering: bool,
+    /// Set layouts of the bound pipelines.
+    compute_sets: Option<Vec<Vec<(u32, vk::DescriptorType)>>>,
+    graphics_sets: Option<Vec<Vec<(u32, vk::DescriptorType)>>>,
+}
+
+/// Tracks the state of a `CommandList` to reject calls that are invalid in
+/// it, with `RendererCreateInfo::validate_usage`.
+#[derive(Default)]
+pub(crate) struct CommandListValidator {
+    state: Mutex<State>,
+    /// Source and swapchain formats already warned about, see
+    /// `present_encoding`.
+    gamma_warnings: Mutex<HashSet<(vk::Format, vk::Format)>>,
+}
+
+impl CommandListValidator {
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.recording {
+            fail("Command list reset while recording, call `end` first");
+        }
+        *state = State::default();
+    }
+
+    pub(crate) fn begin(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.recording {
+            fail("`begin` called twice without `end`");
+        }
+        *state = State { recording: true, ..State::default() };
+    }
+
+    pub(crate) fn end(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.recording {
+            fail("`end` called without `begin`");
+        }
+        if state.rendering {
+            fail("`end` called inside `begin_rendering`, call `end_rendering` first");
+        }
+        state.recording = false;
+    }
+
+    pub(crate) fn command(&self, name: &str) {
+        let state = self.state.lock().unwrap();
+        if !state.recording {
+            fail(&format!("`{name}` recorded outside of `begin`/`end`"));
+        }
+    }
+
+    /// Transfers and dispatches aren't allowed inside a render pass.
+    pub(crate) fn outside_rendering(&self, name: &str) {
+        self.command(name);
+        if self.state.lock().unwrap().rendering {
+            fail(&format!("`{name}` recorded inside `begin_rendering`/`end_rendering`"));
+        }
+    }
+
+    pub(crate) fn bind_pipeline(&self, bind_point: vk::PipelineBindPoint, set_layouts: Vec<Vec<(u32, vk::DescriptorType)>>) {
+        self.command("bind_pipeline");
+        let mut state = self.state.lock().unwrap();
+        match bind_point {
+            vk::PipelineBindPoint::COMPUTE => state.compute_sets = Some(set_layouts),
+            _ => state.graphics_sets = Some(set_layouts),
+        }
+    }
+
+    pub(crate) fn bind_set(&self, bind_point: vk::PipelineBindPoint, set_index: u32, layout: &DescriptorSetLayout) {
+        self.command("bind_descriptor_set");
+        let state = self.state.lock().unwrap();
+        let sets = match bind_point {
+            vk::PipelineBindPoint::COMPUTE => &state.compute_sets,
+            _ => &state.graphics_sets,
+        };
+        let Some(sets) = sets else {
+            fail("Descriptor set bound before any pipeline, bind the pipeline first");
+        };
+        match sets.get(set_index as usize) {
+            None => fail(&format!("The bound pipeline has no set {set_index}")),
+            Some(expected) if *expected != layout.bindings => {
+                fail(&format!("Descriptor set layout {:?} doesn't match set {set_index} of the bound pipeline, {expected:?}", layout.bindings))
+            }
+            Some(_) => {}
+        }
+    }
+
+    pub(crate) fn dispatch(&self) {
+        self.outside_rendering("dispatch_compute_pipeline");
+        if self.state.lock().unwrap().compute_sets.is_none() {
+            fail("Dispatch without a bound compute pipeline");
+        }
+    }
+
+    pub(crate) fn begin_rendering(&self) {
+        self.outside_rendering("begin_rendering");
+        self.state.lock().unwrap().rendering = true;
+    }
+
+    pub(crate) fn end_rendering(&self) {
+        self.command("end_rendering");
+        let mut state = self.state.lock().unwrap();
+        if !state.rendering {
+            fail("`end_rendering` called without `begin_rendering`");
+        }
+        state.rendering = false;
+    }
+
+    /// Attachment clears only exist inside a render pass.
+    pub(crate) fn inside_rendering(&self, name: &str) {
+        self.command(name);
+        if !self.state.lock().unwrap().rendering {
+            fail(&format!("`{name}` recorded outside of `begin_rendering`/`end_rendering`"));
+        }
+    }
+
+    /// Warns about copies to the swapchain that get the sRGB curve wrong,
+    /// once per format pair. They are valid and could be intended, unlike
+    /// the misuses that fail.
+    pub(crate) fn present_encoding(&self, source: vk::Format, target: vk::SurfaceFormatKHR) {
+        let Some(reason) = gamma_mismatch(source, target) else {
+            return;
+        };
+        if self.gamma_warnings.lock().unwrap().insert((source, target.format)) {
+            log::warn!(target: log_target::VULKAN, "HAL validation: copying {source:?} to a {:?} swapchain, {reason}", target.format);
+        }
+    }
+
+    pub(crate) fn draw(&self) {
+        self.command("draw");
+        let state = self.state.lock().unwrap();
+        if !state.rendering {
+            fail("Draw outside of `begin_rendering`/`end_rendering`");
+        }
+        if state.graphics_sets.is_none() {
+            fail("Draw without a bound graphics pipeline");
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::culling::BoundingSphere;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, Filter, GraphicsPipelineCreateInfo, MemoryLocation, PipelineLayoutCreateInfo, PushConstantRange, RasterState, SamplerCreateInfo, ShaderCreateInfo, ShaderStages, TextureCreateInfo, TextureKind, VertexLayout, VertexSemantic};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::{add, cross, normalize, scale, sub, Mat4, Vec3};
+use crate::render::mesh::Mesh;
+
+const CAPTURE_DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+const CAPTURE_PUSH_CONSTANTS_SIZE: u32 = 144;
+/// Matches `Instance` in `impostor.vert`, std430.
+const INSTANCE_SIZE: usize = 48;
+/// Two triangles per impostor.
+const VERTICES_PER_IMPOSTOR: u32 = 6;
+
+pub struct ImpostorAtlasCreateInfo {
+    /// Width and height of a single capture.
+    pub resolution: u32,
+    /// Captures are laid out on a `grid_size` x `grid_size` hemi-octahedral grid.
+    pub grid_size: u32,
+    pub format: vk::Format,
+}
+
+/// Texture array holding captures of an object from a set of directions
+/// over the upper hemisphere, one direction per layer. Filled at load time
+/// by `ImpostorCapture`, drawn by `ImpostorRenderer`.
+pub struct ImpostorAtlas {
+    texture: Texture,
+    grid_size: u32,
+    /// Object space bounds of the captured mesh, the captures are framed to it.
+    bounding_sphere: BoundingSphere,
+}
+
+impl ImpostorAtlas {
+    pub fn new(renderer: Arc<Renderer>, create_info: ImpostorAtlasCreateInfo) -> Self {
+        assert!(create_info.grid_size > 0);
+
+        let texture = {
+            let resolution = create_info.resolution;
+            let create_info = TextureCreateInfo {
+                format: create_info.format,
+                extent: vk::Extent3D { width: resolution, height: resolution, depth: 1 },
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                aspect: vk::ImageAspectFlags::COLOR,
+                // At least two layers so the texture always gets an array view.
+                array_layers: (create_info.grid_size * create_info.grid_size).max(2),
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer, create_info)
+        };
+
+        texture.set_name("impostor atlas");
+
+        Self { texture, grid_size: create_info.grid_size, bounding_sphere: BoundingSphere::default() }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.grid_size * self.grid_size
+    }
+
+    /// Unit direction from the object towards the capture camera for `layer`.
+    pub fn capture_direction(&self, layer: u32) -> [f32; 3] {
+        assert!(layer < self.layer_count());
+        let x = layer % self.grid_size;
+        let y = layer / self.grid_size;
+        let u = (x as f32 + 0.5) / self.grid_size as f32;
+        let v = (y as f32 + 0.5) / self.grid_size as f32;
+        hemi_octahedral_decode(u, v)
+    }
+
+    /// Right and up axes of the capture image for `layer`, in object space.
+    /// The view looks along `-capture_direction(layer)` with +Y up, or -Z
+    /// up for a capture straight from above.
+    pub fn capture_axes(&self, layer: u32) -> (Vec3, Vec3) {
+        let forward = scale(self.capture_direction(layer), -1.0);
+        let up = if forward[1].abs() > 0.999 { [0.0, 0.0, -1.0] } else { [0.0, 1.0, 0.0] };
+        let right = normalize(cross(forward, up));
+        (right, cross(right, forward))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere
+    }
+
+    /// Layer whose capture direction is closest to `to_camera`, the direction
+    /// from the object towards the viewer. Directions below the horizon
+    /// are clamped to it.
+    pub fn select_layer(&self, to_camera: [f32; 3]) -> u32 {
+        let (u, v) = hemi_octahedral_encode(to_camera);
+        let last = self.grid_size - 1;
+        let x = ((u * self.grid_size as f32) as u32).min(last);
+        let y = ((v * self.grid_size as f32) as u32).min(last);
+        y * self.grid_size + x
+    }
+}
+
+/// Renders a mesh into every layer of an `ImpostorAtlas`, with an
+/// orthographic camera around its bounding sphere. The captures hold the
+/// base color lit by a fixed key light, alpha marks covered texels.
+/// Meant to run once at load time, the capture can be dropped after its
+/// commands were submitted.
+pub struct ImpostorCapture {
+    depth: Arc<Texture>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+}
+
+impl ImpostorCapture {
+    /// `vertex_layout` is the layout of the meshes to capture, they need
+    /// positions and normals.
+    pub fn new(renderer: Arc<Renderer>, atlas: &ImpostorAtlas, vertex_layout: VertexLayout) -> Self {
+        let extent = atlas.texture.extent();
+
+        let depth = {
+            let create_info = TextureCreateInfo {
+                format: CAPTURE_DEPTH_FORMAT,
+                extent,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                aspect: vk::ImageAspectFlags::DEPTH,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: CAPTURE_PUSH_CONSTANTS_SIZE,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let mut vertex_layout = vertex_layout;
+        vertex_layout.attributes.retain(|a| matches!(a.semantic, VertexSemantic::Position | VertexSemantic::Normal));
+        assert_eq!(vertex_layout.attributes.len(), 2, "impostor captures need positions and normals");
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/impostor_capture_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/impostor_capture_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout,
+                color_formats: vec![atlas.texture.format()],
+                depth_format: CAPTURE_DEPTH_FORMAT,
+                extent: vk::Extent2D { width: extent.width, height: extent.height },
+                raster: RasterState::default(),
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer, create_info)
+        };
+
+        Self { depth: Arc::new(depth), pipeline, pipeline_layout }
+    }
+
+    /// Records the captures of `mesh`, all submeshes in `base_color`, into
+    /// every layer of `atlas`. The atlas is ready for sampling once the
+    /// commands completed.
+    pub fn record(&self, command_list: &mut CommandList, atlas: &mut ImpostorAtlas, mesh: &Mesh, base_color: [f32; 4]) {
+        let sphere = mesh.bounding_sphere();
+        let radius = sphere.radius.max(f32::EPSILON);
+        atlas.bounding_sphere = sphere;
+
+        command_list.transition_texture_layout(&atlas.texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.depth, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        command_list.retain(self.depth.clone());
+
+        // The view sits outside the sphere, so it never clips the mesh.
+        let projection = Mat4::orthographic(-radius, radius, -radius, radius, radius, 3.0 * radius);
+        for layer in 0..atlas.layer_count() {
+            let eye = add(sphere.center, scale(atlas.capture_direction(layer), 2.0 * radius));
+            let (_, up) = atlas.capture_axes(layer);
+            let view = Mat4::look_at(eye, sphere.center, up);
+
+            command_list.begin_layer_rendering(&atlas.texture, layer, &self.depth, [0.0; 4]);
+            command_list.bind_graphics_pipeline(self.pipeline.clone());
+
+            let mut push_constants = Vec::with_capacity(CAPTURE_PUSH_CONSTANTS_SIZE as usize);
+            push_constants.extend_from_slice(&(projection * view).to_bytes());
+            push_constants.extend_from_slice(&Mat4::IDENTITY.to_bytes());
+            push_constants.extend(base_color.iter().flat_map(|c| c.to_ne_bytes()));
+            command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &push_constants);
+
+            mesh.bind(command_list);
+            for submesh in mesh.submeshes() {
+                if mesh.is_indexed() {
+                    command_list.draw_indexed(submesh.indices.end - submesh.indices.start, 1, submesh.indices.start, submesh.base_vertex, 0);
+                } else {
+                    command_list.draw(mesh.vertex_count(), 1, 0, 0);
+                }
+            }
+
+            command_list.end_rendering();
+            // The depth target is cleared by the next layer.
+            command_list.transition_texture_layout(&self.depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        }
+
+        command_list.transition_texture_layout(&atlas.texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
+
+/// An object drawn as an impostor. Rotation is not applied, captures are
+/// taken in object space around +Y.
+#[derive(Clone, Copy, Debug)]
+pub struct ImpostorInstance {
+    pub position: Vec3,
+    pub scale: f32,
+}
+
+pub struct ImpostorRendererCreateInfo {
+    pub max_instances: u32,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+/// Draws instances of a captured object as quads textured with the capture
+/// closest to the view direction. Layers are picked on the CPU per instance
+/// and per frame, there is no blending between neighbouring captures.
+///
+/// `draw` is recorded inside `begin_rendering`.
+pub struct ImpostorRenderer {
+    renderer: Arc<Renderer>,
+    max_instances: u32,
+    instance_buffers: Vec<Buffer>,
+    sampler: Arc<Sampler>,
+    descriptor_set: Arc<DescriptorSet>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+}
+
+impl ImpostorRenderer {
+    pub fn new(renderer: Arc<Renderer>, create_info: ImpostorRendererCreateInfo) -> Self {
+        let max_instances = create_info.max_instances.max(1);
+
+        let instance_buffers = (0..FRAME_OVERLAP)
+            .map(|_| {
+                let create_info = BufferCreateInfo {
+                    size: (max_instances as usize * INSTANCE_SIZE) as u64,
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                    location: MemoryLocation::CpuToGpu,
+                };
+                Buffer::new(renderer.clone(), create_info)
+            })
+            .collect();
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: vec![
+                    DescriptorSetBinding { stage: ShaderStages::Vertex, typ: BindingType::StorageBuffer, binding: 0 },
+                    DescriptorSetBinding { stage: ShaderStages::Fragment, typ: BindingType::SampledTexture, binding: 1 },
+                ],
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_layout.clone());
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_layout],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: 64,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/impostor_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/impostor_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: VertexLayout::default(),
+                color_formats: vec![create_info.color_format],
+                depth_format: create_info.depth_format,
+                extent: create_info.extent,
+                raster: RasterState {
+                    cull_mode: vk::CullModeFlags::NONE,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer.clone(), create_info)
+        };
+
+        Self { renderer, max_instances, instance_buffers, sampler, descriptor_set, pipeline, pipeline_layout }
+    }
+
+    pub fn max_instances(&self) -> u32 {
+        self.max_instances
+    }
+
+    /// Draws `instances` of the object captured in `atlas`, instances past
+    /// `max_instances` are dropped.
+    pub fn draw(&mut self, command_list: &mut CommandList, atlas: &ImpostorAtlas, instances: &[ImpostorInstance], view_projection: Mat4, camera_position: Vec3) {
+        let count = instances.len().min(self.max_instances as usize);
+        if count == 0 {
+            return;
+        }
+
+        let sphere = atlas.bounding_sphere();
+        let mut data = Vec::with_capacity(count * INSTANCE_SIZE);
+        for instance in &instances[..count] {
+            let center = add(instance.position, scale(sphere.center, instance.scale));
+            let layer = atlas.select_layer(normalize(sub(camera_position, center)));
+            let (right, up) = atlas.capture_axes(layer);
+            let floats = [
+                center[0], center[1], center[2], sphere.radius * instance.scale,
+                right[0], right[1], right[2], layer as f32,
+                up[0], up[1], up[2], 0.0,
+            ];
+            data.extend(floats.iter().flat_map(|f| f.to_ne_bytes()));
+        }
+
+        let frame = self.renderer.current_frame();
+        self.instance_buffers[frame].write(0, &data);
+        self.descriptor_set.write_storage_buffer(0, &self.instance_buffers[frame]);
+        self.descriptor_set.write_sampled_texture(1, atlas.texture(), &self.sampler);
+
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, self.descriptor_set.clone());
+        command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &view_projection.to_bytes());
+        command_list.draw(VERTICES_PER_IMPOSTOR, count as u32, 0, 0);
+    }
+}
+
+/// Maps a direction on the +Y hemisphere to [0, 1]^2.
+fn hemi_octahedral_encode(dir: [f32; 3]) -> (f32, f32) {
+    let [x, y, z] = dir;
+    let y = y.max(0.0);
+    let sum = x.abs() + y + z.abs();
+    if sum == 0.0 {
+        return (0.5, 0.5);
+    }
+
+    let (px, pz) = (x / sum, z / sum);
+    ((px + pz) * 0.5 + 0.5, (px - pz) * 0.5 + 0.5)
+}
+
+fn hemi_octahedral_decode(u: f32, v: f32) -> [f32; 3] {
+    let (a, b) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+    let x = (a + b) * 0.5;
+    let z = (a - b) * 0.5;
+    let y = 1.0 - x.abs() - z.abs();
+    let len = (x * x + y * y + z * z).sqrt();
+    [x / len, y / len, z / len]
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::{BufferCreateInfo, MemoryLocation, ShaderStages};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::material::MaterialInstance;
+use crate::render::math::Mat4;
+use crate::render::mesh::Mesh;
+
+/// Per instance data written by the batcher, one `InstanceData` per
+/// instance in a single buffer. It is bound as a vertex stream with an
+/// `InstanceTransform` attribute after the mesh streams, and can also be
+/// read as a storage buffer indexed by `gl_InstanceIndex`, which already
+/// includes the batch offset.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct InstanceData {
+    pub model: Mat4,
+}
+
+struct Batch {
+    mesh: Arc<Mesh>,
+    material: Arc<MaterialInstance>,
+    instances: Vec<InstanceData>,
+}
+
+/// Groups draws of the same mesh with the same material into instanced
+/// draws. Collect a frame's draws with `add`, then `record` them.
+///
+/// Materials must be created with `VertexLayout::with_instance_stream` and
+/// at least 128 bytes of push constants, the view projection and model
+/// matrices of the built-in mesh vertex shader.
+pub struct InstanceBatcher {
+    renderer: Arc<Renderer>,
+    batches: Vec<Batch>,
+    /// Keyed by mesh address and material sort key.
+    lookup: HashMap<(usize, u64), usize>,
+    instance_buffers: Vec<Option<Arc<Buffer>>>,
+}
+
+impl InstanceBatcher {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        Self { renderer, batches: Vec::new(), lookup: HashMap::new(), instance_buffers: vec![None; FRAME_OVERLAP] }
+    }
+
+    pub fn add(&mut self, mesh: &Arc<Mesh>, material: &Arc<MaterialInstance>, transform: Mat4) {
+        let key = (Arc::as_ptr(mesh) as usize, material.sort_key());
+        let idx = *self.lookup.entry(key).or_insert_with(|| {
+            self.batches.push(Batch { mesh: mesh.clone(), material: material.clone(), instances: Vec::new() });
+            self.batches.len() - 1
+        });
+        self.batches[idx].instances.push(InstanceData { model: transform });
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.batches.iter().map(|b| b.instances.len()).sum()
+    }
+
+    /// Instance data of the last recorded frame in this frame slot, for
+    /// shaders that read it as a storage buffer.
+    pub fn instance_buffer(&self) -> Option<Arc<Buffer>> {
+        self.instance_buffers[self.renderer.current_frame()].clone()
+    }
+
+    fn upload(&mut self) -> Arc<Buffer> {
+        let data = self.batches.iter()
+            .flat_map(|b| &b.instances)
+            .flat_map(|i| i.model.to_bytes())
+            .collect::<Vec<_>>();
+
+        let slot = &mut self.instance_buffers[self.renderer.current_frame()];
+
+        // Reuse the buffer when the command list already released it.
+        let reusable = slot.as_mut()
+            .and_then(Arc::get_mut)
+            .is_some_and(|b| b.size() >= data.len() as u64);
+        if !reusable {
+            let create_info = BufferCreateInfo {
+                size: (data.len() as u64).next_power_of_two().max(size_of::<InstanceData>() as u64),
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            *slot = Some(Arc::new(Buffer::new(self.renderer.clone(), create_info)));
+        }
+
+        let buffer = slot.as_mut().unwrap();
+        Arc::get_mut(buffer).unwrap().write(0, &data);
+        buffer.clone()
+    }
+
+    /// Uploads the instance data and records one instanced draw per batch
+    /// and submesh, sorted by material. Must be called inside
+    /// `begin_rendering`, after the command list was reset for this frame.
+    /// The batches are cleared afterwards.
+    pub fn record(&mut self, command_list: &mut CommandList, view_projection: Mat4) {
+        if self.batches.is_empty() {
+            return;
+        }
+
+        self.batches.sort_by_key(|b| (b.material.sort_key(), Arc::as_ptr(&b.mesh) as usize));
+        let instance_buffer = self.upload();
+
+        let mut push_constants = [0u8; 128];
+        push_constants[..64].copy_from_slice(&view_projection.to_bytes());
+        push_constants[64..].copy_from_slice(&Mat4::IDENTITY.to_bytes());
+
+        let mut bound_template = None;
+        let mut first_instance = 0;
+        for batch in &self.batches {
+            let template = batch.material.template();
+            if bound_template != Some(Arc::as_ptr(template)) {
+                command_list.bind_graphics_pipeline(template.pipeline());
+                command_list.push_constants(template.pipeline_layout(), ShaderStages::Vertex | ShaderStages::Fragment, 0, &push_constants);
+                bound_template = Some(Arc::as_ptr(template));
+            }
+            command_list.bind_descriptor_set(template.pipeline_layout(), 0, batch.material.descriptor_set());
+
+            batch.mesh.bind(command_list);
+            command_list.bind_vertex_buffers(batch.mesh.layout().vertex_stream_count() as u32, std::slice::from_ref(&instance_buffer));
+
+            let instance_count = batch.instances.len() as u32;
+            for submesh in batch.mesh.submeshes() {
+                if batch.mesh.is_indexed() {
+                    let index_count = submesh.indices.end - submesh.indices.start;
+                    command_list.draw_indexed(index_count, instance_count, submesh.indices.start, submesh.base_vertex, first_instance);
+                } else {
+                    command_list.draw(batch.mesh.vertex_count(), instance_count, 0, first_instance);
+                }
+            }
+
+            first_instance += instance_count;
+        }
+
+        self.batches.clear();
+        self.lookup.clear();
+    }
+}
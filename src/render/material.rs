@@ -0,0 +1,322 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use ash::vk;
+
+use crate::render::hal::{BindingType, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, Error, GraphicsPipelineCreateInfo, MemoryLocation, PipelineLayoutCreateInfo, PushConstantRange, RasterState, Result, ShaderStages, VertexLayout};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::skinning::{skin_descriptor_layout, SKIN_SET};
+
+/// Descriptor set index of the image-based lighting textures.
+pub const IBL_SET: u32 = 3;
+
+/// Layout of the `IBL_SET` descriptor set: irradiance cubemap, prefiltered
+/// specular cubemap and BRDF lookup table, each with a sampler, read by
+/// the fragment stage.
+pub fn ibl_descriptor_layout(renderer: Arc<Renderer>) -> Arc<DescriptorSetLayout> {
+    let create_info = DescriptorSetLayoutCreateInfo {
+        bindings: (0..3).map(|binding| DescriptorSetBinding {
+            stage: ShaderStages::Fragment,
+            typ: BindingType::SampledTexture,
+            binding,
+        }).collect(),
+        push_descriptor: false,
+    };
+    DescriptorSetLayout::new(renderer, create_info)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaterialParamType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl MaterialParamType {
+    fn components(self) -> usize {
+        match self {
+            MaterialParamType::Float => 1,
+            MaterialParamType::Vec2 => 2,
+            MaterialParamType::Vec3 => 3,
+            MaterialParamType::Vec4 => 4,
+        }
+    }
+
+    /// std140 alignment in bytes.
+    fn alignment(self) -> usize {
+        match self {
+            MaterialParamType::Float => 4,
+            MaterialParamType::Vec2 => 8,
+            MaterialParamType::Vec3 | MaterialParamType::Vec4 => 16,
+        }
+    }
+}
+
+pub struct MaterialParam {
+    pub name: &'static str,
+    pub typ: MaterialParamType,
+    pub default: [f32; 4],
+}
+
+/// Describes a family of materials sharing shaders and pipeline state.
+///
+/// The material descriptor set is set 0: binding 0 is a uniform block with
+/// the parameters in declaration order using std140 packing, followed by one
+/// storage image binding per texture slot.
+pub struct MaterialTemplateCreateInfo {
+    pub vertex_shader: Arc<Shader>,
+    pub fragment_shader: Arc<Shader>,
+    pub vertex_layout: VertexLayout,
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub raster: RasterState,
+    pub params: Vec<MaterialParam>,
+    pub textures: Vec<&'static str>,
+    /// Adds the joint matrices set at `skinning::SKIN_SET` to the pipeline
+    /// layout, for use with the skinned vertex shader.
+    pub skinned: bool,
+    /// Adds the environment lighting set at `IBL_SET`, for fragment shaders
+    /// using the image-based lighting helpers of the IBL pass.
+    pub image_based_lighting: bool,
+    /// Push constants visible to the vertex and fragment stages, e.g. for
+    /// per-object transforms.
+    pub push_constants_size: u32,
+}
+
+struct ParamSlot {
+    name: &'static str,
+    typ: MaterialParamType,
+    offset: usize,
+}
+
+static NEXT_TEMPLATE_ID: AtomicU32 = AtomicU32::new(0);
+static NEXT_INSTANCE_ID: AtomicU32 = AtomicU32::new(0);
+
+pub struct MaterialTemplate {
+    renderer: Arc<Renderer>,
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_layout: Arc<DescriptorSetLayout>,
+    params: Vec<ParamSlot>,
+    defaults: Vec<u8>,
+    textures: Vec<&'static str>,
+    id: u32,
+}
+
+impl MaterialTemplate {
+    pub fn new(renderer: Arc<Renderer>, create_info: MaterialTemplateCreateInfo) -> Arc<Self> {
+        let mut params = Vec::with_capacity(create_info.params.len());
+        let mut size = 0usize;
+        for param in &create_info.params {
+            let offset = size.next_multiple_of(param.typ.alignment());
+            size = offset + param.typ.components() * 4;
+            params.push(ParamSlot { name: param.name, typ: param.typ, offset });
+        }
+
+        // Uniform blocks are sized in multiples of a vec4.
+        let mut defaults = vec![0u8; size.next_multiple_of(16).max(16)];
+        for (param, slot) in create_info.params.iter().zip(&params) {
+            write_floats(&mut defaults, slot, &param.default[..slot.typ.components()]);
+        }
+
+        let stages = ShaderStages::Vertex | ShaderStages::Fragment;
+
+        let descriptor_layout = {
+            let mut bindings = vec![DescriptorSetBinding {
+                stage: stages,
+                typ: BindingType::UniformBuffer,
+                binding: 0,
+            }];
+            bindings.extend((0..create_info.textures.len()).map(|i| DescriptorSetBinding {
+                stage: stages,
+                typ: BindingType::Texture,
+                binding: i as u32 + 1,
+            }));
+            DescriptorSetLayout::new(renderer.clone(), DescriptorSetLayoutCreateInfo { bindings, push_descriptor: false })
+        };
+
+        let pipeline_layout = {
+            let push_constant_ranges = if create_info.push_constants_size > 0 {
+                vec![PushConstantRange {
+                    stage: stages,
+                    offset: 0,
+                    size: create_info.push_constants_size,
+                }]
+            } else {
+                vec![]
+            };
+
+            let mut sets = vec![descriptor_layout.clone()];
+            let mut add_set = |index: u32, layout: Arc<DescriptorSetLayout>| {
+                // Unused set indices in between get empty layouts.
+                while sets.len() < index as usize {
+                    sets.push(DescriptorSetLayout::new(renderer.clone(), DescriptorSetLayoutCreateInfo { bindings: vec![], push_descriptor: false }));
+                }
+                sets.push(layout);
+            };
+            if create_info.skinned {
+                add_set(SKIN_SET, skin_descriptor_layout(renderer.clone()));
+            }
+            if create_info.image_based_lighting {
+                add_set(IBL_SET, ibl_descriptor_layout(renderer.clone()));
+            }
+
+            let create_info = PipelineLayoutCreateInfo {
+                sets,
+                push_constant_ranges,
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: create_info.vertex_shader,
+                fragment_shader: Some(create_info.fragment_shader),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout,
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: create_info.vertex_layout,
+                color_formats: create_info.color_formats,
+                depth_format: create_info.depth_format,
+                extent: create_info.extent,
+                raster: create_info.raster,
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer.clone(), create_info)
+        };
+
+        Arc::new(Self {
+            renderer,
+            pipeline,
+            descriptor_layout,
+            params,
+            defaults,
+            textures: create_info.textures,
+            id: NEXT_TEMPLATE_ID.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    pub fn pipeline(&self) -> Arc<GraphicsPipeline> {
+        self.pipeline.clone()
+    }
+
+    pub fn pipeline_layout(&self) -> Arc<PipelineLayout> {
+        self.pipeline.layout()
+    }
+
+    fn param(&self, name: &str) -> Result<&ParamSlot> {
+        self.params.iter().find(|p| p.name == name)
+            .ok_or_else(|| Error::Backend(format!("Unknown material parameter {name}")))
+    }
+
+    fn texture_slot(&self, name: &str) -> Result<usize> {
+        self.textures.iter().position(|&t| t == name)
+            .ok_or_else(|| Error::Backend(format!("Unknown material texture {name}")))
+    }
+}
+
+fn write_floats(data: &mut [u8], slot: &ParamSlot, values: &[f32]) {
+    for (i, value) in values.iter().enumerate() {
+        let offset = slot.offset + i * 4;
+        data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+}
+
+/// Parameter values and textures of a material. Changes are uploaded by
+/// `prepare`, which is called once per frame before the instance is bound.
+pub struct MaterialInstance {
+    template: Arc<MaterialTemplate>,
+    descriptor_set: Arc<DescriptorSet>,
+    params: Vec<u8>,
+    textures: Vec<Option<Arc<Texture>>>,
+    uniform_buffers: Vec<Buffer>,
+    id: u32,
+}
+
+impl MaterialInstance {
+    pub fn new(template: Arc<MaterialTemplate>) -> Self {
+        let renderer = template.renderer.clone();
+        let descriptor_set = DescriptorSet::new(renderer.clone(), template.descriptor_layout.clone());
+
+        let uniform_buffers = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: template.defaults.len() as u64,
+                usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        Self {
+            descriptor_set,
+            params: template.defaults.clone(),
+            textures: vec![None; template.textures.len()],
+            uniform_buffers,
+            id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+            template,
+        }
+    }
+
+    pub fn template(&self) -> &Arc<MaterialTemplate> {
+        &self.template
+    }
+
+    /// Sets a parameter, `values` must match the declared type's size.
+    pub fn set_param(&mut self, name: &str, values: &[f32]) -> Result<()> {
+        let slot = self.template.param(name)?;
+        if values.len() != slot.typ.components() {
+            return Err(Error::Backend(format!("Material parameter {name} is {:?}, got {} values", slot.typ, values.len())));
+        }
+        write_floats(&mut self.params, slot, values);
+        Ok(())
+    }
+
+    /// Current value of a parameter, as many values as the declared type.
+    pub fn param(&self, name: &str) -> Result<Vec<f32>> {
+        let slot = self.template.param(name)?;
+        Ok((0..slot.typ.components()).map(|i| {
+            let offset = slot.offset + i * 4;
+            f32::from_ne_bytes(self.params[offset..offset + 4].try_into().unwrap())
+        }).collect())
+    }
+
+    pub fn set_texture(&mut self, name: &str, texture: Arc<Texture>) -> Result<()> {
+        let slot = self.template.texture_slot(name)?;
+        self.textures[slot] = Some(texture);
+        Ok(())
+    }
+
+    /// Uploads the parameters and writes the descriptor set of the current
+    /// frame. Every texture slot must be filled.
+    pub fn prepare(&mut self) {
+        let frame = self.template.renderer.current_frame();
+        let buffer = &mut self.uniform_buffers[frame];
+        buffer.write(0, &self.params);
+        self.descriptor_set.write_uniform_buffer(0, buffer);
+
+        for (i, texture) in self.textures.iter().enumerate() {
+            let texture = texture.as_ref()
+                .unwrap_or_else(|| panic!("Material texture {} is not set", self.template.textures[i]));
+            self.descriptor_set.write_texture(i as u32 + 1, texture);
+        }
+    }
+
+    pub fn descriptor_set(&self) -> Arc<DescriptorSet> {
+        self.descriptor_set.clone()
+    }
+
+    /// Key for sorting draws so that ones sharing a pipeline, then a
+    /// material, end up next to each other.
+    pub fn sort_key(&self) -> u64 {
+        (self.template.id as u64) << 32 | self.id as u64
+    }
+}
@@ -0,0 +1,298 @@
+use std::ops::Mul;
+
+pub type Vec3 = [f32; 3];
+pub type Vec4 = [f32; 4];
+/// Rotation quaternion as `[x, y, z, w]`, matching glTF.
+pub type Quat = [f32; 4];
+
+pub fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: Vec3, s: f32) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+pub fn normalize(a: Vec3) -> Vec3 {
+    let len = length(a);
+    if len > 0.0 { scale(a, 1.0 / len) } else { a }
+}
+
+pub fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    add(a, scale(sub(b, a), t))
+}
+
+pub const QUAT_IDENTITY: Quat = [0.0, 0.0, 0.0, 1.0];
+
+pub fn quat_normalize(q: Quat) -> Quat {
+    let len = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if len > 0.0 { [q[0] / len, q[1] / len, q[2] / len, q[3] / len] } else { QUAT_IDENTITY }
+}
+
+pub fn quat_mul(a: Quat, b: Quat) -> Quat {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+/// Spherical interpolation along the shortest arc.
+pub fn quat_slerp(a: Quat, b: Quat, t: f32) -> Quat {
+    let mut cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+    let b = if cos < 0.0 {
+        cos = -cos;
+        [-b[0], -b[1], -b[2], -b[3]]
+    } else {
+        b
+    };
+
+    // Nearly parallel, fall back to normalized lerp to avoid dividing by ~0.
+    let (wa, wb) = if cos > 0.9995 {
+        (1.0 - t, t)
+    } else {
+        let angle = cos.acos();
+        let sin = angle.sin();
+        (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+    };
+
+    quat_normalize([
+        a[0] * wa + b[0] * wb,
+        a[1] * wa + b[1] * wb,
+        a[2] * wa + b[2] * wb,
+        a[3] * wa + b[3] * wb,
+    ])
+}
+
+pub fn quat_rotate(q: Quat, v: Vec3) -> Vec3 {
+    let u = [q[0], q[1], q[2]];
+    let t = scale(cross(u, v), 2.0);
+    add(add(v, scale(t, q[3])), cross(u, t))
+}
+
+/// Column-major 4x4 matrix, laid out like GLSL's `mat4`.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mat4(pub [Vec4; 4]);
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub fn from_translation(t: Vec3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.0[3] = [t[0], t[1], t[2], 1.0];
+        m
+    }
+
+    pub fn from_scale(s: Vec3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.0[0][0] = s[0];
+        m.0[1][1] = s[1];
+        m.0[2][2] = s[2];
+        m
+    }
+
+    pub fn from_rotation(q: Quat) -> Self {
+        let [x, y, z, w] = q;
+        let (x2, y2, z2) = (x + x, y + y, z + z);
+        let (xx, xy, xz) = (x * x2, x * y2, x * z2);
+        let (yy, yz, zz) = (y * y2, y * z2, z * z2);
+        let (wx, wy, wz) = (w * x2, w * y2, w * z2);
+
+        Mat4([
+            [1.0 - (yy + zz), xy + wz, xz - wy, 0.0],
+            [xy - wz, 1.0 - (xx + zz), yz + wx, 0.0],
+            [xz + wy, yz - wx, 1.0 - (xx + yy), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Translation * rotation * scale, the glTF node transform order.
+    pub fn from_trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        let mut m = Self::from_rotation(rotation);
+        for (column, s) in m.0.iter_mut().zip(scale) {
+            column[0] *= s;
+            column[1] *= s;
+            column[2] *= s;
+        }
+        m.0[3] = [translation[0], translation[1], translation[2], 1.0];
+        m
+    }
+
+    /// Right handed view matrix looking from `eye` towards `target`.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let f = normalize(sub(target, eye));
+        let s = normalize(cross(f, up));
+        let u = cross(s, f);
+
+        Mat4([
+            [s[0], u[0], -f[0], 0.0],
+            [s[1], u[1], -f[1], 0.0],
+            [s[2], u[2], -f[2], 0.0],
+            [-dot(s, eye), -dot(u, eye), dot(f, eye), 1.0],
+        ])
+    }
+
+    /// Infinite reverse-Z perspective projection for Vulkan clip space:
+    /// depth is 1 at `near` and goes to 0 at infinity, Y points down.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32) -> Self {
+        let f = 1.0 / (fov_y * 0.5).tan();
+        Mat4([
+            [f / aspect, 0.0, 0.0, 0.0],
+            [0.0, -f, 0.0, 0.0],
+            [0.0, 0.0, 0.0, -1.0],
+            [0.0, 0.0, near, 0.0],
+        ])
+    }
+
+    /// Reverse-Z orthographic projection: depth is 1 at `near` and 0 at `far`.
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let w = right - left;
+        let h = top - bottom;
+        let d = far - near;
+        Mat4([
+            [2.0 / w, 0.0, 0.0, 0.0],
+            [0.0, -2.0 / h, 0.0, 0.0],
+            [0.0, 0.0, 1.0 / d, 0.0],
+            [-(right + left) / w, (top + bottom) / h, far / d, 1.0],
+        ])
+    }
+
+    pub fn column(&self, i: usize) -> Vec4 {
+        self.0[i]
+    }
+
+    pub fn row(&self, i: usize) -> Vec4 {
+        [self.0[0][i], self.0[1][i], self.0[2][i], self.0[3][i]]
+    }
+
+    pub fn transpose(&self) -> Self {
+        Mat4([self.row(0), self.row(1), self.row(2), self.row(3)])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let v = self.transform([p[0], p[1], p[2], 1.0]);
+        [v[0] / v[3], v[1] / v[3], v[2] / v[3]]
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let v = self.transform([v[0], v[1], v[2], 0.0]);
+        [v[0], v[1], v[2]]
+    }
+
+    pub fn transform(&self, v: Vec4) -> Vec4 {
+        let m = &self.0;
+        let mut r = [0.0; 4];
+        for (i, r) in r.iter_mut().enumerate() {
+            *r = m[0][i] * v[0] + m[1][i] * v[1] + m[2][i] * v[2] + m[3][i] * v[3];
+        }
+        r
+    }
+
+    /// General inverse, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = self.transpose().0;
+        let a = [
+            m[0][0], m[0][1], m[0][2], m[0][3],
+            m[1][0], m[1][1], m[1][2], m[1][3],
+            m[2][0], m[2][1], m[2][2], m[2][3],
+            m[3][0], m[3][1], m[3][2], m[3][3],
+        ];
+
+        let s0 = a[0] * a[5] - a[4] * a[1];
+        let s1 = a[0] * a[6] - a[4] * a[2];
+        let s2 = a[0] * a[7] - a[4] * a[3];
+        let s3 = a[1] * a[6] - a[5] * a[2];
+        let s4 = a[1] * a[7] - a[5] * a[3];
+        let s5 = a[2] * a[7] - a[6] * a[3];
+        let c5 = a[10] * a[15] - a[14] * a[11];
+        let c4 = a[9] * a[15] - a[13] * a[11];
+        let c3 = a[9] * a[14] - a[13] * a[10];
+        let c2 = a[8] * a[15] - a[12] * a[11];
+        let c1 = a[8] * a[14] - a[12] * a[10];
+        let c0 = a[8] * a[13] - a[12] * a[9];
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv = 1.0 / det;
+
+        let rows = [
+            [
+                (a[5] * c5 - a[6] * c4 + a[7] * c3) * inv,
+                (-a[1] * c5 + a[2] * c4 - a[3] * c3) * inv,
+                (a[13] * s5 - a[14] * s4 + a[15] * s3) * inv,
+                (-a[9] * s5 + a[10] * s4 - a[11] * s3) * inv,
+            ],
+            [
+                (-a[4] * c5 + a[6] * c2 - a[7] * c1) * inv,
+                (a[0] * c5 - a[2] * c2 + a[3] * c1) * inv,
+                (-a[12] * s5 + a[14] * s2 - a[15] * s1) * inv,
+                (a[8] * s5 - a[10] * s2 + a[11] * s1) * inv,
+            ],
+            [
+                (a[4] * c4 - a[5] * c2 + a[7] * c0) * inv,
+                (-a[0] * c4 + a[1] * c2 - a[3] * c0) * inv,
+                (a[12] * s4 - a[13] * s2 + a[15] * s0) * inv,
+                (-a[8] * s4 + a[9] * s2 - a[11] * s0) * inv,
+            ],
+            [
+                (-a[4] * c3 + a[5] * c1 - a[6] * c0) * inv,
+                (a[0] * c3 - a[1] * c1 + a[2] * c0) * inv,
+                (-a[12] * s3 + a[13] * s1 - a[14] * s0) * inv,
+                (a[8] * s3 - a[9] * s1 + a[10] * s0) * inv,
+            ],
+        ];
+
+        Some(Mat4(rows).transpose())
+    }
+
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(self.0.as_flattened()) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        Mat4(rhs.0.map(|column| self.transform(column)))
+    }
+}
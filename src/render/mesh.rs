@@ -0,0 +1,451 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::culling::{Aabb, BoundingSphere};
+use crate::render::meshopt::{self, Meshlets};
+use crate::render::hal::{BufferCopy, BufferCreateInfo, Error, MemoryLocation, Result, VertexAttribute, VertexLayout, VertexSemantic};
+#[cfg(feature = "ray-tracing")]
+use crate::render::hal::vulkan::acceleration_structure::{AccelerationStructure, Triangles};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::renderer::Renderer;
+
+/// Built-in mesh vertex shader. Takes position, normal and uv, plus joints
+/// and weights when skinned and an `InstanceTransform` stream when
+/// instanced, and a `view_projection` and `model` matrix as push constants.
+/// Outputs the world space normal and the uv.
+pub fn vertex_shader_code(skinned: bool, instanced: bool) -> &'static [u32] {
+    match (skinned, instanced) {
+        (false, false) => include_bytes_align_as!(u32, "shaders/mesh.spv"),
+        (true, false) => include_bytes_align_as!(u32, "shaders/mesh_skinned.spv"),
+        (false, true) => include_bytes_align_as!(u32, "shaders/mesh_instanced.spv"),
+        (true, true) => include_bytes_align_as!(u32, "shaders/mesh_skinned_instanced.spv"),
+    }
+}
+
+/// Range of the index buffer drawn with a single material.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Submesh {
+    pub indices: Range<u32>,
+    /// Added to every index of the range.
+    pub base_vertex: i32,
+}
+
+/// Vertex and index buffers in GPU memory, laid out as described by the
+/// vertex layout. Indices are 16 bit when all vertices are addressable.
+pub struct Mesh {
+    layout: VertexLayout,
+    vertex_buffers: Vec<Arc<Buffer>>,
+    index_buffer: Option<Arc<Buffer>>,
+    index_type: vk::IndexType,
+    vertex_count: u32,
+    index_count: u32,
+    submeshes: Vec<Submesh>,
+    bounds: Aabb,
+    bounding_sphere: BoundingSphere,
+    meshlets: Vec<Meshlets>,
+}
+
+impl Mesh {
+    pub fn layout(&self) -> &VertexLayout {
+        &self.layout
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.index_buffer.is_some()
+    }
+
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+
+    pub fn vertex_buffers(&self) -> &[Arc<Buffer>] {
+        &self.vertex_buffers
+    }
+
+    pub fn index_buffer(&self) -> Option<&Arc<Buffer>> {
+        self.index_buffer.as_ref()
+    }
+
+    /// Object space bounds of the positions, empty without positions.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Object space sphere around the center of `bounds` enclosing the
+    /// positions.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere
+    }
+
+    /// Meshlets of every submesh, in the order of `submeshes`, with vertex
+    /// indices relative to the submesh's `base_vertex`. Empty unless built
+    /// with `MeshBuilder::meshlets`.
+    pub fn meshlets(&self) -> &[Meshlets] {
+        &self.meshlets
+    }
+
+    /// 16 or 32 bit, shaders pulling indices themselves need to know which.
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
+
+    /// Records the build of a bottom level acceleration structure with a
+    /// geometry per submesh. The mesh needs positions and has to be built
+    /// with `MeshBuilder::ray_tracing`.
+    #[cfg(feature = "ray-tracing")]
+    pub fn build_acceleration_structure(&self, renderer: Arc<Renderer>, command_list: &mut CommandList) -> Result<Arc<AccelerationStructure>> {
+        let geometries = self.triangles()?;
+        Ok(AccelerationStructure::bottom_level(renderer, command_list, &geometries))
+    }
+
+    /// Acceleration structure geometries, one per submesh.
+    #[cfg(feature = "ray-tracing")]
+    pub(crate) fn triangles(&self) -> Result<Vec<Triangles<'_>>> {
+        let position = self.layout.attributes.iter().find(|a| a.semantic == VertexSemantic::Position)
+            .ok_or_else(|| Error::Backend("Acceleration structures need positions".to_string()))?;
+        let vertex_buffer = &self.vertex_buffers[position.binding as usize];
+        // Without indices the vertices are the triangle list.
+        let ranges = match self.index_buffer {
+            Some(_) => self.submeshes.iter().map(|s| (s.indices.clone(), s.base_vertex.max(0) as u32)).collect(),
+            None => vec![(0..self.vertex_count, 0)],
+        };
+        Ok(ranges.into_iter().map(|(range, base_vertex)| Triangles {
+            vertex_buffer,
+            vertex_offset: position.offset as u64,
+            vertex_stride: self.layout.strides[position.binding as usize] as u64,
+            vertex_count: self.vertex_count,
+            index_buffer: self.index_buffer.as_deref(),
+            index_type: self.index_type,
+            first: range.start,
+            count: range.end - range.start,
+            base_vertex,
+            opaque: true,
+        }).collect())
+    }
+
+    /// Binds the vertex streams starting at binding 0, and the index buffer.
+    /// Instance streams of the layout follow at `layout().vertex_stream_count()`.
+    pub fn bind(&self, command_list: &mut CommandList) {
+        command_list.bind_vertex_buffers(0, &self.vertex_buffers);
+        if let Some(index_buffer) = &self.index_buffer {
+            command_list.bind_index_buffer(index_buffer.clone(), self.index_type);
+        }
+    }
+}
+
+/// Collects vertex attributes from CPU arrays and uploads them into a
+/// `Mesh` with the requested layout.
+pub struct MeshBuilder {
+    layout: VertexLayout,
+    attributes: Vec<(VertexSemantic, Vec<f32>)>,
+    indices: Vec<u32>,
+    submeshes: Vec<Submesh>,
+    vertex_pulling: bool,
+    ray_tracing: bool,
+    optimize: bool,
+    meshlets: bool,
+}
+
+impl MeshBuilder {
+    pub fn new(layout: VertexLayout) -> Self {
+        Self { layout, attributes: Vec::new(), indices: Vec::new(), submeshes: Vec::new(), vertex_pulling: false, ray_tracing: false, optimize: false, meshlets: false }
+    }
+
+    /// Reorders the triangles of every submesh for the vertex cache and,
+    /// when no submesh has a `base_vertex`, the vertices in order of use.
+    /// The drawn triangles stay the same.
+    pub fn optimize(mut self) -> Self {
+        self.optimize = true;
+        self
+    }
+
+    /// Splits every submesh into meshlets for `MeshletCullPass`, reordering
+    /// its triangles so those of a meshlet are contiguous. Needs positions
+    /// and indices, best combined with `optimize`.
+    pub fn meshlets(mut self) -> Self {
+        self.meshlets = true;
+        self
+    }
+
+    /// Makes the vertex and index buffers readable as storage buffers and
+    /// through their device address, so shaders can fetch vertices by
+    /// `gl_VertexIndex` with `VertexLayout::pulled_attribute` offsets.
+    /// Pipelines drawing the mesh this way use an empty vertex layout.
+    pub fn vertex_pulling(mut self) -> Self {
+        self.vertex_pulling = true;
+        self
+    }
+
+    /// Makes the vertex and index buffers usable as acceleration structure
+    /// build inputs, on the graphics queue with
+    /// `Mesh::build_acceleration_structure` or on the compute queue with an
+    /// `AccelerationStructureManager`.
+    #[cfg(feature = "ray-tracing")]
+    pub fn ray_tracing(mut self) -> Self {
+        self.ray_tracing = true;
+        self
+    }
+
+    pub fn positions(self, positions: &[[f32; 3]]) -> Self {
+        self.attribute(VertexSemantic::Position, positions.as_flattened())
+    }
+
+    pub fn normals(self, normals: &[[f32; 3]]) -> Self {
+        self.attribute(VertexSemantic::Normal, normals.as_flattened())
+    }
+
+    pub fn uvs(self, uvs: &[[f32; 2]]) -> Self {
+        self.attribute(VertexSemantic::Uv, uvs.as_flattened())
+    }
+
+    pub fn tangents(self, tangents: &[[f32; 4]]) -> Self {
+        self.attribute(VertexSemantic::Tangent, tangents.as_flattened())
+    }
+
+    pub fn colors(self, colors: &[[f32; 4]]) -> Self {
+        self.attribute(VertexSemantic::Color, colors.as_flattened())
+    }
+
+    pub fn joints(self, joints: &[[u32; 4]]) -> Self {
+        // Stored bit for bit, the attribute is read as uvec4 in the shader.
+        let data = joints.as_flattened().iter().map(|&j| f32::from_bits(j)).collect::<Vec<_>>();
+        self.attribute(VertexSemantic::Joints, &data)
+    }
+
+    pub fn weights(self, weights: &[[f32; 4]]) -> Self {
+        self.attribute(VertexSemantic::Weights, weights.as_flattened())
+    }
+
+    /// Raw attribute data, `semantic.components()` floats per vertex.
+    pub fn attribute(mut self, semantic: VertexSemantic, data: &[f32]) -> Self {
+        self.attributes.retain(|(s, _)| *s != semantic);
+        self.attributes.push((semantic, data.to_vec()));
+        self
+    }
+
+    pub fn indices(mut self, indices: &[u32]) -> Self {
+        self.indices = indices.to_vec();
+        self
+    }
+
+    /// Without submeshes, the mesh gets a single one covering all indices.
+    pub fn submesh(mut self, submesh: Submesh) -> Self {
+        self.submeshes.push(submesh);
+        self
+    }
+
+    /// Attributes stored in the mesh, instance streams are provided at draw time.
+    fn vertex_attributes(&self) -> impl Iterator<Item = &VertexAttribute> {
+        let streams = self.layout.vertex_stream_count() as u32;
+        self.layout.attributes.iter().filter(move |a| a.binding < streams)
+    }
+
+    fn vertex_count(&self) -> Result<u32> {
+        let mut vertex_count = None;
+        for attribute in self.vertex_attributes() {
+            let (_, data) = self.attributes.iter().find(|(s, _)| *s == attribute.semantic)
+                .ok_or_else(|| Error::Backend(format!("Mesh is missing {:?} data required by its layout", attribute.semantic)))?;
+
+            let components = attribute.semantic.components() as usize;
+            if data.len() % components != 0 {
+                return Err(Error::Backend(format!("{:?} data isn't a whole number of vertices", attribute.semantic)));
+            }
+
+            let count = data.len() / components;
+            if vertex_count.is_some_and(|c| c != count) {
+                return Err(Error::Backend(format!("{:?} has {count} vertices, other attributes have {}", attribute.semantic, vertex_count.unwrap())));
+            }
+            vertex_count = Some(count);
+        }
+        Ok(vertex_count.unwrap_or(0) as u32)
+    }
+
+    fn optimize_indices(&mut self, vertex_count: u32) {
+        let vertex_count = vertex_count as usize;
+        if self.submeshes.is_empty() {
+            meshopt::optimize_vertex_cache(&mut self.indices, vertex_count);
+        }
+        for submesh in &self.submeshes {
+            let range = submesh.indices.start as usize..submesh.indices.end as usize;
+            meshopt::optimize_vertex_cache(&mut self.indices[range], vertex_count);
+        }
+
+        if self.submeshes.iter().any(|s| s.base_vertex != 0) {
+            return;
+        }
+        let order = meshopt::optimize_vertex_fetch(&mut self.indices, vertex_count);
+        for (semantic, data) in &mut self.attributes {
+            let components = semantic.components() as usize;
+            if data.len() != vertex_count * components {
+                continue;
+            }
+            *data = order.iter()
+                .flat_map(|&old| &data[old as usize * components..(old as usize + 1) * components])
+                .copied()
+                .collect();
+        }
+    }
+
+    fn build_meshlets(&mut self, vertex_count: u32) -> Result<Vec<Meshlets>> {
+        let (_, positions) = self.attributes.iter().find(|(s, _)| *s == VertexSemantic::Position)
+            .ok_or_else(|| Error::Backend("Meshlets need positions".to_string()))?;
+        if self.indices.is_empty() {
+            return Err(Error::Backend("Meshlets need indices".to_string()));
+        }
+        let positions = positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect::<Vec<_>>();
+
+        let submeshes = if self.submeshes.is_empty() {
+            vec![Submesh { indices: 0..self.indices.len() as u32, base_vertex: 0 }]
+        } else {
+            self.submeshes.clone()
+        };
+        submeshes.iter().map(|submesh| {
+            let range = submesh.indices.start as usize..submesh.indices.end as usize;
+            let indices = self.indices[range.clone()].iter().map(|&i| i as i64 + submesh.base_vertex as i64).collect::<Vec<_>>();
+            if let Some(index) = indices.iter().find(|&&i| !(0..vertex_count as i64).contains(&i)) {
+                return Err(Error::Backend(format!("Index {index} with base vertex is out of range of {vertex_count} vertices")));
+            }
+            let indices = indices.into_iter().map(|i| i as u32).collect::<Vec<_>>();
+
+            let mut meshlets = meshopt::build_meshlets(&indices, &positions, meshopt::MAX_MESHLET_VERTICES, meshopt::MAX_MESHLET_TRIANGLES);
+            for vertex in &mut meshlets.vertices {
+                *vertex = (*vertex as i64 - submesh.base_vertex as i64) as u32;
+            }
+            self.indices[range].copy_from_slice(&meshlets.indices());
+            Ok(meshlets)
+        }).collect()
+    }
+
+    /// Packs the vertex streams and records their upload into the command
+    /// list. The mesh can be drawn by commands recorded after this.
+    pub fn build(mut self, renderer: Arc<Renderer>, command_list: &mut CommandList) -> Result<Mesh> {
+        let vertex_count = self.vertex_count()?;
+        if let Some(&index) = self.indices.iter().find(|&&i| i >= vertex_count) {
+            return Err(Error::Backend(format!("Index {index} is out of range of {vertex_count} vertices")));
+        }
+        if self.optimize {
+            self.optimize_indices(vertex_count);
+        }
+        let meshlets = if self.meshlets {
+            self.build_meshlets(vertex_count)?
+        } else {
+            Vec::new()
+        };
+
+        let mut streams = self.layout.strides[..self.layout.vertex_stream_count()].iter()
+            .map(|&stride| vec![0u8; stride as usize * vertex_count as usize])
+            .collect::<Vec<_>>();
+        for attribute in self.vertex_attributes() {
+            let (_, data) = self.attributes.iter().find(|(s, _)| *s == attribute.semantic).unwrap();
+            let stride = self.layout.strides[attribute.binding as usize] as usize;
+            let size = attribute.semantic.components() as usize * 4;
+            let stream = &mut streams[attribute.binding as usize];
+            for (vertex, values) in data.chunks_exact(size / 4).enumerate() {
+                let offset = vertex * stride + attribute.offset as usize;
+                let bytes = values.iter().flat_map(|v| v.to_ne_bytes()).collect::<Vec<_>>();
+                stream[offset..offset + size].copy_from_slice(&bytes);
+            }
+        }
+
+        let mut pulling_usage = if self.vertex_pulling {
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+        } else {
+            vk::BufferUsageFlags::empty()
+        };
+        if self.ray_tracing {
+            pulling_usage |= vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
+        let vertex_buffers = streams.iter()
+            .map(|data| upload(&renderer, command_list, data, vk::BufferUsageFlags::VERTEX_BUFFER | pulling_usage, self.ray_tracing))
+            .collect();
+
+        let (index_buffer, index_type) = if self.indices.is_empty() {
+            (None, vk::IndexType::UINT32)
+        } else if vertex_count <= u16::MAX as u32 + 1 {
+            let data = self.indices.iter().flat_map(|&i| (i as u16).to_ne_bytes()).collect::<Vec<_>>();
+            (Some(upload(&renderer, command_list, &data, vk::BufferUsageFlags::INDEX_BUFFER | pulling_usage, self.ray_tracing)), vk::IndexType::UINT16)
+        } else {
+            let data = self.indices.iter().flat_map(|i| i.to_ne_bytes()).collect::<Vec<_>>();
+            (Some(upload(&renderer, command_list, &data, vk::BufferUsageFlags::INDEX_BUFFER | pulling_usage, self.ray_tracing)), vk::IndexType::UINT32)
+        };
+
+        command_list.memory_barrier();
+
+        let positions = self.attributes.iter()
+            .find(|(s, _)| *s == VertexSemantic::Position)
+            .map(|(_, data)| data.chunks_exact(3).map(|p| [p[0], p[1], p[2]]));
+        let (bounds, bounding_sphere) = match positions {
+            Some(positions) => {
+                let bounds = Aabb::from_points(positions.clone());
+                (bounds, BoundingSphere::from_points(bounds.center(), positions))
+            }
+            None => Default::default(),
+        };
+
+        let index_count = self.indices.len() as u32;
+        let submeshes = if self.submeshes.is_empty() {
+            vec![Submesh { indices: 0..index_count, base_vertex: 0 }]
+        } else {
+            self.submeshes
+        };
+
+        Ok(Mesh {
+            layout: self.layout,
+            vertex_buffers,
+            index_buffer,
+            index_type,
+            vertex_count,
+            index_count,
+            submeshes,
+            bounds,
+            bounding_sphere,
+            meshlets,
+        })
+    }
+}
+
+/// `shared` buffers can also be read from the compute queue.
+fn upload(renderer: &Arc<Renderer>, command_list: &mut CommandList, data: &[u8], usage: vk::BufferUsageFlags, shared: bool) -> Arc<Buffer> {
+    let size = data.len().max(4) as u64;
+
+    let mut staging = {
+        let create_info = BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            location: MemoryLocation::CpuToGpu,
+        };
+        Buffer::new(renderer.clone(), create_info)
+    };
+    staging.write(0, data);
+
+    let buffer = {
+        let create_info = BufferCreateInfo {
+            size,
+            usage: usage | vk::BufferUsageFlags::TRANSFER_DST,
+            location: MemoryLocation::GpuOnly,
+        };
+        if shared {
+            Buffer::new_shared(renderer.clone(), create_info)
+        } else {
+            Buffer::new(renderer.clone(), create_info)
+        }
+    };
+
+    command_list.copy_buffer(&staging, &buffer, &[BufferCopy { src_offset: 0, dst_offset: 0, size }]);
+    command_list.retain(Arc::new(staging));
+
+    Arc::new(buffer)
+}
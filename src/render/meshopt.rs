@@ -0,0 +1,414 @@
+use crate::render::culling::Aabb;
+use crate::render::math::{add, cross, dot, length, normalize, scale, sub, Vec3};
+
+/// Post-transform cache size the triangle order is optimized for.
+const CACHE_SIZE: usize = 32;
+
+/// Forsyth's vertex score: recently used vertices score high, as do
+/// vertices with few remaining triangles, so they are finished off early.
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices, fixed so the next triangle doesn't
+        // always reuse them in the same order.
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    cache_score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+/// Reorders triangles for the post-transform vertex cache with Tom
+/// Forsyth's linear-speed algorithm. The triangles and their winding are
+/// kept, only their order changes.
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Triangles using every vertex, the first `remaining[v]` of every list
+    // are the ones not emitted yet.
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for &v in indices.iter() {
+        offsets[v as usize + 1] += 1;
+    }
+    for v in 0..vertex_count {
+        offsets[v + 1] += offsets[v];
+    }
+    let mut adjacency = vec![0u32; triangle_count * 3];
+    let mut fill = offsets.clone();
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        for &v in triangle {
+            adjacency[fill[v as usize]] = t as u32;
+            fill[v as usize] += 1;
+        }
+    }
+    let mut remaining = (0..vertex_count).map(|v| (offsets[v + 1] - offsets[v]) as u32).collect::<Vec<_>>();
+
+    let mut cache_positions = vec![None; vertex_count];
+    let mut vertex_scores = (0..vertex_count).map(|v| vertex_score(None, remaining[v])).collect::<Vec<_>>();
+    let mut triangle_scores = indices.chunks_exact(3)
+        .map(|triangle| triangle.iter().map(|&v| vertex_scores[v as usize]).sum::<f32>())
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut next_unemitted = 0;
+    let mut best = (0..triangle_count).max_by(|&a, &b| triangle_scores[a].total_cmp(&triangle_scores[b]));
+
+    while output.len() < triangle_count * 3 {
+        let t = match best {
+            Some(t) => t,
+            None => {
+                // Nothing in the cache has triangles left, restart anywhere.
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[t] = true;
+        let triangle = [indices[t * 3], indices[t * 3 + 1], indices[t * 3 + 2]];
+        output.extend_from_slice(&triangle);
+
+        for &v in &triangle {
+            let v = v as usize;
+            let list = &mut adjacency[offsets[v]..offsets[v] + remaining[v] as usize];
+            let position = list.iter().position(|&a| a == t as u32).unwrap();
+            list.swap(position, list.len() - 1);
+            remaining[v] -= 1;
+        }
+
+        let mut new_cache = Vec::with_capacity(CACHE_SIZE + 3);
+        for &v in triangle.iter().chain(&cache) {
+            if !new_cache.contains(&v) {
+                new_cache.push(v);
+            }
+        }
+        for &v in new_cache.iter().skip(CACHE_SIZE) {
+            cache_positions[v as usize] = None;
+        }
+        for (position, &v) in new_cache.iter().take(CACHE_SIZE).enumerate() {
+            cache_positions[v as usize] = Some(position);
+        }
+
+        for &v in &new_cache {
+            let v = v as usize;
+            let score = vertex_score(cache_positions[v], remaining[v]);
+            let delta = score - vertex_scores[v];
+            vertex_scores[v] = score;
+            for &a in &adjacency[offsets[v]..offsets[v] + remaining[v] as usize] {
+                triangle_scores[a as usize] += delta;
+            }
+        }
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+
+        best = None;
+        let mut best_score = f32::MIN;
+        for &v in &cache {
+            let v = v as usize;
+            for &a in &adjacency[offsets[v]..offsets[v] + remaining[v] as usize] {
+                if triangle_scores[a as usize] > best_score {
+                    best_score = triangle_scores[a as usize];
+                    best = Some(a as usize);
+                }
+            }
+        }
+    }
+
+    indices.copy_from_slice(&output);
+}
+
+/// Renumbers vertices in the order the indices first reference them, so
+/// vertex fetches walk memory linearly. Returns the old index of every new
+/// vertex, unreferenced vertices go last.
+pub fn optimize_vertex_fetch(indices: &mut [u32], vertex_count: usize) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut order = Vec::with_capacity(vertex_count);
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            remap[old] = order.len() as u32;
+            order.push(old as u32);
+        }
+        *index = remap[old];
+    }
+    for (old, new) in remap.iter_mut().enumerate() {
+        if *new == u32::MAX {
+            *new = order.len() as u32;
+            order.push(old as u32);
+        }
+    }
+    order
+}
+
+/// Average cache miss ratio, vertex shader invocations per triangle with a
+/// FIFO cache of `cache_size` entries: 3 without reuse, 0.5 at best for
+/// large regular meshes.
+pub fn average_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size + 1);
+    let mut misses = 0;
+    for &v in indices {
+        if !cache.contains(&v) {
+            misses += 1;
+            cache.push_back(v);
+            if cache.len() > cache_size {
+                cache.pop_front();
+            }
+        }
+    }
+    misses as f32 / triangle_count as f32
+}
+
+pub const MAX_MESHLET_VERTICES: usize = 64;
+/// 124 rather than 128 leaves room for the primitive count in a 128 byte
+/// mesh shader output block.
+pub const MAX_MESHLET_TRIANGLES: usize = 124;
+
+/// Culling data of a meshlet, in the space of its positions.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct MeshletBounds {
+    pub center: Vec3,
+    pub radius: f32,
+    /// The meshlet faces away from cameras where
+    /// `dot(normalize(cone_apex - camera), cone_axis) >= cone_cutoff`.
+    /// The axis is zero for meshlets that can't be backface culled.
+    pub cone_apex: Vec3,
+    pub cone_axis: Vec3,
+    pub cone_cutoff: f32,
+}
+
+/// A small cluster of triangles, addressing at most `MAX_MESHLET_VERTICES`
+/// vertices with 8 bit local indices.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Meshlet {
+    /// Into `Meshlets::vertices`.
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    /// Into `Meshlets::triangles`, in triangles.
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+    pub bounds: MeshletBounds,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Meshlets {
+    pub meshlets: Vec<Meshlet>,
+    /// Vertex indices of the meshlets.
+    pub vertices: Vec<u32>,
+    /// Three local vertex indices per triangle.
+    pub triangles: Vec<u8>,
+}
+
+impl Meshlets {
+    /// Index list drawing the meshlets in order, the triangles of a meshlet
+    /// start at index `3 * triangle_offset`.
+    pub fn indices(&self) -> Vec<u32> {
+        self.meshlets.iter().flat_map(|meshlet| {
+            let vertices = &self.vertices[meshlet.vertex_offset as usize..];
+            let start = meshlet.triangle_offset as usize * 3;
+            self.triangles[start..start + meshlet.triangle_count as usize * 3].iter().map(move |&local| vertices[local as usize])
+        }).collect()
+    }
+}
+
+/// Splits triangles into meshlets in the order of `indices`, so triangles
+/// sharing vertices are expected to be close, as after
+/// `optimize_vertex_cache`. `positions` are indexed by `indices`.
+pub fn build_meshlets(indices: &[u32], positions: &[Vec3], max_vertices: usize, max_triangles: usize) -> Meshlets {
+    assert!((3..=256).contains(&max_vertices) && max_triangles > 0, "Meshlet limits out of range");
+
+    let mut meshlets = Meshlets::default();
+    let mut current = Meshlet::default();
+    // Local index of every vertex in the current meshlet.
+    let mut local = std::collections::HashMap::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let new_vertices = triangle.iter().enumerate()
+            .filter(|&(i, v)| !local.contains_key(v) && !triangle[..i].contains(v))
+            .count();
+        if current.vertex_count as usize + new_vertices > max_vertices || current.triangle_count as usize == max_triangles {
+            finish_meshlet(&mut meshlets, &mut current, positions);
+            local.clear();
+        }
+
+        for &v in triangle {
+            let index = *local.entry(v).or_insert_with(|| {
+                meshlets.vertices.push(v);
+                current.vertex_count += 1;
+                current.vertex_count - 1
+            });
+            meshlets.triangles.push(index as u8);
+        }
+        current.triangle_count += 1;
+    }
+    if current.triangle_count > 0 {
+        finish_meshlet(&mut meshlets, &mut current, positions);
+    }
+    meshlets
+}
+
+fn finish_meshlet(meshlets: &mut Meshlets, current: &mut Meshlet, positions: &[Vec3]) {
+    let vertices = &meshlets.vertices[current.vertex_offset as usize..];
+    let start = current.triangle_offset as usize * 3;
+    let triangles = meshlets.triangles[start..].chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|i| positions[vertices[triangle[i] as usize] as usize]))
+        .collect::<Vec<_>>();
+    current.bounds = meshlet_bounds(&triangles);
+
+    meshlets.meshlets.push(*current);
+    *current = Meshlet {
+        vertex_offset: meshlets.vertices.len() as u32,
+        triangle_offset: meshlets.triangles.len() as u32 / 3,
+        ..Meshlet::default()
+    };
+}
+
+/// Bounding sphere around the box of the triangles, and a normal cone
+/// around their average normal.
+fn meshlet_bounds(triangles: &[[Vec3; 3]]) -> MeshletBounds {
+    let aabb = Aabb::from_points(triangles.iter().flatten().copied());
+    let center = aabb.center();
+    let radius = triangles.iter().flatten().map(|&p| length(sub(p, center))).fold(0.0, f32::max);
+    let mut bounds = MeshletBounds { center, radius, cone_apex: center, cone_axis: [0.0; 3], cone_cutoff: 1.0 };
+
+    // First corner and normal of every triangle with an area.
+    let planes = triangles.iter().filter_map(|[a, b, c]| {
+        let normal = cross(sub(*b, *a), sub(*c, *a));
+        (length(normal) > f32::EPSILON).then(|| (*a, normalize(normal)))
+    }).collect::<Vec<_>>();
+    let sum = planes.iter().fold([0.0; 3], |sum, &(_, n)| add(sum, n));
+    if planes.is_empty() || length(sum) <= f32::EPSILON {
+        return bounds;
+    }
+    let axis = normalize(sum);
+
+    // Past about 84 degrees the cone hardly ever culls anything.
+    let min_dot = planes.iter().map(|&(_, n)| dot(n, axis)).fold(1.0, f32::min);
+    if min_dot <= 0.1 {
+        return bounds;
+    }
+
+    // Move the apex back along the axis until it's behind every triangle,
+    // so all of them face away from cameras inside the negated cone.
+    let apex_distance = planes.iter().map(|&(a, n)| dot(sub(center, a), n) / dot(n, axis)).fold(0.0, f32::max);
+
+    bounds.cone_apex = sub(center, scale(axis, apex_distance));
+    bounds.cone_axis = axis;
+    bounds.cone_cutoff = (1.0 - min_dot * min_dot).sqrt();
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles per cell of a `size` x `size` grid of quads.
+    fn grid(size: u32) -> (Vec<u32>, Vec<Vec3>) {
+        let row = size + 1;
+        let positions = (0..row * row).map(|i| [(i % row) as f32, 0.0, (i / row) as f32]).collect();
+        let indices = (0..size * size).flat_map(|cell| {
+            let (x, y) = (cell % size, cell / size);
+            let i = y * row + x;
+            [i, i + row, i + 1, i + 1, i + row, i + row + 1]
+        }).collect();
+        (indices, positions)
+    }
+
+    /// Fisher-Yates over the triangles with a fixed xorshift seed.
+    fn shuffle_triangles(indices: &mut [u32]) {
+        let mut state = 0x2545_f491_u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let triangle_count = indices.len() / 3;
+        for i in (1..triangle_count).rev() {
+            let j = next() as usize % (i + 1);
+            for k in 0..3 {
+                indices.swap(i * 3 + k, j * 3 + k);
+            }
+        }
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles = indices.chunks_exact(3)
+            .map(|t| {
+                // Rotate the smallest index first, keeping the winding.
+                let start = (0..3).min_by_key(|&i| t[i]).unwrap();
+                [t[start], t[(start + 1) % 3], t[(start + 2) % 3]]
+            })
+            .collect::<Vec<_>>();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn vertex_cache_optimization_lowers_acmr() {
+        let (mut indices, positions) = grid(60);
+        shuffle_triangles(&mut indices);
+        let before = average_cache_miss_ratio(&indices, 16);
+
+        let shuffled = indices.clone();
+        optimize_vertex_cache(&mut indices, positions.len());
+        let after = average_cache_miss_ratio(&indices, 16);
+
+        assert!(before > 2.9, "shuffled ACMR {before}");
+        assert!(after < 0.7, "optimized ACMR {after}");
+        assert_eq!(sorted_triangles(&indices), sorted_triangles(&shuffled));
+    }
+
+    #[test]
+    fn vertex_fetch_optimization_numbers_vertices_by_first_use() {
+        let mut indices = vec![5, 2, 7, 2, 5, 0];
+        let order = optimize_vertex_fetch(&mut indices, 8);
+
+        assert_eq!(indices, [0, 1, 2, 1, 0, 3]);
+        assert_eq!(&order[..4], &[5, 2, 7, 0]);
+        let mut unused = order[4..].to_vec();
+        unused.sort();
+        assert_eq!(unused, [1, 3, 4, 6]);
+    }
+
+    #[test]
+    fn meshlets_respect_limits_and_keep_triangles() {
+        let (mut indices, positions) = grid(60);
+        optimize_vertex_cache(&mut indices, positions.len());
+        let meshlets = build_meshlets(&indices, &positions, MAX_MESHLET_VERTICES, MAX_MESHLET_TRIANGLES);
+
+        assert!(!meshlets.meshlets.is_empty());
+        for meshlet in &meshlets.meshlets {
+            assert!(meshlet.vertex_count as usize <= MAX_MESHLET_VERTICES);
+            assert!(meshlet.triangle_count as usize <= MAX_MESHLET_TRIANGLES);
+            let start = meshlet.triangle_offset as usize * 3;
+            let local = &meshlets.triangles[start..start + meshlet.triangle_count as usize * 3];
+            assert!(local.iter().all(|&i| (i as u32) < meshlet.vertex_count));
+        }
+        assert_eq!(meshlets.indices(), indices);
+    }
+
+    #[test]
+    fn flat_meshlet_gets_a_cone() {
+        let (indices, positions) = grid(2);
+        let meshlets = build_meshlets(&indices, &positions, MAX_MESHLET_VERTICES, MAX_MESHLET_TRIANGLES);
+
+        assert_eq!(meshlets.meshlets.len(), 1);
+        let bounds = meshlets.meshlets[0].bounds;
+        // Every triangle of the grid faces +Y.
+        assert!(bounds.cone_axis[1] > 0.99, "cone axis {:?}", bounds.cone_axis);
+        assert!(bounds.cone_cutoff < 0.01);
+        assert!((bounds.radius - 2f32.sqrt()).abs() < 1e-5);
+    }
+}
@@ -1,2 +1,25 @@
+#[cfg(feature = "asset")]
+pub mod asset;
+pub mod camera;
+pub mod culling;
+pub mod cvars;
+#[cfg(feature = "debug")]
+pub mod debug;
 pub mod hal;
-pub mod util;
\ No newline at end of file
+#[cfg(feature = "impostor")]
+pub mod impostor;
+pub mod instancing;
+pub mod material;
+pub mod math;
+pub mod mesh;
+pub mod meshopt;
+#[cfg(feature = "passes")]
+pub mod passes;
+#[cfg(feature = "ray-tracing")]
+pub mod ray_tracing;
+pub mod registry;
+pub mod scene;
+pub mod skinning;
+pub mod util;
+pub mod variants;
+pub mod view;
\ No newline at end of file
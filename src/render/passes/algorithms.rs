@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BufferCreateInfo, MemoryLocation};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::passes::kernel::ComputeKernel;
+
+const BLOCK_SIZE: u32 = 256;
+const RADIX_BITS: u32 = 4;
+const RADIX_DIGITS: u32 = 1 << RADIX_BITS;
+
+pub struct GpuAlgorithmsCreateInfo {
+    /// Largest number of elements passed to any of the algorithms.
+    pub max_elements: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReduceOp {
+    Add,
+    Min,
+    Max,
+}
+
+/// Building blocks for GPU-driven pipelines over `u32` values in storage
+/// buffers: exclusive scan, reduction and radix sort.
+///
+/// Everything is recorded into the given command list with barriers in
+/// between the dispatches, so results can be used by the following
+/// commands. Any number of calls can be recorded per frame.
+pub struct GpuAlgorithms {
+    max_elements: u32,
+    /// Block totals of each scan level, the last one has a single element.
+    scan_levels: Vec<Buffer>,
+    histograms: Buffer,
+    keys_scratch: Buffer,
+    values_scratch: Buffer,
+    scan_blocks_kernel: ComputeKernel,
+    scan_add_kernel: ComputeKernel,
+    reduce_kernel: ComputeKernel,
+    histogram_kernel: ComputeKernel,
+    scatter_kernel: ComputeKernel,
+}
+
+fn create_storage_buffer(renderer: &Arc<Renderer>, elements: u32, name: &str) -> Buffer {
+    let create_info = BufferCreateInfo {
+        size: elements.max(1) as u64 * 4,
+        usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+        location: MemoryLocation::GpuOnly,
+    };
+    let buffer = Buffer::new(renderer.clone(), create_info);
+    buffer.set_name(name);
+    buffer
+}
+
+fn block_count(count: u32) -> u32 {
+    count.div_ceil(BLOCK_SIZE)
+}
+
+impl GpuAlgorithms {
+    pub fn new(renderer: Arc<Renderer>, create_info: GpuAlgorithmsCreateInfo) -> Self {
+        let max_elements = create_info.max_elements.max(1);
+        let histogram_size = RADIX_DIGITS * block_count(max_elements);
+
+        // The scan levels also serve the histogram scan of the sort and the
+        // intermediate results of reductions.
+        let mut scan_levels = Vec::new();
+        let mut count = max_elements.max(histogram_size);
+        loop {
+            count = block_count(count);
+            scan_levels.push(create_storage_buffer(&renderer, count, "scan block sums"));
+            if count == 1 {
+                break;
+            }
+        }
+
+        let storage = BindingType::StorageBuffer;
+        let scan_blocks_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/scan_blocks.spv"), &[storage; 3], 4);
+        let scan_add_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/scan_add.spv"), &[storage; 2], 4);
+        let reduce_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/reduce.spv"), &[storage; 2], 12);
+        let histogram_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/radix_histogram.spv"), &[storage; 2], 12);
+        let scatter_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/radix_scatter.spv"), &[storage; 5], 16);
+
+        Self {
+            max_elements,
+            scan_levels,
+            histograms: create_storage_buffer(&renderer, histogram_size, "radix histograms"),
+            keys_scratch: create_storage_buffer(&renderer, max_elements, "radix keys scratch"),
+            values_scratch: create_storage_buffer(&renderer, max_elements, "radix values scratch"),
+            scan_blocks_kernel,
+            scan_add_kernel,
+            reduce_kernel,
+            histogram_kernel,
+            scatter_kernel,
+        }
+    }
+
+    pub fn max_elements(&self) -> u32 {
+        self.max_elements
+    }
+
+    /// Exclusive prefix sum of the first `count` values of `input` into
+    /// `output`, which may be the same buffer.
+    pub fn exclusive_scan(&self, command_list: &mut CommandList, input: &Buffer, output: &Buffer, count: u32) {
+        assert!(count <= self.max_elements, "Scan of {count} elements exceeds max_elements");
+        self.scan_level(command_list, input, output, count, 0);
+    }
+
+    fn scan_level(&self, command_list: &mut CommandList, input: &Buffer, output: &Buffer, count: u32, level: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let block_sums = &self.scan_levels[level];
+        let blocks = block_count(count);
+
+        let descriptor_set = self.scan_blocks_kernel.transient_descriptor_set();
+        descriptor_set.write_storage_buffer(0, input);
+        descriptor_set.write_storage_buffer(1, output);
+        descriptor_set.write_storage_buffer(2, block_sums);
+        self.scan_blocks_kernel.dispatch_with(command_list, descriptor_set, &count.to_ne_bytes(), blocks, 1, 1);
+        command_list.memory_barrier();
+
+        if blocks > 1 {
+            self.scan_level(command_list, block_sums, block_sums, blocks, level + 1);
+
+            let descriptor_set = self.scan_add_kernel.transient_descriptor_set();
+            descriptor_set.write_storage_buffer(0, output);
+            descriptor_set.write_storage_buffer(1, block_sums);
+            self.scan_add_kernel.dispatch_with(command_list, descriptor_set, &count.to_ne_bytes(), blocks, 1, 1);
+            command_list.memory_barrier();
+        }
+    }
+
+    /// Reduces the first `count` values of `input` to one value, written
+    /// to `output` at element `output_offset`.
+    pub fn reduce(&self, command_list: &mut CommandList, input: &Buffer, output: &Buffer, output_offset: u32, count: u32, op: ReduceOp) {
+        assert!(count <= self.max_elements, "Reduction of {count} elements exceeds max_elements");
+        let op = match op {
+            ReduceOp::Add => 0u32,
+            ReduceOp::Min => 1,
+            ReduceOp::Max => 2,
+        };
+
+        let mut source = input;
+        let mut count = count;
+        let mut level = 0;
+        loop {
+            let blocks = block_count(count).max(1);
+            let (destination, offset) = if blocks == 1 {
+                (output, output_offset)
+            } else {
+                (&self.scan_levels[level], 0)
+            };
+
+            let mut push_constants = [0u8; 12];
+            push_constants[0..4].copy_from_slice(&count.to_ne_bytes());
+            push_constants[4..8].copy_from_slice(&op.to_ne_bytes());
+            push_constants[8..12].copy_from_slice(&offset.to_ne_bytes());
+
+            let descriptor_set = self.reduce_kernel.transient_descriptor_set();
+            descriptor_set.write_storage_buffer(0, source);
+            descriptor_set.write_storage_buffer(1, destination);
+            self.reduce_kernel.dispatch_with(command_list, descriptor_set, &push_constants, blocks, 1, 1);
+            command_list.memory_barrier();
+
+            if blocks == 1 {
+                break;
+            }
+            source = destination;
+            count = blocks;
+            level += 1;
+        }
+    }
+
+    /// Stable ascending sort of the first `count` keys. Only the lowest
+    /// `key_bits` bits are compared, fewer bits take fewer passes.
+    pub fn sort_keys(&self, command_list: &mut CommandList, keys: &Buffer, count: u32, key_bits: u32) {
+        self.radix_sort(command_list, keys, None, count, key_bits);
+    }
+
+    /// Like `sort_keys`, moving `values` along with their keys.
+    pub fn sort_pairs(&self, command_list: &mut CommandList, keys: &Buffer, values: &Buffer, count: u32, key_bits: u32) {
+        self.radix_sort(command_list, keys, Some(values), count, key_bits);
+    }
+
+    fn radix_sort(&self, command_list: &mut CommandList, keys: &Buffer, values: Option<&Buffer>, count: u32, key_bits: u32) {
+        assert!(count <= self.max_elements, "Sort of {count} elements exceeds max_elements");
+        if count <= 1 {
+            return;
+        }
+
+        let blocks = block_count(count);
+        // Even, so the result ends up back in `keys` and `values`.
+        let passes = key_bits.clamp(1, 32).div_ceil(RADIX_BITS).next_multiple_of(2);
+        let has_values = values.is_some() as u32;
+        // Bound but never written when sorting keys only.
+        let values = values.unwrap_or(keys);
+        let values_scratch = if has_values != 0 { &self.values_scratch } else { &self.keys_scratch };
+
+        for pass in 0..passes {
+            let shift = pass * RADIX_BITS;
+            let (keys_in, keys_out, values_in, values_out) = if pass % 2 == 0 {
+                (keys, &self.keys_scratch, values, values_scratch)
+            } else {
+                (&self.keys_scratch, keys, values_scratch, values)
+            };
+
+            let mut push_constants = [0u8; 16];
+            push_constants[0..4].copy_from_slice(&count.to_ne_bytes());
+            push_constants[4..8].copy_from_slice(&shift.to_ne_bytes());
+            push_constants[8..12].copy_from_slice(&blocks.to_ne_bytes());
+            push_constants[12..16].copy_from_slice(&has_values.to_ne_bytes());
+
+            let descriptor_set = self.histogram_kernel.transient_descriptor_set();
+            descriptor_set.write_storage_buffer(0, keys_in);
+            descriptor_set.write_storage_buffer(1, &self.histograms);
+            self.histogram_kernel.dispatch_with(command_list, descriptor_set, &push_constants[..12], blocks, 1, 1);
+            command_list.memory_barrier();
+
+            self.scan_level(command_list, &self.histograms, &self.histograms, RADIX_DIGITS * blocks, 0);
+
+            let descriptor_set = self.scatter_kernel.transient_descriptor_set();
+            descriptor_set.write_storage_buffer(0, keys_in);
+            descriptor_set.write_storage_buffer(1, keys_out);
+            descriptor_set.write_storage_buffer(2, values_in);
+            descriptor_set.write_storage_buffer(3, values_out);
+            descriptor_set.write_storage_buffer(4, &self.histograms);
+            self.scatter_kernel.dispatch_with(command_list, descriptor_set, &push_constants, blocks, 1, 1);
+            command_list.memory_barrier();
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, Filter, MemoryLocation, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::{add, dot, length, scale, Mat4, Vec3};
+use crate::render::passes::kernel::ComputeKernel;
+
+const LUT_WORKGROUP_SIZE: u32 = 8;
+const PIXEL_WORKGROUP_SIZE: u32 = 16;
+/// std140 size of `Params` in the atmosphere shaders.
+const PARAMS_SIZE: usize = 176;
+
+pub const LUT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const TRANSMITTANCE_EXTENT: vk::Extent2D = vk::Extent2D { width: 256, height: 64 };
+const MULTISCATTERING_EXTENT: vk::Extent2D = vk::Extent2D { width: 32, height: 32 };
+const SKY_VIEW_EXTENT: vk::Extent2D = vk::Extent2D { width: 192, height: 108 };
+
+/// Earth-like atmosphere, lengths in km and coefficients per km.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AtmosphereParams {
+    pub bottom_radius: f32,
+    pub top_radius: f32,
+    pub rayleigh_scattering: Vec3,
+    pub rayleigh_scale_height: f32,
+    pub mie_scattering: f32,
+    pub mie_extinction: f32,
+    pub mie_scale_height: f32,
+    /// Cornette-Shanks asymmetry of the aerosols' phase.
+    pub mie_anisotropy: f32,
+    /// At the peak of the ozone layer, 25 km up.
+    pub ozone_absorption: Vec3,
+    pub ground_albedo: Vec3,
+}
+
+impl Default for AtmosphereParams {
+    fn default() -> Self {
+        Self {
+            bottom_radius: 6360.0,
+            top_radius: 6460.0,
+            rayleigh_scattering: [5.802e-3, 13.558e-3, 33.1e-3],
+            rayleigh_scale_height: 8.0,
+            mie_scattering: 3.996e-3,
+            mie_extinction: 4.44e-3,
+            mie_scale_height: 1.2,
+            mie_anisotropy: 0.8,
+            ozone_absorption: [0.650e-3, 1.881e-3, 0.085e-3],
+            ground_albedo: [0.3; 3],
+        }
+    }
+}
+
+impl AtmosphereParams {
+    fn extinction(&self, height: f32) -> Vec3 {
+        let altitude = (height - self.bottom_radius).max(0.0);
+        let rayleigh_density = (-altitude / self.rayleigh_scale_height).exp();
+        let mie_density = (-altitude / self.mie_scale_height).exp();
+        let ozone_density = (1.0 - (altitude - 25.0).abs() / 15.0).max(0.0);
+        [0, 1, 2].map(|i| self.rayleigh_scattering[i] * rayleigh_density + self.mie_extinction * mie_density + self.ozone_absorption[i] * ozone_density)
+    }
+
+    /// Transmittance from `height` above the planet center to the top of
+    /// the atmosphere, along a direction with a zenith angle cosine of
+    /// `cos_zenith`. Zero through the ground.
+    pub fn transmittance(&self, height: f32, cos_zenith: f32) -> Vec3 {
+        let origin = [0.0, height, 0.0];
+        let direction = [(1.0 - cos_zenith * cos_zenith).max(0.0).sqrt(), cos_zenith, 0.0];
+        if ray_sphere(origin, direction, self.bottom_radius).is_some() {
+            return [0.0; 3];
+        }
+
+        const STEPS: usize = 40;
+        let distance = ray_sphere(origin, direction, self.top_radius).unwrap_or(0.0);
+        let step_length = distance / STEPS as f32;
+        let mut optical_depth = [0.0; 3];
+        for i in 0..STEPS {
+            let position = add(origin, scale(direction, (i as f32 + 0.5) * step_length));
+            optical_depth = add(optical_depth, scale(self.extinction(length(position)), step_length));
+        }
+        optical_depth.map(|d| (-d).exp())
+    }
+}
+
+/// Distance to the nearest intersection in front of `origin`.
+fn ray_sphere(origin: Vec3, direction: Vec3, radius: f32) -> Option<f32> {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let s = discriminant.sqrt();
+    [-b - s, -b + s].into_iter().find(|&t| t >= 0.0)
+}
+
+/// Sun position from the local solar time, ignoring the equation of time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeOfDay {
+    /// 12 is solar noon.
+    pub hours: f32,
+    /// Degrees, positive to the north.
+    pub latitude: f32,
+    /// 0 is January 1st.
+    pub day_of_year: u32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self { hours: 10.0, latitude: 45.0, day_of_year: 172 }
+    }
+}
+
+impl TimeOfDay {
+    /// Unit vector towards the sun, with +X east, +Y up and -Z north.
+    pub fn to_sun(&self) -> Vec3 {
+        let declination = (-23.44f32).to_radians() * (std::f32::consts::TAU * (self.day_of_year as f32 + 10.0) / 365.0).cos();
+        let hour_angle = (15.0 * (self.hours - 12.0)).to_radians();
+        let latitude = self.latitude.to_radians();
+
+        let east = -declination.cos() * hour_angle.sin();
+        let north = declination.sin() * latitude.cos() - declination.cos() * latitude.sin() * hour_angle.cos();
+        let up = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        [east, up, -north]
+    }
+}
+
+/// Physically based sky after Hillaire's "A Scalable and Production Ready
+/// Sky and Atmosphere Rendering Technique". Transmittance and multiple
+/// scattering LUTs are computed when the atmosphere changes, a sky view
+/// LUT around the camera every frame, and the sky is drawn from it into
+/// the pixels without geometry.
+///
+/// The sun follows `time_of_day`, and lights the scene through the
+/// atmosphere:
+///
+/// ```ignore
+/// lighting.light_direction = atmosphere.sun_direction();
+/// lighting.light_color = atmosphere.sun_color(camera_position);
+/// ```
+pub struct AtmospherePass {
+    pub atmosphere: AtmosphereParams,
+    pub time_of_day: TimeOfDay,
+    /// Outside of the atmosphere.
+    pub sun_illuminance: Vec3,
+    /// Degrees.
+    pub sun_angular_radius: f32,
+    /// Size of a world unit in km, world space `y = 0` is the ground.
+    pub km_per_unit: f32,
+
+    renderer: Arc<Renderer>,
+    params: Vec<Buffer>,
+    transmittance: Texture,
+    multiscattering: Texture,
+    sky_view: Texture,
+    point_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    transmittance_kernel: ComputeKernel,
+    multiscattering_kernel: ComputeKernel,
+    sky_view_kernel: ComputeKernel,
+    sky_kernel: ComputeKernel,
+    /// Atmosphere the LUTs were last computed for.
+    baked: Option<AtmosphereParams>,
+}
+
+impl AtmospherePass {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        let lut = |extent: vk::Extent2D, name| {
+            let create_info = TextureCreateInfo {
+                format: LUT_FORMAT,
+                extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            let texture = Texture::new(renderer.clone(), create_info);
+            texture.set_name(name);
+            texture
+        };
+
+        let params = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: PARAMS_SIZE as u64,
+                usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        let sampler = |filter| {
+            let create_info = SamplerCreateInfo {
+                filter,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let kernel = |code, bindings: &[BindingType]| ComputeKernel::new(renderer.clone(), code, bindings, 0);
+        let transmittance_kernel = kernel(
+            include_bytes_align_as!(u32, "shaders/atmosphere_transmittance.spv"),
+            &[BindingType::UniformBuffer, BindingType::Texture]);
+        let multiscattering_kernel = kernel(
+            include_bytes_align_as!(u32, "shaders/atmosphere_multiscattering.spv"),
+            &[BindingType::UniformBuffer, BindingType::SampledTexture, BindingType::Texture]);
+        let sky_view_kernel = kernel(
+            include_bytes_align_as!(u32, "shaders/atmosphere_sky_view.spv"),
+            &[BindingType::UniformBuffer, BindingType::SampledTexture, BindingType::SampledTexture, BindingType::Texture]);
+        let sky_kernel = kernel(
+            include_bytes_align_as!(u32, "shaders/atmosphere_sky.spv"),
+            &[BindingType::UniformBuffer, BindingType::SampledTexture, BindingType::SampledTexture, BindingType::SampledTexture, BindingType::Texture]);
+
+        Self {
+            atmosphere: AtmosphereParams::default(),
+            time_of_day: TimeOfDay::default(),
+            sun_illuminance: [1.0; 3],
+            sun_angular_radius: 0.2667,
+            km_per_unit: 0.001,
+            params,
+            transmittance: lut(TRANSMITTANCE_EXTENT, "atmosphere transmittance"),
+            multiscattering: lut(MULTISCATTERING_EXTENT, "atmosphere multiscattering"),
+            sky_view: lut(SKY_VIEW_EXTENT, "atmosphere sky view"),
+            point_sampler: sampler(Filter::Nearest),
+            linear_sampler: sampler(Filter::Linear),
+            transmittance_kernel,
+            multiscattering_kernel,
+            sky_view_kernel,
+            sky_kernel,
+            baked: None,
+            renderer,
+        }
+    }
+
+    /// Direction the sun light travels in, for `DeferredLightingPass` and
+    /// the shadow cascades.
+    pub fn sun_direction(&self) -> Vec3 {
+        self.time_of_day.to_sun().map(|v| -v)
+    }
+
+    /// Sun illuminance reaching `camera_position` through the atmosphere,
+    /// black once the sun has set.
+    pub fn sun_color(&self, camera_position: Vec3) -> Vec3 {
+        let transmittance = self.atmosphere.transmittance(self.camera_height(camera_position), self.time_of_day.to_sun()[1]);
+        [0, 1, 2].map(|i| self.sun_illuminance[i] * transmittance[i])
+    }
+
+    fn camera_height(&self, camera_position: Vec3) -> f32 {
+        // Kept slightly above the ground, where the horizon is defined.
+        self.atmosphere.bottom_radius + (camera_position[1] * self.km_per_unit).max(0.001)
+    }
+
+    /// The sky view LUT of the last `record`, in `GENERAL` layout, for
+    /// reflections of the sky.
+    pub fn sky_view(&self) -> &Texture {
+        &self.sky_view
+    }
+
+    fn params(&self, view: Mat4, projection: Mat4, camera_position: Vec3) -> [u8; PARAMS_SIZE] {
+        let atmosphere = &self.atmosphere;
+        let mut rotation = view;
+        rotation.0[3] = [0.0, 0.0, 0.0, 1.0];
+        let inverse_view_projection = (projection * rotation).inverse().unwrap_or(Mat4::IDENTITY);
+
+        let vectors = [
+            (atmosphere.rayleigh_scattering, atmosphere.rayleigh_scale_height),
+            ([atmosphere.mie_scattering, atmosphere.mie_extinction, atmosphere.mie_scale_height], atmosphere.mie_anisotropy),
+            (atmosphere.ozone_absorption, 0.0),
+            (atmosphere.ground_albedo, 0.0),
+            (self.time_of_day.to_sun(), self.sun_angular_radius.to_radians().cos()),
+            (self.sun_illuminance, 0.0),
+        ];
+
+        let mut data = [0u8; PARAMS_SIZE];
+        for (i, ([x, y, z], w)) in vectors.into_iter().enumerate() {
+            for (j, value) in [x, y, z, w].into_iter().enumerate() {
+                let offset = i * 16 + j * 4;
+                data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+        data[96..160].copy_from_slice(&inverse_view_projection.to_bytes());
+        for (i, value) in [atmosphere.bottom_radius, atmosphere.top_radius, self.camera_height(camera_position)].into_iter().enumerate() {
+            let offset = 160 + i * 4;
+            data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        }
+        data
+    }
+
+    /// Draws the sky seen with `view` and `projection` from
+    /// `camera_position` into `target`, an `rgba16f` storage texture,
+    /// where `depth` is 0. `depth` needs `SAMPLED` usage, both are expected
+    /// in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, depth: &Texture, target: &Texture, view: Mat4, projection: Mat4, camera_position: Vec3) {
+        let params = self.params(view, projection, camera_position);
+        let buffer = &mut self.params[self.renderer.current_frame()];
+        buffer.write(0, &params);
+
+        let groups = |extent: vk::Extent2D| (extent.width.div_ceil(LUT_WORKGROUP_SIZE), extent.height.div_ceil(LUT_WORKGROUP_SIZE));
+
+        if self.baked != Some(self.atmosphere) {
+            if self.baked.is_none() {
+                for texture in [&self.transmittance, &self.multiscattering, &self.sky_view] {
+                    command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+                }
+            }
+
+            let descriptor_set = self.transmittance_kernel.transient_descriptor_set();
+            descriptor_set.write_uniform_buffer(0, buffer);
+            descriptor_set.write_texture(1, &self.transmittance);
+            let (x, y) = groups(TRANSMITTANCE_EXTENT);
+            self.transmittance_kernel.dispatch_with(command_list, descriptor_set, &[], x, y, 1);
+            command_list.transition_texture_layout(&self.transmittance, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+            let descriptor_set = self.multiscattering_kernel.transient_descriptor_set();
+            descriptor_set.write_uniform_buffer(0, buffer);
+            descriptor_set.write_sampled_texture(1, &self.transmittance, &self.linear_sampler);
+            descriptor_set.write_texture(2, &self.multiscattering);
+            let (x, y) = groups(MULTISCATTERING_EXTENT);
+            self.multiscattering_kernel.dispatch_with(command_list, descriptor_set, &[], x, y, 1);
+            command_list.transition_texture_layout(&self.multiscattering, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+            self.baked = Some(self.atmosphere);
+        }
+
+        let descriptor_set = self.sky_view_kernel.transient_descriptor_set();
+        descriptor_set.write_uniform_buffer(0, buffer);
+        descriptor_set.write_sampled_texture(1, &self.transmittance, &self.linear_sampler);
+        descriptor_set.write_sampled_texture(2, &self.multiscattering, &self.linear_sampler);
+        descriptor_set.write_texture(3, &self.sky_view);
+        let (x, y) = groups(SKY_VIEW_EXTENT);
+        self.sky_view_kernel.dispatch_with(command_list, descriptor_set, &[], x, y, 1);
+        command_list.transition_texture_layout(&self.sky_view, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        let descriptor_set = self.sky_kernel.transient_descriptor_set();
+        descriptor_set.write_uniform_buffer(0, buffer);
+        descriptor_set.write_sampled_texture(1, depth, &self.point_sampler);
+        descriptor_set.write_sampled_texture(2, &self.sky_view, &self.linear_sampler);
+        descriptor_set.write_sampled_texture(3, &self.transmittance, &self.linear_sampler);
+        descriptor_set.write_texture(4, target);
+        let extent = target.extent();
+        self.sky_kernel.dispatch_with(command_list, descriptor_set, &[], extent.width.div_ceil(PIXEL_WORKGROUP_SIZE), extent.height.div_ceil(PIXEL_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+const PUSH_CONSTANTS_SIZE: u32 = 8;
+
+pub struct CheckerboardPassCreateInfo {
+    pub extent: vk::Extent3D,
+}
+
+/// Reconstructs a full frame from a frame where only half of the pixels,
+/// alternating in a checkerboard pattern every frame, were shaded. Missing
+/// pixels are taken from the reprojected history, clamped to the rendered
+/// neighbourhood.
+pub struct CheckerboardPass {
+    history: [Texture; 2],
+    zero_velocity: Texture,
+    kernel: ComputeKernel,
+    frame: u64,
+    initialized: bool,
+}
+
+impl CheckerboardPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: CheckerboardPassCreateInfo) -> Self {
+        let history = std::array::from_fn(|_| {
+            let create_info = TextureCreateInfo {
+                format: HDR_FORMAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        });
+
+        let zero_velocity = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::R16G16_SFLOAT,
+                extent: vk::Extent3D { width: 1, height: 1, depth: 1 },
+                usage: vk::ImageUsageFlags::STORAGE,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/checkerboard.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::Texture, BindingType::Texture],
+            PUSH_CONSTANTS_SIZE);
+
+        Self { history, zero_velocity, kernel, frame: 0, initialized: false }
+    }
+
+    /// Pixels with `(x + y) & 1 == parity()` have to be shaded this frame.
+    pub fn parity(&self) -> u32 {
+        (self.frame & 1) as u32
+    }
+
+    /// Result of the last `record` call, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.history[((self.frame + 1) & 1) as usize]
+    }
+
+    /// `current` holds this frame's shaded pixels, `velocity` is an optional
+    /// `R16G16_SFLOAT` texture with per-pixel motion towards the previous frame.
+    pub fn record(&mut self, command_list: &mut CommandList, current: &Texture, velocity: Option<&Texture>) {
+        if !self.initialized {
+            for texture in self.history.iter().chain([&self.zero_velocity]) {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            self.initialized = true;
+        }
+
+        let target = &self.history[(self.frame & 1) as usize];
+        let history = &self.history[((self.frame + 1) & 1) as usize];
+        let velocity_texture = velocity.unwrap_or(&self.zero_velocity);
+
+        self.kernel.descriptor_set.write_texture(0, current);
+        self.kernel.descriptor_set.write_texture(1, history);
+        self.kernel.descriptor_set.write_texture(2, velocity_texture);
+        self.kernel.descriptor_set.write_texture(3, target);
+
+        command_list.transition_texture_layout(current, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(history, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&self.parity().to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&(velocity.is_some() as u32).to_ne_bytes());
+
+        let extent = target.extent();
+        self.kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+
+        self.frame += 1;
+    }
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use ash::vk;
+use slotmap::{new_key_type, SlotMap};
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, BufferTextureCopy, Filter, MemoryLocation, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::{normalize, Mat4};
+use crate::render::passes::deferred::GBuffer;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+/// std140 size of `Params` in `decals.comp`.
+const PARAMS_SIZE: usize = 80;
+/// Matches `Decal` in `decals.comp`, std430.
+const DECAL_SIZE: usize = 128;
+
+/// Decal images are sRGB encoded color, sampling decodes them before
+/// they are blended into the linear albedo.
+pub const ATLAS_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+new_key_type! {
+    pub struct DecalHandle;
+}
+
+pub struct DecalAtlasCreateInfo {
+    /// Width and height of the atlas texture.
+    pub size: u32,
+}
+
+/// Normalized rectangle of an image in a `DecalAtlas`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DecalRegion {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// Decal images packed into one texture, row by row in shelves as tall as
+/// their first image. Images are never freed, the atlas is meant to be
+/// filled at load time with images of similar heights.
+pub struct DecalAtlas {
+    renderer: Arc<Renderer>,
+    texture: Texture,
+    /// Top and height of every shelf, and the width used in it.
+    shelves: Vec<(u32, u32, u32)>,
+    initialized: bool,
+}
+
+impl DecalAtlas {
+    pub fn new(renderer: Arc<Renderer>, create_info: DecalAtlasCreateInfo) -> Self {
+        let texture = {
+            let create_info = TextureCreateInfo {
+                format: ATLAS_FORMAT,
+                extent: vk::Extent3D { width: create_info.size, height: create_info.size, depth: 1 },
+                usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+        texture.set_name("decal atlas");
+
+        Self { renderer, texture, shelves: Vec::new(), initialized: false }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Finds room for a `width` x `height` image, `None` when the atlas is full.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let size = self.texture.extent().width;
+        if width > size {
+            return None;
+        }
+
+        for (top, shelf_height, used) in &mut self.shelves {
+            if height <= *shelf_height && *used + width <= size {
+                let x = *used;
+                *used += width;
+                return Some((x, *top));
+            }
+        }
+
+        let top = self.shelves.last().map_or(0, |&(top, height, _)| top + height);
+        if top + height > size {
+            return None;
+        }
+        self.shelves.push((top, height, width));
+        Some((0, top))
+    }
+
+    /// Records the upload of an `ATLAS_FORMAT` image, tightly packed, and
+    /// returns where it landed. `None` when the atlas is full.
+    pub fn insert(&mut self, command_list: &mut CommandList, width: u32, height: u32, data: &[u8]) -> Option<DecalRegion> {
+        assert_eq!(data.len(), width as usize * height as usize * 4, "Decal images are rgba8");
+        let (x, y) = self.allocate(width, height)?;
+
+        let mut staging = {
+            let create_info = BufferCreateInfo {
+                size: data.len().max(4) as u64,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(self.renderer.clone(), create_info)
+        };
+        staging.write(0, data);
+
+        let old_layout = if self.initialized { vk::ImageLayout::GENERAL } else { vk::ImageLayout::UNDEFINED };
+        command_list.transition_texture_layout(&self.texture, old_layout, vk::ImageLayout::GENERAL);
+        self.initialized = true;
+
+        let region = BufferTextureCopy {
+            texture_offset: vk::Offset3D { x: x as i32, y: y as i32, z: 0 },
+            texture_extent: vk::Extent3D { width, height, depth: 1 },
+            ..BufferTextureCopy::whole(&self.texture)
+        };
+        command_list.copy_buffer_to_texture(&staging, &self.texture, &[region]);
+        command_list.transition_texture_layout(&self.texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.retain(Arc::new(staging));
+
+        let size = self.texture.extent().width as f32;
+        Some(DecalRegion {
+            offset: [x as f32 / size, y as f32 / size],
+            size: [width as f32 / size, height as f32 / size],
+        })
+    }
+}
+
+/// A box projecting an atlas image onto the surfaces inside it.
+#[derive(Clone, Copy, Debug)]
+pub struct Decal {
+    /// Places the unit box around the origin in the world. The image covers
+    /// the box's local XZ plane, projected along -Y.
+    pub transform: Mat4,
+    pub region: DecalRegion,
+    /// Multiplies the atlas image, alpha scales the blend.
+    pub color: [f32; 4],
+    /// Cosine between the projection and the surfaces' normals below which
+    /// the decal fades out, keeping it off surfaces parallel to it.
+    pub angle_fade: f32,
+    /// Decals with a higher order are drawn over those with a lower one,
+    /// equal orders in the order they were added.
+    pub order: i32,
+}
+
+struct DecalState {
+    decal: Decal,
+    sequence: u64,
+}
+
+pub struct DecalPassCreateInfo {
+    pub max_decals: u32,
+}
+
+/// Deferred decals: applied in a compute pass between the geometry pass and
+/// the lighting resolve, blending the atlas images of the decals over the
+/// G-buffer albedo where their boxes contain the surface. Every box is
+/// bounded by a screen space rectangle on the CPU, most pixels skip most
+/// decals with one test.
+///
+/// ```ignore
+/// gbuffer.begin(command_list);
+/// // Opaque geometry.
+/// command_list.end_rendering();
+/// decals.record(command_list, &gbuffer, &atlas, view_projection);
+/// lighting.record(command_list, &inputs, &hdr);
+/// ```
+pub struct DecalPass {
+    renderer: Arc<Renderer>,
+    max_decals: u32,
+    decals: SlotMap<DecalHandle, DecalState>,
+    next_sequence: u64,
+    params: Vec<Buffer>,
+    /// Sorted decals uploaded every frame, per frame in flight.
+    decal_buffers: Vec<Buffer>,
+    point_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    kernel: ComputeKernel,
+}
+
+impl DecalPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: DecalPassCreateInfo) -> Self {
+        let max_decals = create_info.max_decals.max(1);
+
+        let buffer = |size: usize, usage| {
+            let create_info = BufferCreateInfo {
+                size: size as u64,
+                usage,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+        let params = (0..FRAME_OVERLAP).map(|_| buffer(PARAMS_SIZE, vk::BufferUsageFlags::UNIFORM_BUFFER)).collect();
+        let decal_buffers = (0..FRAME_OVERLAP)
+            .map(|_| buffer(max_decals as usize * DECAL_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER))
+            .collect();
+
+        let sampler = |filter| {
+            let create_info = SamplerCreateInfo {
+                filter,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/decals.spv"),
+            &[
+                BindingType::StorageBuffer,
+                BindingType::UniformBuffer,
+                BindingType::SampledTexture,
+                BindingType::SampledTexture,
+                BindingType::Texture,
+                BindingType::Texture,
+            ],
+            0);
+
+        Self {
+            max_decals,
+            decals: SlotMap::with_key(),
+            next_sequence: 0,
+            params,
+            decal_buffers,
+            point_sampler: sampler(Filter::Nearest),
+            linear_sampler: sampler(Filter::Linear),
+            kernel,
+            renderer,
+        }
+    }
+
+    pub fn add(&mut self, decal: Decal) -> DecalHandle {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.decals.insert(DecalState { decal, sequence })
+    }
+
+    pub fn remove(&mut self, handle: DecalHandle) -> Option<Decal> {
+        self.decals.remove(handle).map(|s| s.decal)
+    }
+
+    pub fn decal_mut(&mut self, handle: DecalHandle) -> Option<&mut Decal> {
+        self.decals.get_mut(handle).map(|s| &mut s.decal)
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    pub fn max_decals(&self) -> u32 {
+        self.max_decals
+    }
+
+    /// Pixels of a `width` x `height` target covered by the box of `decal`,
+    /// min and max corners. `None` when it's off screen.
+    fn screen_rect(decal: &Decal, view_projection: Mat4, width: f32, height: f32) -> Option<[f32; 4]> {
+        let clip_from_decal = view_projection * decal.transform;
+        let mut min = [f32::MAX; 2];
+        let mut max = [f32::MIN; 2];
+        for corner in 0..8 {
+            let local = [0, 1, 2].map(|axis| if corner & (1 << axis) != 0 { 0.5 } else { -0.5 });
+            let [x, y, _, w] = clip_from_decal.transform([local[0], local[1], local[2], 1.0]);
+            // The box crosses the camera plane, the projection of its
+            // corners doesn't bound it.
+            if w <= 0.0 {
+                return Some([0.0, 0.0, width, height]);
+            }
+            for (axis, value) in [x / w, y / w].into_iter().enumerate() {
+                min[axis] = min[axis].min(value);
+                max[axis] = max[axis].max(value);
+            }
+        }
+
+        if max[0] < -1.0 || min[0] > 1.0 || max[1] < -1.0 || min[1] > 1.0 {
+            return None;
+        }
+        let to_pixels = |ndc: f32, size: f32| (ndc * 0.5 + 0.5) * size;
+        Some([to_pixels(min[0], width), to_pixels(min[1], height), to_pixels(max[0], width), to_pixels(max[1], height)])
+    }
+
+    /// Blends the decals visible with `view_projection` into the albedo of
+    /// `gbuffer`, sampling `atlas`. Recorded outside of rendering after the
+    /// geometry pass, leaves the G-buffer in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, gbuffer: &GBuffer, atlas: &DecalAtlas, view_projection: Mat4) {
+        let extent = gbuffer.albedo().extent();
+        let (width, height) = (extent.width as f32, extent.height as f32);
+
+        let mut sorted: Vec<&DecalState> = self.decals.values().collect();
+        sorted.sort_by_key(|s| (s.decal.order, s.sequence));
+
+        let mut data = Vec::with_capacity(sorted.len() * DECAL_SIZE);
+        let mut count = 0u32;
+        for state in sorted {
+            if count == self.max_decals {
+                break;
+            }
+            let decal = &state.decal;
+            let Some(screen_rect) = Self::screen_rect(decal, view_projection, width, height) else {
+                continue;
+            };
+            let Some(world_to_decal) = decal.transform.inverse() else {
+                continue;
+            };
+            let [x, y, z] = normalize(decal.transform.transform_vector([0.0, -1.0, 0.0]));
+
+            data.extend(world_to_decal.to_bytes());
+            let region = decal.region;
+            let floats = [region.offset[0], region.offset[1], region.size[0], region.size[1]];
+            data.extend(floats.iter().chain(&decal.color).chain(&screen_rect).flat_map(|v| v.to_ne_bytes()));
+            data.extend([x, y, z, decal.angle_fade].iter().flat_map(|v| v.to_ne_bytes()));
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+
+        let mut params = [0u8; PARAMS_SIZE];
+        params[0..64].copy_from_slice(&view_projection.inverse().unwrap_or(Mat4::IDENTITY).to_bytes());
+        params[64..68].copy_from_slice(&count.to_ne_bytes());
+
+        let frame = self.renderer.current_frame();
+        self.params[frame].write(0, &params);
+        self.decal_buffers[frame].write(0, &data);
+
+        for texture in [gbuffer.albedo(), gbuffer.normal(), gbuffer.depth()] {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        }
+        let descriptor_set = self.kernel.transient_descriptor_set();
+        descriptor_set.write_storage_buffer(0, &self.decal_buffers[frame]);
+        descriptor_set.write_uniform_buffer(1, &self.params[frame]);
+        descriptor_set.write_sampled_texture(2, gbuffer.depth(), &self.point_sampler);
+        descriptor_set.write_sampled_texture(3, atlas.texture(), &self.linear_sampler);
+        descriptor_set.write_texture(4, gbuffer.albedo());
+        descriptor_set.write_texture(5, gbuffer.normal());
+        self.kernel.dispatch_with(command_list, descriptor_set, &[], extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(gbuffer.albedo(), vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, ColorAttachment, DepthAttachment, Filter, LoadOp, MemoryLocation, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::material::{MaterialParam, MaterialParamType};
+use crate::render::math::{normalize, Mat4, Vec3};
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::light_cull::LightCullPass;
+
+const WORKGROUP_SIZE: u32 = 16;
+/// std140 size of `Params` in the lighting shader.
+const PARAMS_SIZE: usize = 208;
+
+pub const ALBEDO_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+pub const NORMAL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Reference G-buffer fragment shader, for material templates using the
+/// mesh vertex shader, `GBuffer::color_formats` and the parameters of
+/// `gbuffer_material_params`.
+pub fn gbuffer_fragment_shader_code() -> &'static [u32] {
+    include_bytes_align_as!(u32, "shaders/gbuffer_frag.spv")
+}
+
+pub fn gbuffer_material_params() -> Vec<MaterialParam> {
+    vec![MaterialParam { name: "base_color", typ: MaterialParamType::Vec4, default: [1.0; 4] }]
+}
+
+pub struct GBufferCreateInfo {
+    pub extent: vk::Extent3D,
+}
+
+/// Render targets of the geometry pass: albedo with coverage in alpha,
+/// world space normals and depth. The deferred path in place of forward
+/// shading:
+///
+/// ```ignore
+/// gbuffer.begin(command_list);
+/// // Opaque geometry with G-buffer materials.
+/// command_list.end_rendering();
+/// light_cull.record(command_list, &lights, view, projection);
+/// lighting.record(command_list, &inputs, &hdr);
+/// gbuffer.begin_forward(command_list, &hdr);
+/// // Transparent geometry with forward materials.
+/// command_list.end_rendering();
+/// ```
+pub struct GBuffer {
+    albedo: Texture,
+    normal: Texture,
+    depth: Texture,
+}
+
+impl GBuffer {
+    pub fn new(renderer: Arc<Renderer>, create_info: GBufferCreateInfo) -> Self {
+        let color_target = |format| {
+            let create_info = TextureCreateInfo {
+                format,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let depth = {
+            let create_info = TextureCreateInfo {
+                format: DEPTH_FORMAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::DEPTH,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            albedo: color_target(ALBEDO_FORMAT),
+            normal: color_target(NORMAL_FORMAT),
+            depth,
+        }
+    }
+
+    /// Formats of the geometry pass pipelines, in fragment output order.
+    pub fn color_formats() -> Vec<vk::Format> {
+        vec![ALBEDO_FORMAT, NORMAL_FORMAT]
+    }
+
+    pub fn albedo(&self) -> &Texture {
+        &self.albedo
+    }
+
+    pub fn normal(&self) -> &Texture {
+        &self.normal
+    }
+
+    pub fn depth(&self) -> &Texture {
+        &self.depth
+    }
+
+    /// Clears the targets and starts the geometry pass, ended with
+    /// `CommandList::end_rendering`.
+    pub fn begin(&self, command_list: &mut CommandList) {
+        for texture in [&self.albedo, &self.normal, &self.depth] {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        }
+
+        let colors = [
+            ColorAttachment { texture: &self.albedo, load: LoadOp::Clear([0.0; 4]) },
+            ColorAttachment { texture: &self.normal, load: LoadOp::DontCare },
+        ];
+        let depth = DepthAttachment { texture: &self.depth, load: LoadOp::Clear(0.0) };
+        command_list.begin_rendering_attachments(&colors, Some(depth));
+    }
+
+    /// Starts the forward pass after the lighting resolve, drawing into
+    /// `target` tested against the G-buffer depth. For transparent and
+    /// other forward shaded geometry, pipelines shouldn't write depth.
+    pub fn begin_forward(&self, command_list: &mut CommandList, target: &Texture) {
+        command_list.transition_texture_layout(&self.depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let colors = [ColorAttachment { texture: target, load: LoadOp::Load }];
+        let depth = DepthAttachment { texture: &self.depth, load: LoadOp::Load };
+        command_list.begin_rendering_attachments(&colors, Some(depth));
+    }
+}
+
+/// What the lighting resolve reads, all in `GENERAL` layout.
+#[derive(Clone, Copy)]
+pub struct DeferredLightingInputs<'a> {
+    pub gbuffer: &'a GBuffer,
+    /// Recorded before with the same `view` and `projection`.
+    pub clusters: &'a LightCullPass,
+    /// Scales the ambient term, e.g. `SsaoPass::output` or
+    /// `AmbientOcclusionPass::output`.
+    pub ambient_occlusion: Option<&'a Texture>,
+    /// Visibility of the directional light, e.g. `RtShadowPass::output`.
+    pub shadow: Option<&'a Texture>,
+    pub view: Mat4,
+    pub projection: Mat4,
+}
+
+/// Lighting resolve of the deferred path: lights a `GBuffer` with one
+/// directional light, a constant ambient term and the point lights sorted
+/// by a `LightCullPass`, into an HDR target.
+pub struct DeferredLightingPass {
+    /// Direction the light travels in, world space.
+    pub light_direction: Vec3,
+    pub light_color: Vec3,
+    pub ambient: Vec3,
+
+    renderer: Arc<Renderer>,
+    params: Vec<Buffer>,
+    sampler: Arc<Sampler>,
+    kernel: ComputeKernel,
+}
+
+impl DeferredLightingPass {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        let params = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: PARAMS_SIZE as u64,
+                usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/deferred_lighting.spv"),
+            &[
+                BindingType::StorageBuffer,
+                BindingType::StorageBuffer,
+                BindingType::StorageBuffer,
+                BindingType::Texture,
+                BindingType::Texture,
+                BindingType::SampledTexture,
+                BindingType::Texture,
+                BindingType::UniformBuffer,
+                BindingType::SampledTexture,
+                BindingType::SampledTexture,
+            ],
+            0);
+
+        Self {
+            light_direction: [0.0, -1.0, 0.0],
+            light_color: [1.0; 3],
+            ambient: [0.03; 3],
+            renderer,
+            params,
+            sampler,
+            kernel,
+        }
+    }
+
+    fn params(&self, inputs: &DeferredLightingInputs) -> [u8; PARAMS_SIZE] {
+        let to_light = normalize(self.light_direction).map(|v| -v);
+        let inverse_projection = inputs.projection.inverse().unwrap_or(Mat4::IDENTITY);
+
+        let mut data = [0u8; PARAMS_SIZE];
+        data[0..64].copy_from_slice(&inverse_projection.to_bytes());
+        data[64..128].copy_from_slice(&inputs.view.to_bytes());
+        for (i, vector) in [to_light, self.light_color, self.ambient].iter().enumerate() {
+            for (j, value) in vector.iter().enumerate() {
+                let offset = 128 + i * 16 + j * 4;
+                data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+        data[176..192].copy_from_slice(&inputs.clusters.cluster_data().to_bytes());
+        data[192..196].copy_from_slice(&(inputs.ambient_occlusion.is_some() as u32).to_ne_bytes());
+        data[196..200].copy_from_slice(&(inputs.shadow.is_some() as u32).to_ne_bytes());
+        data
+    }
+
+    /// Records the lighting of the G-buffer into `target`, an `rgba16f`
+    /// storage texture of the same extent in `GENERAL` layout. Pixels
+    /// without geometry keep their content.
+    pub fn record(&mut self, command_list: &mut CommandList, inputs: &DeferredLightingInputs, target: &Texture) {
+        let extent = target.extent();
+        let gbuffer = inputs.gbuffer;
+
+        let params = self.params(inputs);
+        let buffer = &mut self.params[self.renderer.current_frame()];
+        buffer.write(0, &params);
+
+        let descriptor_set = &self.kernel.descriptor_set;
+        inputs.clusters.write_clusters(descriptor_set, 0);
+        descriptor_set.write_texture(3, gbuffer.albedo());
+        descriptor_set.write_texture(4, gbuffer.normal());
+        descriptor_set.write_sampled_texture(5, gbuffer.depth(), &self.sampler);
+        descriptor_set.write_texture(6, target);
+        descriptor_set.write_uniform_buffer(7, buffer);
+        // The depth stands in when there's no occlusion, the shader skips it.
+        descriptor_set.write_sampled_texture(8, inputs.ambient_occlusion.unwrap_or(gbuffer.depth()), &self.sampler);
+        descriptor_set.write_sampled_texture(9, inputs.shadow.unwrap_or(gbuffer.depth()), &self.sampler);
+
+        for texture in [gbuffer.albedo(), gbuffer.normal(), gbuffer.depth(), target].into_iter().chain(inputs.ambient_occlusion).chain(inputs.shadow) {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        }
+        self.kernel.dispatch(command_list, &[], extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
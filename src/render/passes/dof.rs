@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::camera::Camera;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+const COC_PUSH_CONSTANTS_SIZE: u32 = 16;
+const BLUR_PUSH_CONSTANTS_SIZE: u32 = 8;
+/// Side of the tiles the near field blur spreads over, one CoC workgroup.
+const TILE_SIZE: u32 = WORKGROUP_SIZE;
+
+pub struct DofPassCreateInfo {
+    pub extent: vk::Extent2D,
+}
+
+/// Depth of field from the camera's `Lens`. The circle of confusion of every
+/// pixel comes from the thin lens model, then a scatter as gather blur
+/// spreads the far field behind the focus plane and the near field in front
+/// of it, which bleeds over focused pixels as an out of focus foreground
+/// does.
+///
+/// Runs on the HDR target before tonemapping, so bright highlights make
+/// bokeh shapes.
+pub struct DofPass {
+    /// Samples gathered per blurred pixel.
+    pub samples: u32,
+    /// Largest circle of confusion radius in pixels, bounds the cost of
+    /// the blur. At most 16, the tile size the near field spreads over.
+    pub max_radius: f32,
+
+    coc: Texture,
+    tile_near: Texture,
+    output: Texture,
+    sampler: Arc<Sampler>,
+    coc_kernel: ComputeKernel,
+    blur_kernel: ComputeKernel,
+    initialized: bool,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, format: vk::Format, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl DofPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: DofPassCreateInfo) -> Self {
+        let extent = create_info.extent;
+        let tiles = vk::Extent2D { width: extent.width.div_ceil(TILE_SIZE), height: extent.height.div_ceil(TILE_SIZE) };
+        let coc = create_target(&renderer, extent, vk::Format::R16_SFLOAT, "dof circle of confusion");
+        let tile_near = create_target(&renderer, tiles, vk::Format::R16_SFLOAT, "dof near tiles");
+        let output = create_target(&renderer, extent, HDR_FORMAT, "dof output");
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let coc_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/dof_coc.spv"),
+            &[BindingType::SampledTexture, BindingType::Texture, BindingType::Texture],
+            COC_PUSH_CONSTANTS_SIZE);
+        let blur_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/dof_blur.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::Texture, BindingType::Texture],
+            BLUR_PUSH_CONSTANTS_SIZE);
+        coc_kernel.descriptor_set.write_texture(1, &coc);
+        coc_kernel.descriptor_set.write_texture(2, &tile_near);
+        blur_kernel.descriptor_set.write_texture(1, &coc);
+        blur_kernel.descriptor_set.write_texture(2, &tile_near);
+        blur_kernel.descriptor_set.write_texture(3, &output);
+
+        Self {
+            samples: 48,
+            max_radius: TILE_SIZE as f32,
+            coc,
+            tile_near,
+            output,
+            sampler,
+            coc_kernel,
+            blur_kernel,
+            initialized: false,
+        }
+    }
+
+    /// Result of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    /// Signed circle of confusion radius in pixels of the last `record`,
+    /// negative in the near field, in `GENERAL` layout.
+    pub fn circle_of_confusion(&self) -> &Texture {
+        &self.coc
+    }
+
+    /// Blurs `source`, an `rgba16f` HDR target, with the lens of `camera`.
+    /// `depth` needs `SAMPLED` usage and comes from `camera`'s projection.
+    /// Both are expected in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, source: &Texture, depth: &Texture, camera: &Camera) {
+        if !self.initialized {
+            for texture in [&self.coc, &self.tile_near, &self.output] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            self.initialized = true;
+        }
+
+        let extent = self.output.extent();
+        let coc_scale = camera.lens.coc_scale(extent.height);
+        let words = [
+            coc_scale.to_bits(),
+            camera.lens.focus_distance.to_bits(),
+            self.max_radius.clamp(1.0, TILE_SIZE as f32).to_bits(),
+            camera.near.to_bits(),
+        ];
+        let push_constants: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        self.coc_kernel.descriptor_set.write_sampled_texture(0, depth, &self.sampler);
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.coc_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.coc, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.tile_near, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let words = [self.samples.max(1), TILE_SIZE];
+        let push_constants: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        self.blur_kernel.descriptor_set.write_texture(0, source);
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.blur_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BufferCreateInfo, MemoryLocation};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+const HISTOGRAM_BINS: u64 = 256;
+const HISTOGRAM_PUSH_CONSTANTS_SIZE: u32 = 8;
+const ADAPT_PUSH_CONSTANTS_SIZE: u32 = 32;
+/// Middle gray, the luminance the adapted average is exposed to.
+const KEY: f32 = 0.18;
+
+/// Eye adaptation: a histogram of the HDR target's luminance, averaged and
+/// smoothed over time into an exposure that stays on the GPU. The tonemapper
+/// picks it up with `TonemapPass::set_auto_exposure`:
+///
+/// ```ignore
+/// tonemap.set_auto_exposure(Some(exposure.exposure_buffer().clone()));
+/// // Every frame:
+/// exposure.record(command_list, &hdr, delta_time);
+/// tonemap.record(command_list, &hdr);
+/// ```
+pub struct AutoExposurePass {
+    /// Luminance range of the histogram in EV, darker pixels are ignored
+    /// and brighter ones clamped.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    /// Adaptation rates per second, towards brighter and darker scenes.
+    pub speed_up: f32,
+    pub speed_down: f32,
+    /// Exposure compensation in EV.
+    pub compensation: f32,
+
+    histogram: Buffer,
+    exposure: Arc<Buffer>,
+    histogram_kernel: ComputeKernel,
+    adapt_kernel: ComputeKernel,
+    initialized: bool,
+}
+
+impl AutoExposurePass {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        let buffer = |size: u64, name| {
+            let create_info = BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuOnly,
+            };
+            let buffer = Buffer::new(renderer.clone(), create_info);
+            buffer.set_name(name);
+            buffer
+        };
+        let histogram = buffer(HISTOGRAM_BINS * 4, "luminance histogram");
+        let exposure = Arc::new(buffer(8, "exposure"));
+
+        let histogram_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/exposure_histogram.spv"),
+            &[BindingType::Texture, BindingType::StorageBuffer],
+            HISTOGRAM_PUSH_CONSTANTS_SIZE);
+        let adapt_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/exposure_adapt.spv"),
+            &[BindingType::StorageBuffer, BindingType::StorageBuffer],
+            ADAPT_PUSH_CONSTANTS_SIZE);
+        histogram_kernel.descriptor_set.write_storage_buffer(1, &histogram);
+        adapt_kernel.descriptor_set.write_storage_buffer(0, &histogram);
+        adapt_kernel.descriptor_set.write_storage_buffer(1, &exposure);
+
+        Self {
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+            compensation: 0.0,
+            histogram,
+            exposure,
+            histogram_kernel,
+            adapt_kernel,
+            initialized: false,
+        }
+    }
+
+    /// The adapted luminance and the exposure derived from it, two `f32`.
+    pub fn exposure_buffer(&self) -> &Arc<Buffer> {
+        &self.exposure
+    }
+
+    /// Measures `source`, an `rgba16f` HDR target in `GENERAL` layout, and
+    /// adapts the exposure over `delta_time` seconds. The first frame
+    /// adapts immediately.
+    pub fn record(&mut self, command_list: &mut CommandList, source: &Texture, delta_time: f32) {
+        let reset = !self.initialized;
+        if reset {
+            command_list.fill_buffer(&self.histogram, 0, vk::WHOLE_SIZE, 0);
+            command_list.memory_barrier();
+            self.initialized = true;
+        }
+
+        let log_range = (self.max_log_luminance - self.min_log_luminance).max(0.001);
+        let extent = source.extent();
+
+        let mut push_constants = [0u8; HISTOGRAM_PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&self.min_log_luminance.to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&(1.0 / log_range).to_ne_bytes());
+
+        self.histogram_kernel.descriptor_set.write_texture(0, source);
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.histogram_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.memory_barrier();
+
+        let key = KEY * self.compensation.exp2();
+        let words = [
+            self.min_log_luminance.to_bits(),
+            log_range.to_bits(),
+            extent.width * extent.height,
+            delta_time.to_bits(),
+            self.speed_up.to_bits(),
+            self.speed_down.to_bits(),
+            key.to_bits(),
+            reset as u32,
+        ];
+        let push_constants: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        self.adapt_kernel.dispatch(command_list, &push_constants, 1, 1, 1);
+        command_list.memory_barrier();
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, Filter, MemoryLocation, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::{normalize, Mat4, Vec3};
+use crate::render::passes::kernel::ComputeKernel;
+
+const FROXEL_WORKGROUP_SIZE: u32 = 4;
+const COLUMN_WORKGROUP_SIZE: u32 = 8;
+const PIXEL_WORKGROUP_SIZE: u32 = 16;
+/// std140 size of `Params` in the fog shaders.
+const PARAMS_SIZE: usize = 352;
+
+pub const FROXEL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+pub struct VolumetricFogPassCreateInfo {
+    /// Size of the froxel grid, the depth is the number of slices.
+    pub froxels: vk::Extent3D,
+    pub near: f32,
+    /// View distance covered by the froxels, fog further away is ignored.
+    pub far: f32,
+}
+
+/// Height fog lit by an unshadowed sun and a constant ambient term,
+/// evaluated in a view aligned froxel grid with exponentially growing
+/// depth slices. Three compute passes inject the scattering and extinction
+/// of every froxel, reprojecting the previous frame's, accumulate them
+/// along the view rays, and apply the result to a lit target.
+pub struct VolumetricFogPass {
+    /// Extinction per unit distance at `base_height` and below.
+    pub density: f32,
+    /// Exponential falloff of the density per unit above `base_height`.
+    pub height_falloff: f32,
+    pub base_height: f32,
+    /// Scattered fraction of the extinguished light.
+    pub albedo: Vec3,
+    /// Henyey-Greenstein asymmetry, positive values scatter forward and
+    /// brighten the fog looking towards the sun.
+    pub anisotropy: f32,
+    /// Direction the sun light travels in, world space.
+    pub sun_direction: Vec3,
+    pub sun_color: Vec3,
+    pub ambient: Vec3,
+    /// Weight of the reprojected previous frame, 0 disables the temporal
+    /// filter and the jitter of the froxel samples.
+    pub history_weight: f32,
+    pub near: f32,
+    pub far: f32,
+
+    renderer: Arc<Renderer>,
+    params: Vec<Buffer>,
+    /// Two grids alternating as the injection target of the current frame
+    /// and the history read from the previous one.
+    scattering: [Texture; 2],
+    current: usize,
+    integrated: Texture,
+    point_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    inject_kernel: ComputeKernel,
+    integrate_kernel: ComputeKernel,
+    apply_kernel: ComputeKernel,
+    frame: u64,
+    previous_view: Option<(Mat4, Mat4)>,
+    initialized: bool,
+}
+
+impl VolumetricFogPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: VolumetricFogPassCreateInfo) -> Self {
+        let froxels = |name| {
+            let create_info = TextureCreateInfo {
+                format: FROXEL_FORMAT,
+                extent: create_info.froxels,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D3,
+            };
+            let texture = Texture::new(renderer.clone(), create_info);
+            texture.set_name(name);
+            texture
+        };
+
+        let params = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: PARAMS_SIZE as u64,
+                usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        let sampler = |filter| {
+            let create_info = SamplerCreateInfo {
+                filter,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let inject_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/fog_inject.spv"),
+            &[BindingType::UniformBuffer, BindingType::SampledTexture, BindingType::Texture],
+            0);
+        let integrate_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/fog_integrate.spv"),
+            &[BindingType::UniformBuffer, BindingType::Texture, BindingType::Texture],
+            0);
+        let apply_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/fog_apply.spv"),
+            &[BindingType::UniformBuffer, BindingType::SampledTexture, BindingType::SampledTexture, BindingType::Texture],
+            0);
+
+        Self {
+            density: 0.02,
+            height_falloff: 0.1,
+            base_height: 0.0,
+            albedo: [0.9; 3],
+            anisotropy: 0.6,
+            sun_direction: [0.0, -1.0, 0.0],
+            sun_color: [1.0; 3],
+            ambient: [0.03; 3],
+            history_weight: 0.9,
+            near: create_info.near,
+            far: create_info.far,
+            params,
+            scattering: [froxels("fog scattering 0"), froxels("fog scattering 1")],
+            current: 0,
+            integrated: froxels("fog integrated"),
+            point_sampler: sampler(Filter::Nearest),
+            linear_sampler: sampler(Filter::Linear),
+            inject_kernel,
+            integrate_kernel,
+            apply_kernel,
+            frame: 0,
+            previous_view: None,
+            initialized: false,
+            renderer,
+        }
+    }
+
+    /// Accumulated scattering and transmittance up to the end of every
+    /// slice, in `GENERAL` layout after `record`. For forward shaded
+    /// transparents to fog themselves.
+    pub fn integrated(&self) -> &Texture {
+        &self.integrated
+    }
+
+    fn params(&self, view: Mat4, projection: Mat4, jitter: f32) -> [u8; PARAMS_SIZE] {
+        let inverse_view = view.inverse().unwrap_or(Mat4::IDENTITY);
+        let inverse_projection = projection.inverse().unwrap_or(Mat4::IDENTITY);
+        let (previous_view, previous_projection) = self.previous_view.unwrap_or((view, projection));
+        let has_history = self.previous_view.is_some() && self.history_weight > 0.0;
+
+        let mut data = [0u8; PARAMS_SIZE];
+        for (i, matrix) in [inverse_view, inverse_projection, previous_view, previous_projection].iter().enumerate() {
+            data[i * 64..(i + 1) * 64].copy_from_slice(&matrix.to_bytes());
+        }
+        let vectors = [
+            (self.albedo, self.density),
+            (normalize(self.sun_direction), self.anisotropy),
+            (self.sun_color, self.height_falloff),
+            (self.ambient, self.base_height),
+        ];
+        for (i, ([x, y, z], w)) in vectors.into_iter().enumerate() {
+            for (j, value) in [x, y, z, w].into_iter().enumerate() {
+                let offset = 256 + i * 16 + j * 4;
+                data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+        for (i, value) in [self.near, self.far, self.history_weight, jitter].into_iter().enumerate() {
+            let offset = 320 + i * 4;
+            data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        }
+        data[336..340].copy_from_slice(&(has_history as u32).to_ne_bytes());
+        data
+    }
+
+    /// Fogs `target`, the lighting of the frame drawn with `view` and
+    /// `projection`, an `rgba16f` storage texture. `depth` needs `SAMPLED`
+    /// usage, both are expected in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, depth: &Texture, target: &Texture, view: Mat4, projection: Mat4) {
+        if !self.initialized {
+            for texture in self.scattering.iter().chain([&self.integrated]) {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            self.initialized = true;
+        }
+
+        // Golden ratio sequence, spreading the samples of consecutive
+        // frames over the slices.
+        let jitter = if self.history_weight > 0.0 {
+            (self.frame as f32 * 0.618_034).fract()
+        } else {
+            0.5
+        };
+        self.frame += 1;
+
+        let params = self.params(view, projection, jitter);
+        let buffer = &mut self.params[self.renderer.current_frame()];
+        buffer.write(0, &params);
+
+        let history = self.current;
+        self.current = 1 - self.current;
+        let froxels = self.integrated.extent();
+
+        let descriptor_set = self.inject_kernel.transient_descriptor_set();
+        descriptor_set.write_uniform_buffer(0, buffer);
+        descriptor_set.write_sampled_texture(1, &self.scattering[history], &self.linear_sampler);
+        descriptor_set.write_texture(2, &self.scattering[self.current]);
+        let groups = [froxels.width, froxels.height, froxels.depth].map(|size| size.div_ceil(FROXEL_WORKGROUP_SIZE));
+        self.inject_kernel.dispatch_with(command_list, descriptor_set, &[], groups[0], groups[1], groups[2]);
+        command_list.transition_texture_layout(&self.scattering[self.current], vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let descriptor_set = self.integrate_kernel.transient_descriptor_set();
+        descriptor_set.write_uniform_buffer(0, buffer);
+        descriptor_set.write_texture(1, &self.scattering[self.current]);
+        descriptor_set.write_texture(2, &self.integrated);
+        self.integrate_kernel.dispatch_with(command_list, descriptor_set, &[], froxels.width.div_ceil(COLUMN_WORKGROUP_SIZE), froxels.height.div_ceil(COLUMN_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.integrated, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        let descriptor_set = self.apply_kernel.transient_descriptor_set();
+        descriptor_set.write_uniform_buffer(0, buffer);
+        descriptor_set.write_sampled_texture(1, depth, &self.point_sampler);
+        descriptor_set.write_sampled_texture(2, &self.integrated, &self.linear_sampler);
+        descriptor_set.write_texture(3, target);
+        let extent = target.extent();
+        self.apply_kernel.dispatch_with(command_list, descriptor_set, &[], extent.width.div_ceil(PIXEL_WORKGROUP_SIZE), extent.height.div_ceil(PIXEL_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.previous_view = Some((view, projection));
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::Mat4;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::taa::VELOCITY_FORMAT;
+use crate::render::passes::upscale::{UpscaleInputs, Upscaler};
+
+const WORKGROUP_SIZE: u32 = 16;
+const VELOCITY_PUSH_CONSTANTS_SIZE: u32 = 72;
+const ACCUMULATE_PUSH_CONSTANTS_SIZE: u32 = 16;
+
+pub struct Fsr2UpscalerCreateInfo {
+    /// Size the scene is rendered at, see `Renderer::render_extent`.
+    pub input_extent: vk::Extent2D,
+    /// Usually the swapchain extent.
+    pub output_extent: vk::Extent2D,
+}
+
+/// Temporal upscaler modelled on FSR 2: jittered frames rendered below the
+/// output resolution are accumulated into a full resolution history. This
+/// is a reimplementation of the technique, not AMD's library, which can be
+/// integrated through the same `Upscaler` trait.
+///
+/// Runs on HDR colors, render with `Camera::set_jitter_phases` and the
+/// `jitter_phases` of the upscaler, relative to the input extent.
+pub struct Fsr2Upscaler {
+    /// Cap of the accumulated history weight in frames, higher is smoother
+    /// but ghosts more.
+    pub max_accumulation: f32,
+
+    input_extent: vk::Extent2D,
+    /// Alternating accumulation targets with the history weight in alpha.
+    history: [Texture; 2],
+    current: usize,
+    history_valid: bool,
+    initialized: bool,
+    previous_view_projection: Option<Mat4>,
+    output: Texture,
+    velocity: Texture,
+    sampler: Arc<Sampler>,
+    velocity_kernel: ComputeKernel,
+    accumulate_kernel: ComputeKernel,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, format: vk::Format, usage: vk::ImageUsageFlags, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl Fsr2Upscaler {
+    pub fn new(renderer: Arc<Renderer>, create_info: Fsr2UpscalerCreateInfo) -> Self {
+        let history_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        let history = [
+            create_target(&renderer, create_info.output_extent, HDR_FORMAT, history_usage, "fsr2 history 0"),
+            create_target(&renderer, create_info.output_extent, HDR_FORMAT, history_usage, "fsr2 history 1"),
+        ];
+        let output_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC;
+        let output = create_target(&renderer, create_info.output_extent, HDR_FORMAT, output_usage, "fsr2 output");
+        let velocity = create_target(&renderer, create_info.input_extent, VELOCITY_FORMAT, vk::ImageUsageFlags::STORAGE, "fsr2 velocity");
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let velocity_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/taa_velocity.spv"),
+            &[BindingType::SampledTexture, BindingType::Texture],
+            VELOCITY_PUSH_CONSTANTS_SIZE);
+        let accumulate_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/fsr2_accumulate.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::SampledTexture, BindingType::Texture, BindingType::Texture],
+            ACCUMULATE_PUSH_CONSTANTS_SIZE);
+
+        Self {
+            max_accumulation: 16.0,
+            input_extent: create_info.input_extent,
+            history,
+            current: 0,
+            history_valid: false,
+            initialized: false,
+            previous_view_projection: None,
+            output,
+            velocity,
+            sampler,
+            velocity_kernel,
+            accumulate_kernel,
+        }
+    }
+}
+
+impl Upscaler for Fsr2Upscaler {
+    fn input_extent(&self) -> vk::Extent2D {
+        self.input_extent
+    }
+
+    fn output_extent(&self) -> vk::Extent2D {
+        let extent = self.output.extent();
+        vk::Extent2D { width: extent.width, height: extent.height }
+    }
+
+    fn hdr_input(&self) -> bool {
+        true
+    }
+
+    /// 8 times the ratio of output to input pixels, so that every output
+    /// pixel gets covered by a few samples, as FSR 2 recommends.
+    fn jitter_phases(&self) -> u64 {
+        let output = self.output_extent();
+        let ratio = output.width as f32 / self.input_extent.width.max(1) as f32;
+        (8.0 * ratio * ratio).ceil().max(8.0) as u64
+    }
+
+    fn reset(&mut self) {
+        self.history_valid = false;
+        self.previous_view_projection = None;
+    }
+
+    fn record(&mut self, command_list: &mut CommandList, inputs: &UpscaleInputs) {
+        if !self.initialized {
+            for texture in &self.history {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            command_list.transition_texture_layout(&self.output, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            command_list.transition_texture_layout(&self.velocity, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            self.initialized = true;
+        }
+
+        command_list.transition_texture_layout(inputs.color, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let velocity = match inputs.motion_vectors {
+            Some(velocity) => velocity,
+            None => {
+                let previous = self.previous_view_projection.unwrap_or(inputs.view_projection);
+                let reprojection = previous * inputs.view_projection.inverse().unwrap_or(Mat4::IDENTITY);
+
+                let mut push_constants = [0u8; VELOCITY_PUSH_CONSTANTS_SIZE as usize];
+                push_constants[0..64].copy_from_slice(&reprojection.to_bytes());
+                push_constants[64..68].copy_from_slice(&inputs.jitter[0].to_ne_bytes());
+                push_constants[68..72].copy_from_slice(&inputs.jitter[1].to_ne_bytes());
+
+                command_list.transition_texture_layout(inputs.depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+                self.velocity_kernel.descriptor_set.write_sampled_texture(0, inputs.depth, &self.sampler);
+                self.velocity_kernel.descriptor_set.write_texture(1, &self.velocity);
+                self.velocity_kernel.dispatch(
+                    command_list,
+                    &push_constants,
+                    self.input_extent.width.div_ceil(WORKGROUP_SIZE),
+                    self.input_extent.height.div_ceil(WORKGROUP_SIZE),
+                    1);
+                command_list.transition_texture_layout(&self.velocity, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+                &self.velocity
+            }
+        };
+
+        let history = self.current;
+        self.current = 1 - self.current;
+
+        // The shader wants the jitter in input pixels, NDC spans two.
+        let jitter = [
+            inputs.jitter[0] * self.input_extent.width as f32 * 0.5,
+            inputs.jitter[1] * self.input_extent.height as f32 * 0.5,
+        ];
+        let mut push_constants = [0u8; ACCUMULATE_PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&jitter[0].to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&jitter[1].to_ne_bytes());
+        push_constants[8..12].copy_from_slice(&self.max_accumulation.max(1.0).to_ne_bytes());
+        push_constants[12..16].copy_from_slice(&(self.history_valid as u32).to_ne_bytes());
+
+        let extent = self.output_extent();
+        self.accumulate_kernel.descriptor_set.write_texture(0, inputs.color);
+        self.accumulate_kernel.descriptor_set.write_texture(1, velocity);
+        self.accumulate_kernel.descriptor_set.write_sampled_texture(2, &self.history[history], &self.sampler);
+        self.accumulate_kernel.descriptor_set.write_texture(3, &self.history[self.current]);
+        self.accumulate_kernel.descriptor_set.write_texture(4, &self.output);
+        self.accumulate_kernel.dispatch(
+            command_list,
+            &push_constants,
+            extent.width.div_ceil(WORKGROUP_SIZE),
+            extent.height.div_ceil(WORKGROUP_SIZE),
+            1);
+        command_list.transition_texture_layout(&self.history[self.current], vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.history_valid = true;
+        self.previous_view_projection = Some(inputs.view_projection);
+    }
+
+    fn output(&self) -> &Texture {
+        &self.output
+    }
+}
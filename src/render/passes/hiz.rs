@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::DescriptorSet;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+pub const HIZ_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+pub struct HiZPassCreateInfo {
+    /// Extent of the depth buffers the pyramid is built from.
+    pub extent: vk::Extent3D,
+}
+
+/// Hierarchical depth pyramid: level 0 is a copy of the depth buffer and
+/// every further level keeps the farthest depth of the texels it covers,
+/// for occlusion culling and screen-space ray marching. Levels are built
+/// with one dispatch each.
+pub struct HiZPass {
+    pyramid: Texture,
+    sampler: Arc<Sampler>,
+    initialized: bool,
+    copy_kernel: ComputeKernel,
+    downsample_kernel: ComputeKernel,
+    /// One per level after the first, each reading the level before.
+    downsample_sets: Vec<Arc<DescriptorSet>>,
+}
+
+impl HiZPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: HiZPassCreateInfo) -> Self {
+        let extent = create_info.extent;
+        let mip_levels = u32::BITS - extent.width.max(extent.height).max(1).leading_zeros();
+
+        let pyramid = {
+            let create_info = TextureCreateInfo {
+                format: HIZ_FORMAT,
+                extent,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels,
+                kind: TextureKind::D2,
+            };
+            let texture = Texture::new(renderer.clone(), create_info);
+            texture.set_name("hi-z pyramid");
+            texture
+        };
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let copy_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/hiz_copy.spv"),
+            &[BindingType::SampledTexture, BindingType::Texture],
+            0);
+        let downsample_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/hiz_downsample.spv"),
+            &[BindingType::Texture, BindingType::Texture],
+            0);
+
+        let downsample_sets = (1..mip_levels).map(|level| {
+            let descriptor_set = downsample_kernel.create_descriptor_set();
+            descriptor_set.write_texture_mip(0, &pyramid, level - 1);
+            descriptor_set.write_texture_mip(1, &pyramid, level);
+            descriptor_set
+        }).collect();
+
+        Self { pyramid, sampler, initialized: false, copy_kernel, downsample_kernel, downsample_sets }
+    }
+
+    /// The pyramid with its full mip chain, in `GENERAL` layout after
+    /// `record`.
+    pub fn pyramid(&self) -> &Texture {
+        &self.pyramid
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.downsample_sets.len() as u32 + 1
+    }
+
+    /// Builds the pyramid from `depth`, which needs `SAMPLED` usage and is
+    /// expected in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, depth: &Texture) {
+        if !self.initialized {
+            command_list.transition_texture_layout(&self.pyramid, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            self.initialized = true;
+        }
+
+        let extent = self.pyramid.extent();
+        let groups = |level: u32| {
+            let width = (extent.width >> level).max(1);
+            let height = (extent.height >> level).max(1);
+            (width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE))
+        };
+
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.copy_kernel.descriptor_set.write_sampled_texture(0, depth, &self.sampler);
+        self.copy_kernel.descriptor_set.write_texture_mip(1, &self.pyramid, 0);
+        let (x, y) = groups(0);
+        self.copy_kernel.dispatch(command_list, &[], x, y, 1);
+
+        for (level, descriptor_set) in (1..).zip(&self.downsample_sets) {
+            command_list.transition_texture_layout(&self.pyramid, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+            let (x, y) = groups(level);
+            self.downsample_kernel.dispatch_with(command_list, descriptor_set.clone(), &[], x, y, 1);
+        }
+        command_list.transition_texture_layout(&self.pyramid, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::DescriptorSet;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::material::ibl_descriptor_layout;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 8;
+const PREFILTER_PUSH_CONSTANTS_SIZE: u32 = 8;
+
+/// GLSL declarations of the `IBL_SET` textures and `ibl_ambient`, to be
+/// pasted into or included by forward shaders.
+pub const IBL_GLSL: &str = include_str!("shaders/ibl.glsl");
+
+pub struct IblPassCreateInfo {
+    /// Face size of the environment cubemap.
+    pub environment_size: u32,
+    pub irradiance_size: u32,
+    /// Face size of the first mip of the prefiltered cubemap.
+    pub prefiltered_size: u32,
+    /// Roughness goes from 0 in the first mip to 1 in the last one.
+    pub prefiltered_mip_levels: u32,
+    pub brdf_lut_size: u32,
+}
+
+/// Bakes an equirectangular HDR environment into a cubemap for the skybox
+/// and the textures for image-based lighting: diffuse irradiance, GGX
+/// prefiltered specular mips and the split-sum BRDF lookup table. Meant to
+/// run at startup or when the environment changes, not every frame.
+pub struct IblPass {
+    /// GGX samples per texel of the prefiltered cubemap.
+    pub sample_count: u32,
+
+    environment: Arc<Texture>,
+    irradiance: Arc<Texture>,
+    prefiltered: Arc<Texture>,
+    brdf_lut: Arc<Texture>,
+    equirect_sampler: Arc<Sampler>,
+    sampler: Arc<Sampler>,
+    descriptor_set: Arc<DescriptorSet>,
+    equirect_kernel: ComputeKernel,
+    irradiance_kernel: ComputeKernel,
+    prefilter_kernel: ComputeKernel,
+    /// One per mip level, they are all dispatched in the same frame.
+    prefilter_sets: Vec<Arc<DescriptorSet>>,
+    brdf_kernel: ComputeKernel,
+    initialized: bool,
+}
+
+fn create_cubemap(renderer: &Arc<Renderer>, size: u32, mip_levels: u32) -> Texture {
+    let create_info = TextureCreateInfo {
+        format: vk::Format::R16G16B16A16_SFLOAT,
+        extent: vk::Extent3D { width: size, height: size, depth: 1 },
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 6,
+        mip_levels,
+        kind: TextureKind::Cube,
+    };
+    Texture::new(renderer.clone(), create_info)
+}
+
+impl IblPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: IblPassCreateInfo) -> Self {
+        let environment = create_cubemap(&renderer, create_info.environment_size, 1);
+        environment.set_name("environment");
+        let irradiance = create_cubemap(&renderer, create_info.irradiance_size, 1);
+        irradiance.set_name("irradiance");
+        let prefiltered = create_cubemap(&renderer, create_info.prefiltered_size, create_info.prefiltered_mip_levels);
+        prefiltered.set_name("prefiltered environment");
+
+        let brdf_lut = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::R16G16_SFLOAT,
+                extent: vk::Extent3D { width: create_info.brdf_lut_size, height: create_info.brdf_lut_size, depth: 1 },
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+        brdf_lut.set_name("brdf lut");
+
+        let equirect_sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::Repeat,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), ibl_descriptor_layout(renderer.clone()));
+
+        let cube_bindings = [BindingType::SampledTexture, BindingType::Texture];
+        let equirect_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/equirect_to_cube.spv"), &cube_bindings, 0);
+        let irradiance_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/irradiance.spv"), &cube_bindings, 0);
+        let prefilter_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/prefilter.spv"), &cube_bindings, PREFILTER_PUSH_CONSTANTS_SIZE);
+        let prefilter_sets = (0..create_info.prefiltered_mip_levels)
+            .map(|_| prefilter_kernel.create_descriptor_set())
+            .collect();
+        let brdf_kernel = ComputeKernel::new(renderer, include_bytes_align_as!(u32, "shaders/brdf_lut.spv"), &[BindingType::Texture], 0);
+
+        Self {
+            sample_count: 1024,
+            environment: Arc::new(environment),
+            irradiance: Arc::new(irradiance),
+            prefiltered: Arc::new(prefiltered),
+            brdf_lut: Arc::new(brdf_lut),
+            equirect_sampler,
+            sampler,
+            descriptor_set,
+            equirect_kernel,
+            irradiance_kernel,
+            prefilter_kernel,
+            prefilter_sets,
+            brdf_kernel,
+            initialized: false,
+        }
+    }
+
+    /// Environment cubemap, for the skybox pass.
+    pub fn environment(&self) -> Arc<Texture> {
+        self.environment.clone()
+    }
+
+    pub fn irradiance(&self) -> Arc<Texture> {
+        self.irradiance.clone()
+    }
+
+    pub fn prefiltered(&self) -> Arc<Texture> {
+        self.prefiltered.clone()
+    }
+
+    pub fn brdf_lut(&self) -> Arc<Texture> {
+        self.brdf_lut.clone()
+    }
+
+    /// Linear clamping sampler for all the baked textures.
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.sampler.clone()
+    }
+
+    /// Writes the `IBL_SET` descriptor set of the current frame, call once
+    /// per frame before binding `descriptor_set`.
+    pub fn prepare(&self) {
+        self.descriptor_set.write_sampled_texture(0, &self.irradiance, &self.sampler);
+        self.descriptor_set.write_sampled_texture(1, &self.prefiltered, &self.sampler);
+        self.descriptor_set.write_sampled_texture(2, &self.brdf_lut, &self.sampler);
+    }
+
+    pub fn descriptor_set(&self) -> Arc<DescriptorSet> {
+        self.descriptor_set.clone()
+    }
+
+    /// Records the bake of `source`, an equirectangular HDR texture with
+    /// `SAMPLED` usage in `GENERAL` layout. Can be recorded at most once
+    /// per frame.
+    pub fn record(&mut self, command_list: &mut CommandList, source: &Texture) {
+        if !self.initialized {
+            for texture in [&self.environment, &self.irradiance, &self.prefiltered, &self.brdf_lut] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+
+            // Doesn't depend on the environment, only computed once.
+            self.brdf_kernel.descriptor_set.write_texture(0, &self.brdf_lut);
+            let size = self.brdf_lut.extent();
+            self.brdf_kernel.dispatch(command_list, &[], size.width.div_ceil(WORKGROUP_SIZE), size.height.div_ceil(WORKGROUP_SIZE), 1);
+            self.initialized = true;
+        }
+
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.equirect_kernel.descriptor_set.write_sampled_texture(0, source, &self.equirect_sampler);
+        self.equirect_kernel.descriptor_set.write_texture_mip(1, &self.environment, 0);
+        let size = self.environment.extent().width.div_ceil(WORKGROUP_SIZE);
+        self.equirect_kernel.dispatch(command_list, &[], size, size, 6);
+
+        command_list.transition_texture_layout(&self.environment, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.irradiance_kernel.descriptor_set.write_sampled_texture(0, &self.environment, &self.sampler);
+        self.irradiance_kernel.descriptor_set.write_texture_mip(1, &self.irradiance, 0);
+        let size = self.irradiance.extent().width.div_ceil(WORKGROUP_SIZE);
+        self.irradiance_kernel.dispatch(command_list, &[], size, size, 6);
+
+        let mip_levels = self.prefiltered.mip_levels();
+        for (level, descriptor_set) in self.prefilter_sets.iter().enumerate() {
+            let level = level as u32;
+            descriptor_set.write_sampled_texture(0, &self.environment, &self.sampler);
+            descriptor_set.write_texture_mip(1, &self.prefiltered, level);
+
+            let roughness = level as f32 / (mip_levels - 1).max(1) as f32;
+            let mut push_constants = [0u8; PREFILTER_PUSH_CONSTANTS_SIZE as usize];
+            push_constants[0..4].copy_from_slice(&roughness.to_ne_bytes());
+            push_constants[4..8].copy_from_slice(&self.sample_count.to_ne_bytes());
+
+            let size = (self.prefiltered.extent().width >> level).max(1).div_ceil(WORKGROUP_SIZE);
+            self.prefilter_kernel.dispatch_with(command_list, descriptor_set.clone(), &push_constants, size, size, 6);
+        }
+
+        for texture in [&self.irradiance, &self.prefiltered, &self.brdf_lut] {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{Arc, Mutex};
+
+use crate::render::hal::{BindingType, ComputePipelineCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, PipelineLayoutCreateInfo, PushConstantRange, ShaderCreateInfo, ShaderStages, SubgroupOperations};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::pipeline::{ComputePipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+
+/// Compute pipeline with a single descriptor set whose bindings are
+/// numbered in the order they are given, shared by the built-in passes.
+pub(crate) struct ComputeKernel {
+    pub(crate) descriptor_set: Arc<DescriptorSet>,
+
+    renderer: Arc<Renderer>,
+    descriptor_layout: Arc<DescriptorSetLayout>,
+    pipeline: Arc<ComputePipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    transient_sets: Mutex<TransientSets>,
+}
+
+#[derive(Default)]
+struct TransientSets {
+    frame_index: u64,
+    used: usize,
+    sets: Vec<Arc<DescriptorSet>>,
+}
+
+impl ComputeKernel {
+    pub(crate) fn new(renderer: Arc<Renderer>, code: &'static [u32], bindings: &[BindingType], push_constants_size: u32) -> Self {
+        let descriptor_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: bindings.iter().enumerate().map(|(binding, &typ)| DescriptorSetBinding {
+                    stage: ShaderStages::Compute,
+                    typ,
+                    binding: binding as u32,
+                }).collect(),
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_layout.clone());
+
+        let shader = Shader::new(renderer.clone(), ShaderCreateInfo { code });
+
+        let pipeline_layout = {
+            let push_constant_ranges = if push_constants_size > 0 {
+                vec![PushConstantRange {
+                    stage: ShaderStages::Compute,
+                    offset: 0,
+                    size: push_constants_size,
+                }]
+            } else {
+                vec![]
+            };
+
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_layout.clone()],
+                push_constant_ranges,
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let create_info = ComputePipelineCreateInfo {
+                shader,
+                pipeline_layout: pipeline_layout.clone(),
+                entrypoint: c"main",
+                subgroup_operations: SubgroupOperations::empty(),
+            };
+            ComputePipeline::new(renderer.clone(), create_info)
+        };
+
+        Self { descriptor_set, renderer, descriptor_layout, pipeline, pipeline_layout, transient_sets: Mutex::default() }
+    }
+
+    /// Additional set with the kernel's layout. Sets can't be rewritten
+    /// after being recorded, so dispatching the kernel several times per
+    /// frame with different bindings takes one set per dispatch.
+    pub(crate) fn create_descriptor_set(&self) -> Arc<DescriptorSet> {
+        DescriptorSet::new(self.renderer.clone(), self.descriptor_layout.clone())
+    }
+
+    /// Set for a single dispatch in the current frame. The sets are
+    /// recycled on the next frame, for kernels dispatched a varying number
+    /// of times per frame.
+    pub(crate) fn transient_descriptor_set(&self) -> Arc<DescriptorSet> {
+        let mut transient = self.transient_sets.lock().unwrap();
+        let frame_index = self.renderer.frame_index();
+        if transient.frame_index != frame_index {
+            transient.frame_index = frame_index;
+            transient.used = 0;
+        }
+
+        if transient.used == transient.sets.len() {
+            let set = self.create_descriptor_set();
+            transient.sets.push(set);
+        }
+        transient.used += 1;
+        transient.sets[transient.used - 1].clone()
+    }
+
+    pub(crate) fn dispatch(&self, command_list: &mut CommandList, push_constants: &[u8], x: u32, y: u32, z: u32) {
+        self.dispatch_with(command_list, self.descriptor_set.clone(), push_constants, x, y, z);
+    }
+
+    pub(crate) fn dispatch_with(&self, command_list: &mut CommandList, descriptor_set: Arc<DescriptorSet>, push_constants: &[u8], x: u32, y: u32, z: u32) {
+        command_list.bind_compute_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, descriptor_set);
+        if !push_constants.is_empty() {
+            command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Compute, 0, push_constants);
+        }
+        command_list.dispatch_compute_pipeline(x, y, z);
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, MemoryLocation, ShaderStages};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::{Mat4, Vec3};
+use crate::render::passes::kernel::ComputeKernel;
+
+pub const CLUSTERS_X: u32 = 16;
+pub const CLUSTERS_Y: u32 = 9;
+pub const CLUSTERS_Z: u32 = 24;
+pub const CLUSTER_COUNT: u32 = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+/// Lights past this count are dropped from a cluster.
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+
+const WORKGROUP_SIZE: u32 = 64;
+const PUSH_CONSTANTS_SIZE: u32 = 76;
+
+/// GLSL declarations of the cluster buffers and the lookup helpers, to be
+/// pasted into or included by forward shaders.
+pub const CLUSTERED_GLSL: &str = include_str!("shaders/clustered.glsl");
+
+/// Matches `PointLight` in `CLUSTERED_GLSL`, std430.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+    /// Distance where the light's contribution reaches zero.
+    pub radius: f32,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl PointLight {
+    fn to_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        let values = [self.position[0], self.position[1], self.position[2], self.radius, self.color[0], self.color[1], self.color[2], self.intensity];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+/// Matches the `ClusterData` struct of `CLUSTERED_GLSL`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ClusterData {
+    pub screen_size: [f32; 2],
+    pub near: f32,
+    pub far: f32,
+}
+
+impl ClusterData {
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip([self.screen_size[0], self.screen_size[1], self.near, self.far]) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        bytes
+    }
+}
+
+/// Layout of the descriptor set forward shaders read the clusters from:
+/// lights, cluster grid and light indices, as in `CLUSTERED_GLSL`.
+pub fn cluster_descriptor_layout(renderer: Arc<Renderer>) -> Arc<DescriptorSetLayout> {
+    let create_info = DescriptorSetLayoutCreateInfo {
+        bindings: (0..3).map(|binding| DescriptorSetBinding {
+            stage: ShaderStages::Fragment,
+            typ: BindingType::StorageBuffer,
+            binding,
+        }).collect(),
+        push_descriptor: false,
+    };
+    DescriptorSetLayout::new(renderer, create_info)
+}
+
+pub struct LightCullPassCreateInfo {
+    pub extent: vk::Extent2D,
+    pub max_lights: u32,
+    pub near: f32,
+    /// View distance covered by the clusters, lights further away are culled.
+    pub far: f32,
+}
+
+/// Sorts point lights into a grid of view space clusters with a compute
+/// shader, so forward shaders only evaluate the lights that can reach a
+/// fragment. Depth slices grow exponentially between `near` and `far`.
+pub struct LightCullPass {
+    pub extent: vk::Extent2D,
+    pub near: f32,
+    pub far: f32,
+
+    renderer: Arc<Renderer>,
+    max_lights: u32,
+    lights: Vec<Buffer>,
+    clusters: Buffer,
+    light_indices: Buffer,
+    descriptor_set: Arc<DescriptorSet>,
+    kernel: ComputeKernel,
+}
+
+impl LightCullPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: LightCullPassCreateInfo) -> Self {
+        let lights = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: (create_info.max_lights.max(1) as usize * size_of::<PointLight>()) as u64,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        let clusters = {
+            let create_info = BufferCreateInfo {
+                size: CLUSTER_COUNT as u64 * 8,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        let light_indices = {
+            let create_info = BufferCreateInfo {
+                size: CLUSTER_COUNT as u64 * MAX_LIGHTS_PER_CLUSTER as u64 * 4,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), cluster_descriptor_layout(renderer.clone()));
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/light_cull.spv"),
+            &[BindingType::StorageBuffer, BindingType::StorageBuffer, BindingType::StorageBuffer],
+            PUSH_CONSTANTS_SIZE);
+
+        Self {
+            extent: create_info.extent,
+            near: create_info.near,
+            far: create_info.far,
+            renderer,
+            max_lights: create_info.max_lights,
+            lights,
+            clusters,
+            light_indices,
+            descriptor_set,
+            kernel,
+        }
+    }
+
+    /// Values for the `ClusterData` of forward shaders.
+    pub fn cluster_data(&self) -> ClusterData {
+        ClusterData {
+            screen_size: [self.extent.width as f32, self.extent.height as f32],
+            near: self.near,
+            far: self.far,
+        }
+    }
+
+    /// Set for `cluster_descriptor_layout`, valid for the frame `record` was
+    /// last called in.
+    pub fn descriptor_set(&self) -> Arc<DescriptorSet> {
+        self.descriptor_set.clone()
+    }
+
+    /// Writes the lights, cluster grid and light indices of the current
+    /// frame to three consecutive bindings, for kernels shading with them.
+    pub(crate) fn write_clusters(&self, descriptor_set: &DescriptorSet, first_binding: u32) {
+        descriptor_set.write_storage_buffer(first_binding, &self.lights[self.renderer.current_frame()]);
+        descriptor_set.write_storage_buffer(first_binding + 1, &self.clusters);
+        descriptor_set.write_storage_buffer(first_binding + 2, &self.light_indices);
+    }
+
+    /// Uploads `lights`, given in world space, and records the culling
+    /// dispatch. Lights past `max_lights` are ignored. Forward shading
+    /// recorded after this can read the clusters.
+    pub fn record(&mut self, command_list: &mut CommandList, lights: &[PointLight], view: Mat4, projection: Mat4) {
+        let light_count = lights.len().min(self.max_lights as usize);
+        let data = lights[..light_count].iter()
+            .flat_map(|light| PointLight { position: view.transform_point(light.position), ..*light }.to_bytes())
+            .collect::<Vec<_>>();
+
+        let lights = &mut self.lights[self.renderer.current_frame()];
+        lights.write(0, &data);
+
+        self.kernel.descriptor_set.write_storage_buffer(0, lights);
+        self.kernel.descriptor_set.write_storage_buffer(1, &self.clusters);
+        self.kernel.descriptor_set.write_storage_buffer(2, &self.light_indices);
+
+        self.descriptor_set.write_storage_buffer(0, lights);
+        self.descriptor_set.write_storage_buffer(1, &self.clusters);
+        self.descriptor_set.write_storage_buffer(2, &self.light_indices);
+
+        let inverse_projection = projection.inverse().unwrap_or(Mat4::IDENTITY);
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..64].copy_from_slice(&inverse_projection.to_bytes());
+        push_constants[64..68].copy_from_slice(&self.near.to_ne_bytes());
+        push_constants[68..72].copy_from_slice(&self.far.to_ne_bytes());
+        push_constants[72..76].copy_from_slice(&(light_count as u32).to_ne_bytes());
+
+        // The previous frame's shading may still read the grid.
+        command_list.memory_barrier();
+        self.kernel.dispatch(command_list, &push_constants, CLUSTER_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+        command_list.memory_barrier();
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BufferCreateInfo, MemoryLocation};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::{Mat4, Vec3};
+use crate::render::mesh::Mesh;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 64;
+const PUSH_CONSTANTS_SIZE: u32 = 80;
+/// Matches `Meshlet` in `meshlet_cull.comp`, std430.
+const MESHLET_SIZE: usize = 64;
+const DRAW_STRIDE: u32 = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+pub struct MeshletCullPassCreateInfo {
+    /// Built with `MeshBuilder::meshlets`.
+    pub mesh: Arc<Mesh>,
+}
+
+/// GPU-driven drawing of a mesh split into meshlets: a compute shader
+/// tests every meshlet against the view frustum and its normal cone and
+/// writes one indexed indirect draw per meshlet, with no instances for
+/// those culled.
+///
+/// `record` runs the culling and has to be recorded outside of rendering,
+/// `draw` is recorded inside `begin_rendering` with the mesh's pipeline
+/// and push constants bound. Both draw the mesh once per frame.
+pub struct MeshletCullPass {
+    mesh: Arc<Mesh>,
+    meshlet_count: u32,
+    _meshlets: Buffer,
+    draw_args: Arc<Buffer>,
+    kernel: ComputeKernel,
+}
+
+impl MeshletCullPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: MeshletCullPassCreateInfo) -> Self {
+        let mesh = create_info.mesh;
+        assert!(!mesh.meshlets().is_empty(), "Mesh was built without meshlets");
+
+        let mut data = Vec::new();
+        for (submesh, meshlets) in mesh.submeshes().iter().zip(mesh.meshlets()) {
+            for meshlet in &meshlets.meshlets {
+                let bounds = meshlet.bounds;
+                let [cx, cy, cz] = bounds.center;
+                let [px, py, pz] = bounds.cone_apex;
+                let [ax, ay, az] = bounds.cone_axis;
+                let floats = [cx, cy, cz, bounds.radius, px, py, pz, bounds.cone_cutoff, ax, ay, az];
+                data.extend(floats.iter().flat_map(|v| v.to_ne_bytes()));
+                let first_index = submesh.indices.start + meshlet.triangle_offset * 3;
+                let words = [first_index, meshlet.triangle_count * 3, submesh.base_vertex as u32, 0, 0];
+                data.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+            }
+        }
+        let meshlet_count = (data.len() / MESHLET_SIZE) as u32;
+
+        let mut meshlets = {
+            let create_info = BufferCreateInfo {
+                size: data.len().max(MESHLET_SIZE) as u64,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+        meshlets.write(0, &data);
+        meshlets.set_name("meshlets");
+
+        let draw_args = {
+            let create_info = BufferCreateInfo {
+                size: meshlet_count.max(1) as u64 * DRAW_STRIDE as u64,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/meshlet_cull.spv"),
+            &[BindingType::StorageBuffer, BindingType::StorageBuffer],
+            PUSH_CONSTANTS_SIZE);
+        kernel.descriptor_set.write_storage_buffer(0, &meshlets);
+        kernel.descriptor_set.write_storage_buffer(1, &draw_args);
+
+        Self { mesh, meshlet_count, _meshlets: meshlets, draw_args: Arc::new(draw_args), kernel }
+    }
+
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+
+    pub fn meshlet_count(&self) -> u32 {
+        self.meshlet_count
+    }
+
+    /// Indexed indirect draws of every meshlet, written by `record`.
+    pub fn draw_args(&self) -> &Arc<Buffer> {
+        &self.draw_args
+    }
+
+    /// Culls the meshlets of the mesh drawn with `model` for a camera at
+    /// `camera_position` in world space.
+    pub fn record(&self, command_list: &mut CommandList, model: Mat4, view_projection: Mat4, camera_position: Vec3) {
+        let camera_position = model.inverse().unwrap_or(Mat4::IDENTITY).transform_point(camera_position);
+
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..64].copy_from_slice(&(view_projection * model).to_bytes());
+        for (chunk, value) in push_constants[64..76].chunks_exact_mut(4).zip(camera_position) {
+            chunk.copy_from_slice(&value.to_ne_bytes());
+        }
+        push_constants[76..80].copy_from_slice(&self.meshlet_count.to_ne_bytes());
+
+        // The previous frame's draws may still read the arguments.
+        command_list.memory_barrier();
+        self.kernel.dispatch(command_list, &push_constants, self.meshlet_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        command_list.memory_barrier();
+    }
+
+    /// Binds the mesh and draws the meshlets that passed the last `record`.
+    pub fn draw(&self, command_list: &mut CommandList) {
+        self.mesh.bind(command_list);
+        command_list.draw_indexed_indirect(self.draw_args.clone(), 0, self.meshlet_count, DRAW_STRIDE);
+    }
+}
@@ -0,0 +1,39 @@
+use ash::vk;
+
+pub mod algorithms;
+pub mod atmosphere;
+pub mod checkerboard;
+pub mod decals;
+pub mod deferred;
+pub mod dof;
+pub mod exposure;
+pub mod fog;
+#[cfg(feature = "fsr2")]
+pub mod fsr2;
+pub mod hiz;
+pub mod ibl;
+pub(crate) mod kernel;
+pub mod light_cull;
+pub mod meshlet_cull;
+pub mod motion_blur;
+pub mod occlusion;
+#[cfg(feature = "particles")]
+pub mod particles;
+#[cfg(feature = "ray-tracing")]
+pub mod path_tracer;
+pub mod picking;
+#[cfg(feature = "ray-tracing")]
+pub mod rt_shadows;
+#[cfg(feature = "ray-tracing")]
+pub mod rtao;
+pub mod shadow;
+pub mod skybox;
+pub mod ssao;
+pub mod taa;
+pub mod tonemap;
+pub mod ui;
+pub mod upscale;
+
+/// Linear HDR scene color, from lighting through the post processing
+/// passes. Only `tonemap` encodes for the display.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
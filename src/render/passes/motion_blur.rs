@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::taa::VELOCITY_FORMAT;
+
+const TILE_WORKGROUP_SIZE: u32 = 8;
+const PIXEL_WORKGROUP_SIZE: u32 = 16;
+const TILE_PUSH_CONSTANTS_SIZE: u32 = 16;
+const GATHER_PUSH_CONSTANTS_SIZE: u32 = 32;
+/// Side of the velocity tiles in pixels, also the longest blur.
+pub const TILE_SIZE: u32 = 16;
+
+pub struct MotionBlurPassCreateInfo {
+    pub extent: vk::Extent2D,
+}
+
+/// Motion blur from the velocity buffer, after McGuire et al.'s "A
+/// Reconstruction Filter for Plausible Motion Blur". The largest motion of
+/// every tile and of its neighbors bounds the blur reaching a pixel, which
+/// then gathers samples along it weighted by depth and velocity, so moving
+/// objects blur over a sharp background and the other way around.
+///
+/// Runs on the HDR target before tonemapping, after TAA when both are used.
+pub struct MotionBlurPass {
+    /// Fraction of the frame time the virtual shutter is open, 0.5 is a
+    /// 180 degree shutter.
+    pub shutter: f32,
+    /// Samples gathered per blurred pixel.
+    pub samples: u32,
+    /// View distance over which a sample goes from in front of a pixel to
+    /// behind it.
+    pub soft_depth_extent: f32,
+
+    tile_max: Texture,
+    neighbor_max: Texture,
+    output: Texture,
+    sampler: Arc<Sampler>,
+    tile_max_kernel: ComputeKernel,
+    neighbor_max_kernel: ComputeKernel,
+    gather_kernel: ComputeKernel,
+    frame: u32,
+    initialized: bool,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, format: vk::Format, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl MotionBlurPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: MotionBlurPassCreateInfo) -> Self {
+        let extent = create_info.extent;
+        let tiles = vk::Extent2D { width: extent.width.div_ceil(TILE_SIZE), height: extent.height.div_ceil(TILE_SIZE) };
+        let tile_max = create_target(&renderer, tiles, VELOCITY_FORMAT, "motion blur tile max");
+        let neighbor_max = create_target(&renderer, tiles, VELOCITY_FORMAT, "motion blur neighbor max");
+        let output = create_target(&renderer, extent, HDR_FORMAT, "motion blur output");
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let tile_max_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/motion_blur_tile_max.spv"),
+            &[BindingType::Texture, BindingType::Texture],
+            TILE_PUSH_CONSTANTS_SIZE);
+        let neighbor_max_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/motion_blur_neighbor_max.spv"),
+            &[BindingType::Texture, BindingType::Texture],
+            0);
+        let gather_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/motion_blur_gather.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            GATHER_PUSH_CONSTANTS_SIZE);
+        neighbor_max_kernel.descriptor_set.write_texture(0, &tile_max);
+        neighbor_max_kernel.descriptor_set.write_texture(1, &neighbor_max);
+
+        Self {
+            shutter: 0.5,
+            samples: 12,
+            soft_depth_extent: 1.0,
+            tile_max,
+            neighbor_max,
+            output,
+            sampler,
+            tile_max_kernel,
+            neighbor_max_kernel,
+            gather_kernel,
+            frame: 0,
+            initialized: false,
+        }
+    }
+
+    /// Result of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    /// Blurs `source`, an `rgba16f` HDR target, along `velocity`, in
+    /// `VELOCITY_FORMAT` as `TaaPass` expects it, or the camera motion
+    /// `TaaPass::velocity` generated from depth. `depth` needs `SAMPLED`
+    /// usage and comes from a reverse-Z projection with the `near` plane.
+    /// All three are expected in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, source: &Texture, velocity: &Texture, depth: &Texture, near: f32) {
+        if !self.initialized {
+            for texture in [&self.tile_max, &self.neighbor_max, &self.output] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            self.initialized = true;
+        }
+
+        let extent = self.output.extent();
+        let tiles = self.tile_max.extent();
+        let velocity_scale = [extent.width as f32 * self.shutter, extent.height as f32 * self.shutter];
+        let max_radius = TILE_SIZE as f32;
+
+        let words = [velocity_scale[0].to_bits(), velocity_scale[1].to_bits(), max_radius.to_bits(), TILE_SIZE];
+        let push_constants: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        self.tile_max_kernel.descriptor_set.write_texture(0, velocity);
+        self.tile_max_kernel.descriptor_set.write_texture(1, &self.tile_max);
+        command_list.transition_texture_layout(velocity, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.tile_max_kernel.dispatch(command_list, &push_constants, tiles.width.div_ceil(TILE_WORKGROUP_SIZE), tiles.height.div_ceil(TILE_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.tile_max, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.neighbor_max_kernel.dispatch(command_list, &[], tiles.width.div_ceil(TILE_WORKGROUP_SIZE), tiles.height.div_ceil(TILE_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.neighbor_max, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let words = [
+            velocity_scale[0].to_bits(),
+            velocity_scale[1].to_bits(),
+            max_radius.to_bits(),
+            TILE_SIZE,
+            self.samples.max(1),
+            near.to_bits(),
+            self.soft_depth_extent.max(0.001).to_bits(),
+            self.frame,
+        ];
+        let push_constants: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        self.frame = self.frame.wrapping_add(1);
+
+        self.gather_kernel.descriptor_set.write_texture(0, source);
+        self.gather_kernel.descriptor_set.write_texture(1, velocity);
+        self.gather_kernel.descriptor_set.write_texture(2, &self.neighbor_max);
+        self.gather_kernel.descriptor_set.write_sampled_texture(3, depth, &self.sampler);
+        self.gather_kernel.descriptor_set.write_texture(4, &self.output);
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.gather_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(PIXEL_WORKGROUP_SIZE), extent.height.div_ceil(PIXEL_WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
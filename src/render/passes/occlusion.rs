@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, Filter, MemoryLocation, SamplerCreateInfo};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::instancing::InstanceData;
+use crate::render::math::Mat4;
+use crate::render::mesh::Mesh;
+use crate::render::passes::hiz::{HiZPass, HiZPassCreateInfo};
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 64;
+const PUSH_CONSTANTS_SIZE: u32 = 72;
+/// Matches `Instance` in `occlusion_cull.comp`, std430.
+const INSTANCE_SIZE: u64 = 32;
+const DRAW_STRIDE: u32 = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+pub struct OcclusionCullPassCreateInfo {
+    /// Indexed with positions, drawn with an instance stream as laid out by
+    /// `VertexLayout::with_instance_stream`.
+    pub mesh: Arc<Mesh>,
+    pub max_instances: u32,
+    /// Extent of the depth buffer the instances are drawn into.
+    pub extent: vk::Extent3D,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OcclusionInstance {
+    pub transform: Mat4,
+    /// Index into the mesh's submeshes. The instance is culled with the
+    /// bounds of the whole mesh.
+    pub submesh: u32,
+}
+
+/// Two-phase occlusion culling of mesh instances with indirect draws:
+///
+/// ```ignore
+/// occlusion.set_instances(&instances);
+/// occlusion.record_first(command_list, view_projection);
+/// // Draw the instances visible last frame, filling the depth buffer.
+/// command_list.begin_rendering(&color, Some(&depth), clear);
+/// occlusion.draw_first(command_list);
+/// command_list.end_rendering();
+/// // Test the rest against a Hi-Z pyramid of that depth and draw those
+/// // that became visible.
+/// occlusion.record_second(command_list, &depth, view_projection);
+/// command_list.begin_rendering_attachments(&load_colors, Some(load_depth));
+/// occlusion.draw_second(command_list);
+/// command_list.end_rendering();
+/// ```
+///
+/// Draws are recorded with the material's pipeline bound and the instance
+/// index as `first_instance`, so the instanced mesh shaders read the
+/// instance transform. The visibility kept for the next frame is indexed by
+/// instance, reordering instances costs a frame of extra second phase draws.
+pub struct OcclusionCullPass {
+    renderer: Arc<Renderer>,
+    mesh: Arc<Mesh>,
+    max_instances: u32,
+    instance_count: u32,
+    /// `InstanceData` of every instance, per frame in flight.
+    transforms: Vec<Arc<Buffer>>,
+    /// Bounding sphere and draw of every instance, per frame in flight.
+    instances: Vec<Buffer>,
+    /// Non-zero for instances visible in the last second phase.
+    visibility: Buffer,
+    first_draws: Arc<Buffer>,
+    second_draws: Arc<Buffer>,
+    hiz: HiZPass,
+    sampler: Arc<Sampler>,
+    kernel: ComputeKernel,
+    initialized: bool,
+}
+
+fn transform_buffer(renderer: &Arc<Renderer>, max_instances: u32) -> Arc<Buffer> {
+    let create_info = BufferCreateInfo {
+        size: max_instances as u64 * size_of::<InstanceData>() as u64,
+        usage: vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        location: MemoryLocation::CpuToGpu,
+    };
+    Arc::new(Buffer::new(renderer.clone(), create_info))
+}
+
+impl OcclusionCullPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: OcclusionCullPassCreateInfo) -> Self {
+        assert!(create_info.mesh.is_indexed(), "Occlusion culled meshes need indices");
+        assert!(!create_info.mesh.bounds().is_empty(), "Occlusion culled meshes need positions for their bounds");
+        let max_instances = create_info.max_instances.max(1);
+
+        let buffer = |size: u64, usage: vk::BufferUsageFlags, location: MemoryLocation| {
+            let create_info = BufferCreateInfo { size, usage, location };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        let transforms = (0..FRAME_OVERLAP).map(|_| transform_buffer(&renderer, max_instances)).collect();
+        let instances = (0..FRAME_OVERLAP)
+            .map(|_| buffer(max_instances as u64 * INSTANCE_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER, MemoryLocation::CpuToGpu))
+            .collect();
+        let visibility = buffer(max_instances as u64 * 4, vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly);
+        let draws_usage = vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER;
+        let first_draws = Arc::new(buffer(max_instances as u64 * DRAW_STRIDE as u64, draws_usage, MemoryLocation::GpuOnly));
+        let second_draws = Arc::new(buffer(max_instances as u64 * DRAW_STRIDE as u64, draws_usage, MemoryLocation::GpuOnly));
+        visibility.set_name("occlusion visibility");
+
+        let hiz = HiZPass::new(renderer.clone(), HiZPassCreateInfo { extent: create_info.extent });
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/occlusion_cull.spv"),
+            &[BindingType::StorageBuffer, BindingType::StorageBuffer, BindingType::StorageBuffer, BindingType::SampledTexture],
+            PUSH_CONSTANTS_SIZE);
+
+        Self {
+            renderer,
+            mesh: create_info.mesh,
+            max_instances,
+            instance_count: 0,
+            transforms,
+            instances,
+            visibility,
+            first_draws,
+            second_draws,
+            hiz,
+            sampler,
+            kernel,
+            initialized: false,
+        }
+    }
+
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// The pyramid built by the last `record_second`.
+    pub fn hiz(&self) -> &HiZPass {
+        &self.hiz
+    }
+
+    /// Uploads this frame's instances, at most `max_instances`.
+    pub fn set_instances(&mut self, instances: &[OcclusionInstance]) {
+        assert!(instances.len() <= self.max_instances as usize, "More instances than max_instances");
+
+        let bounds = self.mesh.bounds();
+        let mut transforms = Vec::with_capacity(instances.len() * 64);
+        let mut data = Vec::with_capacity(instances.len() * INSTANCE_SIZE as usize);
+        for instance in instances {
+            let submesh = &self.mesh.submeshes()[instance.submesh as usize];
+            let sphere = bounds.transform(&instance.transform).bounding_sphere();
+            transforms.extend(instance.transform.to_bytes());
+
+            let [x, y, z] = sphere.center;
+            data.extend([x, y, z, sphere.radius].iter().flat_map(|v| v.to_ne_bytes()));
+            let words = [submesh.indices.end - submesh.indices.start, submesh.indices.start, submesh.base_vertex as u32, 0];
+            data.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+        }
+
+        let frame = self.renderer.current_frame();
+        let slot = &mut self.transforms[frame];
+        // Replace the buffer when a command list not reset yet retains it.
+        if Arc::get_mut(slot).is_none() {
+            *slot = transform_buffer(&self.renderer, self.max_instances);
+        }
+        Arc::get_mut(slot).unwrap().write(0, &transforms);
+        self.instances[frame].write(0, &data);
+        self.instance_count = instances.len() as u32;
+    }
+
+    fn dispatch(&mut self, command_list: &mut CommandList, view_projection: Mat4, phase: u32) {
+        let frame = self.renderer.current_frame();
+        let draws = if phase == 0 { &self.first_draws } else { &self.second_draws };
+        let descriptor_set = self.kernel.transient_descriptor_set();
+        descriptor_set.write_storage_buffer(0, &self.instances[frame]);
+        descriptor_set.write_storage_buffer(1, &self.visibility);
+        descriptor_set.write_storage_buffer(2, draws);
+        descriptor_set.write_sampled_texture(3, self.hiz.pyramid(), &self.sampler);
+
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..64].copy_from_slice(&view_projection.to_bytes());
+        push_constants[64..68].copy_from_slice(&self.instance_count.to_ne_bytes());
+        push_constants[68..72].copy_from_slice(&phase.to_ne_bytes());
+
+        // The previous frame's draws may still read the arguments.
+        command_list.memory_barrier();
+        self.kernel.dispatch_with(command_list, descriptor_set, &push_constants, self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        command_list.memory_barrier();
+    }
+
+    /// Selects the instances inside the frustum that were visible last
+    /// frame. Recorded outside of rendering.
+    pub fn record_first(&mut self, command_list: &mut CommandList, view_projection: Mat4) {
+        if !self.initialized {
+            command_list.fill_buffer(&self.visibility, 0, vk::WHOLE_SIZE, 0);
+            command_list.memory_barrier();
+            self.initialized = true;
+        }
+        self.dispatch(command_list, view_projection, 0);
+    }
+
+    /// Builds the Hi-Z pyramid from `depth`, as in `HiZPass::record`, and
+    /// selects the visible instances the first phase didn't draw.
+    /// Recorded outside of rendering.
+    pub fn record_second(&mut self, command_list: &mut CommandList, depth: &Texture, view_projection: Mat4) {
+        self.hiz.record(command_list, depth);
+        self.dispatch(command_list, view_projection, 1);
+    }
+
+    pub fn draw_first(&self, command_list: &mut CommandList) {
+        self.draw(command_list, self.first_draws.clone());
+    }
+
+    pub fn draw_second(&self, command_list: &mut CommandList) {
+        self.draw(command_list, self.second_draws.clone());
+    }
+
+    fn draw(&self, command_list: &mut CommandList, draws: Arc<Buffer>) {
+        let transforms = &self.transforms[self.renderer.current_frame()];
+        self.mesh.bind(command_list);
+        command_list.bind_vertex_buffers(self.mesh.layout().vertex_stream_count() as u32, std::slice::from_ref(transforms));
+        command_list.draw_indexed_indirect(draws, 0, self.instance_count, DRAW_STRIDE);
+    }
+}
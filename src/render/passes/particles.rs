@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use ash::vk;
+use slotmap::{new_key_type, SlotMap};
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BlendMode, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, GraphicsPipelineCreateInfo, MemoryLocation, PipelineLayoutCreateInfo, PushConstantRange, RasterState, ShaderCreateInfo, ShaderStages, VertexLayout};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::{Mat4, Vec3};
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 64;
+/// Size of `Particle` in the shaders, std430.
+const PARTICLE_SIZE: u64 = 80;
+const EMIT_PUSH_CONSTANTS_SIZE: u32 = 88;
+const SIMULATE_PUSH_CONSTANTS_SIZE: u32 = 24;
+const SORT_PUSH_CONSTANTS_SIZE: u32 = 24;
+const DRAW_PUSH_CONSTANTS_SIZE: u32 = 96;
+/// Two triangles per particle.
+const VERTICES_PER_PARTICLE: u32 = 6;
+
+new_key_type! {
+    pub struct EmitterHandle;
+}
+
+/// Spawns particles at a point, in a cone around `direction`. Speed and
+/// lifetime are picked uniformly between the two values.
+#[derive(Clone, Debug)]
+pub struct ParticleEmitter {
+    pub position: Vec3,
+    pub direction: Vec3,
+    /// Half angle of the emission cone in radians.
+    pub spread: f32,
+    pub speed: [f32; 2],
+    pub lifetime: [f32; 2],
+    pub size: f32,
+    /// Color at birth, blended towards `end_color` over the lifetime.
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    /// Particles per second.
+    pub rate: f32,
+}
+
+struct EmitterState {
+    emitter: ParticleEmitter,
+    /// Particles owed to the emitter, the fraction carries over to the next frame.
+    pending: f32,
+}
+
+pub struct ParticleSystemCreateInfo {
+    pub max_particles: u32,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// `Alpha` sorts the particles back to front, `Additive` doesn't need to.
+    pub blend: BlendMode,
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_ne_bytes()).collect()
+}
+
+/// GPU particles. Emission and simulation run in compute shaders over a
+/// fixed pool of particles: dead slots are kept in a free list that
+/// emitters take from, and the simulation appends the live particles to a
+/// list whose length lands in the arguments of an indirect draw, so the
+/// CPU never reads back the particle count.
+///
+/// `record` runs the compute work and has to be recorded outside of
+/// rendering, `draw` is recorded inside `begin_rendering`.
+pub struct ParticleSystem {
+    pub gravity: Vec3,
+    /// Bitonic sort of the live particles by camera distance, needed for
+    /// correct alpha blending.
+    pub sort: bool,
+
+    max_particles: u32,
+    sort_size: u32,
+    emitters: SlotMap<EmitterHandle, EmitterState>,
+    particles: Buffer,
+    free_list: Buffer,
+    alive_list: Buffer,
+    draw_args: Arc<Buffer>,
+    sort_list: Buffer,
+    emit_kernel: ComputeKernel,
+    simulate_kernel: ComputeKernel,
+    sort_kernel: ComputeKernel,
+    descriptor_set: Arc<DescriptorSet>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    frame: u32,
+    initialized: bool,
+}
+
+impl ParticleSystem {
+    pub fn new(renderer: Arc<Renderer>, create_info: ParticleSystemCreateInfo) -> Self {
+        let max_particles = create_info.max_particles.max(1);
+        let sort_size = max_particles.next_power_of_two();
+
+        let storage_buffer = |size: u64, usage: vk::BufferUsageFlags| {
+            let create_info = BufferCreateInfo {
+                size,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER | usage,
+                location: MemoryLocation::GpuOnly,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        let particles = storage_buffer(max_particles as u64 * PARTICLE_SIZE, vk::BufferUsageFlags::empty());
+        let free_list = storage_buffer(4 + max_particles as u64 * 4, vk::BufferUsageFlags::TRANSFER_DST);
+        let alive_list = storage_buffer(max_particles as u64 * 4, vk::BufferUsageFlags::empty());
+        let draw_args = storage_buffer(size_of::<vk::DrawIndirectCommand>() as u64, vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::TRANSFER_DST);
+        let sort_list = storage_buffer(sort_size as u64 * 8, vk::BufferUsageFlags::empty());
+        particles.set_name("particles");
+
+        let emit_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/particle_emit.spv"),
+            &[BindingType::StorageBuffer; 2],
+            EMIT_PUSH_CONSTANTS_SIZE);
+        let simulate_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/particle_simulate.spv"),
+            &[BindingType::StorageBuffer; 4],
+            SIMULATE_PUSH_CONSTANTS_SIZE);
+        let sort_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/particle_sort.spv"),
+            &[BindingType::StorageBuffer; 4],
+            SORT_PUSH_CONSTANTS_SIZE);
+
+        let descriptor_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: (0..2).map(|binding| DescriptorSetBinding {
+                    stage: ShaderStages::Vertex,
+                    typ: BindingType::StorageBuffer,
+                    binding,
+                }).collect(),
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_layout.clone());
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_layout],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: DRAW_PUSH_CONSTANTS_SIZE,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/particle_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/particle_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: VertexLayout::default(),
+                color_formats: vec![create_info.color_format],
+                depth_format: create_info.depth_format,
+                extent: create_info.extent,
+                raster: RasterState {
+                    cull_mode: vk::CullModeFlags::NONE,
+                    blend: create_info.blend,
+                    depth_write: false,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer, create_info)
+        };
+
+        Self {
+            gravity: [0.0, -9.81, 0.0],
+            sort: create_info.blend == BlendMode::Alpha,
+            max_particles,
+            sort_size,
+            emitters: SlotMap::with_key(),
+            particles,
+            free_list,
+            alive_list,
+            draw_args: Arc::new(draw_args),
+            sort_list,
+            emit_kernel,
+            simulate_kernel,
+            sort_kernel,
+            descriptor_set,
+            pipeline,
+            pipeline_layout,
+            frame: 0,
+            initialized: false,
+        }
+    }
+
+    pub fn add_emitter(&mut self, emitter: ParticleEmitter) -> EmitterHandle {
+        self.emitters.insert(EmitterState { emitter, pending: 0.0 })
+    }
+
+    pub fn remove_emitter(&mut self, handle: EmitterHandle) -> Option<ParticleEmitter> {
+        self.emitters.remove(handle).map(|s| s.emitter)
+    }
+
+    pub fn emitter_mut(&mut self, handle: EmitterHandle) -> Option<&mut ParticleEmitter> {
+        self.emitters.get_mut(handle).map(|s| &mut s.emitter)
+    }
+
+    /// Spawns `count` extra particles from the emitter with the next `record`.
+    pub fn burst(&mut self, handle: EmitterHandle, count: u32) {
+        if let Some(state) = self.emitters.get_mut(handle) {
+            state.pending += count as f32;
+        }
+    }
+
+    pub fn max_particles(&self) -> u32 {
+        self.max_particles
+    }
+
+    fn write_descriptor_sets(&self) {
+        self.emit_kernel.descriptor_set.write_storage_buffer(0, &self.particles);
+        self.emit_kernel.descriptor_set.write_storage_buffer(1, &self.free_list);
+
+        self.simulate_kernel.descriptor_set.write_storage_buffer(0, &self.particles);
+        self.simulate_kernel.descriptor_set.write_storage_buffer(1, &self.free_list);
+        self.simulate_kernel.descriptor_set.write_storage_buffer(2, &self.alive_list);
+        self.simulate_kernel.descriptor_set.write_storage_buffer(3, &self.draw_args);
+
+        self.sort_kernel.descriptor_set.write_storage_buffer(0, &self.particles);
+        self.sort_kernel.descriptor_set.write_storage_buffer(1, &self.alive_list);
+        self.sort_kernel.descriptor_set.write_storage_buffer(2, &self.draw_args);
+        self.sort_kernel.descriptor_set.write_storage_buffer(3, &self.sort_list);
+
+        self.descriptor_set.write_storage_buffer(0, &self.particles);
+        self.descriptor_set.write_storage_buffer(1, &self.sort_list);
+    }
+
+    fn simulate_push_constants(&self, delta_time: f32, init: bool) -> Vec<u8> {
+        let [x, y, z] = self.gravity;
+        words_to_bytes(&[x.to_bits(), y.to_bits(), z.to_bits(), delta_time.to_bits(), self.max_particles, init as u32])
+    }
+
+    /// Emits, simulates and sorts the particles for this frame.
+    pub fn record(&mut self, command_list: &mut CommandList, delta_time: f32, camera_position: Vec3) {
+        self.write_descriptor_sets();
+        // The previous frame may still be drawing from the buffers.
+        command_list.memory_barrier();
+        let groups = self.max_particles.div_ceil(WORKGROUP_SIZE);
+
+        if !self.initialized {
+            let push_constants = self.simulate_push_constants(0.0, true);
+            self.simulate_kernel.dispatch(command_list, &push_constants, groups, 1, 1);
+            command_list.update_buffer(&self.free_list, 0, &(self.max_particles as i32).to_ne_bytes());
+            self.initialized = true;
+        }
+
+        // vk::DrawIndirectCommand, the simulation counts the instances.
+        command_list.update_buffer(&self.draw_args, 0, &words_to_bytes(&[VERTICES_PER_PARTICLE, 0, 0, 0]));
+        command_list.memory_barrier();
+
+        self.frame = self.frame.wrapping_add(1);
+        for (i, state) in self.emitters.values_mut().enumerate() {
+            let emitter = &state.emitter;
+            state.pending += emitter.rate * delta_time;
+            let count = (state.pending.floor() as u32).min(self.max_particles);
+            state.pending -= count as f32;
+            if count == 0 {
+                continue;
+            }
+
+            let seed = self.frame.wrapping_mul(0x9E37_79B9) ^ (i as u32).wrapping_mul(0x85EB_CA6B);
+            let words = [
+                emitter.position[0].to_bits(), emitter.position[1].to_bits(), emitter.position[2].to_bits(), emitter.spread.to_bits(),
+                emitter.direction[0].to_bits(), emitter.direction[1].to_bits(), emitter.direction[2].to_bits(), emitter.speed[0].to_bits(),
+                emitter.speed[1].to_bits(), emitter.lifetime[0].to_bits(), emitter.lifetime[1].to_bits(), emitter.size.to_bits(),
+                emitter.start_color[0].to_bits(), emitter.start_color[1].to_bits(), emitter.start_color[2].to_bits(), emitter.start_color[3].to_bits(),
+                emitter.end_color[0].to_bits(), emitter.end_color[1].to_bits(), emitter.end_color[2].to_bits(), emitter.end_color[3].to_bits(),
+                count, seed,
+            ];
+            self.emit_kernel.dispatch(command_list, &words_to_bytes(&words), count.div_ceil(WORKGROUP_SIZE), 1, 1);
+            // Emitters pop from the same free list.
+            command_list.memory_barrier();
+        }
+
+        let push_constants = self.simulate_push_constants(delta_time, false);
+        self.simulate_kernel.dispatch(command_list, &push_constants, groups, 1, 1);
+        command_list.memory_barrier();
+
+        let [x, y, z] = camera_position;
+        let sort_groups = self.sort_size.div_ceil(WORKGROUP_SIZE);
+        let mut sort_step = |k: u32, j: u32| {
+            let push_constants = words_to_bytes(&[x.to_bits(), y.to_bits(), z.to_bits(), self.sort_size, k, j]);
+            self.sort_kernel.dispatch(command_list, &push_constants, sort_groups, 1, 1);
+            command_list.memory_barrier();
+        };
+
+        // Without sorting this only copies the alive list into the draw order.
+        sort_step(0, 0);
+        if self.sort {
+            let mut k = 2;
+            while k <= self.sort_size {
+                let mut j = k / 2;
+                while j > 0 {
+                    sort_step(k, j);
+                    j /= 2;
+                }
+                k *= 2;
+            }
+        }
+    }
+
+    /// Draws the particles simulated by the last `record` as camera facing
+    /// quads.
+    pub fn draw(&self, command_list: &mut CommandList, view: Mat4, projection: Mat4) {
+        let right = view.row(0);
+        let up = view.row(1);
+
+        let mut push_constants = Vec::with_capacity(DRAW_PUSH_CONSTANTS_SIZE as usize);
+        push_constants.extend_from_slice(&(projection * view).to_bytes());
+        push_constants.extend(words_to_bytes(&[right[0].to_bits(), right[1].to_bits(), right[2].to_bits(), 0]));
+        push_constants.extend(words_to_bytes(&[up[0].to_bits(), up[1].to_bits(), up[2].to_bits(), 0]));
+
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, self.descriptor_set.clone());
+        command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &push_constants);
+        command_list.draw_indirect(self.draw_args.clone(), 0, 1, size_of::<vk::DrawIndirectCommand>() as u32);
+    }
+}
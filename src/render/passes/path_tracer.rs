@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, BufferCreateInfo, Capabilities, MemoryLocation, TextureCreateInfo, TextureKind, VertexSemantic};
+use crate::render::hal::vulkan::acceleration_structure::AccelerationStructure;
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::material::MaterialInstance;
+use crate::render::math::{normalize, Mat4, Vec3};
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::scene::{Draw, DrawList, Light};
+
+const WORKGROUP_SIZE: u32 = 16;
+const PARAMS_SIZE: usize = 112;
+const ACCUMULATION_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+const OUTPUT_FORMAT: vk::Format = HDR_FORMAT;
+/// Initial size of the scene buffers, grown to the next power of two.
+const MIN_BUFFER_SIZE: u64 = 256;
+
+pub struct PathTracerCreateInfo {
+    pub extent: vk::Extent2D,
+}
+
+/// Progressive path tracer over the draws of a `DrawList`, a ground truth
+/// to compare the rasterized lighting against on devices with
+/// `Capabilities::RayQuery`. Every `record` traces one path per pixel
+/// against the top level structure of `AccelerationStructureManager` and
+/// adds it to an accumulation target, `output` is the average so far.
+///
+/// Surfaces are Lambertian with the material's `base_color` and optional
+/// `emissive` parameters, lit by the scene lights in the units of
+/// `DeferredLightingPass` and by a uniform `sky`. The accumulation restarts
+/// whenever the camera, the draws, their materials or the lights change.
+/// Switching between the two is up to the application:
+///
+/// ```ignore
+/// let tlas = manager.update_tlas(frame.command_list(), &draws.draws);
+/// if reference {
+///     path_tracer.record(frame.command_list(), &tlas, &draws, view_projection);
+///     tonemap.record(frame.command_list(), path_tracer.output());
+/// } else {
+///     ..
+/// }
+/// ```
+pub struct PathTracer {
+    /// Bounces after the primary hit, 0 for direct lighting only.
+    pub max_bounces: u32,
+    /// Samples per pixel after which `record` stops tracing, 0 to keep
+    /// refining.
+    pub max_samples: u32,
+    /// Radiance of rays leaving the scene, matches
+    /// `DeferredLightingPass::ambient` for a comparable image.
+    pub sky: Vec3,
+    /// Offset of bounce and shadow ray origins along the surface normal,
+    /// against self intersections.
+    pub ray_bias: f32,
+    /// Length of rays, and of shadow rays towards directional lights.
+    pub max_distance: f32,
+
+    renderer: Arc<Renderer>,
+    accumulation: Texture,
+    output: Texture,
+    params: Vec<Buffer>,
+    instances: Vec<Buffer>,
+    geometries: Vec<Buffer>,
+    lights: Vec<Buffer>,
+    kernel: ComputeKernel,
+    /// Everything the accumulated samples depend on, to detect changes.
+    state: Vec<u8>,
+    sample_count: u32,
+    reset: bool,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, format: vk::Format, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+fn create_buffers(renderer: &Arc<Renderer>, size: u64, usage: vk::BufferUsageFlags) -> Vec<Buffer> {
+    (0..FRAME_OVERLAP).map(|_| {
+        let create_info = BufferCreateInfo {
+            size,
+            usage,
+            location: MemoryLocation::CpuToGpu,
+        };
+        Buffer::new(renderer.clone(), create_info)
+    }).collect()
+}
+
+/// Writes `data` to `buffer`, replaced by a larger one when it doesn't fit.
+/// The frame slot's previous contents are no longer read by then.
+fn upload(renderer: &Arc<Renderer>, buffer: &mut Buffer, data: &[u8]) {
+    if buffer.size() < data.len() as u64 {
+        let create_info = BufferCreateInfo {
+            size: (data.len() as u64).next_power_of_two(),
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+            location: MemoryLocation::CpuToGpu,
+        };
+        *buffer = Buffer::new(renderer.clone(), create_info);
+    }
+    buffer.write(0, data);
+}
+
+/// A color parameter of `material`, `default` when it has none.
+fn material_color(material: &MaterialInstance, name: &str, default: [f32; 4]) -> [f32; 4] {
+    let mut color = default;
+    if let Ok(values) = material.param(name) {
+        for (c, value) in color.iter_mut().zip(values) {
+            *c = value;
+        }
+    }
+    color
+}
+
+/// Appends the `Instance` of `draw` to `instances` and its `Geometry`s to
+/// `geometries`, see `shaders/path_tracer.comp`. Meshes without positions
+/// get an empty record, they aren't in the top level structure either.
+fn write_draw(draw: &Draw, instances: &mut Vec<u8>, geometries: &mut Vec<u8>) {
+    let first_geometry = (geometries.len() / 8) as u32;
+    let triangles = draw.mesh.triangles().unwrap_or_default();
+    for geometry in &triangles {
+        geometries.extend([geometry.first, geometry.base_vertex].iter().flat_map(|w| w.to_ne_bytes()));
+    }
+
+    let (positions, position_stride, indices, index16) = triangles.first().map_or((0, 0, 0, false), |t| (
+        t.vertex_buffer.device_address() + t.vertex_offset,
+        (t.vertex_stride / 4) as u32,
+        t.index_buffer.map_or(0, |b| b.device_address()),
+        t.index_type == vk::IndexType::UINT16,
+    ));
+    let layout = draw.mesh.layout();
+    let (normals, normal_stride) = layout.attributes.iter()
+        .find(|a| a.semantic == VertexSemantic::Normal)
+        .map_or((0, 0), |a| (
+            draw.mesh.vertex_buffers()[a.binding as usize].device_address() + a.offset as u64,
+            layout.strides[a.binding as usize] / 4,
+        ));
+
+    let base_color = material_color(&draw.material, "base_color", [1.0; 4]);
+    let emissive = material_color(&draw.material, "emissive", [0.0; 4]);
+    let words = [
+        positions as u32, (positions >> 32) as u32,
+        normals as u32, (normals >> 32) as u32,
+        indices as u32, (indices >> 32) as u32,
+        position_stride, normal_stride, index16 as u32, first_geometry,
+        0, 0,
+    ];
+    instances.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+    instances.extend(base_color.iter().chain(&emissive).flat_map(|v| v.to_ne_bytes()));
+}
+
+impl PathTracer {
+    /// Panics without `Capabilities::RayQuery`.
+    pub fn new(renderer: Arc<Renderer>, create_info: PathTracerCreateInfo) -> Self {
+        assert!(renderer.capabilities().contains(Capabilities::RayQuery), "The path tracer needs Capabilities::RayQuery");
+
+        let extent = create_info.extent;
+        let accumulation = create_target(&renderer, extent, ACCUMULATION_FORMAT, "path tracer accumulation");
+        let output = create_target(&renderer, extent, OUTPUT_FORMAT, "path tracer output");
+
+        let params = create_buffers(&renderer, PARAMS_SIZE as u64, vk::BufferUsageFlags::UNIFORM_BUFFER);
+        let instances = create_buffers(&renderer, MIN_BUFFER_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER);
+        let geometries = create_buffers(&renderer, MIN_BUFFER_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER);
+        let lights = create_buffers(&renderer, MIN_BUFFER_SIZE, vk::BufferUsageFlags::STORAGE_BUFFER);
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/path_tracer.spv"),
+            &[
+                BindingType::AccelerationStructure,
+                BindingType::StorageBuffer,
+                BindingType::StorageBuffer,
+                BindingType::StorageBuffer,
+                BindingType::Texture,
+                BindingType::Texture,
+                BindingType::UniformBuffer,
+            ],
+            0);
+
+        Self {
+            max_bounces: 4,
+            max_samples: 4096,
+            sky: [0.03; 3],
+            ray_bias: 0.01,
+            max_distance: 1000.0,
+            renderer,
+            accumulation,
+            output,
+            params,
+            instances,
+            geometries,
+            lights,
+            kernel,
+            state: Vec::new(),
+            sample_count: 0,
+            reset: true,
+        }
+    }
+
+    /// Average radiance of the samples so far, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    /// Samples per pixel accumulated in `output`.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Restarts the accumulation, for changes `record` can't see such as
+    /// new material textures.
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// Traces a sample per pixel of `draws` seen with `view_projection`.
+    /// `tlas` has to be built from `draws.draws`, with the draw index as
+    /// the instance custom index like `AccelerationStructureManager`
+    /// does, and the meshes built with `MeshBuilder::ray_tracing`.
+    pub fn record(&mut self, command_list: &mut CommandList, tlas: &AccelerationStructure, draws: &DrawList, view_projection: Mat4) {
+        let mut instances = Vec::new();
+        let mut geometries = Vec::new();
+        for draw in &draws.draws {
+            write_draw(draw, &mut instances, &mut geometries);
+        }
+        let lights = draws.lights.iter().flat_map(|light| {
+            let (position, color, radius) = match light.light {
+                Light::Directional { color, intensity, .. } => {
+                    let to_light = normalize(light.direction).map(|v| -v);
+                    ([to_light[0], to_light[1], to_light[2], 0.0], color.map(|c| c * intensity), 0.0)
+                }
+                Light::Point { color, intensity, radius, .. } => {
+                    ([light.position[0], light.position[1], light.position[2], 1.0], color.map(|c| c * intensity), radius)
+                }
+            };
+            position.into_iter().chain(color).chain([radius]).flat_map(|v| v.to_ne_bytes())
+        }).collect::<Vec<_>>();
+
+        let mut state = view_projection.to_bytes().to_vec();
+        state.extend(draws.draws.iter().flat_map(|draw| draw.transform.to_bytes()));
+        state.extend(&instances);
+        state.extend(&geometries);
+        state.extend(&lights);
+        state.extend(self.sky.iter().chain(&[self.ray_bias, self.max_distance]).flat_map(|v| v.to_ne_bytes()));
+        state.extend(self.max_bounces.to_ne_bytes());
+        if state != self.state {
+            self.state = state;
+            self.reset = true;
+        }
+
+        if self.reset {
+            for texture in [&self.accumulation, &self.output] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            self.sample_count = 0;
+            self.reset = false;
+        }
+        if self.max_samples != 0 && self.sample_count >= self.max_samples {
+            return;
+        }
+
+        let inverse_view_projection = view_projection.inverse().unwrap_or(Mat4::IDENTITY);
+        let mut params = [0u8; PARAMS_SIZE];
+        params[0..64].copy_from_slice(&inverse_view_projection.to_bytes());
+        for (i, value) in self.sky.iter().enumerate() {
+            params[64 + i * 4..68 + i * 4].copy_from_slice(&value.to_ne_bytes());
+        }
+        let words = [self.sample_count, self.max_bounces, (lights.len() / 32) as u32, draws.draws.len() as u32];
+        for (i, word) in words.iter().enumerate() {
+            params[80 + i * 4..84 + i * 4].copy_from_slice(&word.to_ne_bytes());
+        }
+        params[96..100].copy_from_slice(&self.ray_bias.to_ne_bytes());
+        params[100..104].copy_from_slice(&self.max_distance.to_ne_bytes());
+
+        let frame = self.renderer.current_frame();
+        self.params[frame].write(0, &params);
+        upload(&self.renderer, &mut self.instances[frame], &instances);
+        upload(&self.renderer, &mut self.geometries[frame], &geometries);
+        upload(&self.renderer, &mut self.lights[frame], &lights);
+        // The shader reads the vertices and indices through their addresses.
+        for draw in &draws.draws {
+            command_list.retain(draw.mesh.clone());
+        }
+
+        let descriptor_set = &self.kernel.descriptor_set;
+        descriptor_set.write_acceleration_structure(0, tlas);
+        descriptor_set.write_storage_buffer(1, &self.instances[frame]);
+        descriptor_set.write_storage_buffer(2, &self.geometries[frame]);
+        descriptor_set.write_storage_buffer(3, &self.lights[frame]);
+        descriptor_set.write_texture(4, &self.accumulation);
+        descriptor_set.write_texture(5, &self.output);
+        descriptor_set.write_uniform_buffer(6, &self.params[frame]);
+
+        let extent = self.output.extent();
+        self.kernel.dispatch(command_list, &[], extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.accumulation, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.sample_count += 1;
+    }
+}
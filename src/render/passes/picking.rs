@@ -0,0 +1,204 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BufferTextureCopy, ColorAttachment, DepthAttachment, GraphicsPipelineCreateInfo, LoadOp, PipelineLayoutCreateInfo, PushConstantRange, RasterState, ReadbackBufferCreateInfo, Result, ShaderCreateInfo, ShaderStages, TextureCreateInfo, TextureKind, VertexLayout, VertexSemantic};
+use crate::render::hal::vulkan::buffer::ReadbackBuffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::Mat4;
+use crate::render::mesh::Mesh;
+
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// Identifies a pickable object, the ID target is 0 where nothing was drawn.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EntityId(pub NonZeroU32);
+
+pub struct PickingPassCreateInfo {
+    pub extent: vk::Extent3D,
+    /// Layout of the pickable meshes, only positions are read.
+    pub vertex_layout: VertexLayout,
+}
+
+/// Result of a `PickingPass::pick`.
+#[derive(Clone, Copy, Debug)]
+pub struct Pick {
+    pub x: u32,
+    pub y: u32,
+    /// `None` where no entity was drawn.
+    pub entity: Option<EntityId>,
+}
+
+/// Renders entity IDs into an `r32ui` target for click-to-select. Picks
+/// are read back asynchronously:
+///
+/// ```ignore
+/// picking.record(frame.command_list(), &draws, view_projection);
+/// picking.pick(frame.command_list(), cursor_x, cursor_y);
+/// // Frames later:
+/// if let Some(pick) = picking.poll()? { select(pick.entity) }
+/// ```
+pub struct PickingPass {
+    ids: Texture,
+    depth: Texture,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    readback: ReadbackBuffer,
+    /// Position of the pick being read back.
+    pending: Option<(u32, u32)>,
+}
+
+impl PickingPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: PickingPassCreateInfo) -> Self {
+        let ids = {
+            let create_info = TextureCreateInfo {
+                format: ID_FORMAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+        ids.set_name("picking ids");
+
+        let depth = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::D32_SFLOAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                aspect: vk::ImageAspectFlags::DEPTH,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex | ShaderStages::Fragment,
+                    offset: 0,
+                    size: 132,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let mut vertex_layout = create_info.vertex_layout;
+        vertex_layout.attributes.retain(|a| a.semantic == VertexSemantic::Position);
+
+        let pipeline = {
+            // The shadow caster shader transforms positions with the same
+            // view projection and model push constants.
+            let vertex_code = include_bytes_align_as!(u32, "shaders/shadow_depth.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/picking_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout,
+                color_formats: vec![ID_FORMAT],
+                depth_format: vk::Format::D32_SFLOAT,
+                extent: vk::Extent2D { width: create_info.extent.width, height: create_info.extent.height },
+                raster: RasterState::default(),
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer.clone(), create_info)
+        };
+
+        let readback = ReadbackBuffer::new(renderer, ReadbackBufferCreateInfo { size: 4 });
+
+        Self { ids, depth, pipeline, pipeline_layout, readback, pending: None }
+    }
+
+    /// The ID target, in `GENERAL` layout after `record`.
+    pub fn ids(&self) -> &Texture {
+        &self.ids
+    }
+
+    /// Draws every mesh with all its submeshes and its entity ID,
+    /// `transform` is the model matrix.
+    pub fn record(&self, command_list: &mut CommandList, draws: &[(Arc<Mesh>, Mat4, EntityId)], view_projection: Mat4) {
+        command_list.transition_texture_layout(&self.ids, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.depth, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+
+        let colors = [ColorAttachment { texture: &self.ids, load: LoadOp::Clear([0.0; 4]) }];
+        let depth = DepthAttachment { texture: &self.depth, load: LoadOp::Clear(0.0) };
+        command_list.begin_rendering_attachments(&colors, Some(depth));
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+
+        for (mesh, transform, entity) in draws {
+            let mut push_constants = [0u8; 132];
+            push_constants[..64].copy_from_slice(&view_projection.to_bytes());
+            push_constants[64..128].copy_from_slice(&transform.to_bytes());
+            push_constants[128..].copy_from_slice(&entity.0.get().to_ne_bytes());
+            command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex | ShaderStages::Fragment, 0, &push_constants);
+
+            mesh.bind(command_list);
+            for submesh in mesh.submeshes() {
+                if mesh.is_indexed() {
+                    command_list.draw_indexed(submesh.indices.end - submesh.indices.start, 1, submesh.indices.start, submesh.base_vertex, 0);
+                } else {
+                    command_list.draw(mesh.vertex_count(), 1, 0, 0);
+                }
+            }
+        }
+
+        command_list.end_rendering();
+        command_list.transition_texture_layout(&self.ids, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+
+    /// Reads back the entity at a pixel of the last `record`, see
+    /// `ReadbackBuffer::copy_texture`. Replaces a pick not taken yet.
+    pub fn pick(&mut self, command_list: &mut CommandList, x: u32, y: u32) {
+        let extent = self.ids.extent();
+        assert!(x < extent.width && y < extent.height, "Pick outside of the ID target");
+
+        let region = BufferTextureCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+            texture_offset: vk::Offset3D { x: x as i32, y: y as i32, z: 0 },
+            texture_extent: vk::Extent3D { width: 1, height: 1, depth: 1 },
+        };
+        self.readback.copy_texture(command_list, &self.ids, &[region]);
+        self.pending = Some((x, y));
+    }
+
+    /// The pick once read back, `None` while the GPU is busy or when
+    /// nothing was picked.
+    pub fn poll(&mut self) -> Result<Option<Pick>> {
+        let data = self.readback.poll()?;
+        Ok(data.map(|data| self.decode(&data)))
+    }
+
+    /// Waits for the pick, `None` when nothing was picked.
+    pub fn wait(&mut self) -> Result<Option<Pick>> {
+        let data = self.readback.wait()?;
+        Ok(data.map(|data| self.decode(&data)))
+    }
+
+    fn decode(&mut self, data: &[u8]) -> Pick {
+        let (x, y) = self.pending.take().unwrap();
+        let id = u32::from_ne_bytes(data[..4].try_into().unwrap());
+        Pick { x, y, entity: NonZeroU32::new(id).map(EntityId) }
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Capabilities, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::acceleration_structure::AccelerationStructure;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::{normalize, Mat4, Vec3};
+use crate::render::passes::deferred::GBuffer;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::scene::{Light, SceneLight, ShadowMode};
+
+const WORKGROUP_SIZE: u32 = 16;
+const TRACE_PUSH_CONSTANTS_SIZE: u32 = 96;
+const TEMPORAL_PUSH_CONSTANTS_SIZE: u32 = 8;
+const VISIBILITY_FORMAT: vk::Format = vk::Format::R16_SFLOAT;
+
+pub struct RtShadowPassCreateInfo {
+    pub extent: vk::Extent2D,
+}
+
+/// Light a `RtShadowPass` traces towards.
+#[derive(Clone, Copy, Debug)]
+pub enum RtShadowLight {
+    Directional {
+        /// Direction the light travels in, world space.
+        direction: Vec3,
+        /// Angular radius of the light's disk in radians, 0 for hard
+        /// shadows. The sun is about 0.0047.
+        angular_radius: f32,
+    },
+    Point {
+        position: Vec3,
+        /// Radius of the emitting sphere.
+        source_radius: f32,
+    },
+}
+
+impl RtShadowLight {
+    /// The light of `light` when its shadows are `ShadowMode::RayTraced`.
+    pub fn from_scene_light(light: &SceneLight, size: f32) -> Option<Self> {
+        if light.light.shadows() != ShadowMode::RayTraced {
+            return None;
+        }
+        Some(match light.light {
+            Light::Directional { .. } => RtShadowLight::Directional { direction: light.direction, angular_radius: size },
+            Light::Point { .. } => RtShadowLight::Point { position: light.position, source_radius: size },
+        })
+    }
+}
+
+/// Hardware ray traced shadows, an alternative to shadow maps for lights
+/// with `ShadowMode::RayTraced` on devices with `Capabilities::RayQuery`.
+/// Every pixel of the G-buffer traces a ray towards a random point of the
+/// light against a top level acceleration structure, and a temporal filter
+/// accumulates the results into soft shadows. One pass per light, each
+/// keeps its own history:
+///
+/// ```ignore
+/// shadows.record(command_list, &tlas, &gbuffer, taa.velocity(), view_projection, light);
+/// let inputs = DeferredLightingInputs { shadow: Some(shadows.output()), .. };
+/// ```
+pub struct RtShadowPass {
+    /// Offset of the ray origins along the surface normal, against self
+    /// intersections.
+    pub normal_bias: f32,
+    /// Length of directional light rays.
+    pub max_distance: f32,
+    /// Weight of the current frame in the temporal filter, lower is
+    /// smoother and slower to react.
+    pub blend: f32,
+
+    visibility: Texture,
+    history: [Texture; 2],
+    point_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    trace_kernel: ComputeKernel,
+    temporal_kernel: ComputeKernel,
+    frame: u32,
+    reset: bool,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format: VISIBILITY_FORMAT,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl RtShadowPass {
+    /// Panics without `Capabilities::RayQuery`.
+    pub fn new(renderer: Arc<Renderer>, create_info: RtShadowPassCreateInfo) -> Self {
+        assert!(renderer.capabilities().contains(Capabilities::RayQuery), "Ray traced shadows need Capabilities::RayQuery");
+
+        let extent = create_info.extent;
+        let visibility = create_target(&renderer, extent, "rt shadows visibility");
+        let history = [
+            create_target(&renderer, extent, "rt shadows history 0"),
+            create_target(&renderer, extent, "rt shadows history 1"),
+        ];
+
+        let sampler = |filter| {
+            let create_info = SamplerCreateInfo {
+                filter,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+        let point_sampler = sampler(Filter::Nearest);
+        let linear_sampler = sampler(Filter::Linear);
+
+        let trace_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/rt_shadows_trace.spv"),
+            &[BindingType::AccelerationStructure, BindingType::SampledTexture, BindingType::Texture, BindingType::Texture],
+            TRACE_PUSH_CONSTANTS_SIZE);
+        let temporal_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/rt_shadows_temporal.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            TEMPORAL_PUSH_CONSTANTS_SIZE);
+        trace_kernel.descriptor_set.write_texture(3, &visibility);
+        temporal_kernel.descriptor_set.write_texture(0, &visibility);
+
+        Self {
+            normal_bias: 0.02,
+            max_distance: 1000.0,
+            blend: 0.1,
+            visibility,
+            history,
+            point_sampler,
+            linear_sampler,
+            trace_kernel,
+            temporal_kernel,
+            frame: 0,
+            reset: true,
+        }
+    }
+
+    /// Filtered visibility of the last `record`, 0 in shadow and 1 lit, in
+    /// `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.history[(self.frame.wrapping_sub(1) % 2) as usize]
+    }
+
+    /// Discards the history, after camera cuts or when the light changed
+    /// abruptly.
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// Traces the shadows of `light` for the surfaces of `gbuffer`, drawn
+    /// with `view_projection`, against `tlas`. `velocity` is
+    /// `TaaPass::velocity`. All textures are expected in `GENERAL` layout.
+    pub fn record(
+        &mut self,
+        command_list: &mut CommandList,
+        tlas: &AccelerationStructure,
+        gbuffer: &GBuffer,
+        velocity: &Texture,
+        view_projection: Mat4,
+        light: RtShadowLight,
+    ) {
+        if self.reset {
+            for texture in [&self.visibility, &self.history[0], &self.history[1]] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+        }
+
+        let (light_vector, point) = match light {
+            RtShadowLight::Directional { direction, angular_radius } => {
+                let to_light = normalize(direction).map(|v| -v);
+                ([to_light[0], to_light[1], to_light[2], angular_radius.tan()], 0u32)
+            }
+            RtShadowLight::Point { position, source_radius } => ([position[0], position[1], position[2], source_radius], 1),
+        };
+        let inverse_view_projection = view_projection.inverse().unwrap_or(Mat4::IDENTITY);
+
+        let mut push_constants = inverse_view_projection.to_bytes().to_vec();
+        push_constants.extend(light_vector.iter().flat_map(|v| v.to_ne_bytes()));
+        let words = [point, self.frame, self.normal_bias.to_bits(), self.max_distance.to_bits()];
+        push_constants.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+
+        let extent = self.visibility.extent();
+        let descriptor_set = &self.trace_kernel.descriptor_set;
+        descriptor_set.write_acceleration_structure(0, tlas);
+        descriptor_set.write_sampled_texture(1, gbuffer.depth(), &self.point_sampler);
+        descriptor_set.write_texture(2, gbuffer.normal());
+        command_list.transition_texture_layout(gbuffer.normal(), vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(gbuffer.depth(), vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.trace_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.visibility, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let (previous, current) = if self.frame.is_multiple_of(2) { (1, 0) } else { (0, 1) };
+        let mut push_constants = [0u8; TEMPORAL_PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&self.blend.clamp(0.0, 1.0).to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&(self.reset as u32).to_ne_bytes());
+
+        let descriptor_set = &self.temporal_kernel.descriptor_set;
+        descriptor_set.write_texture(1, velocity);
+        descriptor_set.write_sampled_texture(2, &self.history[previous], &self.linear_sampler);
+        descriptor_set.write_texture(3, &self.history[current]);
+        command_list.transition_texture_layout(velocity, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.temporal_kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+        command_list.transition_texture_layout(&self.history[current], vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.frame = self.frame.wrapping_add(1);
+        self.reset = false;
+    }
+}
@@ -0,0 +1,283 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::cvars::{CVarParams, CVars};
+use crate::render::hal::{AddressMode, BindingType, Capabilities, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::acceleration_structure::AccelerationStructure;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::Mat4;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::ssao::{SsaoPass, SsaoPassCreateInfo, OCCLUSION_FORMAT};
+
+const WORKGROUP_SIZE: u32 = 16;
+const TRACE_PUSH_CONSTANTS_SIZE: u32 = 84;
+const TEMPORAL_PUSH_CONSTANTS_SIZE: u32 = 8;
+
+pub struct RtaoPassCreateInfo {
+    pub extent: vk::Extent3D,
+}
+
+/// Hardware ray traced ambient occlusion, on devices with
+/// `Capabilities::RayQuery`. Every pixel of the G-buffer traces
+/// `ray_count` rays over its hemisphere against a top level acceleration
+/// structure, a temporal filter accumulates the results and the SSAO blur
+/// removes the remaining noise. The output has the format and meaning of
+/// `SsaoPass::output`, see `AmbientOcclusionPass` to pick either.
+pub struct RtaoPass {
+    /// Rays per pixel and frame.
+    pub ray_count: u32,
+    /// World space distance within which hits occlude.
+    pub ray_length: f32,
+    /// 0 disables the occlusion, 1 darkens fully occluded pixels to black.
+    pub intensity: f32,
+    /// Offset of the ray origins along the surface normal, against self
+    /// intersections.
+    pub normal_bias: f32,
+    /// Weight of the current frame in the temporal filter, lower is
+    /// smoother and slower to react.
+    pub blend: f32,
+
+    raw: Texture,
+    history: [Texture; 2],
+    output: Texture,
+    point_sampler: Arc<Sampler>,
+    linear_sampler: Arc<Sampler>,
+    trace_kernel: ComputeKernel,
+    temporal_kernel: ComputeKernel,
+    blur_kernel: ComputeKernel,
+    frame: u32,
+    reset: bool,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent3D, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format: OCCLUSION_FORMAT,
+        extent,
+        usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl RtaoPass {
+    /// Panics without `Capabilities::RayQuery`.
+    pub fn new(renderer: Arc<Renderer>, create_info: RtaoPassCreateInfo) -> Self {
+        assert!(renderer.capabilities().contains(Capabilities::RayQuery), "Ray traced ambient occlusion needs Capabilities::RayQuery");
+
+        let extent = create_info.extent;
+        let raw = create_target(&renderer, extent, "rtao raw");
+        let history = [
+            create_target(&renderer, extent, "rtao history 0"),
+            create_target(&renderer, extent, "rtao history 1"),
+        ];
+        let output = create_target(&renderer, extent, "rtao");
+
+        let sampler = |filter| {
+            let create_info = SamplerCreateInfo {
+                filter,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+        let point_sampler = sampler(Filter::Nearest);
+        let linear_sampler = sampler(Filter::Linear);
+
+        let trace_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/rtao_trace.spv"),
+            &[BindingType::AccelerationStructure, BindingType::SampledTexture, BindingType::Texture, BindingType::Texture],
+            TRACE_PUSH_CONSTANTS_SIZE);
+        let temporal_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/rtao_temporal.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            TEMPORAL_PUSH_CONSTANTS_SIZE);
+        let blur_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/ssao_blur.spv"),
+            &[BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            0);
+        trace_kernel.descriptor_set.write_texture(3, &raw);
+        temporal_kernel.descriptor_set.write_texture(0, &raw);
+        blur_kernel.descriptor_set.write_texture(2, &output);
+
+        Self {
+            ray_count: 2,
+            ray_length: 1.0,
+            intensity: 1.0,
+            normal_bias: 0.02,
+            blend: 0.1,
+            raw,
+            history,
+            output,
+            point_sampler,
+            linear_sampler,
+            trace_kernel,
+            temporal_kernel,
+            blur_kernel,
+            frame: 0,
+            reset: true,
+        }
+    }
+
+    /// Filtered occlusion of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    /// Discards the history, after camera cuts.
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// Computes the occlusion of the frame drawn with `view` and
+    /// `projection` against `tlas`. `depth` and `normals` are as for
+    /// `SsaoPass::record`, `velocity` is `TaaPass::velocity`. All textures
+    /// are expected in `GENERAL` layout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        command_list: &mut CommandList,
+        tlas: &AccelerationStructure,
+        depth: &Texture,
+        normals: &Texture,
+        velocity: &Texture,
+        view: Mat4,
+        projection: Mat4,
+    ) {
+        let extent = self.output.extent();
+        let groups_x = extent.width.div_ceil(WORKGROUP_SIZE);
+        let groups_y = extent.height.div_ceil(WORKGROUP_SIZE);
+
+        if self.reset {
+            for texture in [&self.raw, &self.history[0], &self.history[1], &self.output] {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+        }
+
+        let inverse_view_projection = (projection * view).inverse().unwrap_or(Mat4::IDENTITY);
+        let mut push_constants = inverse_view_projection.to_bytes().to_vec();
+        let words = [self.ray_count, self.frame, self.ray_length.to_bits(), self.normal_bias.to_bits(), self.intensity.to_bits()];
+        push_constants.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+
+        let descriptor_set = &self.trace_kernel.descriptor_set;
+        descriptor_set.write_acceleration_structure(0, tlas);
+        descriptor_set.write_sampled_texture(1, depth, &self.point_sampler);
+        descriptor_set.write_texture(2, normals);
+        command_list.transition_texture_layout(normals, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.trace_kernel.dispatch(command_list, &push_constants, groups_x, groups_y, 1);
+        command_list.transition_texture_layout(&self.raw, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let (previous, current) = if self.frame.is_multiple_of(2) { (1, 0) } else { (0, 1) };
+        let mut push_constants = [0u8; TEMPORAL_PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&self.blend.clamp(0.0, 1.0).to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&(self.reset as u32).to_ne_bytes());
+
+        let descriptor_set = &self.temporal_kernel.descriptor_set;
+        descriptor_set.write_texture(1, velocity);
+        descriptor_set.write_sampled_texture(2, &self.history[previous], &self.linear_sampler);
+        descriptor_set.write_texture(3, &self.history[current]);
+        command_list.transition_texture_layout(velocity, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.temporal_kernel.dispatch(command_list, &push_constants, groups_x, groups_y, 1);
+        command_list.transition_texture_layout(&self.history[current], vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let descriptor_set = &self.blur_kernel.descriptor_set;
+        descriptor_set.write_texture(0, &self.history[current]);
+        descriptor_set.write_sampled_texture(1, depth, &self.point_sampler);
+        self.blur_kernel.dispatch(command_list, &[], groups_x, groups_y, 1);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.frame = self.frame.wrapping_add(1);
+        self.reset = false;
+    }
+}
+
+impl CVarParams for RtaoPass {
+    fn register_cvars(&self, cvars: &mut CVars, prefix: &str) {
+        cvars.register_ranged(&format!("{prefix}.ray_count"), self.ray_count, 1..=16, "Rays per pixel and frame");
+        cvars.register_ranged(&format!("{prefix}.ray_length"), self.ray_length, 0.01..=10.0, "World space distance within which hits occlude");
+        cvars.register_ranged(&format!("{prefix}.intensity"), self.intensity, 0.0..=1.0, "Darkening of fully occluded pixels");
+        cvars.register_ranged(&format!("{prefix}.normal_bias"), self.normal_bias, 0.0..=0.5, "Ray origin offset along the normal");
+        cvars.register_ranged(&format!("{prefix}.blend"), self.blend, 0.01..=1.0, "Weight of the current frame in the temporal filter");
+    }
+
+    fn apply_cvars(&mut self, cvars: &CVars, prefix: &str) {
+        cvars.read(&format!("{prefix}.ray_count"), &mut self.ray_count);
+        cvars.read(&format!("{prefix}.ray_length"), &mut self.ray_length);
+        cvars.read(&format!("{prefix}.intensity"), &mut self.intensity);
+        cvars.read(&format!("{prefix}.normal_bias"), &mut self.normal_bias);
+        cvars.read(&format!("{prefix}.blend"), &mut self.blend);
+    }
+}
+
+/// What `AmbientOcclusionPass` reads, all in `GENERAL` layout.
+#[derive(Clone, Copy)]
+pub struct AmbientOcclusionInputs<'a> {
+    /// Needs `SAMPLED` usage.
+    pub depth: &'a Texture,
+    /// World space, in an `rgba16f` storage texture as in the `GBuffer`.
+    pub normals: &'a Texture,
+    /// `TaaPass::velocity`, only read with ray queries.
+    pub velocity: &'a Texture,
+    /// Scene top level structure, only read with ray queries.
+    pub tlas: Option<&'a AccelerationStructure>,
+    pub view: Mat4,
+    pub projection: Mat4,
+}
+
+/// Ray traced ambient occlusion where the hardware allows it, screen space
+/// otherwise, behind one interface. The output scales the ambient term,
+/// `DeferredLightingInputs::ambient_occlusion`:
+///
+/// ```ignore
+/// let mut ao = AmbientOcclusionPass::new(renderer.clone(), extent);
+/// ao.record(command_list, &AmbientOcclusionInputs { tlas: Some(&tlas), .. });
+/// let inputs = DeferredLightingInputs { ambient_occlusion: Some(ao.output()), .. };
+/// ```
+pub enum AmbientOcclusionPass {
+    ScreenSpace(Box<SsaoPass>),
+    RayTraced(Box<RtaoPass>),
+}
+
+impl AmbientOcclusionPass {
+    /// `RtaoPass` with `Capabilities::RayQuery`, `SsaoPass` without.
+    pub fn new(renderer: Arc<Renderer>, extent: vk::Extent3D) -> Self {
+        if renderer.capabilities().contains(Capabilities::RayQuery) {
+            AmbientOcclusionPass::RayTraced(Box::new(RtaoPass::new(renderer, RtaoPassCreateInfo { extent })))
+        } else {
+            AmbientOcclusionPass::ScreenSpace(Box::new(SsaoPass::new(renderer, SsaoPassCreateInfo { extent })))
+        }
+    }
+
+    pub fn output(&self) -> &Texture {
+        match self {
+            AmbientOcclusionPass::ScreenSpace(pass) => pass.output(),
+            AmbientOcclusionPass::RayTraced(pass) => pass.output(),
+        }
+    }
+
+    /// Panics when ray traced without `inputs.tlas`.
+    pub fn record(&mut self, command_list: &mut CommandList, inputs: &AmbientOcclusionInputs) {
+        match self {
+            AmbientOcclusionPass::ScreenSpace(pass) => {
+                pass.record(command_list, inputs.depth, inputs.normals, inputs.view, inputs.projection);
+            }
+            AmbientOcclusionPass::RayTraced(pass) => {
+                let tlas = inputs.tlas.expect("Ray traced ambient occlusion needs a top level acceleration structure");
+                pass.record(command_list, tlas, inputs.depth, inputs.normals, inputs.velocity, inputs.view, inputs.projection);
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Clustered lighting helpers for forward shaders. Bind the buffers of
+// `LightCullPass` to `CLUSTER_SET`, fill a `ClusterData` from
+// `LightCullPass::cluster_data` and loop over the lights of a fragment:
+//
+//     uvec2 cluster = clusters[cluster_index(data, gl_FragCoord.xy, view_depth)];
+//     for (uint i = 0u; i < cluster.y; i++) {
+//         PointLight light = lights[light_indices[cluster.x + i]];
+//         ...
+//     }
+//
+// Light positions are in view space.
+
+#ifndef CLUSTER_SET
+#define CLUSTER_SET 2
+#endif
+
+const uint CLUSTERS_X = 16u;
+const uint CLUSTERS_Y = 9u;
+const uint CLUSTERS_Z = 24u;
+
+struct PointLight {
+    vec3 position;
+    float radius;
+    vec3 color;
+    float intensity;
+};
+
+struct ClusterData {
+    vec2 screen_size;
+    float near;
+    float far;
+};
+
+layout(std430, set = CLUSTER_SET, binding = 0) readonly buffer Lights {
+    PointLight lights[];
+};
+
+layout(std430, set = CLUSTER_SET, binding = 1) readonly buffer ClusterGrid {
+    uvec2 clusters[];
+};
+
+layout(std430, set = CLUSTER_SET, binding = 2) readonly buffer LightIndices {
+    uint light_indices[];
+};
+
+// `view_depth` is the positive distance from the camera.
+uint cluster_index(ClusterData data, vec2 frag_coord, float view_depth)
+{
+    uvec2 tile = uvec2(clamp(frag_coord / data.screen_size, 0.0, 0.999) * vec2(CLUSTERS_X, CLUSTERS_Y));
+    float slice = log(max(view_depth, data.near) / data.near) / log(data.far / data.near) * float(CLUSTERS_Z);
+    uint z = min(uint(slice), CLUSTERS_Z - 1u);
+    return tile.x + tile.y * CLUSTERS_X + z * CLUSTERS_X * CLUSTERS_Y;
+}
+
+// Inverse square falloff windowed to reach zero at the light radius.
+float point_light_attenuation(PointLight light, vec3 view_position)
+{
+    vec3 d = light.position - view_position;
+    float distance2 = dot(d, d);
+    float window = clamp(1.0 - pow(distance2 / (light.radius * light.radius), 2.0), 0.0, 1.0);
+    return window * window / max(distance2, 0.0001);
+}
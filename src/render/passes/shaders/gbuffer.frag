@@ -0,0 +1,20 @@
+#version 460
+
+// Reference G-buffer fragment shader for material templates using the mesh
+// vertex shader, with the parameters of `deferred::gbuffer_material_params`.
+
+layout(location = 0) in vec3 normal;
+
+layout(std140, set = 0, binding = 0) uniform Material {
+    vec4 base_color;
+} material;
+
+layout(location = 0) out vec4 out_albedo;
+layout(location = 1) out vec4 out_normal;
+
+void main()
+{
+    // Alpha marks the pixel as covered for the lighting pass.
+    out_albedo = vec4(material.base_color.rgb, 1.0);
+    out_normal = vec4(normalize(normal), 0.0);
+}
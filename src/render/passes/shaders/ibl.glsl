@@ -0,0 +1,40 @@
+// Image-based lighting helpers for forward shaders. Bind
+// `IblPass::descriptor_set` to `IBL_SET`, materials created with
+// `image_based_lighting` have it in their pipeline layout. Directions are
+// in world space.
+
+#ifndef IBL_SET
+#define IBL_SET 3
+#endif
+
+layout(set = IBL_SET, binding = 0) uniform textureCube ibl_irradiance_texture;
+layout(set = IBL_SET, binding = 0) uniform sampler ibl_irradiance_sampler;
+layout(set = IBL_SET, binding = 1) uniform textureCube ibl_prefiltered_texture;
+layout(set = IBL_SET, binding = 1) uniform sampler ibl_prefiltered_sampler;
+layout(set = IBL_SET, binding = 2) uniform texture2D ibl_brdf_lut_texture;
+layout(set = IBL_SET, binding = 2) uniform sampler ibl_brdf_lut_sampler;
+
+vec3 ibl_fresnel_roughness(float n_dot_v, vec3 f0, float roughness)
+{
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - n_dot_v, 0.0, 1.0), 5.0);
+}
+
+// Ambient light reflected towards `view` (pointing from the surface to the
+// camera) by a metallic-roughness surface.
+vec3 ibl_ambient(vec3 normal, vec3 view, vec3 albedo, float metallic, float roughness)
+{
+    float n_dot_v = max(dot(normal, view), 0.0);
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = ibl_fresnel_roughness(n_dot_v, f0, roughness);
+
+    vec3 irradiance = texture(samplerCube(ibl_irradiance_texture, ibl_irradiance_sampler), normal).rgb;
+    vec3 diffuse = (1.0 - f) * (1.0 - metallic) * irradiance * albedo;
+
+    float max_level = float(textureQueryLevels(samplerCube(ibl_prefiltered_texture, ibl_prefiltered_sampler)) - 1);
+    vec3 r = reflect(-view, normal);
+    vec3 prefiltered = textureLod(samplerCube(ibl_prefiltered_texture, ibl_prefiltered_sampler), r, roughness * max_level).rgb;
+    vec2 brdf = texture(sampler2D(ibl_brdf_lut_texture, ibl_brdf_lut_sampler), vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    return diffuse + specular;
+}
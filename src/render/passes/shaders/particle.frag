@@ -0,0 +1,13 @@
+#version 460
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main()
+{
+    float alpha = color.a * smoothstep(1.0, 0.6, length(uv));
+    // Premultiplied for `BlendMode::Alpha`.
+    out_color = vec4(color.rgb * alpha, alpha);
+}
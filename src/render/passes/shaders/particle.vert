@@ -0,0 +1,54 @@
+#version 460
+
+// Camera facing quad per particle, two triangles from the vertex index. The
+// instance index walks the sorted alive particles.
+
+struct Particle {
+    vec3 position;
+    float age;
+    vec3 velocity;
+    // Dead once `age` reaches it.
+    float lifetime;
+    vec4 start_color;
+    vec4 end_color;
+    float size;
+};
+
+struct SortEntry {
+    float key;
+    uint index;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(std430, set = 0, binding = 1) readonly buffer SortList {
+    SortEntry entries[];
+};
+
+layout(push_constant) uniform Params {
+    mat4 view_projection;
+    vec4 camera_right;
+    vec4 camera_up;
+} params;
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) out vec4 color;
+
+void main()
+{
+    const vec2 corners[6] = vec2[6](
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+    );
+    vec2 corner = corners[gl_VertexIndex];
+
+    Particle p = particles[entries[gl_InstanceIndex].index];
+    float t = clamp(p.age / p.lifetime, 0.0, 1.0);
+
+    vec3 position = p.position + (params.camera_right.xyz * corner.x + params.camera_up.xyz * corner.y) * p.size * 0.5;
+    gl_Position = params.view_projection * vec4(position, 1.0);
+    uv = corner;
+    color = mix(p.start_color, p.end_color, t);
+}
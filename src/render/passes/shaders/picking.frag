@@ -0,0 +1,14 @@
+#version 460
+
+layout(location = 0) out uint out_entity;
+
+layout(push_constant) uniform Draw {
+    mat4 view_projection;
+    mat4 model;
+    uint entity;
+} draw;
+
+void main()
+{
+    out_entity = draw.entity;
+}
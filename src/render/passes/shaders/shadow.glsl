@@ -0,0 +1,53 @@
+// Shadow sampling helpers for forward shaders. Fill a `ShadowData` block
+// from `ShadowPass::shadow_data` and bind `ShadowPass::shadow_map` with
+// `ShadowPass::sampler` as a sampler2DArrayShadow.
+//
+// Depth is reverse-Z, a fragment is lit when its depth is greater or equal
+// to the one stored in the shadow map.
+
+#ifndef SHADOW_NORMAL_OFFSET
+#define SHADOW_NORMAL_OFFSET 0.02
+#endif
+
+#ifndef SHADOW_DEPTH_BIAS
+#define SHADOW_DEPTH_BIAS 0.0005
+#endif
+
+struct ShadowData {
+    mat4 light_view_projection[4];
+    // View space distance where each cascade ends.
+    vec4 splits;
+    uint cascade_count;
+};
+
+uint shadow_select_cascade(ShadowData shadow, float view_depth)
+{
+    for (uint i = 0u; i + 1u < shadow.cascade_count; i++) {
+        if (view_depth < shadow.splits[i]) {
+            return i;
+        }
+    }
+    return shadow.cascade_count - 1u;
+}
+
+// 3x3 percentage closer filtering, returns 1 for fully lit. `view_depth`
+// is the positive distance from the camera, only used to pick a cascade.
+float shadow_pcf(sampler2DArrayShadow shadow_map, ShadowData shadow, vec3 world_position, vec3 world_normal, float view_depth)
+{
+    uint cascade = shadow_select_cascade(shadow, view_depth);
+
+    vec3 offset_position = world_position + normalize(world_normal) * SHADOW_NORMAL_OFFSET;
+    vec4 clip = shadow.light_view_projection[cascade] * vec4(offset_position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    float reference = ndc.z + SHADOW_DEPTH_BIAS;
+
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            lit += texture(shadow_map, vec4(uv + vec2(x, y) * texel, float(cascade), reference));
+        }
+    }
+    return lit / 9.0;
+}
@@ -0,0 +1,13 @@
+#version 460
+
+layout(location = 0) in vec3 position;
+
+layout(push_constant) uniform Caster {
+    mat4 light_view_projection;
+    mat4 model;
+} caster;
+
+void main()
+{
+    gl_Position = caster.light_view_projection * caster.model * vec4(position, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform textureCube environment_texture;
+layout(set = 0, binding = 0) uniform sampler environment_sampler;
+
+layout(location = 0) in vec3 direction;
+layout(location = 0) out vec4 color;
+
+void main()
+{
+    color = vec4(textureLod(samplerCube(environment_texture, environment_sampler), normalize(direction), 0.0).rgb, 1.0);
+}
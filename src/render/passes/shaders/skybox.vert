@@ -0,0 +1,19 @@
+#version 460
+
+// Fullscreen triangle at the far plane, reverse-Z depth 0, so it only
+// covers pixels no geometry was drawn to.
+
+layout(push_constant) uniform Params {
+    // Inverse of projection * rotation-only view.
+    mat4 inverse_view_projection;
+} params;
+
+layout(location = 0) out vec3 direction;
+
+void main()
+{
+    vec2 ndc = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    vec4 p = params.inverse_view_projection * vec4(ndc, 1.0, 1.0);
+    direction = p.xyz / p.w;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
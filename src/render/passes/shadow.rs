@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::cvars::{CVarParams, CVars};
+use crate::render::hal::{AddressMode, Filter, GraphicsPipelineCreateInfo, PipelineLayoutCreateInfo, PushConstantRange, RasterState, SamplerCreateInfo, ShaderCreateInfo, ShaderStages, TextureCreateInfo, TextureKind, VertexLayout, VertexSemantic};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::{add, length, normalize, scale, sub, Mat4, Vec3};
+use crate::render::mesh::Mesh;
+
+pub const MAX_CASCADES: usize = 4;
+
+/// GLSL helpers for sampling the shadow map, to be pasted into or included
+/// by forward shaders. Declares `ShadowData` and `shadow_pcf`.
+pub const SHADOW_GLSL: &str = include_str!("shaders/shadow.glsl");
+
+pub struct ShadowPassCreateInfo {
+    /// Width and height of each shadow map layer.
+    pub resolution: u32,
+    /// Cascades for directional lights, 1 for spot lights.
+    pub cascades: u32,
+    /// Layout of the caster meshes, only positions are read.
+    pub vertex_layout: VertexLayout,
+}
+
+/// Camera parameters needed to fit the cascades to the view frustum.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraFrustum {
+    pub view: Mat4,
+    pub fov_y: f32,
+    pub aspect: f32,
+    pub near: f32,
+    /// Distance up to which shadows are rendered.
+    pub shadow_distance: f32,
+}
+
+/// Cascade split distances with the practical split scheme, `lambda`
+/// blends between uniform (0) and logarithmic (1) splits.
+pub fn cascade_splits(near: f32, far: f32, cascades: u32, lambda: f32) -> Vec<f32> {
+    (1..=cascades).map(|i| {
+        let f = i as f32 / cascades as f32;
+        let log = near * (far / near).powf(f);
+        let uniform = near + (far - near) * f;
+        lambda * log + (1.0 - lambda) * uniform
+    }).collect()
+}
+
+fn light_up(direction: Vec3) -> Vec3 {
+    if direction[1].abs() > 0.99 { [0.0, 0.0, 1.0] } else { [0.0, 1.0, 0.0] }
+}
+
+/// Light space matrix for one directional cascade covering the view frustum
+/// slice between `near` and `far`. The slice is enclosed in a sphere so the
+/// projection doesn't change size when the camera rotates, and snapped to
+/// shadow map texels so it doesn't shimmer when the camera moves.
+/// `caster_distance` extends the volume towards the light, for casters
+/// outside the view.
+pub fn directional_cascade_matrix(camera: &CameraFrustum, direction: Vec3, near: f32, far: f32, resolution: u32, caster_distance: f32) -> Mat4 {
+    let inverse_view = camera.view.inverse().unwrap_or(Mat4::IDENTITY);
+    let tan_half_fov = (camera.fov_y * 0.5).tan();
+
+    let mut corners = Vec::with_capacity(8);
+    for depth in [near, far] {
+        let h = depth * tan_half_fov;
+        let w = h * camera.aspect;
+        for (x, y) in [(-w, -h), (w, -h), (-w, h), (w, h)] {
+            corners.push(inverse_view.transform_point([x, y, -depth]));
+        }
+    }
+
+    let center = scale(corners.iter().fold([0.0; 3], |acc, &c| add(acc, c)), 1.0 / 8.0);
+    let radius = corners.iter().map(|&c| length(sub(c, center))).fold(0.0, f32::max).ceil();
+
+    let direction = normalize(direction);
+    let light_view = Mat4::look_at([0.0; 3], direction, light_up(direction));
+
+    let texel = 2.0 * radius / resolution as f32;
+    let center = light_view.transform_point(center);
+    let x = (center[0] / texel).floor() * texel;
+    let y = (center[1] / texel).floor() * texel;
+    let distance = -center[2];
+
+    let projection = Mat4::orthographic(x - radius, x + radius, y - radius, y + radius, distance - radius - caster_distance, distance + radius);
+    projection * light_view
+}
+
+/// Light space matrix of a spot light with the given outer cone angle.
+pub fn spot_light_matrix(position: Vec3, direction: Vec3, outer_angle: f32, near: f32) -> Mat4 {
+    let direction = normalize(direction);
+    let view = Mat4::look_at(position, add(position, direction), light_up(direction));
+    Mat4::perspective(outer_angle * 2.0, 1.0, near) * view
+}
+
+/// Matches the `ShadowData` block of `SHADOW_GLSL`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ShadowData {
+    pub light_view_projection: [Mat4; MAX_CASCADES],
+    pub splits: [f32; MAX_CASCADES],
+    pub cascade_count: u32,
+}
+
+impl ShadowData {
+    /// std140 layout of the block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(288);
+        for m in &self.light_view_projection {
+            bytes.extend_from_slice(&m.to_bytes());
+        }
+        for s in &self.splits {
+            bytes.extend_from_slice(&s.to_ne_bytes());
+        }
+        bytes.extend_from_slice(&self.cascade_count.to_ne_bytes());
+        bytes.resize(288, 0);
+        bytes
+    }
+}
+
+/// Renders shadow casters into a depth texture array, one layer per
+/// cascade. The shadow map is left in `GENERAL` layout for sampling with
+/// `sampler` and the helpers in `SHADOW_GLSL`.
+pub struct ShadowPass {
+    pub caster_distance: f32,
+    /// Split scheme blend, see `cascade_splits`.
+    pub split_lambda: f32,
+
+    resolution: u32,
+    cascades: u32,
+    shadow_map: Arc<Texture>,
+    sampler: Arc<Sampler>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+    data: ShadowData,
+}
+
+impl ShadowPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: ShadowPassCreateInfo) -> Self {
+        assert!(create_info.cascades >= 1 && create_info.cascades as usize <= MAX_CASCADES);
+
+        let shadow_map = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::D32_SFLOAT,
+                extent: vk::Extent3D { width: create_info.resolution, height: create_info.resolution, depth: 1 },
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::DEPTH,
+                // At least two layers so the texture always gets an array view.
+                array_layers: create_info.cascades.max(2),
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+        shadow_map.set_name("shadow map");
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::ClampToBorder,
+                compare: Some(vk::CompareOp::GREATER_OR_EQUAL),
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: 128,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let mut vertex_layout = create_info.vertex_layout;
+        vertex_layout.attributes.retain(|a| a.semantic == VertexSemantic::Position);
+
+        let pipeline = {
+            let code = include_bytes_align_as!(u32, "shaders/shadow_depth.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code }),
+                fragment_shader: None,
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout,
+                color_formats: vec![],
+                depth_format: vk::Format::D32_SFLOAT,
+                extent: vk::Extent2D { width: create_info.resolution, height: create_info.resolution },
+                raster: RasterState {
+                    // Back faces only reduce acne on lit surfaces.
+                    cull_mode: vk::CullModeFlags::FRONT,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer, create_info)
+        };
+
+        Self {
+            caster_distance: 100.0,
+            split_lambda: 0.75,
+            resolution: create_info.resolution,
+            cascades: create_info.cascades,
+            shadow_map: Arc::new(shadow_map),
+            sampler,
+            pipeline,
+            pipeline_layout,
+            data: ShadowData::default(),
+        }
+    }
+
+    pub fn shadow_map(&self) -> Arc<Texture> {
+        self.shadow_map.clone()
+    }
+
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.sampler.clone()
+    }
+
+    /// Matrices and splits of the last update, for the forward shaders.
+    pub fn shadow_data(&self) -> ShadowData {
+        self.data
+    }
+
+    pub fn update_directional(&mut self, camera: &CameraFrustum, direction: Vec3) {
+        let splits = cascade_splits(camera.near, camera.shadow_distance, self.cascades, self.split_lambda);
+
+        let mut data = ShadowData { cascade_count: self.cascades, ..Default::default() };
+        let mut near = camera.near;
+        for (i, &far) in splits.iter().enumerate() {
+            data.light_view_projection[i] = directional_cascade_matrix(camera, direction, near, far, self.resolution, self.caster_distance);
+            data.splits[i] = far;
+            near = far;
+        }
+        self.data = data;
+    }
+
+    pub fn update_spot(&mut self, position: Vec3, direction: Vec3, outer_angle: f32, near: f32) {
+        let mut data = ShadowData { cascade_count: 1, ..Default::default() };
+        data.light_view_projection[0] = spot_light_matrix(position, direction, outer_angle, near);
+        data.splits[0] = f32::MAX;
+        self.data = data;
+    }
+
+    /// Renders every caster into every cascade. Casters are drawn with all
+    /// their submeshes, `transform` is the model matrix.
+    pub fn record(&self, command_list: &mut CommandList, casters: &[(Arc<Mesh>, Mat4)]) {
+        for cascade in 0..self.data.cascade_count {
+            command_list.begin_depth_rendering(&self.shadow_map, cascade);
+            command_list.bind_graphics_pipeline(self.pipeline.clone());
+
+            let light_view_projection = self.data.light_view_projection[cascade as usize];
+            for (mesh, transform) in casters {
+                let mut push_constants = [0u8; 128];
+                push_constants[..64].copy_from_slice(&light_view_projection.to_bytes());
+                push_constants[64..].copy_from_slice(&transform.to_bytes());
+                command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &push_constants);
+
+                mesh.bind(command_list);
+                for submesh in mesh.submeshes() {
+                    if mesh.is_indexed() {
+                        command_list.draw_indexed(submesh.indices.end - submesh.indices.start, 1, submesh.indices.start, submesh.base_vertex, 0);
+                    } else {
+                        command_list.draw(mesh.vertex_count(), 1, 0, 0);
+                    }
+                }
+            }
+
+            command_list.end_rendering();
+        }
+
+        command_list.transition_texture_layout(&self.shadow_map, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
+
+impl CVarParams for ShadowPass {
+    /// `resolution` is read at creation, changing it takes recreating the
+    /// pass from a `CVars::on_change` callback.
+    fn register_cvars(&self, cvars: &mut CVars, prefix: &str) {
+        cvars.register_ranged(&format!("{prefix}.resolution"), self.resolution, 256..=8192, "Shadow map size in texels");
+        cvars.register_ranged(&format!("{prefix}.caster_distance"), self.caster_distance, 0.0..=1000.0, "Extends the cascades towards the light, for casters outside the view");
+        cvars.register_ranged(&format!("{prefix}.split_lambda"), self.split_lambda, 0.0..=1.0, "Blend of logarithmic over uniform cascade splits");
+    }
+
+    fn apply_cvars(&mut self, cvars: &CVars, prefix: &str) {
+        cvars.read(&format!("{prefix}.caster_distance"), &mut self.caster_distance);
+        cvars.read(&format!("{prefix}.split_lambda"), &mut self.split_lambda);
+    }
+}
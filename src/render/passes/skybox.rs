@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, GraphicsPipelineCreateInfo, PipelineLayoutCreateInfo, PushConstantRange, RasterState, ShaderCreateInfo, ShaderStages, VertexLayout};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::{GraphicsPipeline, PipelineLayout};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::hal::vulkan::shader::Shader;
+use crate::render::math::Mat4;
+
+const PUSH_CONSTANTS_SIZE: u32 = 64;
+
+pub struct SkyboxPassCreateInfo {
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+/// Draws a cubemap behind everything else. Recorded inside
+/// `begin_rendering` after the opaque geometry, the sky is drawn at the far
+/// plane and depth tested so it only fills the uncovered pixels.
+pub struct SkyboxPass {
+    descriptor_set: Arc<DescriptorSet>,
+    pipeline: Arc<GraphicsPipeline>,
+    pipeline_layout: Arc<PipelineLayout>,
+}
+
+impl SkyboxPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: SkyboxPassCreateInfo) -> Self {
+        let descriptor_layout = {
+            let create_info = DescriptorSetLayoutCreateInfo {
+                bindings: vec![DescriptorSetBinding {
+                    stage: ShaderStages::Fragment,
+                    typ: BindingType::SampledTexture,
+                    binding: 0,
+                }],
+                push_descriptor: false,
+            };
+            DescriptorSetLayout::new(renderer.clone(), create_info)
+        };
+
+        let descriptor_set = DescriptorSet::new(renderer.clone(), descriptor_layout.clone());
+
+        let pipeline_layout = {
+            let create_info = PipelineLayoutCreateInfo {
+                sets: vec![descriptor_layout],
+                push_constant_ranges: vec![PushConstantRange {
+                    stage: ShaderStages::Vertex,
+                    offset: 0,
+                    size: PUSH_CONSTANTS_SIZE,
+                }],
+            };
+            PipelineLayout::new(renderer.clone(), create_info)
+        };
+
+        let pipeline = {
+            let vertex_code = include_bytes_align_as!(u32, "shaders/skybox_vert.spv");
+            let fragment_code = include_bytes_align_as!(u32, "shaders/skybox_frag.spv");
+            let create_info = GraphicsPipelineCreateInfo {
+                vertex_shader: Shader::new(renderer.clone(), ShaderCreateInfo { code: vertex_code }),
+                fragment_shader: Some(Shader::new(renderer.clone(), ShaderCreateInfo { code: fragment_code })),
+                geometry_shader: None,
+                tessellation: None,
+                pipeline_layout: pipeline_layout.clone(),
+                vertex_entrypoint: c"main",
+                fragment_entrypoint: c"main",
+                vertex_layout: VertexLayout::default(),
+                color_formats: vec![create_info.color_format],
+                depth_format: create_info.depth_format,
+                extent: create_info.extent,
+                raster: RasterState {
+                    cull_mode: vk::CullModeFlags::NONE,
+                    depth_write: false,
+                    ..RasterState::default()
+                },
+                view_mask: 0,
+            };
+            GraphicsPipeline::new(renderer, create_info)
+        };
+
+        Self { descriptor_set, pipeline, pipeline_layout }
+    }
+
+    /// `environment` is a cubemap in `GENERAL` layout, e.g.
+    /// `IblPass::environment`. Only the rotation of `view` is used.
+    pub fn record(&self, command_list: &mut CommandList, environment: &Texture, sampler: &Sampler, view: Mat4, projection: Mat4) {
+        self.descriptor_set.write_sampled_texture(0, environment, sampler);
+
+        let mut rotation = view;
+        rotation.0[3] = [0.0, 0.0, 0.0, 1.0];
+        let inverse_view_projection = (projection * rotation).inverse().unwrap_or(Mat4::IDENTITY);
+
+        command_list.bind_graphics_pipeline(self.pipeline.clone());
+        command_list.bind_descriptor_set(self.pipeline_layout.clone(), 0, self.descriptor_set.clone());
+        command_list.push_constants(self.pipeline_layout.clone(), ShaderStages::Vertex, 0, &inverse_view_projection.to_bytes());
+        command_list.draw(3, 1, 0, 0);
+    }
+}
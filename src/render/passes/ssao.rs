@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::cvars::{CVarParams, CVars};
+use crate::render::hal::{AddressMode, BindingType, BufferCreateInfo, Filter, MemoryLocation, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::Mat4;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+/// std140 size of `Params` in the occlusion shader.
+const PARAMS_SIZE: usize = 208;
+
+pub const OCCLUSION_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+pub struct SsaoPassCreateInfo {
+    pub extent: vk::Extent3D,
+}
+
+/// Screen-space ambient occlusion from depth and world space normals,
+/// followed by a depth aware blur. The output scales the ambient lighting,
+/// 1 where nothing occludes.
+pub struct SsaoPass {
+    /// View space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// 0 disables the occlusion, 1 darkens fully occluded pixels to black.
+    pub intensity: f32,
+    /// Depth difference below which samples don't occlude, against
+    /// self-occlusion on flat surfaces.
+    pub bias: f32,
+    pub sample_count: u32,
+
+    renderer: Arc<Renderer>,
+    raw: Texture,
+    output: Texture,
+    params: Vec<Buffer>,
+    sampler: Arc<Sampler>,
+    initialized: bool,
+    occlusion_kernel: ComputeKernel,
+    blur_kernel: ComputeKernel,
+}
+
+impl SsaoPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: SsaoPassCreateInfo) -> Self {
+        let target = |name| {
+            let create_info = TextureCreateInfo {
+                format: OCCLUSION_FORMAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            let texture = Texture::new(renderer.clone(), create_info);
+            texture.set_name(name);
+            texture
+        };
+
+        let params = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: PARAMS_SIZE as u64,
+                usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Nearest,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let occlusion_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/ssao.spv"),
+            &[BindingType::SampledTexture, BindingType::Texture, BindingType::Texture, BindingType::UniformBuffer],
+            0);
+        let blur_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/ssao_blur.spv"),
+            &[BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            0);
+
+        Self {
+            radius: 0.5,
+            intensity: 1.0,
+            bias: 0.025,
+            sample_count: 16,
+            raw: target("ssao raw"),
+            output: target("ssao"),
+            renderer,
+            params,
+            sampler,
+            initialized: false,
+            occlusion_kernel,
+            blur_kernel,
+        }
+    }
+
+    /// Blurred occlusion of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    fn params(&self, view: Mat4, projection: Mat4) -> [u8; PARAMS_SIZE] {
+        let inverse_projection = projection.inverse().unwrap_or(Mat4::IDENTITY);
+
+        let mut data = [0u8; PARAMS_SIZE];
+        data[0..64].copy_from_slice(&projection.to_bytes());
+        data[64..128].copy_from_slice(&inverse_projection.to_bytes());
+        data[128..192].copy_from_slice(&view.to_bytes());
+        data[192..196].copy_from_slice(&self.radius.to_ne_bytes());
+        data[196..200].copy_from_slice(&self.intensity.to_ne_bytes());
+        data[200..204].copy_from_slice(&self.bias.to_ne_bytes());
+        data[204..208].copy_from_slice(&self.sample_count.to_ne_bytes());
+        data
+    }
+
+    /// Computes the occlusion of the frame drawn with `view` and
+    /// `projection`. `depth` needs `SAMPLED` usage, `normals` are world
+    /// space in an `rgba16f` storage texture, as in the `GBuffer`. Both are
+    /// expected in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, depth: &Texture, normals: &Texture, view: Mat4, projection: Mat4) {
+        let extent = self.output.extent();
+        let groups_x = extent.width.div_ceil(WORKGROUP_SIZE);
+        let groups_y = extent.height.div_ceil(WORKGROUP_SIZE);
+
+        if !self.initialized {
+            command_list.transition_texture_layout(&self.raw, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            command_list.transition_texture_layout(&self.output, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            self.initialized = true;
+        }
+
+        let params = self.params(view, projection);
+        let buffer = &mut self.params[self.renderer.current_frame()];
+        buffer.write(0, &params);
+
+        command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(normals, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.occlusion_kernel.descriptor_set.write_sampled_texture(0, depth, &self.sampler);
+        self.occlusion_kernel.descriptor_set.write_texture(1, normals);
+        self.occlusion_kernel.descriptor_set.write_texture(2, &self.raw);
+        self.occlusion_kernel.descriptor_set.write_uniform_buffer(3, buffer);
+        self.occlusion_kernel.dispatch(command_list, &[], groups_x, groups_y, 1);
+
+        command_list.transition_texture_layout(&self.raw, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        self.blur_kernel.descriptor_set.write_texture(0, &self.raw);
+        self.blur_kernel.descriptor_set.write_sampled_texture(1, depth, &self.sampler);
+        self.blur_kernel.descriptor_set.write_texture(2, &self.output);
+        self.blur_kernel.dispatch(command_list, &[], groups_x, groups_y, 1);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+}
+
+impl CVarParams for SsaoPass {
+    fn register_cvars(&self, cvars: &mut CVars, prefix: &str) {
+        cvars.register_ranged(&format!("{prefix}.radius"), self.radius, 0.01..=10.0, "View space radius of the sampled hemisphere");
+        cvars.register_ranged(&format!("{prefix}.intensity"), self.intensity, 0.0..=1.0, "Darkening of fully occluded pixels");
+        cvars.register_ranged(&format!("{prefix}.bias"), self.bias, 0.0..=0.5, "Depth difference below which samples don't occlude");
+        cvars.register_ranged(&format!("{prefix}.sample_count"), self.sample_count, 1..=64, "Samples per pixel");
+    }
+
+    fn apply_cvars(&mut self, cvars: &CVars, prefix: &str) {
+        cvars.read(&format!("{prefix}.radius"), &mut self.radius);
+        cvars.read(&format!("{prefix}.intensity"), &mut self.intensity);
+        cvars.read(&format!("{prefix}.bias"), &mut self.bias);
+        cvars.read(&format!("{prefix}.sample_count"), &mut self.sample_count);
+    }
+}
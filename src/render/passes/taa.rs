@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{AddressMode, BindingType, Filter, SamplerCreateInfo, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sampler::Sampler;
+use crate::render::math::Mat4;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::upscale::{UpscaleInputs, Upscaler};
+
+const WORKGROUP_SIZE: u32 = 16;
+const VELOCITY_PUSH_CONSTANTS_SIZE: u32 = 72;
+const RESOLVE_PUSH_CONSTANTS_SIZE: u32 = 16;
+
+/// Length of the jitter sequence, see `Camera::set_jitter`.
+const JITTER_PHASES: u64 = 8;
+
+/// Format of the velocity texture, for forward shaders writing their own
+/// motion vectors.
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+/// How the reprojected history is constrained to the current frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryClamp {
+    /// No rejection, only usable for static scenes.
+    None,
+    /// Clamp to the 3x3 neighborhood bounds.
+    MinMax,
+    /// Clamp to the neighborhood mean plus or minus `variance_gamma`
+    /// standard deviations, tighter and less flickery than `MinMax`.
+    Variance,
+}
+
+pub struct TaaPassCreateInfo {
+    pub extent: vk::Extent2D,
+    pub clamp: HistoryClamp,
+}
+
+/// Temporal anti-aliasing. Render with `Camera::jittered_projection`,
+/// changing the jitter every frame with `Camera::set_jitter`, then resolve
+/// the color target with `record`.
+///
+/// Motion vectors are either given to `record`, in UV units from the
+/// previous to the current frame in `VELOCITY_FORMAT`, or generated from
+/// depth for the camera motion only.
+pub struct TaaPass {
+    pub clamp: HistoryClamp,
+    /// Weight of the history, higher is smoother but ghosts more.
+    pub history_weight: f32,
+    pub variance_gamma: f32,
+
+    /// Two textures alternating as the output of the current frame and the
+    /// history read from the previous one.
+    history: [Texture; 2],
+    current: usize,
+    history_valid: bool,
+    initialized: bool,
+    previous_view_projection: Option<Mat4>,
+    velocity: Texture,
+    sampler: Arc<Sampler>,
+    velocity_kernel: ComputeKernel,
+    resolve_kernel: ComputeKernel,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D, format: vk::Format, usage: vk::ImageUsageFlags, name: &str) -> Texture {
+    let create_info = TextureCreateInfo {
+        format,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    let texture = Texture::new(renderer.clone(), create_info);
+    texture.set_name(name);
+    texture
+}
+
+impl TaaPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: TaaPassCreateInfo) -> Self {
+        let history_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC;
+        let history = [
+            create_target(&renderer, create_info.extent, HDR_FORMAT, history_usage, "taa history 0"),
+            create_target(&renderer, create_info.extent, HDR_FORMAT, history_usage, "taa history 1"),
+        ];
+        let velocity_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let velocity = create_target(&renderer, create_info.extent, VELOCITY_FORMAT, velocity_usage, "taa velocity");
+
+        let sampler = {
+            let create_info = SamplerCreateInfo {
+                filter: Filter::Linear,
+                address_mode: AddressMode::ClampToEdge,
+                compare: None,
+            };
+            Sampler::new(renderer.clone(), create_info)
+        };
+
+        let velocity_kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/taa_velocity.spv"),
+            &[BindingType::SampledTexture, BindingType::Texture],
+            VELOCITY_PUSH_CONSTANTS_SIZE);
+        let resolve_kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/taa_resolve.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::SampledTexture, BindingType::Texture],
+            RESOLVE_PUSH_CONSTANTS_SIZE);
+
+        Self {
+            clamp: create_info.clamp,
+            history_weight: 0.9,
+            variance_gamma: 1.0,
+            history,
+            current: 0,
+            history_valid: false,
+            initialized: false,
+            previous_view_projection: None,
+            velocity,
+            sampler,
+            velocity_kernel,
+            resolve_kernel,
+        }
+    }
+
+    /// Antialiased result of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.history[self.current]
+    }
+
+    /// Motion vectors generated by the last `record` without external
+    /// velocity, for motion blur or upscaling.
+    pub fn velocity(&self) -> &Texture {
+        &self.velocity
+    }
+
+    /// Drops the history, after camera cuts or teleports.
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+        self.previous_view_projection = None;
+    }
+
+    /// Resolves `color` into `output`. `depth` needs `SAMPLED` usage and is
+    /// only read when `velocity` is `None`. `view_projection` and `jitter`
+    /// are the unjittered matrix and the jitter the frame was rendered with.
+    /// Everything is expected to be in `GENERAL` layout.
+    pub fn record(&mut self, command_list: &mut CommandList, color: &Texture, depth: &Texture, velocity: Option<&Texture>, view_projection: Mat4, jitter: [f32; 2]) {
+        let extent = self.velocity.extent();
+        let groups_x = extent.width.div_ceil(WORKGROUP_SIZE);
+        let groups_y = extent.height.div_ceil(WORKGROUP_SIZE);
+
+        if !self.initialized {
+            for texture in &self.history {
+                command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            }
+            command_list.transition_texture_layout(&self.velocity, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+            self.initialized = true;
+        }
+
+        command_list.transition_texture_layout(color, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        let velocity = match velocity {
+            Some(velocity) => velocity,
+            None => {
+                let previous = self.previous_view_projection.unwrap_or(view_projection);
+                let reprojection = previous * view_projection.inverse().unwrap_or(Mat4::IDENTITY);
+
+                let mut push_constants = [0u8; VELOCITY_PUSH_CONSTANTS_SIZE as usize];
+                push_constants[0..64].copy_from_slice(&reprojection.to_bytes());
+                push_constants[64..68].copy_from_slice(&jitter[0].to_ne_bytes());
+                push_constants[68..72].copy_from_slice(&jitter[1].to_ne_bytes());
+
+                command_list.transition_texture_layout(depth, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+                self.velocity_kernel.descriptor_set.write_sampled_texture(0, depth, &self.sampler);
+                self.velocity_kernel.descriptor_set.write_texture(1, &self.velocity);
+                self.velocity_kernel.dispatch(command_list, &push_constants, groups_x, groups_y, 1);
+                command_list.transition_texture_layout(&self.velocity, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+                &self.velocity
+            }
+        };
+
+        let history = self.current;
+        self.current = 1 - self.current;
+
+        let clamp: u32 = match self.clamp {
+            HistoryClamp::None => 0,
+            HistoryClamp::MinMax => 1,
+            HistoryClamp::Variance => 2,
+        };
+        let mut push_constants = [0u8; RESOLVE_PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&self.history_weight.to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&clamp.to_ne_bytes());
+        push_constants[8..12].copy_from_slice(&self.variance_gamma.to_ne_bytes());
+        push_constants[12..16].copy_from_slice(&(self.history_valid as u32).to_ne_bytes());
+
+        self.resolve_kernel.descriptor_set.write_texture(0, color);
+        self.resolve_kernel.descriptor_set.write_texture(1, velocity);
+        self.resolve_kernel.descriptor_set.write_sampled_texture(2, &self.history[history], &self.sampler);
+        self.resolve_kernel.descriptor_set.write_texture(3, &self.history[self.current]);
+        self.resolve_kernel.dispatch(command_list, &push_constants, groups_x, groups_y, 1);
+        command_list.transition_texture_layout(&self.history[self.current], vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+
+        self.history_valid = true;
+        self.previous_view_projection = Some(view_projection);
+    }
+}
+
+/// Anti-aliasing without upscaling, input and output have the same size.
+impl Upscaler for TaaPass {
+    fn input_extent(&self) -> vk::Extent2D {
+        let extent = self.velocity.extent();
+        vk::Extent2D { width: extent.width, height: extent.height }
+    }
+
+    fn output_extent(&self) -> vk::Extent2D {
+        self.input_extent()
+    }
+
+    fn hdr_input(&self) -> bool {
+        true
+    }
+
+    fn jitter_phases(&self) -> u64 {
+        JITTER_PHASES
+    }
+
+    fn reset(&mut self) {
+        TaaPass::reset(self);
+    }
+
+    fn record(&mut self, command_list: &mut CommandList, inputs: &UpscaleInputs) {
+        TaaPass::record(self, command_list, inputs.color, inputs.depth, inputs.motion_vectors, inputs.view_projection, inputs.jitter);
+    }
+
+    fn output(&self) -> &Texture {
+        TaaPass::output(self)
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::cvars::{CVarParams, CVars};
+use crate::render::hal::{BindingType, BufferCreateInfo, Filter, MemoryLocation, ScalingMode, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::ui::UiCompositor;
+use crate::render::passes::upscale::{UpscaleInputs, Upscaler};
+
+const WORKGROUP_SIZE: u32 = 16;
+const PUSH_CONSTANTS_SIZE: u32 = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TonemapOperator {
+    Aces,
+    Reinhard,
+    AgX,
+}
+
+/// How the tonemapped color has to be encoded so that it ends up correct
+/// on screen after being blitted to the swapchain image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputEncoding {
+    /// Swapchain format is `*_SRGB`, the hardware encodes on write.
+    Linear,
+    /// `UNORM` swapchain presented as sRGB, the shader applies the sRGB OETF.
+    Srgb,
+    /// HDR10 swapchain, the shader converts to Rec.2020 and applies the PQ curve.
+    Pq,
+}
+
+impl OutputEncoding {
+    pub fn from_surface_format(surface_format: vk::SurfaceFormatKHR) -> Self {
+        match (surface_format.format, surface_format.color_space) {
+            (_, vk::ColorSpaceKHR::HDR10_ST2084_EXT) => OutputEncoding::Pq,
+            (_, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT) => OutputEncoding::Linear,
+            (vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32, _) => OutputEncoding::Linear,
+            _ => OutputEncoding::Srgb,
+        }
+    }
+}
+
+pub struct TonemapPassCreateInfo {
+    pub extent: vk::Extent3D,
+    pub operator: TonemapOperator,
+    pub exposure: f32,
+    /// Brightness of diffuse white in nits, only used for PQ output.
+    pub paper_white: f32,
+    pub filter: Filter,
+    pub scaling: ScalingMode,
+}
+
+/// Converts the HDR draw target into the swapchain image, applying the
+/// selected tonemapping operator and the encoding required by the
+/// negotiated surface format.
+pub struct TonemapPass {
+    pub operator: TonemapOperator,
+    /// Multiplies the adapted exposure with auto exposure.
+    pub exposure: f32,
+    pub paper_white: f32,
+    pub filter: Filter,
+    pub scaling: ScalingMode,
+
+    encoding: OutputEncoding,
+    output: Texture,
+    /// Written by an `AutoExposurePass`.
+    auto_exposure: Option<Arc<Buffer>>,
+    /// Bound in place of `auto_exposure` when there's none.
+    placeholder_exposure: Buffer,
+    kernel: ComputeKernel,
+}
+
+impl TonemapPass {
+    pub fn new(renderer: Arc<Renderer>, create_info: TonemapPassCreateInfo) -> Self {
+        let output = {
+            let create_info = TextureCreateInfo {
+                format: vk::Format::R16G16B16A16_SFLOAT,
+                extent: create_info.extent,
+                usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let kernel = ComputeKernel::new(
+            renderer.clone(),
+            include_bytes_align_as!(u32, "shaders/tonemap.spv"),
+            &[BindingType::Texture, BindingType::Texture, BindingType::StorageBuffer],
+            PUSH_CONSTANTS_SIZE);
+
+        let placeholder_exposure = {
+            let create_info = BufferCreateInfo {
+                size: 8,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        };
+
+        Self {
+            operator: create_info.operator,
+            exposure: create_info.exposure,
+            paper_white: create_info.paper_white,
+            filter: create_info.filter,
+            scaling: create_info.scaling,
+            encoding: OutputEncoding::from_surface_format(renderer.swapchain_format()),
+            output,
+            auto_exposure: None,
+            placeholder_exposure,
+            kernel,
+        }
+    }
+
+    pub fn encoding(&self) -> OutputEncoding {
+        self.encoding
+    }
+
+    /// Scales `exposure` by the exposure an `AutoExposurePass` writes to
+    /// `buffer`, see `AutoExposurePass::exposure_buffer`.
+    pub fn set_auto_exposure(&mut self, buffer: Option<Arc<Buffer>>) {
+        self.auto_exposure = buffer;
+    }
+
+    fn push_constants(&self) -> [u8; PUSH_CONSTANTS_SIZE as usize] {
+        let operator: u32 = match self.operator {
+            TonemapOperator::Aces => 0,
+            TonemapOperator::Reinhard => 1,
+            TonemapOperator::AgX => 2,
+        };
+        let encoding: u32 = match self.encoding {
+            OutputEncoding::Linear => 0,
+            OutputEncoding::Srgb => 1,
+            OutputEncoding::Pq => 2,
+        };
+
+        let mut data = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        data[0..4].copy_from_slice(&operator.to_ne_bytes());
+        data[4..8].copy_from_slice(&encoding.to_ne_bytes());
+        data[8..12].copy_from_slice(&self.exposure.to_ne_bytes());
+        data[12..16].copy_from_slice(&self.paper_white.to_ne_bytes());
+        data[16..20].copy_from_slice(&(self.auto_exposure.is_some() as u32).to_ne_bytes());
+        data
+    }
+
+    /// Records the tonemapping dispatch and the copy into the current
+    /// swapchain image. `source` is expected to hold linear color, like
+    /// `HDR_FORMAT` targets, in `GENERAL` layout.
+    pub fn record(&self, command_list: &mut CommandList, source: &Texture) {
+        self.dispatch(command_list, source);
+        command_list.copy_to_framebuffer(&self.output, self.filter, self.scaling);
+    }
+
+    /// Like `record` with the UI layer of `ui` composited over the
+    /// tonemapped image, in its output encoding.
+    pub fn record_with_ui(&self, command_list: &mut CommandList, source: &Texture, ui: &UiCompositor) {
+        self.dispatch(command_list, source);
+        ui.composite(command_list, &self.output, self.encoding, self.paper_white);
+        command_list.copy_to_framebuffer(&self.output, self.filter, self.scaling);
+    }
+
+    fn dispatch(&self, command_list: &mut CommandList, source: &Texture) {
+        let extent = self.output.extent();
+
+        self.kernel.descriptor_set.write_texture(0, source);
+        self.kernel.descriptor_set.write_texture(1, &self.output);
+        self.kernel.descriptor_set.write_storage_buffer(2, self.auto_exposure.as_deref().unwrap_or(&self.placeholder_exposure));
+
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        self.kernel.dispatch(command_list, &self.push_constants(), extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+    }
+
+    /// Like `record` with `upscaler` in the chain, before tonemapping when
+    /// it takes HDR input and after otherwise. The pass must have been
+    /// created with the extent of the image it tonemaps, the upscaler's
+    /// output extent in the first case and its input extent in the second.
+    pub fn record_upscaled(&self, command_list: &mut CommandList, inputs: &UpscaleInputs, upscaler: &mut dyn Upscaler) {
+        if upscaler.hdr_input() {
+            upscaler.record(command_list, inputs);
+            self.dispatch(command_list, upscaler.output());
+            command_list.copy_to_framebuffer(&self.output, self.filter, self.scaling);
+        } else {
+            self.dispatch(command_list, inputs.color);
+            upscaler.record(command_list, &UpscaleInputs { color: &self.output, ..*inputs });
+            command_list.copy_to_framebuffer(upscaler.output(), self.filter, self.scaling);
+        }
+    }
+}
+
+impl CVarParams for TonemapPass {
+    fn register_cvars(&self, cvars: &mut CVars, prefix: &str) {
+        cvars.register_ranged(&format!("{prefix}.exposure"), self.exposure, 0.0..=16.0, "Exposure multiplier");
+        cvars.register_ranged(&format!("{prefix}.paper_white"), self.paper_white, 80.0..=500.0, "Nits of diffuse white on HDR displays");
+    }
+
+    fn apply_cvars(&mut self, cvars: &CVars, prefix: &str) {
+        cvars.read(&format!("{prefix}.exposure"), &mut self.exposure);
+        cvars.read(&format!("{prefix}.paper_white"), &mut self.paper_white);
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, ColorAttachment, LoadOp, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::passes::kernel::ComputeKernel;
+use crate::render::passes::tonemap::OutputEncoding;
+
+const WORKGROUP_SIZE: u32 = 16;
+const PUSH_CONSTANTS_SIZE: u32 = 8;
+
+/// Premultiplied alpha with sRGB encoded colors, as sprite batches, text
+/// renderers and egui output them.
+pub const UI_LAYER_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+pub struct UiCompositorCreateInfo {
+    /// Extent of the tonemapped image the UI is composited over.
+    pub extent: vk::Extent2D,
+}
+
+/// 2D layer drawn over the tonemapped scene, so UI colors reach the
+/// screen as authored instead of going through exposure and tonemapping.
+/// The layer keeps its content between frames, only the regions that
+/// changed are cleared and redrawn:
+///
+/// ```ignore
+/// if ui.begin(command_list, &dirty_rects) {
+///     // Sprites, text and egui meshes, with `BlendMode::Alpha` pipelines
+///     // for `UI_LAYER_FORMAT` created with dynamic viewport and scissor.
+///     ui.end(command_list);
+/// }
+/// tonemap.record_with_ui(command_list, &hdr, &ui);
+/// ```
+pub struct UiCompositor {
+    layer: Texture,
+    kernel: ComputeKernel,
+    initialized: bool,
+}
+
+impl UiCompositor {
+    pub fn new(renderer: Arc<Renderer>, create_info: UiCompositorCreateInfo) -> Self {
+        let layer = {
+            let create_info = TextureCreateInfo {
+                format: UI_LAYER_FORMAT,
+                extent: vk::Extent3D { width: create_info.extent.width, height: create_info.extent.height, depth: 1 },
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+        layer.set_name("ui layer");
+
+        let kernel = ComputeKernel::new(
+            renderer,
+            include_bytes_align_as!(u32, "shaders/ui_composite.spv"),
+            &[BindingType::Texture, BindingType::Texture],
+            PUSH_CONSTANTS_SIZE);
+        kernel.descriptor_set.write_texture(1, &layer);
+
+        Self { layer, kernel, initialized: false }
+    }
+
+    pub fn layer(&self) -> &Texture {
+        &self.layer
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        let extent = self.layer.extent();
+        vk::Extent2D { width: extent.width, height: extent.height }
+    }
+
+    fn full_rect(&self) -> vk::Rect2D {
+        vk::Rect2D { offset: vk::Offset2D::default(), extent: self.extent() }
+    }
+
+    /// Bounding rectangle of `rects`, clamped to the layer.
+    fn union(&self, rects: &[vk::Rect2D]) -> Option<vk::Rect2D> {
+        let full = self.full_rect();
+        let (mut min, mut max) = ([i32::MAX; 2], [i32::MIN; 2]);
+        for rect in rects {
+            min = [min[0].min(rect.offset.x), min[1].min(rect.offset.y)];
+            max = [max[0].max(rect.offset.x + rect.extent.width as i32), max[1].max(rect.offset.y + rect.extent.height as i32)];
+        }
+        let min = [min[0].max(0), min[1].max(0)];
+        let max = [max[0].min(full.extent.width as i32), max[1].min(full.extent.height as i32)];
+        if min[0] >= max[0] || min[1] >= max[1] {
+            return None;
+        }
+        Some(vk::Rect2D {
+            offset: vk::Offset2D { x: min[0], y: min[1] },
+            extent: vk::Extent2D { width: (max[0] - min[0]) as u32, height: (max[1] - min[1]) as u32 },
+        })
+    }
+
+    /// Clears the bounding rectangle of the `dirty` regions and starts
+    /// rendering into the layer, scissored to it. Everything overlapping the
+    /// rectangle has to be redrawn. The whole layer is dirty the first time.
+    /// Returns false without starting rendering when nothing is dirty.
+    pub fn begin(&mut self, command_list: &mut CommandList, dirty: &[vk::Rect2D]) -> bool {
+        let rect = if self.initialized { self.union(dirty) } else { Some(self.full_rect()) };
+        let Some(rect) = rect else {
+            return false;
+        };
+
+        let old_layout = if self.initialized { vk::ImageLayout::GENERAL } else { vk::ImageLayout::UNDEFINED };
+        command_list.transition_texture_layout(&self.layer, old_layout, vk::ImageLayout::GENERAL);
+        self.initialized = true;
+
+        command_list.begin_rendering_attachments(&[ColorAttachment { texture: &self.layer, load: LoadOp::Load }], None);
+        command_list.clear_color_attachment(0, rect, [0.0; 4]);
+
+        let full = self.full_rect();
+        command_list.set_viewport(vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: full.extent.width as f32,
+            height: full.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        });
+        command_list.set_scissor(rect);
+        true
+    }
+
+    pub fn end(&self, command_list: &mut CommandList) {
+        command_list.end_rendering();
+        command_list.transition_texture_layout(&self.layer, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+    }
+
+    /// Blends the layer over `target`, a tonemapped `rgba16f` storage
+    /// texture of the layer's extent encoded with `encoding`, in `GENERAL`
+    /// layout. With PQ output the UI's white is `paper_white` nits.
+    pub fn composite(&self, command_list: &mut CommandList, target: &Texture, encoding: OutputEncoding, paper_white: f32) {
+        if !self.initialized {
+            return;
+        }
+
+        let encoding: u32 = match encoding {
+            OutputEncoding::Linear => 0,
+            OutputEncoding::Srgb => 1,
+            OutputEncoding::Pq => 2,
+        };
+        let mut push_constants = [0u8; PUSH_CONSTANTS_SIZE as usize];
+        push_constants[0..4].copy_from_slice(&encoding.to_ne_bytes());
+        push_constants[4..8].copy_from_slice(&paper_white.to_ne_bytes());
+
+        self.kernel.descriptor_set.write_texture(0, target);
+        command_list.transition_texture_layout(target, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        let extent = target.extent();
+        self.kernel.dispatch(command_list, &push_constants, extent.width.div_ceil(WORKGROUP_SIZE), extent.height.div_ceil(WORKGROUP_SIZE), 1);
+    }
+}
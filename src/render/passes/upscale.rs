@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::include_bytes_align_as;
+use crate::render::hal::{BindingType, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::Mat4;
+use crate::render::passes::HDR_FORMAT;
+use crate::render::passes::kernel::ComputeKernel;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+/// What an `Upscaler` gets to work with, all in `GENERAL` layout and at
+/// the upscaler's input extent.
+#[derive(Clone, Copy)]
+pub struct UpscaleInputs<'a> {
+    /// HDR scene color for upscalers with `hdr_input`, tonemapped color
+    /// otherwise. `TonemapPass::record_upscaled` picks the right one.
+    pub color: &'a Texture,
+    /// Needs `SAMPLED` usage, only read by temporal upscalers.
+    pub depth: &'a Texture,
+    /// In `taa::VELOCITY_FORMAT`. Temporal upscalers generate the camera
+    /// motion from depth when `None`.
+    pub motion_vectors: Option<&'a Texture>,
+    /// Without jitter.
+    pub view_projection: Mat4,
+    /// NDC jitter the frame was rendered with, see `Camera::jitter`.
+    pub jitter: [f32; 2],
+}
+
+/// Integration point for upscalers, spatial or temporal, built in or
+/// wrapping an external library. The renderer draws at `input_extent`,
+/// the upscaler produces `output` at `output_extent`.
+pub trait Upscaler {
+    fn input_extent(&self) -> vk::Extent2D;
+
+    fn output_extent(&self) -> vk::Extent2D;
+
+    /// Runs on the HDR scene color before tonemapping rather than on the
+    /// tonemapped image.
+    fn hdr_input(&self) -> bool;
+
+    /// Length of the jitter sequence to render with, see
+    /// `Camera::set_jitter_phases`. 1 when no jitter is needed.
+    fn jitter_phases(&self) -> u64;
+
+    /// Drops accumulated history, after camera cuts.
+    fn reset(&mut self);
+
+    fn record(&mut self, command_list: &mut CommandList, inputs: &UpscaleInputs);
+
+    /// Result of the last `record`, in `GENERAL` layout.
+    fn output(&self) -> &Texture;
+}
+
+pub struct UpscalePassCreateInfo {
+    /// Size the input is rendered at, see `Renderer::render_extent`.
+    pub input_extent: vk::Extent2D,
+    /// Usually the swapchain extent.
+    pub output_extent: vk::Extent2D,
+    pub sharpness: f32,
+}
+
+/// FSR 1 style spatial upscaler: a deringed Catmull-Rom upscale followed by
+/// contrast adaptive sharpening. Runs after tonemapping, on display referred
+/// colors. Plain bilinear upscaling needs no pass, `copy_to_framebuffer`
+/// blits with the requested filter.
+pub struct UpscalePass {
+    /// 0 disables sharpening, 1 is the strongest.
+    pub sharpness: f32,
+
+    input_extent: vk::Extent2D,
+    upscaled: Texture,
+    output: Texture,
+    upscale_kernel: ComputeKernel,
+    sharpen_kernel: ComputeKernel,
+}
+
+fn create_target(renderer: &Arc<Renderer>, extent: vk::Extent2D) -> Texture {
+    let create_info = TextureCreateInfo {
+        format: HDR_FORMAT,
+        extent: vk::Extent3D { width: extent.width, height: extent.height, depth: 1 },
+        usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::STORAGE,
+        aspect: vk::ImageAspectFlags::COLOR,
+        array_layers: 1,
+        mip_levels: 1,
+        kind: TextureKind::D2,
+    };
+    Texture::new(renderer.clone(), create_info)
+}
+
+impl UpscalePass {
+    pub fn new(renderer: Arc<Renderer>, create_info: UpscalePassCreateInfo) -> Self {
+        let upscaled = create_target(&renderer, create_info.output_extent);
+        upscaled.set_name("upscaled");
+        let output = create_target(&renderer, create_info.output_extent);
+        output.set_name("upscaled sharpened");
+
+        let bindings = [BindingType::Texture, BindingType::Texture];
+        let upscale_kernel = ComputeKernel::new(renderer.clone(), include_bytes_align_as!(u32, "shaders/upscale.spv"), &bindings, 0);
+        let sharpen_kernel = ComputeKernel::new(renderer, include_bytes_align_as!(u32, "shaders/sharpen.spv"), &bindings, 4);
+
+        Self {
+            sharpness: create_info.sharpness,
+            input_extent: create_info.input_extent,
+            upscaled,
+            output,
+            upscale_kernel,
+            sharpen_kernel,
+        }
+    }
+
+    /// Result of the last `record`, in `GENERAL` layout.
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    /// `source` is expected to be in `GENERAL` layout.
+    pub fn record(&self, command_list: &mut CommandList, source: &Texture) {
+        let extent = self.output.extent();
+        let groups_x = extent.width.div_ceil(WORKGROUP_SIZE);
+        let groups_y = extent.height.div_ceil(WORKGROUP_SIZE);
+
+        command_list.transition_texture_layout(source, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.upscaled, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+
+        self.upscale_kernel.descriptor_set.write_texture(0, source);
+        self.upscale_kernel.descriptor_set.write_texture(1, &self.upscaled);
+        self.upscale_kernel.dispatch(command_list, &[], groups_x, groups_y, 1);
+
+        command_list.transition_texture_layout(&self.upscaled, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        command_list.transition_texture_layout(&self.output, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+
+        self.sharpen_kernel.descriptor_set.write_texture(0, &self.upscaled);
+        self.sharpen_kernel.descriptor_set.write_texture(1, &self.output);
+        self.sharpen_kernel.dispatch(command_list, &self.sharpness.clamp(0.0, 1.0).to_ne_bytes(), groups_x, groups_y, 1);
+    }
+}
+
+impl Upscaler for UpscalePass {
+    fn input_extent(&self) -> vk::Extent2D {
+        self.input_extent
+    }
+
+    fn output_extent(&self) -> vk::Extent2D {
+        let extent = self.output.extent();
+        vk::Extent2D { width: extent.width, height: extent.height }
+    }
+
+    fn hdr_input(&self) -> bool {
+        false
+    }
+
+    fn jitter_phases(&self) -> u64 {
+        1
+    }
+
+    fn reset(&mut self) {}
+
+    fn record(&mut self, command_list: &mut CommandList, inputs: &UpscaleInputs) {
+        UpscalePass::record(self, command_list, inputs.color);
+    }
+
+    fn output(&self) -> &Texture {
+        &self.output
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::{CommandListCreateInfo, QueueType, Result};
+use crate::render::hal::vulkan::acceleration_structure::{AccelerationStructure, AccelerationStructureInstance, CompactedSizeQueries};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::sync::Fence;
+use crate::render::mesh::Mesh;
+use crate::render::scene::Draw;
+
+pub struct AccelerationStructureManagerCreateInfo {
+    /// Maximum number of bottom level structures built per frame.
+    pub max_batch_size: u32,
+    /// Copies bottom level structures into compacted ones after their
+    /// build, which usually take half the memory or less.
+    pub compaction: bool,
+}
+
+struct BlasEntry {
+    mesh: Arc<Mesh>,
+    /// `None` until the first build executed.
+    blas: Option<Arc<AccelerationStructure>>,
+}
+
+/// Work submitted to the compute queue from one frame slot.
+#[derive(Default)]
+struct Batch {
+    /// Meshes and built structures, in compacted size query order. The
+    /// meshes are kept to tell them apart from meshes added at the same
+    /// address after a `remove`.
+    built: Vec<(Arc<Mesh>, Arc<AccelerationStructure>)>,
+    compacted: Vec<(Arc<Mesh>, Arc<AccelerationStructure>)>,
+}
+
+/// Bottom level acceleration structures of meshes, and a top level
+/// structure of a draw list updated every frame.
+///
+/// Bottom level structures are built in batches on the compute queue, see
+/// `Capabilities::AsyncCompute`, without stalling the frame: a mesh added
+/// with `add` appears in the top level structure some frames later. With
+/// `compaction`, each one is then replaced by a compacted copy. The top
+/// level structure is refit while the draws keep their meshes and order,
+/// and rebuilt otherwise:
+///
+/// ```ignore
+/// manager.add(&mesh)?;
+/// loop {
+///     let mut frame = frames.begin_frame()?;
+///     manager.update()?;
+///     let tlas = manager.update_tlas(frame.command_list(), &draws.draws);
+///     shadows.record(frame.command_list(), &tlas, ..);
+///     frame.end()?;
+/// }
+/// ```
+pub struct AccelerationStructureManager {
+    /// Number of refits after which the top level structure is rebuilt
+    /// anyway, refit structures trace slower the further instances moved.
+    pub max_refits: u32,
+
+    /// Compute queue list, declared first to wait for its work on drop.
+    command_list: CommandList,
+    fence: Fence,
+    queries: [CompactedSizeQueries; FRAME_OVERLAP],
+    batches: [Option<Batch>; FRAME_OVERLAP],
+    /// Keyed by mesh address.
+    entries: HashMap<usize, BlasEntry>,
+    queued: VecDeque<usize>,
+    /// Meshes and compacted sizes of structures to compact in the next
+    /// batch.
+    pending_compactions: Vec<(Arc<Mesh>, u64)>,
+    max_batch_size: u32,
+    compaction: bool,
+    tlas: Option<Arc<AccelerationStructure>>,
+    refits: u32,
+    renderer: Arc<Renderer>,
+}
+
+impl AccelerationStructureManager {
+    /// Panics without `Capabilities::RayQuery`.
+    pub fn new(renderer: Arc<Renderer>, create_info: AccelerationStructureManagerCreateInfo) -> Self {
+        assert!(renderer.ray_tracing.is_some(), "Acceleration structures need Capabilities::RayQuery");
+        let command_list = {
+            let create_info = CommandListCreateInfo { queue: QueueType::Compute };
+            CommandList::new(renderer.clone(), create_info)
+        };
+        let max_batch_size = create_info.max_batch_size.max(1);
+
+        Self {
+            max_refits: 60,
+            command_list,
+            fence: Fence::new(renderer.clone()),
+            queries: std::array::from_fn(|_| CompactedSizeQueries::new(renderer.clone(), max_batch_size)),
+            batches: std::array::from_fn(|_| None),
+            entries: HashMap::new(),
+            queued: VecDeque::new(),
+            pending_compactions: Vec::new(),
+            max_batch_size,
+            compaction: create_info.compaction,
+            tlas: None,
+            refits: 0,
+            renderer,
+        }
+    }
+
+    /// Queues the build of `mesh`'s bottom level structure. The mesh needs
+    /// positions, has to be built with `MeshBuilder::ray_tracing`, and its
+    /// upload must have executed, the compute queue doesn't wait for the
+    /// graphics queue.
+    pub fn add(&mut self, mesh: &Arc<Mesh>) -> Result<()> {
+        mesh.triangles()?;
+        let key = Arc::as_ptr(mesh) as usize;
+        if let Entry::Vacant(entry) = self.entries.entry(key) {
+            entry.insert(BlasEntry { mesh: mesh.clone(), blas: None });
+            self.queued.push_back(key);
+        }
+        Ok(())
+    }
+
+    /// Drops the structure of `mesh`, once no frame in flight uses it.
+    pub fn remove(&mut self, mesh: &Arc<Mesh>) {
+        let key = Arc::as_ptr(mesh) as usize;
+        self.entries.remove(&key);
+        self.queued.retain(|&k| k != key);
+        self.pending_compactions.retain(|(m, _)| !Arc::ptr_eq(m, mesh));
+    }
+
+    /// The structure of `mesh` once its build executed.
+    pub fn blas(&self, mesh: &Arc<Mesh>) -> Option<&Arc<AccelerationStructure>> {
+        self.entries.get(&(Arc::as_ptr(mesh) as usize))?.blas.as_ref()
+    }
+
+    /// Meshes added whose structure isn't built yet.
+    pub fn pending_count(&self) -> usize {
+        self.entries.values().filter(|e| e.blas.is_none()).count()
+    }
+
+    /// GPU memory of the bottom level structures built so far.
+    pub fn blas_memory(&self) -> u64 {
+        self.entries.values().filter_map(|e| e.blas.as_ref()).map(|b| b.size()).sum()
+    }
+
+    /// The top level structure of the last `update_tlas`.
+    pub fn tlas(&self) -> Option<&Arc<AccelerationStructure>> {
+        self.tlas.as_ref()
+    }
+
+    /// Takes the structures of the compute batch of this frame slot once it
+    /// executed, and submits the next batch. Call once per frame, after the
+    /// frame fence has been waited on.
+    pub fn update(&mut self) -> Result<()> {
+        crate::trace_span!("AccelerationStructureManager::update");
+        let frame = self.renderer.current_frame();
+        if self.batches[frame].is_some() {
+            if !self.fence.is_signaled()? {
+                return Ok(());
+            }
+            let batch = self.batches[frame].take().unwrap();
+            self.collect(batch)?;
+        }
+        self.submit_batch()
+    }
+
+    /// The entry of `mesh`, unless it was removed and another mesh added at
+    /// its address since.
+    fn entry_mut(&mut self, mesh: &Arc<Mesh>) -> Option<&mut BlasEntry> {
+        self.entries.get_mut(&(Arc::as_ptr(mesh) as usize)).filter(|e| Arc::ptr_eq(&e.mesh, mesh))
+    }
+
+    fn collect(&mut self, batch: Batch) -> Result<()> {
+        let frame = self.renderer.current_frame();
+        let sizes = if self.compaction {
+            self.queries[frame].results(batch.built.len() as u32)?
+        } else {
+            Vec::new()
+        };
+
+        for (i, (mesh, blas)) in batch.built.into_iter().enumerate() {
+            let Some(entry) = self.entry_mut(&mesh) else {
+                continue;
+            };
+            entry.blas = Some(blas.clone());
+            if let Some(&size) = sizes.get(i) {
+                if size > 0 && size < blas.size() {
+                    self.pending_compactions.push((mesh, size));
+                }
+            }
+        }
+        for (mesh, compacted) in batch.compacted {
+            if let Some(entry) = self.entry_mut(&mesh) {
+                entry.blas = Some(compacted);
+            }
+        }
+        Ok(())
+    }
+
+    fn submit_batch(&mut self) -> Result<()> {
+        if self.queued.is_empty() && self.pending_compactions.is_empty() {
+            return Ok(());
+        }
+        let frame = self.renderer.current_frame();
+        let count = self.queued.len().min(self.max_batch_size as usize);
+        let keys = self.queued.drain(..count).collect::<Vec<_>>();
+        let compactions = std::mem::take(&mut self.pending_compactions);
+
+        self.command_list.reset();
+        self.command_list.begin();
+
+        let mut batch = Batch::default();
+        for (mesh, size) in compactions {
+            if let Some(blas) = self.entries.get(&(Arc::as_ptr(&mesh) as usize)).and_then(|e| e.blas.as_ref()) {
+                let compacted = blas.compact(&mut self.command_list, size);
+                batch.compacted.push((mesh, compacted));
+            }
+        }
+
+        let meshes = keys.iter().map(|key| self.entries[key].mesh.clone()).collect::<Vec<_>>();
+        let triangles = meshes.iter().map(|mesh| mesh.triangles()).collect::<Result<Vec<_>>>()?;
+        let structures = triangles.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let mut flags = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE;
+        if self.compaction {
+            flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION;
+        }
+        let built = AccelerationStructure::bottom_level_batch(self.renderer.clone(), &mut self.command_list, &structures, flags);
+        if self.compaction {
+            self.queries[frame].write(&self.command_list, &built);
+        }
+        // Removed meshes are still read until the batch executed.
+        for mesh in &meshes {
+            self.command_list.retain(mesh.clone());
+        }
+        batch.built = meshes.into_iter().zip(built).collect();
+
+        self.command_list.end();
+        self.fence.reset()?;
+        self.renderer.submit(&self.command_list, &[], &[], &self.fence)?;
+        self.batches[frame] = Some(batch);
+        Ok(())
+    }
+
+    /// Records the update of the top level structure to `draws` whose
+    /// meshes have a built structure, others are left out. The instance
+    /// custom index is the draw's index in `draws`.
+    pub fn update_tlas(&mut self, command_list: &mut CommandList, draws: &[Draw]) -> Arc<AccelerationStructure> {
+        crate::trace_span!("AccelerationStructureManager::update_tlas");
+        let instances = draws.iter().enumerate().filter_map(|(i, draw)| {
+            let blas = self.blas(&draw.mesh)?;
+            let mut instance = AccelerationStructureInstance::new(blas.clone(), draw.transform);
+            instance.custom_index = i as u32;
+            Some(instance)
+        }).collect::<Vec<_>>();
+
+        let refit = self.refits < self.max_refits && self.tlas.as_ref().is_some_and(|tlas| tlas.can_refit(&instances));
+        if refit {
+            self.tlas.as_ref().unwrap().refit(command_list, &instances);
+            self.refits += 1;
+        } else {
+            let flags = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE;
+            let tlas = AccelerationStructure::top_level_with_flags(self.renderer.clone(), command_list, &instances, flags);
+            tlas.set_name("scene tlas");
+            self.tlas = Some(tlas);
+            self.refits = 0;
+        }
+        self.tlas.clone().unwrap()
+    }
+}
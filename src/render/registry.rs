@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use slotmap::{new_key_type, SlotMap};
+
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::pipeline::ComputePipeline;
+
+new_key_type! {
+    pub struct TextureHandle;
+    pub struct BufferHandle;
+    pub struct PipelineHandle;
+}
+
+/// Owns GPU resources and hands out small copyable handles with generation
+/// counters, so a stale handle to a removed resource resolves to `None`
+/// instead of aliasing a new one.
+///
+/// The registry is owned by the application rather than the `Renderer`:
+/// every resource keeps its renderer alive, so storing them on the renderer
+/// would form a reference cycle.
+#[derive(Default)]
+pub struct ResourceRegistry {
+    textures: SlotMap<TextureHandle, Arc<Texture>>,
+    buffers: SlotMap<BufferHandle, Arc<Buffer>>,
+    pipelines: SlotMap<PipelineHandle, Arc<ComputePipeline>>,
+}
+
+impl ResourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_texture(&mut self, texture: impl Into<Arc<Texture>>) -> TextureHandle {
+        self.textures.insert(texture.into())
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<&Arc<Texture>> {
+        self.textures.get(handle)
+    }
+
+    /// Replaces the texture behind an existing handle, e.g. after a hot reload.
+    pub fn replace_texture(&mut self, handle: TextureHandle, texture: impl Into<Arc<Texture>>) -> Option<Arc<Texture>> {
+        self.textures.get_mut(handle).map(|slot| std::mem::replace(slot, texture.into()))
+    }
+
+    pub fn remove_texture(&mut self, handle: TextureHandle) -> Option<Arc<Texture>> {
+        self.textures.remove(handle)
+    }
+
+    pub fn insert_buffer(&mut self, buffer: impl Into<Arc<Buffer>>) -> BufferHandle {
+        self.buffers.insert(buffer.into())
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Option<&Arc<Buffer>> {
+        self.buffers.get(handle)
+    }
+
+    pub fn replace_buffer(&mut self, handle: BufferHandle, buffer: impl Into<Arc<Buffer>>) -> Option<Arc<Buffer>> {
+        self.buffers.get_mut(handle).map(|slot| std::mem::replace(slot, buffer.into()))
+    }
+
+    pub fn remove_buffer(&mut self, handle: BufferHandle) -> Option<Arc<Buffer>> {
+        self.buffers.remove(handle)
+    }
+
+    pub fn insert_pipeline(&mut self, pipeline: Arc<ComputePipeline>) -> PipelineHandle {
+        self.pipelines.insert(pipeline)
+    }
+
+    pub fn pipeline(&self, handle: PipelineHandle) -> Option<&Arc<ComputePipeline>> {
+        self.pipelines.get(handle)
+    }
+
+    pub fn replace_pipeline(&mut self, handle: PipelineHandle, pipeline: Arc<ComputePipeline>) -> Option<Arc<ComputePipeline>> {
+        self.pipelines.get_mut(handle).map(|slot| std::mem::replace(slot, pipeline))
+    }
+
+    pub fn remove_pipeline(&mut self, handle: PipelineHandle) -> Option<Arc<ComputePipeline>> {
+        self.pipelines.remove(handle)
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    /// Drops every registered resource. Resources still referenced by
+    /// in-flight command lists stay alive until those are reset.
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.buffers.clear();
+        self.pipelines.clear();
+    }
+}
@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use slotmap::{new_key_type, SlotMap};
+
+use crate::render::camera::Camera;
+use crate::render::culling::{CullStats, Frustum};
+use crate::render::instancing::InstanceBatcher;
+use crate::render::material::MaterialInstance;
+use crate::render::math::{normalize, Mat4, Quat, QUAT_IDENTITY, Vec3};
+use crate::render::mesh::Mesh;
+
+new_key_type! {
+    pub struct NodeId;
+}
+
+/// Local transform of a node relative to its parent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translation: [0.0; 3], rotation: QUAT_IDENTITY, scale: [1.0; 3] }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self { translation, ..Self::default() }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_trs(self.translation, self.rotation, self.scale)
+    }
+}
+
+#[derive(Clone)]
+pub struct MeshComponent {
+    pub mesh: Arc<Mesh>,
+    pub material: Arc<MaterialInstance>,
+}
+
+/// How a light's shadows are rendered.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ShadowMode {
+    None,
+    #[default]
+    ShadowMap,
+    /// With `RtShadowPass`, falls back to shadow maps without
+    /// `Capabilities::RayQuery`.
+    RayTraced,
+}
+
+/// Lights shine down -Z of their node.
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    Directional { color: Vec3, intensity: f32, shadows: ShadowMode },
+    Point { color: Vec3, intensity: f32, radius: f32, shadows: ShadowMode },
+}
+
+impl Light {
+    pub fn shadows(&self) -> ShadowMode {
+        match *self {
+            Light::Directional { shadows, .. } | Light::Point { shadows, .. } => shadows,
+        }
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    pub mesh: Option<MeshComponent>,
+    pub light: Option<Light>,
+    /// Position and rotation of the camera are ignored, the view follows
+    /// the node.
+    pub camera: Option<Camera>,
+
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Transform,
+    world: Mat4,
+    dirty: bool,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.local
+    }
+
+    /// As of the last `Scene::update_transforms`.
+    pub fn world_transform(&self) -> Mat4 {
+        self.world
+    }
+}
+
+pub struct Draw {
+    /// The null `NodeId::default()` for draws added with
+    /// `DrawList::extend_meshes`.
+    pub node: NodeId,
+    pub mesh: Arc<Mesh>,
+    pub material: Arc<MaterialInstance>,
+    pub transform: Mat4,
+}
+
+pub struct SceneLight {
+    pub node: NodeId,
+    pub light: Light,
+    pub position: Vec3,
+    pub direction: Vec3,
+}
+
+pub struct SceneCamera {
+    pub node: NodeId,
+    pub camera: Camera,
+    pub view: Mat4,
+}
+
+/// Flat output of `Scene::extract`, in depth-first order. Can also be
+/// filled from an ECS query instead of a `Scene`:
+///
+/// ```ignore
+/// list.clear();
+/// list.extend_meshes(world.query::<(&GlobalTransform, &MeshComponent)>().iter().map(|(_, (t, m))| (t.0, m)));
+/// ```
+#[derive(Default)]
+pub struct DrawList {
+    pub draws: Vec<Draw>,
+    pub lights: Vec<SceneLight>,
+    pub cameras: Vec<SceneCamera>,
+}
+
+impl DrawList {
+    /// Adds draws of meshes with their world transforms.
+    pub fn extend_meshes<'a>(&mut self, meshes: impl IntoIterator<Item = (Mat4, &'a MeshComponent)>) {
+        self.draws.extend(meshes.into_iter().map(|(transform, mesh)| Draw {
+            node: NodeId::default(),
+            mesh: mesh.mesh.clone(),
+            material: mesh.material.clone(),
+            transform,
+        }));
+    }
+
+    /// Empties the list, keeping its allocations for the next frame.
+    pub fn clear(&mut self) {
+        self.draws.clear();
+        self.lights.clear();
+        self.cameras.clear();
+    }
+
+    /// Drops the draws whose mesh bounds are outside the view frustum.
+    /// Meshes without bounds are kept. The cheaper bounding sphere test runs
+    /// first, the box is only tested for draws the sphere doesn't reject.
+    pub fn cull(&mut self, view_projection: Mat4) -> CullStats {
+        let frustum = Frustum::from_view_projection(view_projection);
+        let submitted = self.draws.len();
+        self.draws.retain(|draw| {
+            let bounds = draw.mesh.bounds();
+            bounds.is_empty() || (frustum.intersects_sphere(&draw.mesh.bounding_sphere().transform(&draw.transform))
+                && frustum.intersects_aabb(&bounds.transform(&draw.transform)))
+        });
+        CullStats { submitted, culled: submitted - self.draws.len() }
+    }
+
+    /// Adds every draw to `batcher`.
+    pub fn batch(&self, batcher: &mut InstanceBatcher) {
+        for draw in &self.draws {
+            batcher.add(&draw.mesh, &draw.material, draw.transform);
+        }
+    }
+}
+
+/// Node hierarchy with parent relative transforms. World matrices are
+/// recomputed by `update_transforms` for nodes whose transform, or one of
+/// whose ancestors' transforms, changed since.
+#[derive(Default)]
+pub struct Scene {
+    nodes: SlotMap<NodeId, Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>, transform: Transform) -> NodeId {
+        let node = Node {
+            name: name.to_string(),
+            mesh: None,
+            light: None,
+            camera: None,
+            parent,
+            children: Vec::new(),
+            local: transform,
+            world: Mat4::IDENTITY,
+            dirty: true,
+        };
+        let id = self.nodes.insert(node);
+        self.siblings(parent).push(id);
+        id
+    }
+
+    /// Removes the node with all its descendants.
+    pub fn remove_node(&mut self, id: NodeId) {
+        let Some(parent) = self.nodes.get(id).map(|n| n.parent) else {
+            return;
+        };
+        self.siblings(parent).retain(|&c| c != id);
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes.remove(id) {
+                stack.extend(node.children);
+            }
+        }
+    }
+
+    /// Moves the node under `parent`, keeping its local transform.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            assert!(a != id, "Node can't be parented to its own descendant");
+            ancestor = self.nodes[a].parent;
+        }
+
+        let old_parent = self.nodes[id].parent;
+        self.siblings(old_parent).retain(|&c| c != id);
+        self.siblings(parent).push(id);
+
+        let node = &mut self.nodes[id];
+        node.parent = parent;
+        node.dirty = true;
+    }
+
+    fn siblings(&mut self, parent: Option<NodeId>) -> &mut Vec<NodeId> {
+        match parent {
+            Some(parent) => &mut self.nodes[parent].children,
+            None => &mut self.roots,
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    /// For editing components, transforms are set with `set_transform`.
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id)
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        let node = &mut self.nodes[id];
+        node.local = transform;
+        node.dirty = true;
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Depth-first over every node, parents before their children.
+    pub fn traverse(&self, mut f: impl FnMut(NodeId, &Node)) {
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id];
+            f(id, node);
+            stack.extend(node.children.iter().rev());
+        }
+    }
+
+    pub fn update_transforms(&mut self) {
+        let mut stack = self.roots.iter().rev().map(|&id| (id, Mat4::IDENTITY, false)).collect::<Vec<_>>();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id];
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().rev().map(|&child| (child, world, changed)));
+        }
+    }
+
+    /// Updates the transforms and collects the components with their world
+    /// transforms.
+    pub fn extract(&mut self) -> DrawList {
+        self.update_transforms();
+
+        let mut list = DrawList::default();
+        self.traverse(|id, node| {
+            if let Some(mesh) = &node.mesh {
+                list.draws.push(Draw { node: id, mesh: mesh.mesh.clone(), material: mesh.material.clone(), transform: node.world });
+            }
+            if let Some(light) = node.light {
+                list.lights.push(SceneLight {
+                    node: id,
+                    light,
+                    position: node.world.transform_point([0.0; 3]),
+                    direction: normalize(node.world.transform_vector([0.0, 0.0, -1.0])),
+                });
+            }
+            if let Some(camera) = node.camera {
+                let view = node.world.inverse().unwrap_or(Mat4::IDENTITY);
+                list.cameras.push(SceneCamera { node: id, camera, view });
+            }
+        });
+        list
+    }
+}
@@ -0,0 +1,18 @@
+#version 460
+
+layout(set = 0, binding = 1) uniform sampler2DArray captures;
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) flat in float layer;
+
+layout(location = 0) out vec4 out_color;
+
+void main()
+{
+    // Captures have a single mip level.
+    vec4 color = textureLod(captures, vec3(uv, layer), 0.0);
+    if (color.a < 0.5) {
+        discard;
+    }
+    out_color = vec4(color.rgb, 1.0);
+}
@@ -0,0 +1,40 @@
+#version 460
+
+// Quad per impostor, two triangles from the vertex index. The quad lies in
+// the image plane of the capture picked for the instance, so it lines up
+// with what was rendered into that layer.
+
+struct Instance {
+    // xyz center, w half size.
+    vec4 center;
+    // xyz right axis of the capture, w layer.
+    vec4 right;
+    vec4 up;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Instances {
+    Instance instances[];
+};
+
+layout(push_constant) uniform Params {
+    mat4 view_projection;
+} params;
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) flat out float layer;
+
+void main()
+{
+    const vec2 corners[6] = vec2[6](
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+    );
+    vec2 corner = corners[gl_VertexIndex];
+
+    Instance instance = instances[gl_InstanceIndex];
+    vec3 position = instance.center.xyz + (instance.right.xyz * corner.x + instance.up.xyz * corner.y) * instance.center.w;
+    gl_Position = params.view_projection * vec4(position, 1.0);
+    // Captures have Y pointing down, like the projection.
+    uv = vec2(corner.x, -corner.y) * 0.5 + 0.5;
+    layer = instance.right.w;
+}
@@ -0,0 +1,17 @@
+#version 460
+
+// Lighting is baked into the captures with a fixed key light from above,
+// impostors are drawn unlit.
+
+layout(location = 0) in vec3 normal;
+layout(location = 1) flat in vec4 base_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main()
+{
+    const vec3 light = normalize(vec3(0.3, 1.0, 0.2));
+    float diffuse = max(dot(normalize(normal), light), 0.0);
+    // Alpha marks covered texels, the background is cleared to 0.
+    out_color = vec4(base_color.rgb * (0.3 + 0.7 * diffuse), 1.0);
+}
@@ -0,0 +1,20 @@
+#version 460
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(push_constant) uniform Capture {
+    mat4 view_projection;
+    mat4 model;
+    vec4 base_color;
+} capture;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) flat out vec4 out_color;
+
+void main()
+{
+    out_normal = mat3(capture.model) * normal;
+    out_color = capture.base_color;
+    gl_Position = capture.view_projection * capture.model * vec4(position, 1.0);
+}
@@ -0,0 +1,53 @@
+#version 460
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+
+#ifdef SKINNED
+layout(location = 5) in uvec4 joints;
+layout(location = 6) in vec4 weights;
+
+// Joint matrices of the skinned mesh, already multiplied by the inverse
+// bind matrices.
+layout(std430, set = 1, binding = 0) readonly buffer Skin {
+    mat4 joint_matrices[];
+};
+#endif
+
+#ifdef INSTANCED
+// Model matrix columns, see `VertexSemantic::InstanceTransform`.
+layout(location = 7) in vec4 instance_column0;
+layout(location = 8) in vec4 instance_column1;
+layout(location = 9) in vec4 instance_column2;
+layout(location = 10) in vec4 instance_column3;
+#endif
+
+layout(push_constant) uniform Object {
+    mat4 view_projection;
+    mat4 model;
+} object;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec2 out_uv;
+
+void main()
+{
+    mat4 model = object.model;
+
+#ifdef INSTANCED
+    model = model * mat4(instance_column0, instance_column1, instance_column2, instance_column3);
+#endif
+
+#ifdef SKINNED
+    mat4 skin = weights.x * joint_matrices[joints.x]
+        + weights.y * joint_matrices[joints.y]
+        + weights.z * joint_matrices[joints.z]
+        + weights.w * joint_matrices[joints.w];
+    model = model * skin;
+#endif
+
+    gl_Position = object.view_projection * model * vec4(position, 1.0);
+    out_normal = mat3(model) * normal;
+    out_uv = uv;
+}
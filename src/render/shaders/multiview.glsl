@@ -0,0 +1,36 @@
+// Per-view matrices of multiview passes. Fill a `MultiviewData` block from
+// `view::MultiviewData::to_bytes` and draw between
+// `CommandList::begin_rendering_views` and `end_rendering`; every draw runs
+// once per view, with `gl_ViewIndex` selecting the view's data.
+//
+// Define MULTIVIEW_SET and MULTIVIEW_BINDING before including to move the
+// block off set 0, binding 0.
+
+#extension GL_EXT_multiview : require
+
+#ifndef MULTIVIEW_SET
+#define MULTIVIEW_SET 0
+#endif
+
+#ifndef MULTIVIEW_BINDING
+#define MULTIVIEW_BINDING 0
+#endif
+
+#define MULTIVIEW_MAX_VIEWS 6
+
+layout(set = MULTIVIEW_SET, binding = MULTIVIEW_BINDING) uniform MultiviewData {
+    mat4 view_projection[MULTIVIEW_MAX_VIEWS];
+    // World space eye position in xyz.
+    vec4 position[MULTIVIEW_MAX_VIEWS];
+    uint view_count;
+} multiview;
+
+mat4 multiview_view_projection()
+{
+    return multiview.view_projection[gl_ViewIndex];
+}
+
+vec3 multiview_position()
+{
+    return multiview.position[gl_ViewIndex].xyz;
+}
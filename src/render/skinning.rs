@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::hal::{BindingType, BufferCreateInfo, DescriptorSetBinding, DescriptorSetLayoutCreateInfo, Error, MemoryLocation, Result, ShaderStages};
+use crate::render::hal::vulkan::buffer::Buffer;
+use crate::render::hal::vulkan::descriptor_set::{DescriptorSet, DescriptorSetLayout};
+use crate::render::hal::vulkan::FRAME_OVERLAP;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::{lerp, quat_slerp, Mat4, Quat, Vec3, QUAT_IDENTITY};
+
+/// Descriptor set index of the joint matrices in skinned pipelines.
+pub const SKIN_SET: u32 = 1;
+
+/// Layout of the `SKIN_SET` descriptor set: a storage buffer with one
+/// matrix per joint, read by the vertex stage.
+pub fn skin_descriptor_layout(renderer: Arc<Renderer>) -> Arc<DescriptorSetLayout> {
+    let create_info = DescriptorSetLayoutCreateInfo {
+        bindings: vec![DescriptorSetBinding {
+            stage: ShaderStages::Vertex,
+            typ: BindingType::StorageBuffer,
+            binding: 0,
+        }],
+        push_descriptor: false,
+    };
+    DescriptorSetLayout::new(renderer, create_info)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self { translation: [0.0; 3], rotation: QUAT_IDENTITY, scale: [1.0; 3] }
+    }
+}
+
+impl Transform {
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_trs(self.translation, self.rotation, self.scale)
+    }
+
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: lerp(self.translation, other.translation, t),
+            rotation: quat_slerp(self.rotation, other.rotation, t),
+            scale: lerp(self.scale, other.scale, t),
+        }
+    }
+}
+
+/// Mirrors a glTF skin joint: the node's rest transform relative to its
+/// parent and the skin's inverse bind matrix.
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub rest: Transform,
+    pub inverse_bind: Mat4,
+}
+
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    /// Joints must be sorted so that parents come before their children.
+    pub fn new(joints: Vec<Joint>) -> Result<Self> {
+        for (i, joint) in joints.iter().enumerate() {
+            if joint.parent.is_some_and(|p| p >= i) {
+                return Err(Error::Asset(format!("Joint {} comes before its parent", joint.name)));
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn find_joint(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+}
+
+/// Local transform of every joint of a skeleton.
+#[derive(Clone)]
+pub struct Pose {
+    pub local: Vec<Transform>,
+}
+
+impl Pose {
+    pub fn rest(skeleton: &Skeleton) -> Self {
+        Self { local: skeleton.joints.iter().map(|j| j.rest).collect() }
+    }
+
+    /// Blends towards `other` by `weight`, e.g. to cross-fade two clips.
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (a, b) in self.local.iter_mut().zip(&other.local) {
+            *a = a.lerp(b, weight);
+        }
+    }
+
+    /// Skinning matrices, the joint's model space transform times its
+    /// inverse bind matrix, in the layout expected by `Skin::update`.
+    pub fn joint_matrices(&self, skeleton: &Skeleton) -> Vec<Mat4> {
+        let mut global: Vec<Mat4> = Vec::with_capacity(self.local.len());
+        for (joint, local) in skeleton.joints.iter().zip(&self.local) {
+            let transform = match joint.parent {
+                Some(parent) => global[parent] * local.to_matrix(),
+                None => local.to_matrix(),
+            };
+            global.push(transform);
+        }
+
+        global.iter().zip(&skeleton.joints)
+            .map(|(global, joint)| *global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+/// Keyframes animating one property of one joint, like a glTF animation
+/// channel together with its sampler.
+#[derive(Clone, Debug)]
+pub struct AnimationChannel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, increasing.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+impl AnimationChannel {
+    /// Keyframe pair around `time` and the interpolation factor between them.
+    fn keyframes(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+
+        let prev = next - 1;
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => (time - self.times[prev]) / (self.times[next] - self.times[prev]),
+        };
+        (prev, next, t)
+    }
+
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        if self.times.is_empty() {
+            return;
+        }
+
+        let (a, b, t) = self.keyframes(time);
+        match &self.values {
+            ChannelValues::Translation(v) => transform.translation = lerp(v[a], v[b], t),
+            ChannelValues::Rotation(v) => transform.rotation = quat_slerp(v[a], v[b], t),
+            ChannelValues::Scale(v) => transform.scale = lerp(v[a], v[b], t),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<AnimationChannel>,
+}
+
+impl AnimationClip {
+    pub fn duration(&self) -> f32 {
+        self.channels.iter()
+            .filter_map(|c| c.times.last().copied())
+            .fold(0.0, f32::max)
+    }
+
+    /// Overwrites the joints animated by this clip in `pose`. Joints without
+    /// channels keep their current transform.
+    pub fn sample(&self, time: f32, looping: bool, pose: &mut Pose) {
+        let duration = self.duration();
+        let time = if looping && duration > 0.0 { time.rem_euclid(duration) } else { time };
+
+        for channel in &self.channels {
+            if let Some(transform) = pose.local.get_mut(channel.joint) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+}
+
+/// Joint matrices of a skinned mesh instance, double buffered like the
+/// rest of the per frame data.
+pub struct Skin {
+    renderer: Arc<Renderer>,
+    descriptor_set: Arc<DescriptorSet>,
+    buffers: Vec<Buffer>,
+    joint_count: usize,
+}
+
+impl Skin {
+    pub fn new(renderer: Arc<Renderer>, joint_count: usize) -> Self {
+        let descriptor_set = DescriptorSet::new(renderer.clone(), skin_descriptor_layout(renderer.clone()));
+
+        let buffers = (0..FRAME_OVERLAP).map(|_| {
+            let create_info = BufferCreateInfo {
+                size: (joint_count.max(1) * size_of::<Mat4>()) as u64,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: MemoryLocation::CpuToGpu,
+            };
+            Buffer::new(renderer.clone(), create_info)
+        }).collect();
+
+        Self { renderer, descriptor_set, buffers, joint_count }
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joint_count
+    }
+
+    /// Uploads the joint matrices for the current frame and points the
+    /// descriptor set at them.
+    pub fn update(&mut self, joint_matrices: &[Mat4]) {
+        assert!(joint_matrices.len() <= self.joint_count, "Skin was created for {} joints", self.joint_count);
+
+        let data = joint_matrices.iter().flat_map(|m| m.to_bytes()).collect::<Vec<_>>();
+        let buffer = &mut self.buffers[self.renderer.current_frame()];
+        buffer.write(0, &data);
+        self.descriptor_set.write_storage_buffer(0, buffer);
+    }
+
+    pub fn descriptor_set(&self) -> Arc<DescriptorSet> {
+        self.descriptor_set.clone()
+    }
+}
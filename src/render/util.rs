@@ -1,3 +1,15 @@
+/// `log` targets of the subsystems, to filter by, e.g. with
+/// `RUST_LOG=patoka::hal::vulkan=warn` under env_logger. `tracing`
+/// subscribers receive the records through `tracing_log::LogTracer`.
+pub mod log_target {
+    /// Validation layer and driver messages, adapter selection, device loss.
+    pub const VULKAN: &str = "patoka::hal::vulkan";
+    /// Swapchain creation and present mode fallbacks.
+    pub const SWAPCHAIN: &str = "patoka::hal::swapchain";
+    /// Failed asset loads.
+    pub const ASSET: &str = "patoka::asset";
+}
+
 #[macro_use]
 pub mod macros {
     #[repr(C)] // guarantee 'bytes' comes after '_align'
@@ -27,4 +39,15 @@ pub mod macros {
             }
         };
     }
+
+    /// Enters a `tracing` span until the end of the enclosing block, compiled
+    /// out without the `trace` feature.
+    #[macro_export]
+    #[doc(hidden)]
+    macro_rules! trace_span {
+        ($($args:tt)*) => {
+            #[cfg(feature = "trace")]
+            let _span = ::tracing::info_span!($($args)*).entered();
+        };
+    }
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use bitflags::Flags;
+
+use crate::render::hal::{Result, ShaderCreateInfo};
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::hal::vulkan::shader::Shader;
+
+/// Compiles shader source to SPIR-V with the given defines set, e.g. by
+/// calling shaderc or glslc at runtime.
+pub type ShaderCompiler = Box<dyn Fn(&[&'static str]) -> Result<Vec<u32>> + Send + Sync>;
+
+/// Creates the pipeline for a compiled variant of the shader.
+pub type VariantBuilder<P> = Box<dyn Fn(Arc<Shader>) -> Arc<P> + Send + Sync>;
+
+/// Pipelines for every combination of shader defines, keyed by a bitflags
+/// type whose flag names are the define names:
+///
+/// ```ignore
+/// bitflags! {
+///     #[derive(Clone, Copy)]
+///     struct MaterialDefines: u32 {
+///         const ALPHA_TEST = 0x1;
+///         const SKINNED = 0x2;
+///         const NORMAL_MAP = 0x4;
+///     }
+/// }
+/// ```
+///
+/// Variants are compiled the first time they're requested and cached after.
+pub struct PipelineVariantSet<F: Flags, P> where F::Bits: Hash + Eq {
+    renderer: Arc<Renderer>,
+    compiler: ShaderCompiler,
+    builder: VariantBuilder<P>,
+    variants: Mutex<HashMap<F::Bits, Arc<P>>>,
+}
+
+impl<F: Flags, P> PipelineVariantSet<F, P> where F::Bits: Hash + Eq {
+    pub fn new(renderer: Arc<Renderer>, compiler: ShaderCompiler, builder: VariantBuilder<P>) -> Self {
+        Self { renderer, compiler, builder, variants: Mutex::new(HashMap::new()) }
+    }
+
+    /// Compiles the variant on a cache miss, which blocks the calling thread.
+    /// Use `prewarm` at load time for the variants known to be needed.
+    pub fn get(&self, defines: F) -> Result<Arc<P>> {
+        let key = defines.bits();
+        if let Some(pipeline) = self.variants.lock().unwrap().get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        // Compile without holding the lock, so other variants can be served
+        // meanwhile. Two threads racing on the same variant both compile it.
+        let names = defines.iter_names().map(|(name, _)| name).collect::<Vec<_>>();
+        let code = (self.compiler)(&names)?;
+        let shader = Shader::new(self.renderer.clone(), ShaderCreateInfo { code: &code });
+        let pipeline = (self.builder)(shader);
+
+        Ok(self.variants.lock().unwrap().entry(key).or_insert(pipeline).clone())
+    }
+
+    pub fn prewarm(&self, variants: impl IntoIterator<Item = F>) -> Result<()> {
+        for defines in variants {
+            self.get(defines)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_compiled(&self, defines: F) -> bool {
+        self.variants.lock().unwrap().contains_key(&defines.bits())
+    }
+
+    pub fn compiled_count(&self) -> usize {
+        self.variants.lock().unwrap().len()
+    }
+
+    /// Drops every cached variant, e.g. after the shader source changed.
+    pub fn clear(&self) {
+        self.variants.lock().unwrap().clear();
+    }
+}
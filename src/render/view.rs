@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::render::camera::Camera;
+use crate::render::hal::{ColorAttachment, DepthAttachment, LoadOp, TextureCreateInfo, TextureKind};
+use crate::render::hal::vulkan::command_list::CommandList;
+use crate::render::hal::vulkan::image::Texture;
+use crate::render::hal::vulkan::renderer::Renderer;
+use crate::render::math::{add, Mat4, Vec3, Vec4};
+
+/// GLSL block and helpers for the per-view data of multiview passes, to be
+/// included by their shaders. Declares `MultiviewData` and
+/// `multiview_view_projection`.
+pub const MULTIVIEW_GLSL: &str = include_str!("shaders/multiview.glsl");
+
+/// Views of one multiview pass `MultiviewData` holds, the most every
+/// device with `Capabilities::Multiview` supports.
+pub const MAX_VIEWS: usize = 6;
+
+/// Cubemap faces in layer order, +X, -X, +Y, -Y, +Z, -Z, as the direction
+/// looked in and the up vector.
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// Expresses `plane`, `(normal, distance)` with `dot(normal, p) + distance`
+/// positive in front, in the space `to_space` maps to.
+fn transform_plane(to_space: Mat4, plane: Vec4) -> Vec4 {
+    let inverse = to_space.inverse().unwrap_or(Mat4::IDENTITY);
+    inverse.0.map(|column| column.iter().zip(plane).map(|(a, b)| a * b).sum())
+}
+
+/// View and projection a scene is rendered with. Unlike `Camera`, mirrored
+/// views can be represented.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ViewMatrices {
+    pub view: Mat4,
+    pub projection: Mat4,
+}
+
+impl ViewMatrices {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self { view: camera.view(), projection: camera.projection() }
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection * self.view
+    }
+
+    /// World space position of the eye.
+    pub fn position(&self) -> Vec3 {
+        self.view.inverse().unwrap_or(Mat4::IDENTITY).transform_point([0.0; 3])
+    }
+
+    /// This view reflected by the world space `plane`, for mirrors and
+    /// planar reflections, clipped to the plane's front. The image is also
+    /// flipped horizontally to keep the triangles' winding, sample it at
+    /// `(1 - u, v)` with the screen coordinates of this view.
+    pub fn mirrored(&self, plane: Vec4) -> Self {
+        let [x, y, z, d] = plane;
+        let reflection = Mat4([
+            [1.0 - 2.0 * x * x, -2.0 * y * x, -2.0 * z * x, 0.0],
+            [-2.0 * x * y, 1.0 - 2.0 * y * y, -2.0 * z * y, 0.0],
+            [-2.0 * x * z, -2.0 * y * z, 1.0 - 2.0 * z * z, 0.0],
+            [-2.0 * d * x, -2.0 * d * y, -2.0 * d * z, 1.0],
+        ]);
+        let mirrored = Self {
+            view: self.view * reflection,
+            projection: Mat4::from_scale([-1.0, 1.0, 1.0]) * self.projection,
+        };
+        mirrored.clipped(plane)
+    }
+
+    /// The view through a portal: a viewer on the +Z side of `source`
+    /// looking through its XY plane sees what lies on the -Z side of
+    /// `destination`, clipped to it. Both are portal to world transforms,
+    /// rotate the destination by half a turn around Y to exit from its +Z
+    /// side.
+    pub fn through_portal(&self, source: Mat4, destination: Mat4) -> Self {
+        let to_destination = destination.inverse().unwrap_or(Mat4::IDENTITY);
+        let portal = Self {
+            view: self.view * source * to_destination,
+            projection: self.projection,
+        };
+        portal.clipped(transform_plane(to_destination, [0.0, 0.0, -1.0, 0.0]))
+    }
+
+    /// Moves the near plane onto the world space `plane`, clipping what's
+    /// behind it, with Lengyel's oblique projection adapted to reverse-Z.
+    /// Depth precision drops far from the plane, and with a field of view
+    /// beyond 120 degrees geometry at grazing angles may be clipped as if
+    /// past the far plane.
+    pub fn clipped(&self, plane: Vec4) -> Self {
+        let plane = transform_plane(self.view, plane);
+        let mut projection = self.projection;
+        // Depth is 1 on the plane, where `z = w`.
+        for (column, p) in projection.0.iter_mut().zip(plane) {
+            column[2] = column[3] - 0.5 * p;
+        }
+        Self { view: self.view, projection }
+    }
+}
+
+/// The six faces of a cubemap seen from `position`, in layer order.
+/// Cubemaps are addressed mirrored compared to `Mat4::perspective` views,
+/// so pipelines drawing into the faces need the opposite
+/// `RasterState::front_face`.
+pub fn cube_face_views(position: Vec3, near: f32) -> [ViewMatrices; 6] {
+    let projection = Mat4::from_scale([1.0, -1.0, 1.0]) * Mat4::perspective(std::f32::consts::FRAC_PI_2, 1.0, near);
+    CUBE_FACES.map(|(forward, up)| ViewMatrices {
+        view: Mat4::look_at(position, add(position, forward), up),
+        projection,
+    })
+}
+
+/// Matches the `MultiviewData` block of `MULTIVIEW_GLSL`, the per-view
+/// data of a pass rendered with `CommandList::begin_rendering_views`:
+///
+/// ```ignore
+/// let views = MultiviewData::from_views(&cube_face_views(probe_position, 0.1));
+/// uniforms.write(0, &views.to_bytes());
+/// command_list.begin_rendering_views(&colors, Some(depth), views.view_mask());
+/// // Draw the scene once, with pipelines created with the same view mask.
+/// command_list.end_rendering();
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MultiviewData {
+    pub view_projection: [Mat4; MAX_VIEWS],
+    /// World space eye positions.
+    pub position: [Vec3; MAX_VIEWS],
+    pub view_count: u32,
+}
+
+impl MultiviewData {
+    /// Up to `MAX_VIEWS` views, e.g. the eyes of a stereo pair or the faces
+    /// from `cube_face_views`.
+    pub fn from_views(views: &[ViewMatrices]) -> Self {
+        assert!(views.len() <= MAX_VIEWS, "Multiview passes render at most {MAX_VIEWS} views");
+        let mut data = Self { view_count: views.len() as u32, ..Default::default() };
+        for (i, view) in views.iter().enumerate() {
+            data.view_projection[i] = view.view_projection();
+            data.position[i] = view.position();
+        }
+        data
+    }
+
+    /// Views without an eye position, such as shadow cascades, see
+    /// `ShadowData::light_view_projection`.
+    pub fn from_view_projections(view_projections: &[Mat4]) -> Self {
+        assert!(view_projections.len() <= MAX_VIEWS, "Multiview passes render at most {MAX_VIEWS} views");
+        let mut data = Self { view_count: view_projections.len() as u32, ..Default::default() };
+        data.view_projection[..view_projections.len()].copy_from_slice(view_projections);
+        data
+    }
+
+    /// Covers every view, for `CommandList::begin_rendering_views` and
+    /// `GraphicsPipelineCreateInfo::view_mask`.
+    pub fn view_mask(&self) -> u32 {
+        (1 << self.view_count) - 1
+    }
+
+    /// std140 layout of the block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(496);
+        for m in &self.view_projection {
+            bytes.extend_from_slice(&m.to_bytes());
+        }
+        for p in &self.position {
+            for c in p.iter().chain(&[1.0]) {
+                bytes.extend_from_slice(&c.to_ne_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.view_count.to_ne_bytes());
+        bytes.resize(496, 0);
+        bytes
+    }
+}
+
+pub struct OffscreenViewCreateInfo {
+    pub extent: vk::Extent2D,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+}
+
+/// Color and depth targets of a secondary view of the scene, rendered in
+/// the same frame as the main one and sampled by it afterwards: mirrors,
+/// portals, security camera screens. Every view drawn into needs its own
+/// instances of the passes it uses, as they keep per-view history and
+/// targets, and pipelines created for its extent or with a dynamic
+/// viewport.
+///
+/// ```ignore
+/// let mirror = camera_view.mirrored(mirror_plane);
+/// offscreen.begin(command_list, [0.0; 4]);
+/// // Draw the scene with `mirror.view_projection()`.
+/// offscreen.end(command_list);
+/// // Draw the main view, the mirror material samples `offscreen.color()`.
+/// ```
+pub struct OffscreenView {
+    color: Texture,
+    depth: Texture,
+}
+
+impl OffscreenView {
+    pub fn new(renderer: Arc<Renderer>, create_info: OffscreenViewCreateInfo) -> Self {
+        let extent = vk::Extent3D { width: create_info.extent.width, height: create_info.extent.height, depth: 1 };
+        let target = |format, usage, aspect| {
+            let create_info = TextureCreateInfo {
+                format,
+                extent,
+                usage: usage | vk::ImageUsageFlags::SAMPLED,
+                aspect,
+                array_layers: 1,
+                mip_levels: 1,
+                kind: TextureKind::D2,
+            };
+            Texture::new(renderer.clone(), create_info)
+        };
+
+        let color = target(
+            create_info.color_format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE,
+            vk::ImageAspectFlags::COLOR);
+        let depth = target(create_info.depth_format, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, vk::ImageAspectFlags::DEPTH);
+        color.set_name("offscreen view color");
+        depth.set_name("offscreen view depth");
+
+        Self { color, depth }
+    }
+
+    pub fn color(&self) -> &Texture {
+        &self.color
+    }
+
+    pub fn depth(&self) -> &Texture {
+        &self.depth
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        let extent = self.color.extent();
+        vk::Extent2D { width: extent.width, height: extent.height }
+    }
+
+    /// Clears the targets, depth to the reverse-Z far plane, and starts
+    /// rendering into them.
+    pub fn begin(&self, command_list: &mut CommandList, clear_color: [f32; 4]) {
+        for texture in [&self.color, &self.depth] {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+        }
+
+        let colors = [ColorAttachment { texture: &self.color, load: LoadOp::Clear(clear_color) }];
+        let depth = DepthAttachment { texture: &self.depth, load: LoadOp::Clear(0.0) };
+        command_list.begin_rendering_attachments(&colors, Some(depth));
+    }
+
+    /// Ends rendering and makes the targets visible to the passes and
+    /// draws sampling them, in `GENERAL` layout.
+    pub fn end(&self, command_list: &mut CommandList) {
+        command_list.end_rendering();
+        for texture in [&self.color, &self.depth] {
+            command_list.transition_texture_layout(texture, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        }
+    }
+}